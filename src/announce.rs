@@ -0,0 +1,94 @@
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+
+use crate::events::NowPlaying;
+use crate::http;
+use crate::ipc::COMMAND_REQUEST_TIMEOUT;
+
+/*
+ *  Optional announcements to a Discord channel, enabled by setting discord_webhook_url in lamp.toml.
+ *
+ *  - The active track is posted as an embed through the channel's webhook on the announce command, or whenever a new
+ *    album starts if announce_albums is enabled.
+ *  - Announcements are separate from rich presence, and are shown to everyone who can read the channel.
+ */
+pub fn announcement_payload(player_name: &str, now_playing: &NowPlaying) -> Value {
+    let mut embed = json!({
+        "author": { "name": now_playing.artist },
+        "title": now_playing.title,
+        "footer": { "text": format!("Listening on {}", player_name) },
+    });
+    if let Some(album) = &now_playing.album {
+        embed["fields"] = json!([{ "name": "Album", "value": album, "inline": true }]);
+    }
+    if let Some(link) = &now_playing.link {
+        embed["url"] = json!(link);
+    }
+    // The cover is shown from its uploaded link, so it is only included if album art was uploaded.
+    if let Some(image_link) = &now_playing.image_link {
+        embed["thumbnail"] = json!({ "url": image_link });
+    }
+
+    json!({ "embeds": [embed] })
+}
+
+pub async fn announce(webhook_url: &str, player_name: &str, now_playing: &NowPlaying) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = announcement_payload(player_name, now_playing).to_string();
+    let response = http::send(|client| client.post(webhook_url)
+                                             .header(CONTENT_TYPE, "application/json")
+                                             .body(payload.clone())
+                                             .timeout(COMMAND_REQUEST_TIMEOUT)).await?;
+
+    // Discord describes failures in the message field of the response.
+    let status_code = response.status();
+    if !status_code.is_success() {
+        let response_json = serde_json::from_str::<Value>(&response.text().await?).unwrap_or(Value::Null);
+        let error = response_json["message"].as_str().map(String::from).unwrap_or(status_code.to_string());
+        return Err(Box::from(format!("The announcement could not be posted: {}", error)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    #[tokio::test]
+    async fn posts_track_embed_to_discord_webhook() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let webhook_url = format!("http://{}/api/webhooks/1/token", listener.local_addr().unwrap());
+        let now_playing = NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            image_link: Some(String::from("https://images.example/art.jpg")),
+            start_time: Some(1000),
+            end_time: Some(1180),
+            ..NowPlaying::default()
+        };
+
+        let announce_task = {
+            let (webhook_url, now_playing) = (webhook_url.clone(), now_playing.clone());
+            tokio::spawn(async move { announce(&webhook_url, "cmus", &now_playing).await.map_err(|e| e.to_string()) })
+        };
+        let request = receive_http_request(&listener, "204 No Content", "").await;
+        assert_eq!(announce_task.await.unwrap(), Ok(()));
+
+        assert_eq!(request.request_line, "POST /api/webhooks/1/token HTTP/1.1");
+        assert_eq!(serde_json::from_str::<Value>(&request.body).unwrap(), json!({
+            "embeds": [{
+                "author": { "name": "Test Artist" },
+                "title": "Test Title",
+                "footer": { "text": "Listening on cmus" },
+                "fields": [{ "name": "Album", "value": "Test Album", "inline": true }],
+                "thumbnail": { "url": "https://images.example/art.jpg" },
+            }],
+        }));
+
+        let announce_task = tokio::spawn(async move { announce(&webhook_url, "cmus", &now_playing).await.map_err(|e| e.to_string()) });
+        receive_http_request(&listener, "404 Not Found", r#"{"message": "Unknown Webhook", "code": 10015}"#).await;
+        assert_eq!(announce_task.await.unwrap(), Err(String::from("The announcement could not be posted: Unknown Webhook")));
+    }
+}
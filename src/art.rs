@@ -0,0 +1,916 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use fast_image_resize::images::Image;
+use fast_image_resize::{IntoImageView, Resizer, ResizeOptions};
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{imageops, AnimationDecoder, ColorType, DynamicImage, ImageEncoder, ImageFormat, ImageReader, Limits, Rgb, RgbImage, Rgba, RgbaImage};
+use reqwest::multipart::{Form, Part};
+use reqwest::Body;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::art_lookup;
+use crate::art_lookup::ArtQuery;
+use crate::error::{ArtError, LampError};
+use crate::error_log;
+use crate::error_log::fs;
+use crate::events::Event;
+use crate::http;
+use crate::metadata::AlbumArt;
+use crate::metrics;
+use crate::paths;
+use crate::state_file;
+use crate::supervisor;
+
+/*
+ *  The album art service processes album art for each track in its own task, so uploads never hold up the event loop.
+ *
+ *  - Links to uploaded images are stored in a hash map keyed by the hashed album art filename. The map is written to
+ *    albumart_hash.json after each new upload, every flush_period if it has unsaved changes, and when the service stops.
+ *
+ *  - Each link is stored with the time it was last confirmed to be available. Links confirmed within verify_period are
+ *    used without checking them again, so most tracks are shown without waiting on the image host.
+ *
+ *  - Each link is also stored with the time it was last used. Once the map holds more than link_limit links, the least
+ *    recently used are removed, so the hash file stays small for large libraries. A link_limit of 0 keeps every link.
+ *
+ *  - Tracks are looked up online on the sources in art_sources that come before their own album art (see art_lookup).
+ *    Public HTTPS links found are shown directly, while other album art found, or all of it if rehost_remote_art is
+ *    enabled, is downloaded and processed the same as embedded album art. If nothing is found, the track's own album
+ *    art is processed instead, if it has any. Rehost requests download album art linked by the player the same way.
+ *
+ *  - Once the image link for a track has been verified or uploaded, an ArtReady event is emitted.
+ *
+ *  - Requests are queued in a bounded channel. If the queue is full, the event loop shows the track without album art
+ *    rather than waiting on a slow upload.
+ */
+pub enum ArtRequest {
+    Process { track_id: u64, file_path: String, album_art: AlbumArt, catbox_user_hash: Option<String> },
+    Lookup { track_id: u64, file_path: String, query: ArtQuery, fallback_art: Option<AlbumArt>, rehost_remote_art: bool, catbox_user_hash: Option<String> },
+    Rehost { track_id: u64, file_path: String, image_link: String, query: ArtQuery, catbox_user_hash: Option<String> },
+    ClearCache,
+}
+
+// A link to uploaded album art, and the Unix timestamps at which it was last confirmed to be available and last used.
+// Links saved before their use was recorded are treated as last used when they were verified.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedLink {
+    pub link: String,
+    pub verified_at: u64,
+    #[serde(default)]
+    pub last_used: u64,
+}
+
+// Hash files written before verification times were recorded map filenames to bare links. Those links are loaded as
+// never verified, so they are checked the next time they are used.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredLink {
+    Cached(CachedLink),
+    Link(String),
+}
+
+// How album art is encoded for upload. With jpeg_quality set, every image is transcoded to JPEG at that quality, from 1
+// to 100, which keeps uploads of large embedded PNGs small. With jpeg_max_kb also set, the quality is lowered in steps
+// until the JPEG fits in that many kilobytes, or the lowest quality is reached. Otherwise images keep their original
+// format where possible. Non-square images are made square as set by fit, and padded onto pad_color, or a blurred copy of the image without it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArtEncoding {
+    pub jpeg_quality: Option<u8>,
+    pub jpeg_max_kb: Option<u64>,
+    pub fit: ArtFit,
+    pub pad_color: Option<[u8; 3]>,
+}
+
+// How non-square album art is made square. Crop keeps the center of the image, while Pad keeps the whole image and fills
+// the space around it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtFit {
+    #[default]
+    Crop,
+    Pad,
+}
+
+// Limits on decoding album art, which bound the memory used while resizing an image.
+const MAX_DECODE_DIMENSION: u32 = 8192;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+// Steps the JPEG quality is lowered by to fit album art in jpeg_max_kb, and the lowest quality it is lowered to.
+const JPEG_QUALITY_STEP: u8 = 10;
+const MIN_JPEG_QUALITY: u8 = 10;
+
+// Size the image is shrunk to before it is scaled back up into a blurred background for padded album art.
+const PAD_BLUR_SIZE: u32 = 16;
+
+// catbox.moe upload endpoint, and the time allowed for each upload to finish.
+const CATBOX_API_URL: &str = "https://catbox.moe/user/api.php";
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Maximum number of requests waiting for the album art service. Further requests are refused until it catches up.
+const ART_QUEUE_SIZE: usize = 4;
+
+/*
+ *  ImageHost is implemented by the services album art is uploaded to.
+ *
+ *  - upload uploads the encoded image as file_name and returns its link. user_hash identifies the account it is uploaded to,
+ *    if any. The image is shared rather than copied, so it can be sent again if the upload is retried.
+ *  - link_status checks whether a previously uploaded link is still available.
+ *  - MAX_UPLOAD_SIZE is the largest file, in bytes, the host accepts.
+ */
+pub trait ImageHost: Send + Sync + 'static {
+    const MAX_UPLOAD_SIZE: u64;
+    fn upload(&self, image_data: Bytes, file_name: &str, user_hash: Option<String>) -> impl Future<Output = Result<String, ArtError>> + Send;
+    fn link_status(&self, image_link: &str) -> impl Future<Output = Result<bool, ArtError>> + Send;
+}
+
+// Uploads to catbox.moe through the shared HTTP client.
+pub struct Catbox;
+
+impl Catbox {
+    pub fn new() -> Self {
+        Catbox
+    }
+}
+
+impl ImageHost for Catbox {
+    const MAX_UPLOAD_SIZE: u64 = 200 * 1024 * 1024;
+
+    async fn upload(&self, image_data: Bytes, file_name: &str, user_hash: Option<String>) -> Result<String, ArtError> {
+        // The image is streamed into the request body straight from the encoded buffer, which each attempt shares.
+        let image_length = image_data.len() as u64;
+        let response = http::send(|client| {
+            let image_part = Part::stream_with_length(Body::from(image_data.clone()), image_length).file_name(String::from(file_name));
+            let form = Form::new().text("reqtype", "fileupload")
+                                  .text("userhash", user_hash.clone().unwrap_or_default())
+                                  .part("fileToUpload", image_part);
+            client.post(CATBOX_API_URL).multipart(form).timeout(UPLOAD_TIMEOUT)
+        }).await?;
+
+        // catbox.moe responds with the link on success, or a description of the error otherwise.
+        let status_code = response.status();
+        let response_text = response.text().await?;
+        let response_text = response_text.trim();
+        if !status_code.is_success() || !response_text.starts_with("https://") {
+            return Err(ArtError::UploadRefused { host: "catbox.moe", status: status_code, message: String::from(response_text) });
+        }
+        Ok(String::from(response_text))
+    }
+
+    async fn link_status(&self, image_link: &str) -> Result<bool, ArtError> {
+        let response = http::send(|client| client.head(image_link)).await?;
+        if response.status() == reqwest::StatusCode::OK { Ok(true) } else { Ok(false) }
+    }
+}
+
+pub fn start_art_service<H: ImageHost>(image_host: H,
+                                       filename_hash: HashMap<String, CachedLink>,
+                                       flush_period: Duration,
+                                       verify_period: Duration,
+                                       link_limit: usize,
+                                       encoding: ArtEncoding,
+                                       event_sender: mpsc::UnboundedSender<Event>) -> (mpsc::Sender<ArtRequest>, JoinHandle<()>) {
+    let (art_sender, art_receiver) = mpsc::channel::<ArtRequest>(ART_QUEUE_SIZE);
+
+    // The receiver is shared so pending requests are kept if the worker panics and is restarted.
+    let art_receiver = Arc::new(Mutex::new(art_receiver));
+    let image_host = Arc::new(image_host);
+    let mut initial_filename_hash = Some(filename_hash);
+    let art_task = supervisor::supervise("album art", move || {
+        // After a restart, links are reloaded from the hash file, which is saved after every upload.
+        let filename_hash = initial_filename_hash.take().unwrap_or_else(|| match load_hash_file() {
+            Ok(filename_hash) => filename_hash,
+            Err(e) => {
+                error_log::log_error("art:load_hash_file Warning", format!("Restarting with an empty album art cache: {}", e).as_str());
+                HashMap::new()
+            }
+        });
+        let link_settings = LinkSettings { flush_period, verify_period, link_limit };
+        run_art_task(image_host.clone(), filename_hash, link_settings, encoding, art_receiver.clone(), event_sender.clone())
+    });
+
+    (art_sender, art_task)
+}
+
+// How long links are kept unsaved and unverified, and how many are kept, as given to start_art_service.
+struct LinkSettings {
+    flush_period: Duration,
+    verify_period: Duration,
+    link_limit: usize,
+}
+
+async fn run_art_task<H: ImageHost>(image_host: Arc<H>,
+                                    mut filename_hash: HashMap<String, CachedLink>,
+                                    link_settings: LinkSettings,
+                                    encoding: ArtEncoding,
+                                    art_receiver: Arc<Mutex<mpsc::Receiver<ArtRequest>>>,
+                                    event_sender: mpsc::UnboundedSender<Event>) {
+    let LinkSettings { flush_period, verify_period, link_limit } = link_settings;
+    let mut art_receiver = art_receiver.lock_owned().await;
+    let mut unsaved_changes = evict_least_recently_used(&mut filename_hash, link_limit); // Whether filename_hash has changed since it was last written to the hash file.
+    let mut flush_interval = tokio::time::interval_at(tokio::time::Instant::now() + flush_period, flush_period);
+
+    // Process requests until the event loop drops its sender.
+    loop {
+        let art_request = tokio::select! {
+            art_request = art_receiver.recv() => match art_request {
+                Some(art_request) => art_request,
+                None => break,
+            },
+            _ = flush_interval.tick() => {
+                if unsaved_changes {
+                    unsaved_changes = !save_hash_file(&filename_hash);
+                }
+                continue;
+            }
+        };
+
+        let (track_id, file_path, art_result, catbox_user_hash) = match art_request {
+            ArtRequest::Process { track_id, file_path, album_art, catbox_user_hash } => (track_id, file_path, Ok(Some(album_art)), catbox_user_hash),
+            ArtRequest::Lookup { track_id, file_path, query, fallback_art, rehost_remote_art, catbox_user_hash } => {
+                let art_result = match art_lookup::find_album_art(&query).await {
+                    // Public HTTPS links are shown directly, without uploading the image.
+                    Some(image_link) if !rehost_remote_art && image_link.starts_with("https://") => {
+                        if event_sender.send(Event::ArtReady { track_id, image_link: Some(image_link) }).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    // Without a catbox user hash, album art that would have to be uploaded isn't downloaded.
+                    Some(image_link) if catbox_user_hash.is_some() => art_lookup::fetch_album_art(&query, &image_link).await,
+                    _ => Ok(None),
+                };
+                let art_result = match art_result {
+                    Err(e) if fallback_art.is_some() => {
+                        error_log::log_error("art:fetch_album_art Error", format!("Album art could not be downloaded for the file at {}: {}", file_path, e).as_str());
+                        Ok(fallback_art)
+                    }
+                    art_result => art_result.map(|album_art| album_art.or(fallback_art)),
+                };
+                (track_id, file_path, art_result, catbox_user_hash)
+            }
+            ArtRequest::Rehost { track_id, file_path, image_link, query, catbox_user_hash } => {
+                (track_id, file_path, art_lookup::fetch_album_art(&query, &image_link).await, catbox_user_hash)
+            }
+            ArtRequest::ClearCache => {
+                filename_hash.clear();
+                unsaved_changes = !save_hash_file(&filename_hash);
+                continue;
+            }
+        };
+        let album_art = match art_result {
+            Ok(Some(album_art)) => album_art,
+            art_result => {
+                if let Err(e) = art_result {
+                    error_log::log_error("art:fetch_album_art Error", format!("Album art could not be downloaded for the file at {}: {}", file_path, e).as_str());
+                }
+                if event_sender.send(Event::ArtReady { track_id, image_link: None }).is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let mut image_link: Option<String> = None;
+
+        // If the filename is already in the hash map, use its link if it was verified recently, or verify the link status.
+        // Each use is recorded for eviction, and saved with the next flush.
+        if let Some(cached_link) = filename_hash.get_mut(&cache_key(&album_art.filename, encoding)) {
+            let now = unix_time();
+            cached_link.last_used = now;
+            unsaved_changes = true;
+            if now.saturating_sub(cached_link.verified_at) < verify_period.as_secs() {
+                image_link = Some(cached_link.link.clone());
+            } else {
+                let link_status_good = match image_host.link_status(&cached_link.link).await {
+                    Ok(link_status) => link_status,
+                    Err(e) => {
+                        error_log::log_lamp_error("art:link_status_good", &LampError::from(e));
+                        false
+                    }
+                };
+
+                // If link is good, use it as the image link. The new verification time is saved with the next flush.
+                if link_status_good {
+                    cached_link.verified_at = now;
+                    unsaved_changes = true;
+                    image_link = Some(cached_link.link.clone());
+                }
+            }
+        }
+
+        // Filename is not in the hash map or the link is bad. Upload album art and update link in hash map.
+        if image_link.is_none() {
+            match write_album_art(image_host.as_ref(), album_art, encoding, &catbox_user_hash).await {
+                Ok(key_link_pair) => {
+                    metrics::increment(&metrics::UPLOADS);
+                    image_link = Some(key_link_pair.1.clone());
+                    filename_hash.insert(key_link_pair.0, CachedLink { link: key_link_pair.1, verified_at: unix_time(), last_used: unix_time() });
+                    evict_least_recently_used(&mut filename_hash, link_limit);
+
+                    // Save new links right away so they aren't lost if lamp-drpc doesn't exit cleanly.
+                    unsaved_changes = !save_hash_file(&filename_hash);
+                },
+                Err(image_error) => {
+                    metrics::increment(&metrics::UPLOAD_FAILURES);
+                    error_log::log_error("art:write_album_art Error", format!("Error while processing album art image on file {}: {}", &file_path, image_error).as_str());
+                }
+            }
+        }
+
+        if event_sender.send(Event::ArtReady { track_id, image_link }).is_err() {
+            break;
+        }
+    }
+
+    // Update hash file with all changes on exit.
+    save_hash_file(&filename_hash);
+}
+
+// Removes the least recently used links beyond link_limit, returning whether any were removed.
+fn evict_least_recently_used(filename_hash: &mut HashMap<String, CachedLink>, link_limit: usize) -> bool {
+    if link_limit == 0 || filename_hash.len() <= link_limit {
+        return false;
+    }
+
+    let mut filenames_by_use = filename_hash.iter().map(|(filename, cached_link)| (cached_link.last_used, filename.clone())).collect::<Vec<(u64, String)>>();
+    filenames_by_use.sort_unstable();
+    for (_, filename) in filenames_by_use.into_iter().take(filename_hash.len() - link_limit) {
+        filename_hash.remove(&filename);
+    }
+    true
+}
+
+// Returns whether the hash file was written successfully. Failures are logged.
+pub fn save_hash_file(filename_hash: &HashMap<String, CachedLink>) -> bool {
+    match write_to_hash_file(filename_hash) {
+        Ok(_) => true,
+        Err(e) => {
+            error_log::log_error("art:write_to_hash_file Error", e.to_string().as_str());
+            false
+        }
+    }
+}
+
+pub fn load_hash_file() -> Result<HashMap<String, CachedLink>, Box<dyn std::error::Error>> {
+    // Check for hashed link file. If it exists, read it, otherwise create blank one.
+    let hash_file_path = hash_file_path();
+    let mut filename_hash = HashMap::<String, CachedLink>::new();
+
+    match fs::exists(&hash_file_path) {
+        Ok(true) => {
+            // Read existing hash file.
+            let hash_file = File::open(&hash_file_path)?;
+
+            let hash_reader = BufReader::new(hash_file);
+            match serde_json::from_reader::<_, HashMap<String, StoredLink>>(hash_reader) {
+                Ok(existing_filename_hash) => {
+                    filename_hash = existing_filename_hash.into_iter().map(|(filename, stored_link)| match stored_link {
+                        StoredLink::Cached(CachedLink { link, verified_at, last_used: 0 }) => (filename, CachedLink { link, verified_at, last_used: verified_at }),
+                        StoredLink::Cached(cached_link) => (filename, cached_link),
+                        StoredLink::Link(link) => (filename, CachedLink { link, verified_at: 0, last_used: 0 }),
+                    }).collect();
+                }
+                Err(e) => {
+                    // Hash file is corrupt. Back it up and start with an empty map, so album art is simply uploaded again.
+                    let backup_file_path = format!("{}.corrupt-{}", hash_file_path.display(), chrono::offset::Local::now().format("%Y%m%d%H%M%S"));
+                    fs::rename(&hash_file_path, &backup_file_path)?;
+                    error_log::log_error("art:load_hash_file Warning", format!("The hash file at {} could not be parsed and was moved to {}. Starting with an empty album art cache.\n{}", hash_file_path.display(), backup_file_path, e).as_str());
+                }
+            }
+        },
+        Ok(false) => {
+            // Create new hash file.
+            state_file::write_atomic(&hash_file_path, b"{\n}")?;
+        }
+        Err(e) => {
+            return Err(Box::from(e));
+        }
+    }
+
+    Ok(filename_hash)
+}
+
+fn write_to_hash_file(filename_hash: &HashMap<String, CachedLink>) -> Result<(), Box<dyn std::error::Error>> {
+    // Replace the contents of the hash file with the current hash map, creating it again if it does not exist.
+    let hash_string = serde_json::to_string_pretty(&filename_hash)?;
+    state_file::write_atomic(&hash_file_path(), hash_string.as_bytes())?;
+    Ok(())
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
+
+fn hash_file_path() -> PathBuf {
+    paths::state_dir().join("albumart_hash.json")
+}
+
+// Writes the resized album art to the covers directory in the state directory, returning its path.
+// Covers are named by their hashed album art filename, so each album's cover is only written once.
+pub fn cache_cover(album_art: AlbumArt) -> Result<PathBuf, ArtError> {
+    let covers_dir = paths::state_dir().join("covers");
+    let cover_path = covers_dir.join(encoded_filename(&album_art.filename, ArtEncoding::default()));
+    if cover_path.exists() {
+        return Ok(cover_path);
+    }
+
+    fs::create_dir_all(&covers_dir)?;
+    state_file::write_atomic(&cover_path, &resize_album_art(album_art, ArtEncoding::default())?)?;
+    Ok(cover_path)
+}
+
+pub async fn write_album_art<H: ImageHost>(image_host: &H, album_art: AlbumArt, encoding: ArtEncoding, catbox_user_hash: &Option<String>) -> Result<(String, String), ArtError> {
+    // Decoding and resizing are CPU-bound, so they run on a blocking thread rather than holding up other tasks.
+    // A panic while processing a malformed image is returned as an error for this image only.
+    // Animated GIFs the host accepts are uploaded unmodified, so they animate on Discord, since resizing keeps only their
+    // first frame.
+    let key = cache_key(&album_art.filename, encoding);
+    let max_upload_size = H::MAX_UPLOAD_SIZE;
+    let (image_data, upload_filename) = tokio::task::spawn_blocking(move || {
+        if album_art.data.len() as u64 <= max_upload_size && is_animated_gif(&album_art) {
+            return Ok((album_art.data, album_art.filename));
+        }
+        let upload_filename = encoded_filename(&album_art.filename, encoding);
+        resize_album_art(album_art, encoding).map(|image_data| (image_data, upload_filename))
+    }).await??;
+
+    // Upload the encoded image straight from memory. Converting it to Bytes takes ownership of the buffer without copying it.
+    let uploaded_link = image_host.upload(Bytes::from(image_data), &upload_filename, catbox_user_hash.clone()).await?;
+
+    Ok((key, uploaded_link))
+}
+
+// Whether the album art is a GIF with more than one frame. Only the first two frames are decoded.
+fn is_animated_gif(album_art: &AlbumArt) -> bool {
+    if !album_art.filename.ends_with(".gif") {
+        return false;
+    }
+    match GifDecoder::new(Cursor::new(album_art.data.as_slice())) {
+        Ok(gif_decoder) => gif_decoder.into_frames().take(2).filter(Result::is_ok).count() > 1,
+        Err(_) => false,
+    }
+}
+
+// The filename of the album art once resize_album_art has encoded it. Images transcoded to JPEG, or WebP, GIF, and BMP
+// images converted to PNG, are named for their new format, while album art is still stored in the hash file under its
+// cache key.
+fn encoded_filename(filename: &str, encoding: ArtEncoding) -> String {
+    match filename.rsplit_once('.') {
+        Some((file_stem, _)) if encoding.jpeg_quality.is_some() => format!("{}.jpg", file_stem),
+        Some((file_stem, "webp" | "gif" | "bmp")) => format!("{}.png", file_stem),
+        _ => String::from(filename),
+    }
+}
+
+// The key album art's link is stored under in the hash file. Links uploaded with the default encoding are stored under
+// the original filename, and other encodings are added to it, so changing the encoding uploads the album art again.
+pub fn cache_key(filename: &str, encoding: ArtEncoding) -> String {
+    let mut key = String::from(filename);
+    if let Some(jpeg_quality) = encoding.jpeg_quality {
+        key.push_str(&format!(":jpeg{}", jpeg_quality));
+        if let Some(jpeg_max_kb) = encoding.jpeg_max_kb {
+            key.push_str(&format!(":max{}kb", jpeg_max_kb));
+        }
+    }
+    match (encoding.fit, encoding.pad_color) {
+        (ArtFit::Crop, _) => {}
+        (ArtFit::Pad, Some([red, green, blue])) => key.push_str(&format!(":pad{:02x}{:02x}{:02x}", red, green, blue)),
+        (ArtFit::Pad, None) => key.push_str(":padblur"),
+    }
+    key
+}
+
+// Resizes the album art to a square between 512x512 and 1024x1024, returning the image encoded in its original format.
+// WebP, GIF, and BMP images, which not every client shows, are encoded as PNG instead, and every image is encoded as
+// JPEG if the encoding sets a JPEG quality.
+pub fn resize_album_art(album_art: AlbumArt, encoding: ArtEncoding) -> Result<Vec<u8>, ArtError> {
+    // Determine format of image to write.
+    let mut reader: ImageReader<Cursor<Vec<u8>>>;
+    let mime_type: &str;
+
+    if let Some(split_filename) = album_art.filename.rsplit_once('.') {
+        mime_type = split_filename.1;
+    } else {
+        return Err(ArtError::UnsupportedFormat(album_art.filename));
+    }
+
+    match mime_type {
+        "jpg" | "jpeg" => {
+            reader = ImageReader::new(Cursor::new(album_art.data));
+            reader.set_format(ImageFormat::Jpeg);
+        }
+        "png" => {
+            reader = ImageReader::new(Cursor::new(album_art.data));
+            reader.set_format(ImageFormat::Png);
+        }
+        "webp" => {
+            reader = ImageReader::new(Cursor::new(album_art.data));
+            reader.set_format(ImageFormat::WebP);
+        }
+        "gif" => {
+            reader = ImageReader::new(Cursor::new(album_art.data));
+            reader.set_format(ImageFormat::Gif);
+        }
+        "bmp" => {
+            reader = ImageReader::new(Cursor::new(album_art.data));
+            reader.set_format(ImageFormat::Bmp);
+        }
+        &_ => return Err(ArtError::UnsupportedFormat(String::from(mime_type))),
+    } 
+
+    // Decode image and get dimensions.
+    // Decoding is limited so oversized images fail with an error instead of allocating large buffers.
+    let mut decode_limits = Limits::default();
+    decode_limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    decode_limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    decode_limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    reader.limits(decode_limits);
+    let img = reader.decode()?;
+
+    // JPEG only holds 8-bit grayscale or RGB pixels, so other images transcoded to it are converted to RGB first, dropping
+    // any transparency.
+    let img = match (encoding.jpeg_quality, img.color()) {
+        (Some(_), ColorType::L8 | ColorType::Rgb8) | (None, _) => img,
+        (Some(_), _) => DynamicImage::ImageRgb8(img.into_rgb8()),
+    };
+
+    // Padded album art is already a square between 512x512 and 1024x1024, so it is encoded without further cropping.
+    let img = match encoding.fit {
+        ArtFit::Pad if img.width() != img.height() => pad_album_art(img, encoding.pad_color)?,
+        _ => img,
+    };
+    let dimensions = (img.width(), img.height());
+
+    // Determine new image dimensions based on current dimensions. 
+    // If the image is already a square between 512x512 and 1024x1024, no cropping is necessary.
+    let (dst_width, dst_height): (u32, u32);
+    let mut dst_image: Image<'_>;
+
+    // Image is already square (equal dimensions).
+    if dimensions.0 == dimensions.1 {
+        if dimensions.0 < 512 {
+            (dst_width, dst_height) = (512, 512);
+        } else if dimensions.0 > 1024 {
+            (dst_width, dst_height) = (1024, 1024);
+        } else {
+            (dst_width, dst_height) = (dimensions.0, dimensions.1);
+        }
+
+        match img.pixel_type() {
+            Some(pt) => dst_image = Image::new(dst_width, dst_height, pt),
+            None => return Err(ArtError::UnknownPixelType),
+        }
+
+        // Resize image with no cropping.
+        Resizer::new().resize(&img, &mut dst_image, None)?;
+    } else {
+        // Image is not already square.
+        // Determine which dimension is smaller.
+        let smaller_dimension = dimensions.0.min(dimensions.1);
+
+        // Smaller dimension is between 512 and 1024.
+        // Set both dimensions to the smaller value.
+        if 512 < smaller_dimension && smaller_dimension < 1024 {
+            (dst_width, dst_height) = (smaller_dimension, smaller_dimension);
+        }
+        // Smaller dimension is greater than 1024.
+        // Set both dimensions to 1024.
+        else if 1024 < smaller_dimension {
+            (dst_width, dst_height) = (1024, 1024);
+        }
+        // Smaller dimension is less than 512.
+        // Set both dimensions to 512.
+        else {
+            (dst_width, dst_height) = (512, 512);
+        }
+
+        match img.pixel_type() {
+            Some(pt) => dst_image = Image::new(dst_width, dst_height, pt),
+            None => return Err(ArtError::UnknownPixelType),
+        }
+
+        // Resize image with cropping.
+        Resizer::new().resize(&img, &mut dst_image, &ResizeOptions::new().fit_into_destination(Some((0.5,0.5))),)?;
+    }
+
+    // Only the resized image is needed from here on, so the decoded image is freed before encoding.
+    let color_type = img.color();
+    drop(img);
+
+    // The resized pixels are encoded directly into the buffer that is uploaded, which is sized up front for a typical
+    // compressed image so it rarely needs to grow.
+    let mut result_buf = Vec::<u8>::with_capacity(dst_image.buffer().len() / 4);
+
+    // Decide on image encoder to use based on mime type and encode the resized image.
+    match (encoding.jpeg_quality, mime_type) {
+        (Some(jpeg_quality), _) => {
+            let jpeg_max_size = encoding.jpeg_max_kb.map(|jpeg_max_kb| jpeg_max_kb.saturating_mul(1024));
+            let mut jpeg_quality = jpeg_quality;
+            loop {
+                JpegEncoder::new_with_quality(&mut result_buf, jpeg_quality)
+                    .write_image(
+                    dst_image.buffer(),
+                        dst_width,
+                        dst_height,
+            color_type.into(),)?;
+                if jpeg_max_size.is_none_or(|jpeg_max_size| result_buf.len() as u64 <= jpeg_max_size) || jpeg_quality <= MIN_JPEG_QUALITY {
+                    break;
+                }
+                jpeg_quality = jpeg_quality.saturating_sub(JPEG_QUALITY_STEP).max(MIN_JPEG_QUALITY);
+                result_buf.clear();
+            }
+        },
+        (None, "jpg" | "jpeg") => JpegEncoder::new(&mut result_buf)
+            .write_image(
+            dst_image.buffer(),
+                dst_width,
+                dst_height,
+    color_type.into(),)?,
+        (None, "png" | "webp" | "gif" | "bmp") => PngEncoder::new(&mut result_buf)
+            .write_image(
+            dst_image.buffer(),
+                dst_width,
+                dst_height,
+    color_type.into(),)?,
+        _ => return Err(ArtError::UnsupportedFormat(String::from(mime_type))),
+    }
+
+    Ok(result_buf)
+}
+
+// Scales the image to fit a square between 512x512 and 1024x1024, centered on a solid pad_color background, or on a
+// blurred copy of the image stretched to fill the square.
+fn pad_album_art(img: DynamicImage, pad_color: Option<[u8; 3]>) -> Result<DynamicImage, ArtError> {
+    let larger_dimension = img.width().max(img.height());
+    let side = larger_dimension.clamp(512, 1024);
+
+    // The image is composed as 8-bit pixels, keeping its transparency if it has any.
+    let img = if img.color().has_alpha() { DynamicImage::ImageRgba8(img.into_rgba8()) } else { DynamicImage::ImageRgb8(img.into_rgb8()) };
+    let Some(pixel_type) = img.pixel_type() else {
+        return Err(ArtError::UnknownPixelType);
+    };
+    let mut resizer = Resizer::new();
+
+    let mut padded_image = match pad_color {
+        Some([red, green, blue]) if img.color().has_alpha() => DynamicImage::ImageRgba8(RgbaImage::from_pixel(side, side, Rgba([red, green, blue, 255]))),
+        Some(rgb) => DynamicImage::ImageRgb8(RgbImage::from_pixel(side, side, Rgb(rgb))),
+        None => {
+            let mut blurred_image = Image::new(PAD_BLUR_SIZE, PAD_BLUR_SIZE, pixel_type);
+            resizer.resize(&img, &mut blurred_image, &ResizeOptions::new().fit_into_destination(Some((0.5, 0.5))))?;
+            let mut background = Image::new(side, side, pixel_type);
+            resizer.resize(&blurred_image, &mut background, None)?;
+            composable_image(background, img.color().has_alpha())?
+        }
+    };
+
+    let scaled_width = (u64::from(img.width()) * u64::from(side) / u64::from(larger_dimension)).max(1) as u32;
+    let scaled_height = (u64::from(img.height()) * u64::from(side) / u64::from(larger_dimension)).max(1) as u32;
+    let mut scaled_image = Image::new(scaled_width, scaled_height, pixel_type);
+    resizer.resize(&img, &mut scaled_image, None)?;
+    let scaled_image = composable_image(scaled_image, img.color().has_alpha())?;
+    imageops::overlay(&mut padded_image, &scaled_image, i64::from((side - scaled_width) / 2), i64::from((side - scaled_height) / 2));
+
+    Ok(padded_image)
+}
+
+// Converts resized 8-bit RGB or RGBA pixels back into an image that can be drawn onto another.
+fn composable_image(image: Image<'_>, has_alpha: bool) -> Result<DynamicImage, ArtError> {
+    let (width, height) = (image.width(), image.height());
+    let composable_image = if has_alpha {
+        RgbaImage::from_raw(width, height, image.into_vec()).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(width, height, image.into_vec()).map(DynamicImage::ImageRgb8)
+    };
+    composable_image.ok_or(ArtError::UnknownPixelType)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::art_lookup::ArtSource;
+    use crate::metadata::read_metadata;
+    use crate::mock::{fixture_path, use_test_state_dir, MockImageHost, MockUpload};
+
+    // Reads the album art from a fixture, renamed so each test uses its own temporary file.
+    fn fixture_album_art(fixture_name: &str, filename: &str) -> AlbumArt {
+        let mut album_art = read_metadata(&fixture_path(fixture_name), &false, &[], &[], &[], &ArtSource::ALL, u64::MAX).unwrap().album_art.unwrap();
+        album_art.filename = String::from(filename);
+        album_art
+    }
+
+    // Processes a single request through the album art service, returning the events it emitted.
+    async fn process_album_art(image_host: MockImageHost, filename_hash: HashMap<String, CachedLink>, album_art: AlbumArt) -> Vec<Event> {
+        use_test_state_dir();
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        let (art_sender, art_task) = start_art_service(image_host, filename_hash, Duration::from_secs(60), Duration::from_secs(3600), 0, ArtEncoding::default(), event_sender);
+
+        art_sender.send(ArtRequest::Process { track_id: 1, file_path: String::from("track.mp3"), album_art, catbox_user_hash: None }).await.unwrap();
+        drop(art_sender);
+        art_task.await.unwrap();
+
+        let mut events = Vec::<Event>::new();
+        while let Ok(event) = event_receiver.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn ready_link(events: &[Event]) -> Option<String> {
+        match events.last() {
+            Some(Event::ArtReady { track_id: 1, image_link }) => image_link.clone(),
+            _ => panic!("The album art service did not finish with ArtReady."),
+        }
+    }
+
+    #[tokio::test]
+    async fn uploads_new_album_art_as_square_image() {
+        let image_host = MockImageHost::default();
+        let events = process_album_art(image_host.clone(), HashMap::new(), fixture_album_art("tagged.mp3", "new-art.jpg")).await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(ready_link(&events).as_deref(), Some("https://images.example/new-art.jpg"));
+        assert_eq!(*image_host.uploads.lock().unwrap(), vec![MockUpload { file_name: String::from("new-art.jpg"), dimensions: (512, 512) }]);
+    }
+
+    #[tokio::test]
+    async fn uploads_webp_gif_and_bmp_album_art_as_png() {
+        for (image_format, extension) in [(ImageFormat::WebP, "webp"), (ImageFormat::Gif, "gif"), (ImageFormat::Bmp, "bmp")] {
+            let mut image_data = Vec::new();
+            image::RgbImage::new(600, 600).write_to(&mut Cursor::new(&mut image_data), image_format).unwrap();
+            let album_art = AlbumArt { filename: format!("{}-art.{}", extension, extension), data: image_data };
+            let image_host = MockImageHost::default();
+            let events = process_album_art(image_host.clone(), HashMap::new(), album_art).await;
+
+            let png_filename = format!("{}-art.png", extension);
+            assert_eq!(ready_link(&events), Some(format!("https://images.example/{}", png_filename)));
+            assert_eq!(*image_host.uploads.lock().unwrap(), vec![MockUpload { file_name: png_filename, dimensions: (600, 600) }]);
+        }
+    }
+
+    #[tokio::test]
+    async fn transcodes_album_art_to_jpeg() {
+        let mut image_data = Vec::new();
+        image::RgbaImage::from_fn(600, 600, |x, y| image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8, 128]))
+            .write_to(&mut Cursor::new(&mut image_data), ImageFormat::Png).unwrap();
+        let album_art = AlbumArt { filename: String::from("transparent-art.png"), data: image_data };
+        let image_host = MockImageHost::default();
+        let encoding = ArtEncoding { jpeg_quality: Some(85), ..ArtEncoding::default() };
+        let (filename, link) = write_album_art(&image_host, album_art.clone(), encoding, &None).await.unwrap();
+
+        // The link is stored under the original filename and the JPEG quality, so other qualities upload it again.
+        assert_eq!((filename.as_str(), link.as_str()), ("transparent-art.png:jpeg85", "https://images.example/transparent-art.jpg"));
+        assert_eq!(cache_key("transparent-art.png", ArtEncoding::default()), "transparent-art.png");
+        assert_eq!(cache_key("transparent-art.png", ArtEncoding { jpeg_quality: Some(85), jpeg_max_kb: Some(200), ..ArtEncoding::default() }), "transparent-art.png:jpeg85:max200kb");
+        assert_eq!(*image_host.uploads.lock().unwrap(), vec![MockUpload { file_name: String::from("transparent-art.jpg"), dimensions: (600, 600) }]);
+
+        let low_quality = resize_album_art(album_art.clone(), ArtEncoding { jpeg_quality: Some(20), ..ArtEncoding::default() }).unwrap();
+        let high_quality = resize_album_art(album_art.clone(), ArtEncoding { jpeg_quality: Some(95), ..ArtEncoding::default() }).unwrap();
+        assert_eq!(image::guess_format(&low_quality).unwrap(), ImageFormat::Jpeg);
+        assert!(low_quality.len() < high_quality.len());
+
+        // With a size limit, the quality is lowered until the JPEG fits, but not below the lowest quality.
+        let limited_quality = resize_album_art(album_art.clone(), ArtEncoding { jpeg_quality: Some(95), jpeg_max_kb: Some((high_quality.len() / 1024) as u64), ..ArtEncoding::default() }).unwrap();
+        assert!(limited_quality.len() < high_quality.len());
+        let lowest_quality = resize_album_art(album_art.clone(), ArtEncoding { jpeg_quality: Some(95), jpeg_max_kb: Some(0), ..ArtEncoding::default() }).unwrap();
+        assert_eq!(lowest_quality, resize_album_art(album_art, ArtEncoding { jpeg_quality: Some(MIN_JPEG_QUALITY), ..ArtEncoding::default() }).unwrap());
+    }
+
+    #[test]
+    fn pads_non_square_album_art() {
+        let mut image_data = Vec::new();
+        RgbImage::from_pixel(1200, 600, Rgb([200, 0, 0])).write_to(&mut Cursor::new(&mut image_data), ImageFormat::Png).unwrap();
+        let album_art = AlbumArt { filename: String::from("wide-art.png"), data: image_data };
+        let padded_art = |encoding: ArtEncoding| image::load_from_memory(&resize_album_art(album_art.clone(), encoding).unwrap()).unwrap().into_rgb8();
+
+        // The whole image is scaled down to fit, centered between bars of the pad color.
+        let padded_image = padded_art(ArtEncoding { fit: ArtFit::Pad, pad_color: Some([0, 0, 255]), ..ArtEncoding::default() });
+        assert_eq!(padded_image.dimensions(), (1024, 1024));
+        assert_eq!((padded_image[(512, 100)], padded_image[(512, 923)]), (Rgb([0, 0, 255]), Rgb([0, 0, 255])));
+        assert_eq!((padded_image[(0, 512)], padded_image[(1023, 512)]), (Rgb([200, 0, 0]), Rgb([200, 0, 0])));
+
+        // Without a pad color, the bars are a blurred copy of the image.
+        let padded_image = padded_art(ArtEncoding { fit: ArtFit::Pad, ..ArtEncoding::default() });
+        assert_eq!(padded_image[(512, 100)], Rgb([200, 0, 0]));
+
+        // Cropped album art keeps only the center of the image.
+        assert_eq!(padded_art(ArtEncoding::default()).dimensions(), (600, 600));
+
+        // Padded album art is stored under its background, so changing the fit or pad color uploads it again.
+        assert_eq!(cache_key("wide-art.png", ArtEncoding { fit: ArtFit::Pad, pad_color: Some([0, 0, 255]), ..ArtEncoding::default() }), "wide-art.png:pad0000ff");
+        assert_eq!(cache_key("wide-art.png", ArtEncoding { fit: ArtFit::Pad, ..ArtEncoding::default() }), "wide-art.png:padblur");
+        assert_eq!(cache_key("wide-art.png", ArtEncoding { pad_color: Some([0, 0, 255]), ..ArtEncoding::default() }), "wide-art.png");
+    }
+
+    #[tokio::test]
+    async fn uploads_animated_gifs_unmodified() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, Frame, RgbaImage};
+        // Frames of noise, which compress poorly, so the size of the GIF can be set by the size of its frames.
+        let animated_gif = |size: u32| {
+            let mut noise = 1u32;
+            let mut frame = || Frame::from_parts(RgbaImage::from_fn(size, size, |_, _| {
+                noise = noise.wrapping_mul(1664525).wrapping_add(1013904223);
+                image::Rgba([(noise >> 24) as u8, (noise >> 16) as u8, (noise >> 8) as u8, 255])
+            }), 0, 0, Delay::from_numer_denom_ms(100, 1));
+            let mut image_data = Vec::new();
+            GifEncoder::new(&mut image_data).encode_frames([frame(), frame()]).unwrap();
+            image_data
+        };
+
+        let image_data = animated_gif(100);
+        let album_art = AlbumArt { filename: String::from("animated-art.gif"), data: image_data.clone() };
+        let image_host = MockImageHost::default();
+        let events = process_album_art(image_host.clone(), HashMap::new(), album_art).await;
+        assert_eq!(ready_link(&events).as_deref(), Some("https://images.example/animated-art.gif"));
+        assert_eq!(*image_host.uploads.lock().unwrap(), vec![MockUpload { file_name: String::from("animated-art.gif"), dimensions: (100, 100) }]);
+
+        // Animated GIFs too large for the host are resized like any other image.
+        let image_data = animated_gif(200);
+        assert!(image_data.len() as u64 > MockImageHost::MAX_UPLOAD_SIZE);
+        let album_art = AlbumArt { filename: String::from("large-animated-art.gif"), data: image_data };
+        let image_host = MockImageHost::default();
+        process_album_art(image_host.clone(), HashMap::new(), album_art).await;
+        assert_eq!(*image_host.uploads.lock().unwrap(), vec![MockUpload { file_name: String::from("large-animated-art.png"), dimensions: (512, 512) }]);
+    }
+
+    #[tokio::test]
+    async fn reuses_available_links() {
+        let image_host = MockImageHost::default();
+        let filename_hash = HashMap::from([(String::from("cached-art.png"), CachedLink { link: String::from("https://images.example/cached"), verified_at: 0, last_used: 0 })]);
+        let events = process_album_art(image_host.clone(), filename_hash, fixture_album_art("tagged.flac", "cached-art.png")).await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(ready_link(&events).as_deref(), Some("https://images.example/cached"));
+        assert_eq!(*image_host.link_checks.lock().unwrap(), 1);
+        assert!(image_host.uploads.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_link_check_when_verified_recently() {
+        let image_host = MockImageHost { links_expired: true, ..MockImageHost::default() };
+        let filename_hash = HashMap::from([(String::from("verified-art.png"), CachedLink { link: String::from("https://images.example/verified"), verified_at: unix_time() - 60, last_used: 0 })]);
+        let events = process_album_art(image_host.clone(), filename_hash, fixture_album_art("tagged.flac", "verified-art.png")).await;
+
+        assert_eq!(ready_link(&events).as_deref(), Some("https://images.example/verified"));
+        assert_eq!(*image_host.link_checks.lock().unwrap(), 0);
+        assert!(image_host.uploads.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn uploads_again_when_link_has_expired() {
+        let image_host = MockImageHost { links_expired: true, ..MockImageHost::default() };
+        let filename_hash = HashMap::from([(String::from("expired-art.png"), CachedLink { link: String::from("https://images.example/expired"), verified_at: 0, last_used: 0 })]);
+        let events = process_album_art(image_host.clone(), filename_hash, fixture_album_art("tagged.flac", "expired-art.png")).await;
+
+        assert_eq!(ready_link(&events).as_deref(), Some("https://images.example/expired-art.png"));
+        assert_eq!(image_host.uploads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_links() {
+        let cached_link = |last_used: u64| CachedLink { link: format!("https://images.example/{}", last_used), verified_at: 0, last_used };
+        let mut filename_hash = HashMap::from([(String::from("old.png"), cached_link(100)), (String::from("new.png"), cached_link(300)), (String::from("mid.png"), cached_link(200))]);
+
+        assert!(!evict_least_recently_used(&mut filename_hash, 0));
+        assert!(!evict_least_recently_used(&mut filename_hash, 3));
+        assert!(evict_least_recently_used(&mut filename_hash, 2));
+        let mut kept_filenames = filename_hash.into_keys().collect::<Vec<String>>();
+        kept_filenames.sort();
+        assert_eq!(kept_filenames, vec!["mid.png", "new.png"]);
+    }
+
+    #[tokio::test]
+    async fn failed_upload_is_ready_without_link() {
+        let image_host = MockImageHost { fail_uploads: true, ..MockImageHost::default() };
+        let events = process_album_art(image_host, HashMap::new(), fixture_album_art("tagged.flac", "failed-art.png")).await;
+
+        assert_eq!(ready_link(&events), None);
+    }
+
+    #[tokio::test]
+    async fn malformed_album_art_is_ready_without_link() {
+        let album_art = AlbumArt { filename: String::from("malformed-art.jpg"), data: vec![0xFF, 0xD8, 0x00, 0x01] };
+        let events = process_album_art(MockImageHost::default(), HashMap::new(), album_art).await;
+
+        assert_eq!(ready_link(&events), None);
+    }
+
+    #[test]
+    fn resize_errors_report_their_cause() {
+        let unsupported_art = AlbumArt { filename: String::from("unsupported-art.tiff"), data: vec![0x49, 0x49, 0x2A, 0x00] };
+        assert!(matches!(resize_album_art(unsupported_art, ArtEncoding::default()), Err(ArtError::UnsupportedFormat(format)) if format == "tiff"));
+
+        let malformed_art = AlbumArt { filename: String::from("malformed-art.jpg"), data: vec![0xFF, 0xD8, 0x00, 0x01] };
+        assert!(matches!(resize_album_art(malformed_art, ArtEncoding::default()), Err(ArtError::Image(_))));
+    }
+}
@@ -0,0 +1,352 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::error::ArtError;
+use crate::error_log;
+use crate::http;
+use crate::metadata;
+use crate::metadata::{AlbumArt, MetadataPackage};
+
+// The Cover Art Archive redirects requests for a release's front cover to the image on the Internet Archive.
+const COVER_ART_ARCHIVE_URL: &str = "https://coverartarchive.org";
+const LASTFM_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+const ITUNES_SEARCH_URL: &str = "https://itunes.apple.com/search";
+const DEEZER_SEARCH_URL: &str = "https://api.deezer.com/search/album";
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Last.fm's placeholder image, which some albums without a cover are given in place of an empty link.
+const LASTFM_PLACEHOLDER_IMAGE: &str = "2a96cbd8b46e442fc41c2b86b821562f";
+
+/*
+ *  Album art looked up online, for tracks whose album art isn't taken from the file or its directory.
+ *
+ *  - Album art is taken from the sources in art_sources, in order, until one has it. "embedded" and "folder" are read
+ *    with the tags (see metadata), while the others are looked up here. Sources that fail are logged and skipped.
+ *  - "caa" looks up tracks tagged with a MusicBrainz release ID on the Cover Art Archive, whose 500 pixel thumbnail of
+ *    the release's front cover is used.
+ *  - "lastfm" looks up tracks on Last.fm by their album artist (or artist) and album, if lastfm_api_key is set. The
+ *    largest image Last.fm has for the album is used.
+ *  - "itunes" searches the iTunes Store by album artist and album, and the 600 pixel artwork of the first album found is
+ *    used. "deezer" searches Deezer the same way, and the 1000 pixel cover of the first album found is used.
+ *  - Lookups are made by the album art service. Public HTTPS links found are shown on Discord directly, unless
+ *    rehost_remote_art is enabled, in which case the images are processed and uploaded the same as embedded album art.
+ *    Their filenames are hashed from the release ID, or the artist and album, so each album is only uploaded once.
+ */
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtSource {
+    Embedded,
+    Folder,
+    Caa,
+    Lastfm,
+    Itunes,
+    Deezer,
+}
+
+impl ArtSource {
+    // Every source, in the order they are tried when art_sources isn't set.
+    pub const ALL: [ArtSource; 6] = [ArtSource::Embedded, ArtSource::Folder, ArtSource::Caa, ArtSource::Lastfm, ArtSource::Itunes, ArtSource::Deezer];
+
+    fn service_name(self) -> &'static str {
+        match self {
+            ArtSource::Embedded => "the file",
+            ArtSource::Folder => "the file's directory",
+            ArtSource::Caa => "the Cover Art Archive",
+            ArtSource::Lastfm => "Last.fm",
+            ArtSource::Itunes => "the iTunes Store",
+            ArtSource::Deezer => "Deezer",
+        }
+    }
+
+    // Whether the source is read from the file or its directory, rather than looked up online.
+    pub fn is_local(self) -> bool {
+        matches!(self, ArtSource::Embedded | ArtSource::Folder)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtQuery {
+    pub release_id: Option<String>,
+    pub artist: String,
+    pub album: Option<String>,
+    pub lastfm_api_key: Option<String>,
+    pub sources: Vec<ArtSource>, // The online sources to look the track up on, in order.
+}
+
+impl ArtQuery {
+    // Whether any of the sources can be asked for the album art.
+    pub fn is_searchable(&self) -> bool {
+        self.sources.iter().any(|source| match source {
+            ArtSource::Caa => self.release_id.is_some(),
+            ArtSource::Lastfm => self.lastfm_api_key.is_some() && self.album.is_some(),
+            ArtSource::Itunes | ArtSource::Deezer => self.album.is_some(),
+            ArtSource::Embedded | ArtSource::Folder => false,
+        })
+    }
+}
+
+// Returns the link to the album art of the first source that has it, or None if no source has album art for the query.
+pub async fn find_album_art(query: &ArtQuery) -> Option<String> {
+    for source in &query.sources {
+        let lookup_result = match (source, &query.release_id, &query.album) {
+            (ArtSource::Caa, Some(release_id), _) => resolve_image(&cover_art_archive_url(COVER_ART_ARCHIVE_URL, release_id)).await,
+            (ArtSource::Lastfm, _, Some(album)) => match &query.lastfm_api_key {
+                Some(lastfm_api_key) => find_lastfm_cover(LASTFM_API_URL, lastfm_api_key, &query.artist, album).await,
+                None => Ok(None),
+            },
+            (ArtSource::Itunes, _, Some(album)) => find_itunes_cover(ITUNES_SEARCH_URL, &query.artist, album).await,
+            (ArtSource::Deezer, _, Some(album)) => find_deezer_cover(DEEZER_SEARCH_URL, &query.artist, album).await,
+            _ => Ok(None),
+        };
+        match lookup_result {
+            Ok(Some(image_url)) => return Some(image_url),
+            Ok(None) => (),
+            Err(e) => error_log::log_error("art_lookup:find_album_art Error", format!("Album art could not be looked up on {} for {} - {}: {}", source.service_name(), query.artist, query.album.as_deref().unwrap_or_default(), e).as_str()),
+        }
+    }
+    None
+}
+
+// Downloads the album art at the link, so it can be processed and uploaded the same as embedded album art. max_art_kb
+// only limits what is read from the library, so downloaded images are never skipped for their size.
+pub async fn fetch_album_art(query: &ArtQuery, image_url: &String) -> Result<Option<AlbumArt>, ArtError> {
+    let Some(image_data) = fetch_image(image_url).await? else {
+        return Ok(None);
+    };
+    let metadata_pack = MetadataPackage {
+        release_id: query.release_id.clone(),
+        album_artist: Some(query.artist.clone()),
+        album: query.album.clone(),
+        ..MetadataPackage::default()
+    };
+    Ok(metadata::package_album_art(&metadata_pack, None, &image_data, u64::MAX, image_url))
+}
+
+fn cover_art_archive_url(api_url: &str, release_id: &str) -> String {
+    format!("{}/release/{}/front-500", api_url.trim_end_matches('/'), release_id)
+}
+
+// Checks that there is an image at the link, returning the link it is served from once redirects are followed, or None
+// if there is no image there.
+pub async fn resolve_image(image_url: &str) -> Result<Option<String>, ArtError> {
+    let response = http::send(|client| client.head(image_url).timeout(LOOKUP_TIMEOUT)).await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.url().to_string()))
+}
+
+// Fetches the image at the link. Returns None if there is no image there, e.g. for releases without a front cover.
+pub async fn fetch_image(image_url: &str) -> Result<Option<Vec<u8>>, ArtError> {
+    let response = http::send(|client| client.get(image_url).timeout(LOOKUP_TIMEOUT)).await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.bytes().await?.to_vec()))
+}
+
+// Looks up the album on Last.fm, returning the link to its largest image. Returns None if the album couldn't be found
+// or has no image.
+pub async fn find_lastfm_cover(api_url: &str, api_key: &str, artist: &str, album: &str) -> Result<Option<String>, ArtError> {
+    let query = [("method", "album.getinfo"), ("api_key", api_key), ("artist", artist), ("album", album), ("autocorrect", "1"), ("format", "json")];
+    let response = http::send(|client| client.get(api_url).query(&query).timeout(LOOKUP_TIMEOUT)).await?;
+    let status = response.status();
+    let response_json: Value = serde_json::from_str(&response.text().await?)?;
+
+    // Error 6 is returned for albums that couldn't be found. Other errors, such as an invalid API key, are reported.
+    match response_json["error"].as_u64() {
+        Some(6) => return Ok(None),
+        Some(_) => return Err(ArtError::LookupRefused { service: "Last.fm", message: response_json["message"].as_str().unwrap_or_default().to_owned() }),
+        None if !status.is_success() => return Err(ArtError::LookupRefused { service: "Last.fm", message: status.to_string() }),
+        None => (),
+    }
+
+    // Images are listed from smallest to largest.
+    let image_url = response_json["album"]["image"].as_array().and_then(|images| {
+        images.iter().rev().filter_map(|image| image["#text"].as_str()).find(|image_url| !image_url.is_empty())
+    });
+    Ok(image_url.filter(|image_url| !image_url.contains(LASTFM_PLACEHOLDER_IMAGE)).map(String::from))
+}
+
+// Searches the iTunes Store for the album, returning the link to its 600 pixel artwork. Returns None if no album was
+// found. Search results only link to 100 pixel artwork, but larger sizes are served by changing the size in the link.
+pub async fn find_itunes_cover(api_url: &str, artist: &str, album: &str) -> Result<Option<String>, ArtError> {
+    let term = format!("{} {}", artist, album);
+    let query = [("term", term.as_str()), ("media", "music"), ("entity", "album"), ("limit", "1")];
+    let response = http::send(|client| client.get(api_url).query(&query).timeout(LOOKUP_TIMEOUT)).await?.error_for_status()?;
+    let response_json: Value = serde_json::from_str(&response.text().await?)?;
+
+    let image_url = response_json["results"][0]["artworkUrl100"].as_str();
+    Ok(image_url.map(|image_url| image_url.replace("100x100bb", "600x600bb")))
+}
+
+// Searches Deezer for the album, returning the link to its largest cover. Returns None if no album was found.
+pub async fn find_deezer_cover(api_url: &str, artist: &str, album: &str) -> Result<Option<String>, ArtError> {
+    let search = format!("artist:\"{}\" album:\"{}\"", artist.replace('"', ""), album.replace('"', ""));
+    let query = [("q", search.as_str()), ("limit", "1")];
+    let response = http::send(|client| client.get(api_url).query(&query).timeout(LOOKUP_TIMEOUT)).await?.error_for_status()?;
+    let response_json: Value = serde_json::from_str(&response.text().await?)?;
+
+    // Deezer reports errors, such as exceeding its rate limit, in the body of a successful response.
+    if let Some(message) = response_json["error"]["message"].as_str() {
+        return Err(ArtError::LookupRefused { service: "Deezer", message: message.to_owned() });
+    }
+    let image_url = response_json["data"][0]["cover_xl"].as_str().filter(|image_url| !image_url.is_empty());
+    Ok(image_url.map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    // Answers a single request with the given status and body, returning the request line.
+    async fn serve_image(listener: &TcpListener, status: &str, image_data: &[u8]) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request_line = String::new();
+        BufReader::new(&mut stream).read_line(&mut request_line).await.unwrap();
+        let response_head = format!("HTTP/1.1 {}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, image_data.len());
+        stream.write_all(response_head.as_bytes()).await.unwrap();
+        stream.write_all(image_data).await.unwrap();
+        request_line.trim().to_owned()
+    }
+
+    #[tokio::test]
+    async fn fetches_front_covers_from_the_cover_art_archive() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let release_id = "0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a";
+        let image_url = cover_art_archive_url(&format!("http://{}/", listener.local_addr().unwrap()), release_id);
+
+        let fetch_task = {
+            let image_url = image_url.clone();
+            tokio::spawn(async move { fetch_image(&image_url).await.map_err(|e| e.to_string()) })
+        };
+        let request_line = serve_image(&listener, "200 OK", &[0xFF, 0xD8, 0xFF, 0xE0]).await;
+        assert_eq!(request_line, format!("GET /release/{}/front-500 HTTP/1.1", release_id));
+        assert_eq!(fetch_task.await.unwrap(), Ok(Some(vec![0xFF, 0xD8, 0xFF, 0xE0])));
+
+        // Releases without a front cover aren't an error.
+        let fetch_task = tokio::spawn(async move { fetch_image(&image_url).await.map_err(|e| e.to_string()) });
+        serve_image(&listener, "404 Not Found", b"").await;
+        assert_eq!(fetch_task.await.unwrap(), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn resolves_cover_art_archive_links() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let image_url = cover_art_archive_url(&format!("http://{}", listener.local_addr().unwrap()), "0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a");
+
+        let resolve_task = {
+            let image_url = image_url.clone();
+            tokio::spawn(async move { resolve_image(&image_url).await.map_err(|e| e.to_string()) })
+        };
+        let request = receive_http_request(&listener, "200 OK", "").await;
+        assert_eq!(request.request_line, "HEAD /release/0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a/front-500 HTTP/1.1");
+        assert_eq!(resolve_task.await.unwrap(), Ok(Some(image_url.clone())));
+
+        let resolve_task = tokio::spawn(async move { resolve_image(&image_url).await.map_err(|e| e.to_string()) });
+        receive_http_request(&listener, "404 Not Found", "").await;
+        assert_eq!(resolve_task.await.unwrap(), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn finds_the_largest_lastfm_cover() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let api_url = format!("http://{}/2.0/", listener.local_addr().unwrap());
+        let find_cover = |api_url: String| tokio::spawn(async move {
+            find_lastfm_cover(&api_url, "test-key", "Test Artist", "Test Album").await.map_err(|e| e.to_string())
+        });
+
+        let find_task = find_cover(api_url.clone());
+        let request = receive_http_request(&listener, "200 OK", r##"{"album": {"image": [
+            {"#text": "https://lastfm.example/174s/cover.png", "size": "large"},
+            {"#text": "https://lastfm.example/300x300/cover.png", "size": "extralarge"},
+            {"#text": "", "size": "mega"}
+        ]}}"##).await;
+        assert_eq!(request.request_line, "GET /2.0/?method=album.getinfo&api_key=test-key&artist=Test+Artist&album=Test+Album&autocorrect=1&format=json HTTP/1.1");
+        assert_eq!(find_task.await.unwrap(), Ok(Some(String::from("https://lastfm.example/300x300/cover.png"))));
+
+        // Albums that can't be found, or only have the placeholder image, have no cover.
+        let find_task = find_cover(api_url.clone());
+        receive_http_request(&listener, "404 Not Found", r##"{"error": 6, "message": "Album not found"}"##).await;
+        assert_eq!(find_task.await.unwrap(), Ok(None));
+        let find_task = find_cover(api_url.clone());
+        receive_http_request(&listener, "200 OK", r##"{"album": {"image": [{"#text": "https://lastfm.example/300x300/2a96cbd8b46e442fc41c2b86b821562f.png"}]}}"##).await;
+        assert_eq!(find_task.await.unwrap(), Ok(None));
+
+        let find_task = find_cover(api_url);
+        receive_http_request(&listener, "403 Forbidden", r##"{"error": 10, "message": "Invalid API key"}"##).await;
+        assert_eq!(find_task.await.unwrap(), Err(String::from("Last.fm refused the album art lookup: Invalid API key")));
+    }
+
+    #[tokio::test]
+    async fn finds_600_pixel_itunes_artwork() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let api_url = format!("http://{}/search", listener.local_addr().unwrap());
+        let find_cover = |api_url: String| tokio::spawn(async move {
+            find_itunes_cover(&api_url, "Test Artist", "Test Album").await.map_err(|e| e.to_string())
+        });
+
+        let find_task = find_cover(api_url.clone());
+        let request = receive_http_request(&listener, "200 OK", r#"{"resultCount": 1, "results": [
+            {"collectionName": "Test Album", "artworkUrl100": "https://itunes.example/image/thumb/Music/cover.jpg/100x100bb.jpg"}
+        ]}"#).await;
+        assert_eq!(request.request_line, "GET /search?term=Test+Artist+Test+Album&media=music&entity=album&limit=1 HTTP/1.1");
+        assert_eq!(find_task.await.unwrap(), Ok(Some(String::from("https://itunes.example/image/thumb/Music/cover.jpg/600x600bb.jpg"))));
+
+        // Albums that aren't in the store have no cover.
+        let find_task = find_cover(api_url);
+        receive_http_request(&listener, "200 OK", r#"{"resultCount": 0, "results": []}"#).await;
+        assert_eq!(find_task.await.unwrap(), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn finds_deezer_covers() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let api_url = format!("http://{}/search/album", listener.local_addr().unwrap());
+        let find_cover = |api_url: String| tokio::spawn(async move {
+            find_deezer_cover(&api_url, "Test Artist", "Test \"Album\"").await.map_err(|e| e.to_string())
+        });
+
+        let find_task = find_cover(api_url.clone());
+        let request = receive_http_request(&listener, "200 OK", r#"{"data": [
+            {"title": "Test Album", "cover_big": "https://deezer.example/images/cover/500x500.jpg", "cover_xl": "https://deezer.example/images/cover/1000x1000.jpg"}
+        ], "total": 1}"#).await;
+        assert_eq!(request.request_line, "GET /search/album?q=artist%3A%22Test+Artist%22+album%3A%22Test+Album%22&limit=1 HTTP/1.1");
+        assert_eq!(find_task.await.unwrap(), Ok(Some(String::from("https://deezer.example/images/cover/1000x1000.jpg"))));
+
+        // Albums that can't be found have no cover, while errors are reported.
+        let find_task = find_cover(api_url.clone());
+        receive_http_request(&listener, "200 OK", r#"{"data": [], "total": 0}"#).await;
+        assert_eq!(find_task.await.unwrap(), Ok(None));
+        let find_task = find_cover(api_url);
+        receive_http_request(&listener, "200 OK", r#"{"error": {"type": "Exception", "message": "Quota limit exceeded", "code": 4}}"#).await;
+        assert_eq!(find_task.await.unwrap(), Err(String::from("Deezer refused the album art lookup: Quota limit exceeded")));
+    }
+
+    #[test]
+    fn tracks_are_looked_up_only_with_a_source() {
+        let query = ArtQuery { release_id: None, artist: String::from("Test Artist"), album: Some(String::from("Test Album")), lastfm_api_key: None, sources: vec![ArtSource::Caa, ArtSource::Lastfm] };
+        assert!(!query.is_searchable());
+        assert!(ArtQuery { release_id: Some(String::from("0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a")), ..query.clone() }.is_searchable());
+        assert!(ArtQuery { lastfm_api_key: Some(String::from("test-key")), ..query.clone() }.is_searchable());
+        assert!(ArtQuery { sources: vec![ArtSource::Deezer], ..query.clone() }.is_searchable());
+        assert!(!ArtQuery { sources: vec![ArtSource::Itunes, ArtSource::Deezer], album: None, ..query.clone() }.is_searchable());
+        assert!(!ArtQuery { sources: vec![ArtSource::Embedded, ArtSource::Folder], ..query }.is_searchable());
+    }
+
+    #[test]
+    fn art_sources_are_read_by_name() {
+        #[derive(Deserialize)]
+        struct TestConfig {
+            art_sources: Vec<ArtSource>,
+        }
+        let test_config: TestConfig = toml::from_str("art_sources = ['folder', 'embedded', 'caa', 'itunes']").unwrap();
+        assert_eq!(test_config.art_sources, vec![ArtSource::Folder, ArtSource::Embedded, ArtSource::Caa, ArtSource::Itunes]);
+        assert!(toml::from_str::<TestConfig>("art_sources = ['spotify']").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+use zbus::connection;
+use zbus::interface;
+
+use crate::ipc;
+use crate::events::{Event, NowPlaying};
+use crate::ipc::{IpcCommand, StatusFormat};
+
+/*
+ *  D-Bus service registered on the session bus as org.lamp_drpc.
+ *
+ *  - Methods mirror the control socket commands and are forwarded to the event loop in the same way. They wait for the
+ *    reply asynchronously, so a slow command doesn't hold up other calls or property reads.
+ *
+ *  - Properties expose the currently displayed track. Values that are not available are reported as empty strings.
+ *    The event loop updates them through DbusService, which also emits PropertiesChanged for listening clients from
+ *    its own task, so the event loop never waits on the bus.
+ */
+const SERVICE_NAME: &str = "org.lamp_drpc";
+const OBJECT_PATH: &str = "/org/lamp_drpc";
+
+#[derive(Default)]
+struct TrackProperties {
+    artist: String,
+    title: String,
+    album: String,
+    art_url: String,
+    presence_enabled: bool,
+}
+
+struct LampInterface {
+    event_sender: UnboundedSender<Event>,
+    properties: Arc<Mutex<TrackProperties>>,
+}
+
+#[interface(name = "org.lamp_drpc")]
+impl LampInterface {
+    async fn status(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Status(StatusFormat::Full)).await
+    }
+
+    async fn reload(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Reload).await
+    }
+
+    async fn set_presence(&self, enabled: bool) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Presence(enabled)).await
+    }
+
+    async fn refresh(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Refresh).await
+    }
+
+    async fn clear_cache(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::ClearCache).await
+    }
+
+    async fn post_now_playing(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::PostNowPlaying).await
+    }
+
+    async fn announce(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Announce).await
+    }
+
+    async fn quit(&self) -> String {
+        ipc::request_command(&self.event_sender, IpcCommand::Quit).await
+    }
+
+    #[zbus(property)]
+    fn artist(&self) -> String {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).artist.clone()
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> String {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).title.clone()
+    }
+
+    #[zbus(property)]
+    fn album(&self) -> String {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).album.clone()
+    }
+
+    #[zbus(property)]
+    fn art_url(&self) -> String {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).art_url.clone()
+    }
+
+    #[zbus(property)]
+    fn presence_enabled(&self) -> bool {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).presence_enabled
+    }
+}
+
+pub struct DbusService {
+    connection: zbus::Connection,
+    properties: Arc<Mutex<TrackProperties>>,
+}
+
+impl DbusService {
+    pub async fn start(event_sender: UnboundedSender<Event>) -> Result<DbusService, Box<dyn std::error::Error>> {
+        let properties = Arc::new(Mutex::new(TrackProperties { presence_enabled: true, ..Default::default() }));
+        let lamp_interface = LampInterface { event_sender, properties: Arc::clone(&properties) };
+
+        let connection = connection::Builder::session()?
+                                            .name(SERVICE_NAME)?
+                                            .serve_at(OBJECT_PATH, lamp_interface)?
+                                            .build()
+                                            .await?;
+
+        Ok(DbusService { connection, properties })
+    }
+
+    pub fn set_now_playing(&self, now_playing: Option<&NowPlaying>) {
+        {
+            let mut properties = self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match now_playing {
+                Some(now_playing) => {
+                    properties.artist = now_playing.artist.clone();
+                    properties.title = now_playing.title.clone();
+                    properties.album = now_playing.album.clone().unwrap_or_default();
+                    properties.art_url = now_playing.image_link.clone().unwrap_or_default();
+                }
+                None => {
+                    properties.artist.clear();
+                    properties.title.clear();
+                    properties.album.clear();
+                    properties.art_url.clear();
+                }
+            }
+        }
+
+        self.emit_changes();
+    }
+
+    pub fn set_presence_enabled(&self, presence_enabled: bool) {
+        self.properties.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).presence_enabled = presence_enabled;
+        self.emit_changes();
+    }
+
+    fn emit_changes(&self) {
+        // Notify listening clients that properties have changed.
+        // Failing to do so only affects clients relying on signals, so errors are ignored.
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            let Ok(interface_ref) = connection.object_server().interface::<_, LampInterface>(OBJECT_PATH).await else {
+                return;
+            };
+            let lamp_interface = interface_ref.get().await;
+            let emitter = interface_ref.signal_emitter();
+            let _ = lamp_interface.artist_changed(emitter).await;
+            let _ = lamp_interface.title_changed(emitter).await;
+            let _ = lamp_interface.album_changed(emitter).await;
+            let _ = lamp_interface.art_url_changed(emitter).await;
+            let _ = lamp_interface.presence_enabled_changed(emitter).await;
+        });
+    }
+}
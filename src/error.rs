@@ -0,0 +1,90 @@
+use thiserror::Error;
+
+/*
+ *  Errors returned by lamp-drpc's modules, grouped by where they come from.
+ *
+ *  - Each module returns its own error type, so callers can match on the variant to decide how to react, such as
+ *    skipping a track quietly rather than exiting.
+ *  - LampError wraps all of them, for code that handles errors from several modules, such as logging.
+ */
+#[derive(Debug, Error)]
+pub enum LampError {
+    #[error(transparent)]
+    Player(#[from] PlayerError),
+    #[error(transparent)]
+    Metadata(#[from] MetadataError),
+    #[error(transparent)]
+    Art(#[from] ArtError),
+    #[error(transparent)]
+    Discord(#[from] DiscordError),
+}
+
+impl LampError {
+    // Expected conditions, such as a file without the required tags, are logged as warnings rather than errors.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, LampError::Metadata(MetadataError::UnsupportedFormat(_) | MetadataError::MissingTag { .. })
+                     | LampError::Art(ArtError::UnsupportedFormat(_)))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PlayerError {
+    #[error("{0} has exited.")]
+    Exited(String),
+    #[error("The PID of target player {0} could not be determined. The player may not be running or may have a different process name than provided in the configuration file.")]
+    NotFound(String),
+    #[error("The player {player_name} did not start within {seconds} seconds of running \"{command}\".")]
+    LaunchTimedOut { player_name: String, seconds: u64, command: String },
+    #[error("The player responded with an error: {0}")]
+    Protocol(String),
+    #[error("Reading the player's output panicked: {0}")]
+    Panicked(String),
+    #[error(transparent)]
+    DBus(#[from] zbus::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error("The file at {0} is not in a supported format.")]
+    UnsupportedFormat(String),
+    #[error("No {tag} tag(s) were found in file {file_path}.")]
+    MissingTag { file_path: String, tag: &'static str },
+    #[error("Tags could not be read from the file at {file_path}:\n{reason}")]
+    Unreadable { file_path: String, reason: String },
+}
+
+#[derive(Debug, Error)]
+pub enum ArtError {
+    #[error("Album art of type {0} is not supported.")]
+    UnsupportedFormat(String),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Resize(#[from] fast_image_resize::ResizeError),
+    #[error("Pixel type of image could not be determined.")]
+    UnknownPixelType,
+    #[error("Processing the album art panicked: {0}")]
+    Panicked(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("{host} refused the upload ({status}): {message}")]
+    UploadRefused { host: &'static str, status: reqwest::StatusCode, message: String },
+    #[error("{service} refused the album art lookup: {message}")]
+    LookupRefused { service: &'static str, message: String },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum DiscordError {
+    #[error("Discord Error on set_activity: {0}")]
+    SetActivity(discord_presence::DiscordError),
+    #[error("Discord Error on clear_activity: {0}")]
+    ClearActivity(discord_presence::DiscordError),
+}
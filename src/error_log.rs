@@ -1,40 +1,227 @@
 pub use std::fs;
 pub use std::io::Write;
 pub use std::process;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use crate::error::LampError;
+use crate::paths;
+
+/*
+ *  Errors are printed to stderr and appended to lamp-error.log in the state directory.
+ *
+ *  - Identical consecutive messages are only written once. When a different message is logged (or flush is called),
+ *    a summary line with the number of repeats is written instead of every copy.
+ *
+ *  - At most TYPE_BURST messages of each error type (e.g. "metadata:read_metadata Error") are written per TYPE_WINDOW,
+ *    so an error that recurs with different details, such as an unsupported file for every track of an album, can't
+ *    flood the log. Further messages of that type are counted, and the count is written once the window has passed.
+ *    Every message is still printed to stderr.
+ *
+ *  - Once the log reaches max_size bytes, it is rotated to lamp-error.log.1, lamp-error.log.2, etc., keeping at most
+ *    rotated_files old logs.
+ */
+struct LogState {
+    max_size: u64,
+    rotated_files: usize,
+    last_message: Option<String>,
+    repeat_count: u64,
+    error_types: BTreeMap<String, TypeWindow>, // Messages written for each error type in its current window.
+}
+
+// The messages of one error type written and suppressed since window_start.
+struct TypeWindow {
+    window_start: Instant,
+    written: u32,
+    suppressed: u64,
+}
+
+// Maximum number of messages of each error type written per TYPE_WINDOW.
+const TYPE_BURST: u32 = 3;
+const TYPE_WINDOW: Duration = Duration::from_secs(60);
+
+static LOG_STATE: Mutex<LogState> = Mutex::new(LogState {
+    max_size: 1024 * 1024,
+    rotated_files: 3,
+    last_message: None,
+    repeat_count: 0,
+    error_types: BTreeMap::new(),
+});
+
+pub fn configure(max_size_kb: u64, rotated_files: usize) {
+    let mut log_state = LOG_STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    log_state.max_size = max_size_kb * 1024;
+    log_state.rotated_files = rotated_files;
+}
+
+// Failures to write to the error log are printed to stderr, but never stop lamp-drpc.
 pub fn log_error(etype: &str, e: &str) {
     eprintln!("{}: {}", &etype, &e);
-    if let Some(home_path) = std::env::home_dir() {
-        match home_path.to_str() {
-            Some(no_unicode_path) => {
-                let err_log_file_path = format!("{no_unicode_path}/.config/lamp-drpc/lamp-error.log");
-                let err_log_file = fs::OpenOptions::new()
-                                .read(false)
-                                .write(true)
-                                .create(true)
-                                .append(true)
-                                .open(err_log_file_path);
-
-                match err_log_file { 
-                    Ok(mut err_log_file) => {
-                        let Ok(_) = write!(err_log_file, "[{}] {}: {}\n", chrono::offset::Local::now(), &etype, &e) else {
-                            eprintln!("error_log:err_log_file write Error: {}", e);
-                            process::exit(1);
-                        };
-                    }
-                    Err(e) => {
-                        eprintln!("error_log:err_log_file match Error: {}", e);
-                        process::exit(1);
-                    }
+
+    let mut log_state = LOG_STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let lines = record_message(&mut log_state, etype, e, Instant::now());
+    if !lines.is_empty() {
+        write_lines(&log_state, &lines);
+    }
+}
+
+// Logs an error returned by one of lamp-drpc's modules under the module it was handled in, as a warning if it is an
+// expected condition.
+pub fn log_lamp_error(source: &str, e: &LampError) {
+    let severity = if e.is_warning() { "Warning" } else { "Error" };
+    log_error(format!("{} {}", source, severity).as_str(), e.to_string().as_str());
+}
+
+// Updates the log state with a new message, returning the lines to write to the log, if any.
+fn record_message(log_state: &mut LogState, etype: &str, e: &str, now: Instant) -> Vec<String> {
+    let message = format!("{}: {}", &etype, &e);
+
+    // Only count repeats of the previous message.
+    if log_state.last_message.as_ref() == Some(&message) {
+        log_state.repeat_count += 1;
+        return Vec::new();
+    }
+
+    // Start a new window for the error type once the previous one has passed, summarizing what it suppressed.
+    let mut lines = Vec::<String>::new();
+    let type_window = log_state.error_types.entry(String::from(etype)).or_insert(TypeWindow { window_start: now, written: 0, suppressed: 0 });
+    if now.duration_since(type_window.window_start) >= TYPE_WINDOW {
+        lines.extend(suppressed_summary(etype, type_window));
+        *type_window = TypeWindow { window_start: now, written: 0, suppressed: 0 };
+    }
+    if type_window.written >= TYPE_BURST {
+        type_window.suppressed += 1;
+        return lines;
+    }
+    type_window.written += 1;
+
+    if let Some(summary) = repeat_summary(log_state) {
+        lines.insert(0, summary);
+    }
+    lines.push(format!("[{}] {}", chrono::offset::Local::now(), message));
+
+    log_state.last_message = Some(message);
+    log_state.repeat_count = 0;
+    lines
+}
+
+// Writes the repeat summary of the previous message, if any. Called before exiting so no repeats go unrecorded.
+pub fn flush() {
+    let mut log_state = LOG_STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let lines = flush_summaries(&mut log_state);
+    if !lines.is_empty() {
+        write_lines(&log_state, &lines);
+    }
+}
+
+fn flush_summaries(log_state: &mut LogState) -> Vec<String> {
+    let mut lines = Vec::<String>::new();
+    lines.extend(repeat_summary(log_state));
+    log_state.repeat_count = 0;
+    for (etype, type_window) in log_state.error_types.iter_mut() {
+        lines.extend(suppressed_summary(etype, type_window));
+        type_window.suppressed = 0;
+    }
+    lines
+}
+
+fn repeat_summary(log_state: &LogState) -> Option<String> {
+    match log_state.repeat_count {
+        0 => None,
+        1 => Some(format!("[{}] Previous message repeated 1 time.", chrono::offset::Local::now())),
+        repeat_count => Some(format!("[{}] Previous message repeated {} times.", chrono::offset::Local::now(), repeat_count)),
+    }
+}
+
+fn suppressed_summary(etype: &str, type_window: &TypeWindow) -> Option<String> {
+    match type_window.suppressed {
+        0 => None,
+        1 => Some(format!("[{}] 1 more \"{}\" message was not written.", chrono::offset::Local::now(), etype)),
+        suppressed => Some(format!("[{}] {} more \"{}\" messages were not written.", chrono::offset::Local::now(), suppressed, etype)),
+    }
+}
+
+fn write_lines(log_state: &LogState, lines: &[String]) {
+    let err_log_file_path = paths::state_dir().join("lamp-error.log").to_string_lossy().into_owned();
+
+    rotate_log(&err_log_file_path, log_state);
+
+    let err_log_file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&err_log_file_path);
+
+    match err_log_file {
+        Ok(mut err_log_file) => {
+            for line in lines {
+                if let Err(write_error) = writeln!(err_log_file, "{}", line) {
+                    eprintln!("error_log:err_log_file write Error: {}", write_error);
+                    return;
                 }
             }
-            None => {
-                eprintln!("error_log:home_path.to_str() Error: Home directory path contains unicode characters.");
-                process::exit(1);
-            }
         }
+        Err(e) => eprintln!("error_log:err_log_file match Error: {}", e),
+    }
+}
+
+fn rotate_log(err_log_file_path: &str, log_state: &LogState) {
+    // Only rotate once the log has reached its maximum size.
+    match fs::metadata(err_log_file_path) {
+        Ok(metadata) if metadata.len() >= log_state.max_size => (),
+        _ => return,
+    }
+
+    // Shift each rotated log up by one, dropping the oldest. Without rotated files, the log is simply started over.
+    let rotate_result = if log_state.rotated_files == 0 {
+        fs::remove_file(err_log_file_path)
     } else {
-        eprintln!("error_log:home_dir() Error: Could not find home directory.");
-        process::exit(1);
+        let _ = fs::remove_file(format!("{}.{}", err_log_file_path, log_state.rotated_files));
+        for index in (1..log_state.rotated_files).rev() {
+            let _ = fs::rename(format!("{}.{}", err_log_file_path, index), format!("{}.{}", err_log_file_path, index + 1));
+        }
+        fs::rename(err_log_file_path, format!("{}.1", err_log_file_path))
+    };
+
+    if let Err(e) = rotate_result {
+        eprintln!("error_log:rotate_log Error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_log_state() -> LogState {
+        LogState { max_size: 1024, rotated_files: 0, last_message: None, repeat_count: 0, error_types: BTreeMap::new() }
+    }
+
+    // Strips the timestamp from each line.
+    fn messages(lines: Vec<String>) -> Vec<String> {
+        lines.into_iter().map(|line| String::from(line.split_once("] ").unwrap().1)).collect()
+    }
+
+    #[test]
+    fn collapses_identical_messages() {
+        let (mut log_state, now) = (test_log_state(), Instant::now());
+        assert_eq!(messages(record_message(&mut log_state, "Error", "First", now)), vec!["Error: First"]);
+        assert!(record_message(&mut log_state, "Error", "First", now).is_empty());
+        assert!(record_message(&mut log_state, "Error", "First", now).is_empty());
+        assert_eq!(messages(record_message(&mut log_state, "Other Error", "Second", now)), vec!["Previous message repeated 2 times.", "Other Error: Second"]);
+    }
+
+    #[test]
+    fn limits_messages_of_each_type() {
+        let (mut log_state, now) = (test_log_state(), Instant::now());
+        for index in 0..5 {
+            let lines = record_message(&mut log_state, "metadata Error", &format!("Unsupported file {}", index), now);
+            assert_eq!(lines.len(), if index < TYPE_BURST as usize { 1 } else { 0 });
+        }
+
+        // Other error types are unaffected, and the suppressed count is written once the window has passed.
+        assert_eq!(record_message(&mut log_state, "art Error", "Upload failed", now).len(), 1);
+        assert_eq!(messages(record_message(&mut log_state, "metadata Error", "Unsupported file 5", now + TYPE_WINDOW)),
+                   vec!["2 more \"metadata Error\" messages were not written.", "metadata Error: Unsupported file 5"]);
+        assert!(flush_summaries(&mut log_state).is_empty());
     }
-}
\ No newline at end of file
+}
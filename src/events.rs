@@ -0,0 +1,123 @@
+use serde_json::{json, Value};
+
+use crate::ipc::IpcRequest;
+
+/*
+ *  Events handled by the main event loop.
+ *
+ *  - Player events are emitted by the player polling task whenever the state of the active player changes.
+ *  - Settle events are emitted by the event loop itself once a changed track has been playing for track_debounce_ms.
+ *  - Art events are emitted by the album art service once album art for a track has been processed.
+ *  - Link events are emitted by the song link service once a universal link has been resolved for a track.
+ *  - Lyric events are emitted by the lyrics service when the current line of the active track's lyrics changes.
+ *  - Stream events are emitted by the radio service when the title playing on the active internet radio stream changes.
+ *  - Commands are emitted by the control socket and D-Bus service.
+ */
+pub enum Event {
+    TrackChanged(Track),
+    TrackSettled { change_id: u64 },
+    PositionChanged { start_time: u64, end_time: Option<u64> },
+    Stopped,
+    Paused, // Emitted when the active track is paused. PositionChanged is emitted once it resumes.
+    PlayerStarted(String), // When several players are configured, the name of the player that is now followed.
+    PlayerExited,
+    ArtReady { track_id: u64, image_link: Option<String> },
+    LyricLine { track_id: u64, line: Option<String> },
+    LinkReady { track_id: u64, link: Option<String> },
+    StreamMetadata { track_id: u64, metadata: PlayerMetadata },
+    Command(IpcRequest),
+}
+
+// A track reported by the active player, along with the timestamps of its playback.
+// Players that don't play local files (e.g. streaming players) report the track's metadata along with it, and
+// file_path is whatever identifies the track, such as its URL.
+#[derive(Clone)]
+pub struct Track {
+    pub file_path: String,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub metadata: Option<PlayerMetadata>,
+    pub tags: PlayerTags,
+}
+
+// Tags kept in the player's library rather than the file, reported for local files and other tracks alike.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlayerTags {
+    pub rating: Option<u8>, // Out of 5 stars.
+    pub original_year: Option<String>,
+}
+
+// Metadata of a track reported by the player itself, used in place of the tags of a local file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerMetadata {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub art_url: Option<String>, // A link to the album art that Discord can show directly, without uploading it.
+}
+
+// Information about the active track shown on Discord and reported to other interfaces.
+#[derive(Clone, Default)]
+pub struct NowPlaying {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub genre: Option<String>, // The genre, release year, and label are read from the tags of local files only.
+    pub year: Option<String>,
+    pub label: Option<String>,
+    pub rating: Option<u8>, // The rating and original release year are reported by the player, e.g. Strawberry.
+    pub original_year: Option<String>,
+    pub image_link: Option<String>,
+    pub link: Option<String>, // The track's song.link page, if odesli_lookup is enabled and it has been resolved.
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+}
+
+impl NowPlaying {
+    pub fn description(&self) -> String {
+        match &self.album {
+            Some(album) => format!("{} - {} ({})", self.artist, self.title, album),
+            None => format!("{} - {}", self.artist, self.title),
+        }
+    }
+
+    // Fills in the {artist}, {title}, {album}, {genre}, {year}, {label}, {rating}, and {original_year} placeholders in the
+    // template. Placeholders of fields the track doesn't have, e.g. {album} for tracks without an album, are left empty.
+    pub fn format(&self, template: &str) -> String {
+        template.replace("{artist}", &self.artist)
+                .replace("{title}", &self.title)
+                .replace("{album}", self.album.as_deref().unwrap_or_default())
+                .replace("{genre}", self.genre.as_deref().unwrap_or_default())
+                .replace("{year}", self.year.as_deref().unwrap_or_default())
+                .replace("{label}", self.label.as_deref().unwrap_or_default())
+                .replace("{rating}", &self.rating.map(|rating| rating.to_string()).unwrap_or_default())
+                .replace("{original_year}", self.original_year.as_deref().unwrap_or_default())
+    }
+}
+
+// JSON describing the given track as playing, or that playback has stopped if there is none.
+// Sent to the webhook and MQTT broker, so both receive the same payload.
+pub fn playback_payload(player_name: &str, now_playing: Option<&NowPlaying>) -> Value {
+    match now_playing {
+        Some(now_playing) => json!({
+            "event": "playing",
+            "player": player_name,
+            "artist": now_playing.artist,
+            "title": now_playing.title,
+            "album": now_playing.album,
+            "genre": now_playing.genre,
+            "year": now_playing.year,
+            "label": now_playing.label,
+            "rating": now_playing.rating,
+            "original_year": now_playing.original_year,
+            "art_url": now_playing.image_link,
+            "link": now_playing.link,
+            "start_time": now_playing.start_time,
+            "end_time": now_playing.end_time,
+        }),
+        None => json!({
+            "event": "stopped",
+            "player": player_name,
+        }),
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection};
+
+use crate::paths;
+
+/*
+ *  Local play history, stored in history.db in the state directory when record_history is enabled.
+ *
+ *  - A play is recorded once a track stops being active, whether it was replaced by another track, stopped, or lamp-drpc exited.
+ *  - Completion is the fraction of the track's duration that elapsed before it stopped being active. It is unknown if
+ *    the player did not report a duration.
+ *
+ *  The history is only ever read locally, through the history and stats commands.
+ */
+pub struct History {
+    connection: Connection,
+}
+
+pub struct Play {
+    pub file_path: String,
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub completion: Option<f64>,
+}
+
+// An album, its artist, and its play count.
+type AlbumPlays = (String, String, u64);
+
+// Number of plays shown by the history command when no count is given, and entries shown in each stats list.
+pub const DEFAULT_HISTORY_LENGTH: usize = 20;
+const STATS_LENGTH: usize = 10;
+
+pub fn history_path() -> PathBuf {
+    paths::state_dir().join("history.db")
+}
+
+impl History {
+    pub fn open(history_path: &Path) -> Result<History, Box<dyn std::error::Error>> {
+        let connection = Connection::open(history_path)?;
+        connection.execute_batch("CREATE TABLE IF NOT EXISTS plays (
+                                      id INTEGER PRIMARY KEY,
+                                      file_path TEXT NOT NULL,
+                                      artist TEXT NOT NULL,
+                                      title TEXT NOT NULL,
+                                      album TEXT,
+                                      started_at INTEGER NOT NULL,
+                                      ended_at INTEGER NOT NULL,
+                                      completion REAL
+                                  );
+                                  CREATE INDEX IF NOT EXISTS plays_started_at ON plays (started_at);")?;
+        Ok(History { connection })
+    }
+
+    pub fn record(&self, play: &Play) -> Result<(), Box<dyn std::error::Error>> {
+        self.connection.execute("INSERT INTO plays (file_path, artist, title, album, started_at, ended_at, completion) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                                params![play.file_path, play.artist, play.title, play.album, play.started_at, play.ended_at, play.completion])?;
+        Ok(())
+    }
+
+    pub fn recent_plays(&self, limit: usize) -> Result<Vec<Play>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare("SELECT file_path, artist, title, album, started_at, ended_at, completion FROM plays ORDER BY started_at DESC, id DESC LIMIT ?1")?;
+        let plays = statement.query_map(params![limit], |row| Ok(Play {
+            file_path: row.get(0)?,
+            artist: row.get(1)?,
+            title: row.get(2)?,
+            album: row.get(3)?,
+            started_at: row.get(4)?,
+            ended_at: row.get(5)?,
+            completion: row.get(6)?,
+        }))?;
+        Ok(plays.collect::<Result<Vec<Play>, rusqlite::Error>>()?)
+    }
+
+    // Returns the most played artists along with their play counts.
+    pub fn top_artists(&self, limit: usize) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare("SELECT artist, COUNT(*) AS play_count FROM plays GROUP BY artist ORDER BY play_count DESC, artist LIMIT ?1")?;
+        let artists = statement.query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(artists.collect::<Result<Vec<(String, u64)>, rusqlite::Error>>()?)
+    }
+
+    // Returns the most played albums along with their artist and play counts.
+    pub fn top_albums(&self, limit: usize) -> Result<Vec<AlbumPlays>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare("SELECT album, artist, COUNT(*) AS play_count FROM plays WHERE album IS NOT NULL GROUP BY album, artist ORDER BY play_count DESC, album LIMIT ?1")?;
+        let albums = statement.query_map(params![limit], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(albums.collect::<Result<Vec<AlbumPlays>, rusqlite::Error>>()?)
+    }
+
+    pub fn play_count(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.connection.query_row("SELECT COUNT(*) FROM plays", [], |row| row.get(0))?)
+    }
+}
+
+// Output of the history command, listing the most recent plays.
+pub fn history_report(history: &History, limit: usize) -> Result<String, Box<dyn std::error::Error>> {
+    let plays = history.recent_plays(limit)?;
+    if plays.is_empty() {
+        return Ok(String::from("No plays have been recorded."));
+    }
+
+    let lines = plays.iter().map(|play| {
+        let started_at = chrono::DateTime::from_timestamp(play.started_at as i64, 0)
+                                .map(|started_at| started_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                                .unwrap_or_default();
+        let completion = play.completion.map(|completion| format!(" [{:.0}%]", completion * 100.0)).unwrap_or_default();
+        match &play.album {
+            Some(album) => format!("{}  {} - {} ({}){}", started_at, play.artist, play.title, album, completion),
+            None => format!("{}  {} - {}{}", started_at, play.artist, play.title, completion),
+        }
+    });
+    Ok(lines.collect::<Vec<String>>().join("\n"))
+}
+
+// Output of the stats command, listing the most played artists and albums.
+pub fn stats_report(history: &History) -> Result<String, Box<dyn std::error::Error>> {
+    let mut report = format!("Total plays: {}\n\nTop artists:", history.play_count()?);
+    for (rank, (artist, play_count)) in history.top_artists(STATS_LENGTH)?.iter().enumerate() {
+        report.push_str(&format!("\n{:>3}. {} ({} plays)", rank + 1, artist, play_count));
+    }
+
+    report.push_str("\n\nTop albums:");
+    for (rank, (album, artist, play_count)) in history.top_albums(STATS_LENGTH)?.iter().enumerate() {
+        report.push_str(&format!("\n{:>3}. {} - {} ({} plays)", rank + 1, artist, album, play_count));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::use_test_state_dir;
+
+    fn test_history(name: &str) -> History {
+        use_test_state_dir();
+        let history_path = paths::state_dir().join(format!("{}.db", name));
+        let _ = std::fs::remove_file(&history_path);
+        History::open(&history_path).unwrap()
+    }
+
+    fn play(artist: &str, title: &str, album: Option<&str>, started_at: u64) -> Play {
+        Play {
+            file_path: format!("/music/{}.flac", title),
+            artist: String::from(artist),
+            title: String::from(title),
+            album: album.map(String::from),
+            started_at,
+            ended_at: started_at + 100,
+            completion: Some(0.5),
+        }
+    }
+
+    #[test]
+    fn lists_most_recent_plays_first() {
+        let history = test_history("recent");
+        history.record(&play("Artist", "First", Some("Album"), 1000)).unwrap();
+        history.record(&play("Artist", "Second", None, 2000)).unwrap();
+        history.record(&play("Artist", "Third", None, 3000)).unwrap();
+
+        let titles = history.recent_plays(2).unwrap().into_iter().map(|play| play.title).collect::<Vec<String>>();
+        assert_eq!(titles, vec!["Third", "Second"]);
+    }
+
+    #[test]
+    fn counts_top_artists_and_albums() {
+        let history = test_history("stats");
+        history.record(&play("Frequent", "One", Some("Often"), 1000)).unwrap();
+        history.record(&play("Frequent", "Two", Some("Often"), 2000)).unwrap();
+        history.record(&play("Frequent", "Three", None, 3000)).unwrap();
+        history.record(&play("Rare", "Four", Some("Seldom"), 4000)).unwrap();
+
+        assert_eq!(history.play_count().unwrap(), 4);
+        assert_eq!(history.top_artists(10).unwrap(), vec![(String::from("Frequent"), 3), (String::from("Rare"), 1)]);
+        assert_eq!(history.top_albums(1).unwrap(), vec![(String::from("Often"), String::from("Frequent"), 2)]);
+    }
+
+    #[test]
+    fn empty_history_has_no_plays() {
+        let history = test_history("empty");
+        assert_eq!(history_report(&history, DEFAULT_HISTORY_LENGTH).unwrap(), "No plays have been recorded.");
+    }
+}
@@ -0,0 +1,63 @@
+use reqwest::{Client, RequestBuilder, Response};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Time allowed to connect to a host, and to wait for each read once connected.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Time to wait before retrying a request that could not connect.
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+
+/*
+ *  Shared HTTP client used for every request lamp-drpc makes, so connections to each host are reused.
+ *
+ *  - Connecting and each read have their own timeouts, so a host that stops responding can't hold up a service
+ *    indefinitely. Requests that must finish sooner set an overall timeout of their own.
+ *  - Requests sent with send are retried once if they could not connect, which covers brief network drops such as
+ *    resuming from suspend.
+ */
+pub fn client() -> &'static Client {
+    HTTP_CLIENT.get_or_init(|| {
+        Client::builder().connect_timeout(CONNECT_TIMEOUT)
+                         .read_timeout(READ_TIMEOUT)
+                         .user_agent(concat!("lamp-drpc/", env!("CARGO_PKG_VERSION")))
+                         .build()
+                         .unwrap_or_else(|_| Client::new())
+    })
+}
+
+// Sends the request built by build_request, building and sending it again if the first attempt could not connect.
+// Requests are built for each attempt, since request bodies such as file uploads can't be sent twice.
+pub async fn send<F: Fn(&Client) -> RequestBuilder>(build_request: F) -> Result<Response, reqwest::Error> {
+    match build_request(client()).send().await {
+        Err(e) if e.is_connect() => {
+            tokio::time::sleep(CONNECT_RETRY_DELAY).await;
+            build_request(client()).send().await
+        }
+        send_result => send_result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    #[tokio::test]
+    async fn retries_requests_that_could_not_connect() {
+        // Reserve a port, then only start listening on it after the first attempt has failed.
+        let local_addr = TcpListener::bind(("127.0.0.1", 0)).await.unwrap().local_addr().unwrap();
+        let send_task = tokio::spawn(async move { send(|client| client.get(format!("http://{}/retry", local_addr))).await.map(|response| response.status().as_u16()) });
+
+        tokio::time::sleep(CONNECT_RETRY_DELAY / 2).await;
+        let listener = TcpListener::bind(local_addr).await.unwrap();
+        let request = receive_http_request(&listener, "200 OK", "").await;
+        assert_eq!(request.request_line, "GET /retry HTTP/1.1");
+        assert_eq!(request.header("user-agent"), Some(concat!("lamp-drpc/", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(send_task.await.unwrap().unwrap(), 200);
+    }
+}
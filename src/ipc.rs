@@ -0,0 +1,245 @@
+use std::io::{self, BufRead, BufReader};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::ServerOptions;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+use crate::error_log;
+use crate::error_log::fs;
+use crate::error_log::Write;
+use crate::events::Event;
+
+/*
+ *  Control socket used to send commands to a running lamp-drpc instance.
+ *
+ *  - Each connection sends a single line containing a command and receives a reply before the connection is closed.
+ *    Connections are handled in their own tasks, and one that doesn't send its command within READ_TIMEOUT is closed,
+ *    so a stalled client never holds up the others.
+ *
+ *  - Commands are forwarded to the event loop as events, along with a sender used to return the reply.
+ *    The event loop is responsible for carrying out the command and answering it. The D-Bus service
+ *    forwards its commands the same way, so both interfaces behave identically.
+ *
+ *  - On Windows, the socket is a named pipe (\\.\pipe\lamp-drpc.sock), which is answered the same way.
+ */
+pub enum IpcCommand {
+    Status(StatusFormat),
+    Reload,
+    Presence(bool),
+    Refresh,
+    ClearCache,
+    PostNowPlaying,
+    Announce,
+    Quit,
+}
+
+// Formats of the status command. Waybar and Text describe only the active track, for use in status bars.
+#[derive(Clone, Copy)]
+pub enum StatusFormat {
+    Full,
+    Waybar,
+    Text,
+}
+
+pub struct IpcRequest {
+    pub command: IpcCommand,
+    pub reply: oneshot::Sender<String>,
+}
+
+// Usage text returned for unrecognized commands and printed by the client.
+pub const COMMAND_USAGE: &str = "Available commands: status [--waybar|--text], reload, presence on|off, refresh, clear-cache, post-now-playing, announce, quit";
+
+// Maximum time a connection will wait for the event loop to reply to a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Maximum time a command may wait on another service, such as posting the active track to Mastodon. It is kept below
+// REPLY_TIMEOUT, so the service's answer or error still reaches the client in the command's reply.
+pub const COMMAND_REQUEST_TIMEOUT: Duration = REPLY_TIMEOUT.saturating_sub(Duration::from_secs(2));
+
+// Maximum time a client has to send its command, and the longest command line read from it.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_COMMAND_LENGTH: u64 = 1024;
+
+impl IpcCommand {
+    pub fn parse(command_line: &str) -> Option<IpcCommand> {
+        let words = command_line.split_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            ["status"] => Some(IpcCommand::Status(StatusFormat::Full)),
+            ["status", "--waybar"] => Some(IpcCommand::Status(StatusFormat::Waybar)),
+            ["status", "--text"] => Some(IpcCommand::Status(StatusFormat::Text)),
+            ["reload"] => Some(IpcCommand::Reload),
+            ["presence", "on"] => Some(IpcCommand::Presence(true)),
+            ["presence", "off"] => Some(IpcCommand::Presence(false)),
+            ["refresh"] => Some(IpcCommand::Refresh),
+            ["clear-cache"] => Some(IpcCommand::ClearCache),
+            ["post-now-playing"] => Some(IpcCommand::PostNowPlaying),
+            ["announce"] => Some(IpcCommand::Announce),
+            ["quit"] => Some(IpcCommand::Quit),
+            _ => None,
+        }
+    }
+}
+
+pub fn socket_path(runtime_dir: &Path) -> PathBuf {
+    runtime_dir.join("lamp-drpc.sock")
+}
+
+#[cfg(unix)]
+pub fn start_listener(socket_path: &PathBuf, event_sender: UnboundedSender<Event>) -> Result<(), Box<dyn std::error::Error>> {
+    // Refuse to start if another instance is already answering on the socket.
+    // Otherwise, remove any stale socket left behind by a previous instance.
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(Box::from(format!("Another instance of lamp-drpc is already listening on {}.", socket_path.display())));
+    }
+    if fs::exists(socket_path)? {
+        fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => spawn_connection(stream, event_sender.clone()),
+                Err(e) => error_log::log_error("ipc:listener.accept() Error", e.to_string().as_str()),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Named pipes are removed along with their last handle, so there is never a stale pipe to remove. Each instance of the
+// pipe answers one client, and the next is created before the connected one is handed off.
+#[cfg(windows)]
+pub fn start_listener(socket_path: &PathBuf, event_sender: UnboundedSender<Event>) -> Result<(), Box<dyn std::error::Error>> {
+    // Refuse to start if another instance has already created the pipe.
+    let mut server = match ServerOptions::new().first_pipe_instance(true).create(socket_path) {
+        Ok(server) => server,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Err(Box::from(format!("Another instance of lamp-drpc is already listening on {}.", socket_path.display()))),
+        Err(e) => return Err(Box::from(e)),
+    };
+    let socket_path = socket_path.clone();
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = server.connect().await {
+                error_log::log_error("ipc:server.connect() Error", e.to_string().as_str());
+                continue;
+            }
+            match ServerOptions::new().create(&socket_path) {
+                Ok(next_server) => spawn_connection(std::mem::replace(&mut server, next_server), event_sender.clone()),
+                Err(e) => error_log::log_error("ipc:ServerOptions::create Error", e.to_string().as_str()),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn spawn_connection<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S, event_sender: UnboundedSender<Event>) {
+    tokio::spawn(async move {
+        if let Err(e) = handle_connection(stream, &event_sender).await {
+            error_log::log_error("ipc:handle_connection Error", e.to_string().as_str());
+        }
+    });
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite>(stream: S, event_sender: &UnboundedSender<Event>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Read a single command line from the client.
+    let mut command_line = String::new();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader.take(MAX_COMMAND_LENGTH));
+    tokio::time::timeout(READ_TIMEOUT, reader.read_line(&mut command_line)).await
+                                                                             .map_err(|_| format!("No command was received within {} seconds.", READ_TIMEOUT.as_secs()))??;
+
+    let reply = match IpcCommand::parse(&command_line) {
+        Some(command) => request_command(event_sender, command).await,
+        None => format!("Error: Unknown command \"{}\". {}", command_line.trim(), COMMAND_USAGE),
+    };
+
+    writer.write_all(format!("{}\n", reply).as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn request_command(event_sender: &UnboundedSender<Event>, command: IpcCommand) -> String {
+    // Forward the command to the event loop and wait for its reply.
+    let (reply_sender, reply_receiver) = oneshot::channel::<String>();
+    if event_sender.send(Event::Command(IpcRequest { command, reply: reply_sender })).is_err() {
+        return String::from("Error: lamp-drpc is shutting down.");
+    }
+    match tokio::time::timeout(REPLY_TIMEOUT, reply_receiver).await {
+        Ok(Ok(reply)) => reply,
+        _ => String::from("Error: lamp-drpc did not respond to the command."),
+    }
+}
+
+pub fn send_command(socket_path: &Path, command_line: &str) -> Result<String, Box<dyn std::error::Error>> {
+    // Connect to a running instance, send the command, and read back the full reply.
+    let mut stream = match connect(socket_path) {
+        Ok(stream) => stream,
+        Err(e) => return Err(Box::from(format!("Could not connect to lamp-drpc at {}. Is it running? ({})", socket_path.display(), e))),
+    };
+    writeln!(stream, "{}", command_line)?;
+    #[cfg(unix)]
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    // A named pipe closed by the server reads as broken rather than ended.
+    let mut reply = String::new();
+    for line in BufReader::new(&stream).lines() {
+        match line {
+            Ok(line) => reply.push_str(&line),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(Box::from(e)),
+        }
+        reply.push('\n');
+    }
+    Ok(reply.trim_end().to_string())
+}
+
+#[cfg(unix)]
+fn connect(socket_path: &Path) -> io::Result<UnixStream> {
+    UnixStream::connect(socket_path)
+}
+
+#[cfg(windows)]
+fn connect(socket_path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().read(true).write(true).open(socket_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn answers_commands_while_another_client_stalls() {
+        let socket_dir = std::env::temp_dir().join(format!("lamp-drpc-ipc-{}", std::process::id()));
+        fs::create_dir_all(&socket_dir).unwrap();
+        let socket_path = socket_path(&socket_dir);
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        start_listener(&socket_path, event_sender).unwrap();
+
+        // Answer commands in place of the event loop.
+        tokio::spawn(async move {
+            while let Some(Event::Command(request)) = event_receiver.recv().await {
+                let _ = request.reply.send(String::from("Playing"));
+            }
+        });
+
+        // A client that connects without ever sending a command doesn't hold up the next one.
+        let _stalled_client = connect(&socket_path).unwrap();
+        let command_socket_path = socket_path.clone();
+        let reply = tokio::time::timeout(Duration::from_secs(2), tokio::task::spawn_blocking(move || send_command(&command_socket_path, "status").unwrap())).await;
+        assert_eq!(reply.unwrap().unwrap(), "Playing");
+
+        let _ = fs::remove_dir_all(&socket_dir);
+    }
+}
@@ -0,0 +1,261 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error_log;
+use crate::events::{Event, NowPlaying};
+use crate::http;
+use crate::supervisor;
+
+// LRCLIB is queried for tracks without a local .lrc file, if lrclib_lookup is enabled.
+const LRCLIB_API_URL: &str = "https://lrclib.net";
+const LRCLIB_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often the current lyric line is checked. Discord limits how often presence can be updated, so lines shorter than
+// this may be skipped.
+const LYRICS_UPDATE_INTERVAL: Duration = Duration::from_secs(4);
+
+/*
+ *  Optional synced lyrics, enabled by setting synced_lyrics in lamp.toml.
+ *
+ *  - Lyrics are read from a .lrc file next to the track (e.g. "01 Title.lrc" for "01 Title.flac"), or fetched from
+ *    LRCLIB if there is none and lrclib_lookup is enabled.
+ *  - The lyrics service follows the position of the active track from its start time, emitting a LyricLine event
+ *    whenever the current line changes. The event loop shows the line in place of the artist on the state line.
+ *  - Tracks without synced lyrics are shown as usual.
+ */
+pub enum LyricsRequest {
+    Follow { track_id: u64, file_path: String, now_playing: Box<NowPlaying>, lrclib_lookup: bool },
+    Resync { track_id: u64, start_time: u64 }, // Moves the start time of the followed track after a seek or pause.
+    Stop,
+}
+
+// A line of synced lyrics, and the time into the track at which it starts.
+#[derive(Debug, PartialEq)]
+pub struct LyricLine {
+    pub time_ms: u64,
+    pub text: String,
+}
+
+// The track whose lyrics are being followed.
+struct FollowedTrack {
+    track_id: u64,
+    start_time: u64,
+    lines: Vec<LyricLine>,
+    current_line: Option<String>, // The line last reported to the event loop.
+}
+
+#[derive(Clone)]
+pub struct LyricsService {
+    lyrics_sender: mpsc::UnboundedSender<LyricsRequest>,
+}
+
+impl LyricsService {
+    pub fn start(event_sender: mpsc::UnboundedSender<Event>) -> (LyricsService, JoinHandle<()>) {
+        let (lyrics_sender, lyrics_receiver) = mpsc::unbounded_channel::<LyricsRequest>();
+
+        // The receiver is shared so pending requests are kept if the worker panics and is restarted.
+        let lyrics_receiver = Arc::new(Mutex::new(lyrics_receiver));
+        let lyrics_task = supervisor::supervise("lyrics", move || run_lyrics_task(lyrics_receiver.clone(), event_sender.clone()));
+
+        (LyricsService { lyrics_sender }, lyrics_task)
+    }
+
+    // Creates a service that follows no lyrics, returning the receiver for the requests it is sent.
+    #[cfg(test)]
+    pub fn mock() -> (LyricsService, mpsc::UnboundedReceiver<LyricsRequest>) {
+        let (lyrics_sender, lyrics_receiver) = mpsc::unbounded_channel::<LyricsRequest>();
+        (LyricsService { lyrics_sender }, lyrics_receiver)
+    }
+
+    pub fn follow(&self, track_id: u64, file_path: &str, now_playing: &NowPlaying, lrclib_lookup: bool) {
+        let _ = self.lyrics_sender.send(LyricsRequest::Follow { track_id, file_path: String::from(file_path), now_playing: Box::new(now_playing.clone()), lrclib_lookup });
+    }
+
+    pub fn resync(&self, track_id: u64, start_time: u64) {
+        let _ = self.lyrics_sender.send(LyricsRequest::Resync { track_id, start_time });
+    }
+
+    pub fn stop(&self) {
+        let _ = self.lyrics_sender.send(LyricsRequest::Stop);
+    }
+}
+
+async fn run_lyrics_task(lyrics_receiver: Arc<Mutex<mpsc::UnboundedReceiver<LyricsRequest>>>, event_sender: mpsc::UnboundedSender<Event>) {
+    let mut lyrics_receiver = lyrics_receiver.lock_owned().await;
+    let mut followed_track: Option<FollowedTrack> = None;
+    let mut update_interval = tokio::time::interval(LYRICS_UPDATE_INTERVAL);
+
+    // Follow tracks until every LyricsService handle has been dropped.
+    loop {
+        tokio::select! {
+            lyrics_request = lyrics_receiver.recv() => match lyrics_request {
+                Some(LyricsRequest::Follow { track_id, file_path, now_playing, lrclib_lookup }) => {
+                    followed_track = None;
+                    let Some(start_time) = now_playing.start_time else {
+                        continue;
+                    };
+                    if let Some(lines) = load_lyrics(&file_path, &now_playing, lrclib_lookup).await {
+                        followed_track = Some(FollowedTrack { track_id, start_time, lines, current_line: None });
+                    }
+                }
+                Some(LyricsRequest::Resync { track_id, start_time }) => {
+                    if let Some(followed_track) = followed_track.as_mut().filter(|followed_track| followed_track.track_id == track_id) {
+                        followed_track.start_time = start_time;
+                    }
+                }
+                Some(LyricsRequest::Stop) => followed_track = None,
+                None => break,
+            },
+            _ = update_interval.tick() => {
+                let Some(followed_track) = &mut followed_track else {
+                    continue;
+                };
+                let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or_default();
+                let position_ms = now_ms.saturating_sub(followed_track.start_time * 1000);
+                let line = line_at(&followed_track.lines, position_ms).map(String::from);
+                if line != followed_track.current_line {
+                    followed_track.current_line = line.clone();
+                    let _ = event_sender.send(Event::LyricLine { track_id: followed_track.track_id, line });
+                }
+            }
+        }
+    }
+}
+
+// Reads the synced lyrics for the track from its .lrc file, or LRCLIB. Returns None if the track has none.
+async fn load_lyrics(file_path: &str, now_playing: &NowPlaying, lrclib_lookup: bool) -> Option<Vec<LyricLine>> {
+    let lrc = match tokio::fs::read_to_string(Path::new(file_path).with_extension("lrc")).await {
+        Ok(lrc) => Some(lrc),
+        Err(_) if lrclib_lookup => match fetch_synced_lyrics(LRCLIB_API_URL, now_playing).await {
+            Ok(lrc) => lrc,
+            Err(e) => {
+                error_log::log_error("lyrics:fetch_synced_lyrics Error", e.to_string().as_str());
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let lines = parse_lrc(&lrc?);
+    if lines.is_empty() { None } else { Some(lines) }
+}
+
+// Looks up the track on LRCLIB, returning its synced lyrics in LRC format if it has any.
+pub async fn fetch_synced_lyrics(api_url: &str, now_playing: &NowPlaying) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut query = vec![("artist_name", now_playing.artist.clone()), ("track_name", now_playing.title.clone())];
+    if let Some(album) = &now_playing.album {
+        query.push(("album_name", album.clone()));
+    }
+    if let (Some(start_time), Some(end_time)) = (now_playing.start_time, now_playing.end_time) {
+        query.push(("duration", end_time.saturating_sub(start_time).to_string()));
+    }
+
+    let response = http::send(|client| client.get(format!("{}/api/get", api_url.trim_end_matches('/'))).query(&query).timeout(LRCLIB_TIMEOUT)).await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let response_json: Value = serde_json::from_str(&response.text().await?)?;
+    Ok(response_json["syncedLyrics"].as_str().map(String::from))
+}
+
+// Parses LRC lyrics, e.g. "[01:23.45]Line". Lines with several timestamps are repeated at each of them, and metadata
+// tags such as "[ar:Artist]" are ignored.
+pub fn parse_lrc(lrc: &str) -> Vec<LyricLine> {
+    let mut lines = Vec::<LyricLine>::new();
+    for lrc_line in lrc.lines() {
+        let mut rest = lrc_line.trim();
+        let mut times = Vec::<u64>::new();
+        while let Some((tag, after_tag)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+            let Some(time_ms) = parse_timestamp(tag) else {
+                break;
+            };
+            times.push(time_ms);
+            rest = after_tag;
+        }
+        for time_ms in times {
+            lines.push(LyricLine { time_ms, text: String::from(rest.trim()) });
+        }
+    }
+
+    lines.sort_by_key(|line| line.time_ms);
+    lines
+}
+
+// Parses a timestamp in the form mm:ss, mm:ss.xx, or mm:ss.xxx, returning it in milliseconds.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (minutes, seconds) = timestamp.split_once(':')?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+    let fraction_ms = match fraction.len() {
+        1 => fraction.parse::<u64>().ok()? * 100,
+        2 => fraction.parse::<u64>().ok()? * 10,
+        3 => fraction.parse::<u64>().ok()?,
+        _ => return None,
+    };
+    Some(minutes.parse::<u64>().ok()? * 60_000 + seconds.parse::<u64>().ok()? * 1000 + fraction_ms)
+}
+
+// The line being sung at the given position, or None before the first line and during instrumental breaks.
+pub fn line_at(lines: &[LyricLine], position_ms: u64) -> Option<&str> {
+    let line = lines.iter().take_while(|line| line.time_ms <= position_ms).last()?;
+    if line.text.is_empty() { None } else { Some(&line.text) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    #[test]
+    fn follows_lrc_lyrics_by_position() {
+        let lines = parse_lrc("[ar:Test Artist]\n\
+                               [00:12.50]First line\n\
+                               [00:20.00][01:05.00]Chorus\n\
+                               [00:30.000]\n\
+                               not a lyric line\n");
+        assert_eq!(lines, vec![
+            LyricLine { time_ms: 12_500, text: String::from("First line") },
+            LyricLine { time_ms: 20_000, text: String::from("Chorus") },
+            LyricLine { time_ms: 30_000, text: String::new() },
+            LyricLine { time_ms: 65_000, text: String::from("Chorus") },
+        ]);
+
+        assert_eq!(line_at(&lines, 5_000), None);
+        assert_eq!(line_at(&lines, 12_500), Some("First line"));
+        assert_eq!(line_at(&lines, 25_000), Some("Chorus"));
+        assert_eq!(line_at(&lines, 40_000), None);
+        assert_eq!(line_at(&lines, 70_000), Some("Chorus"));
+    }
+
+    #[tokio::test]
+    async fn fetches_synced_lyrics_from_lrclib() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let now_playing = NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            start_time: Some(1000),
+            end_time: Some(1180),
+            ..NowPlaying::default()
+        };
+
+        let fetch_task = {
+            let (api_url, now_playing) = (api_url.clone(), now_playing.clone());
+            tokio::spawn(async move { fetch_synced_lyrics(&api_url, &now_playing).await.map_err(|e| e.to_string()) })
+        };
+        let request = receive_http_request(&listener, "200 OK", r#"{"syncedLyrics": "[00:01.00]Line", "plainLyrics": "Line"}"#).await;
+        assert_eq!(fetch_task.await.unwrap(), Ok(Some(String::from("[00:01.00]Line"))));
+        assert_eq!(request.request_line, "GET /api/get?artist_name=Test+Artist&track_name=Test+Title&album_name=Test+Album&duration=180 HTTP/1.1");
+
+        let fetch_task = tokio::spawn(async move { fetch_synced_lyrics(&api_url, &now_playing).await.map_err(|e| e.to_string()) });
+        receive_http_request(&listener, "404 Not Found", r#"{"name": "TrackNotFound"}"#).await;
+        assert_eq!(fetch_task.await.unwrap(), Ok(None));
+    }
+}
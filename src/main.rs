@@ -1,35 +1,124 @@
 use std::collections::HashMap;
 use std::env;
-use std::fs::{remove_file, File};
-use std::io::{BufReader, BufWriter, Cursor};
-use std::thread;
+use std::fs::remove_file;
+use std::path::PathBuf;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use catbox::file::from_file;
-use discord_presence::Client;
-use discord_presence::models::rich_presence::{ActivityTimestamps, ActivityType, DisplayType};
-use fast_image_resize::images::Image;
-use fast_image_resize::{IntoImageView, Resizer, ResizeOptions};
-use image::codecs::jpeg::JpegEncoder;
-use image::codecs::png::PngEncoder;
-use image::{ImageEncoder, ImageFormat, ImageReader};
-use reqwest::header::USER_AGENT;
 use serde::Deserialize;
-use sysinfo::{Pid, ProcessStatus, ProcessesToUpdate, ProcessRefreshKind, RefreshKind, System};
+use sysinfo::System;
+use tokio::sync::{mpsc, oneshot, watch};
 
 mod error_log;
 use error_log::fs;
 use error_log::Write;
 use error_log::process;
 
+mod error;
+use error::{LampError, PlayerError};
+
 mod player;
+use player::AudioStreams;
+use player::Browser;
+#[cfg(unix)]
 use player::Cmus;
+use player::ExternalCommand;
+use player::Mpd;
+use player::Mpris;
+use player::Mpv;
+use player::NowPlayingFile;
+use player::Musikcube;
+use player::Plugin;
+use player::Foobar2000;
+use player::MusicBee;
+use player::Rhythmbox;
+use player::Spotifyd;
+use player::Strawberry;
 use player::StandardPlayer;
+use player::PlaybackState;
+use player::Xmms2;
 use player::Path;
 
 mod metadata;
-use metadata::AlbumArt;
-use metadata::MetadataPackage;
 use metadata::read_metadata;
+use metadata::AlbumArt;
+
+mod ipc;
+use ipc::IpcCommand;
+use ipc::StatusFormat;
+
+mod dbus;
+use dbus::DbusService;
+
+mod events;
+use events::Event;
+use events::NowPlaying;
+use events::PlayerMetadata;
+use events::PlayerTags;
+use events::Track;
+
+mod presence;
+use presence::PresenceService;
+
+mod art;
+use art::ArtRequest;
+use art::ArtEncoding;
+use art::ArtFit;
+use art::Catbox;
+
+mod art_lookup;
+use art_lookup::{ArtQuery, ArtSource};
+
+mod state_file;
+
+mod metrics;
+
+mod runtime_dir;
+
+mod supervisor;
+
+mod paths;
+
+mod http;
+
+mod history;
+use history::{History, Play};
+
+mod webhook;
+use webhook::WebhookService;
+
+mod mqtt;
+use mqtt::{MqttService, MqttSettings};
+
+mod overlay;
+use overlay::{OverlayFiles, OverlayService};
+
+mod now_playing_server;
+
+mod mastodon;
+
+mod mpris;
+use mpris::{MprisService, MprisTrack};
+
+mod status_line;
+
+mod lyrics;
+use lyrics::LyricsService;
+
+mod radio;
+use radio::RadioService;
+
+mod announce;
+
+mod song_link;
+use song_link::LinkService;
+
+mod scan;
+use scan::ScanSettings;
+
+mod rewrite;
+use rewrite::Rewrites;
+
+#[cfg(test)]
+mod mock;
 
 /*
  *  [PLAYER IMPLEMENTATION HERE]
@@ -37,366 +126,1424 @@ use metadata::read_metadata;
  *  must be established to assign player-specific functions in the impl block below.
  */
 enum MusicPlayer {
+    #[cfg(unix)]
     Cmus(player::Cmus),
+    Mpd(player::Mpd),
+    Mpris(player::Mpris),
+    Mpv(player::Mpv),
+    Rhythmbox(player::Rhythmbox),
+    Strawberry(player::Strawberry),
+    Spotifyd(player::Spotifyd),
+    Musikcube(player::Musikcube),
+    Browser(player::Browser),
+    Xmms2(player::Xmms2),
+    ExternalCommand(player::ExternalCommand),
+    NowPlayingFile(player::NowPlayingFile),
+    Plugin(player::Plugin),
+    Foobar2000(player::Foobar2000),
+    MusicBee(player::MusicBee),
+    AudioStreams(player::AudioStreams),
 //  NewPlayer(player::NewPlayer)  
 }
 
 /*
  *  [PLAYER IMPLEMENTATION HERE]
- *  This impl block will provide new MusicPlayers with the correct functions depending on
- *  which variant is assigned. Function arguments and mutability of arguments depend on
- *  implementation in player.rs.
+ *  dispatch_player! forwards a call to the player held by each MusicPlayer variant, so new MusicPlayers only need an
+ *  arm here to be given the correct functions in the impl block below. Function arguments and mutability of arguments
+ *  depend on implementation in player.rs.
  */
-impl StandardPlayer for MusicPlayer {
-    fn verify_running(&self) -> bool {
-        match self {
-            MusicPlayer::Cmus(cmus) => return Cmus::verify_running(&cmus),
-//          MusicPlayer::NewPlayer(newplayer_instance) => return NewPlayer::verify_running(newplayer_instance),
+macro_rules! dispatch_player {
+    ($music_player:expr, $player:ident => $call:expr) => {
+        match $music_player {
+            #[cfg(unix)]
+            MusicPlayer::Cmus($player) => $call,
+            MusicPlayer::Mpd($player) => $call,
+            MusicPlayer::Mpris($player) => $call,
+            MusicPlayer::Mpv($player) => $call,
+            MusicPlayer::Rhythmbox($player) => $call,
+            MusicPlayer::Strawberry($player) => $call,
+            MusicPlayer::Spotifyd($player) => $call,
+            MusicPlayer::Musikcube($player) => $call,
+            MusicPlayer::Browser($player) => $call,
+            MusicPlayer::Xmms2($player) => $call,
+            MusicPlayer::ExternalCommand($player) => $call,
+            MusicPlayer::NowPlayingFile($player) => $call,
+            MusicPlayer::Plugin($player) => $call,
+            MusicPlayer::Foobar2000($player) => $call,
+            MusicPlayer::MusicBee($player) => $call,
+            MusicPlayer::AudioStreams($player) => $call,
+//          MusicPlayer::NewPlayer($player) => $call,
         }
+    };
+}
+
+impl StandardPlayer for MusicPlayer {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        dispatch_player!(self, player => player.verify_running())
     }
 
-    fn get_active_file_path(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        match self {
-            MusicPlayer::Cmus(cmus) => return Cmus::get_active_file_path(cmus),
-//          MusicPlayer::NewPlayer(newplayer_instance) => return NewPlayer::get_active_file_path(newplayer_instance),
-        }
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        dispatch_player!(self, player => player.get_active_file_path())
     }
 
     fn get_duration(&self) -> Option<u64> {
-        match self {
-            MusicPlayer::Cmus(cmus) => return Cmus::get_duration(&cmus),
-//          MusicPlayer::NewPlayer(newplayer_instance) => return NewPlayer::get_duration(newplayer_instance),
-        }
+        dispatch_player!(self, player => player.get_duration())
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        dispatch_player!(self, player => player.get_position())
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        dispatch_player!(self, player => player.get_process_name())
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        dispatch_player!(self, player => player.get_metadata())
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        dispatch_player!(self, player => player.get_player_tags())
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        dispatch_player!(self, player => player.get_playback_state())
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        dispatch_player!(self, player => player.wait_for_change(timeout))
     }
 }
 
 #[derive(Deserialize)]
 struct Config {
-    player_name: String,
+    #[serde(rename = "player_name", deserialize_with = "deserialize_player_names")]
+    player_names: Vec<String>,
+    #[serde(skip)]
+    player_name: String, // The name of the player being followed, one of player_names.
+    process_name: Option<String>,
     player_check_delay: u64,
     run_secondary_checks: bool,
     va_album_individual: bool,
+    #[serde(default = "default_va_names")]
+    va_names: Vec<String>,
+    #[serde(default)]
+    artist_delimiters: Vec<String>,
+    #[serde(default = "default_folder_art_names")]
+    folder_art_names: Vec<String>,
+    #[serde(default = "default_cover_art_archive_lookup")]
+    cover_art_archive_lookup: bool,
+    lastfm_api_key: Option<String>,
+    #[serde(default)]
+    itunes_lookup: bool,
+    #[serde(default)]
+    deezer_lookup: bool,
+    #[serde(default)]
+    rehost_remote_art: bool,
+    art_sources: Option<Vec<ArtSource>>,
     catbox_user_hash: Option<String>,
+    #[serde(default = "default_error_log_max_kb")]
+    error_log_max_kb: u64,
+    #[serde(default = "default_error_log_rotations")]
+    error_log_rotations: usize,
+    #[serde(default = "default_hash_flush_minutes")]
+    hash_flush_minutes: u64,
+    #[serde(default = "default_art_link_verify_hours")]
+    art_link_verify_hours: u64,
+    #[serde(default = "default_art_link_limit")]
+    art_link_limit: usize,
+    metrics_port: Option<u16>,
+    #[serde(default)]
+    spawn_player: bool,
+    player_command: Option<String>,
+    #[serde(default = "default_max_art_kb")]
+    max_art_kb: u64,
+    art_jpeg_quality: Option<u8>,
+    art_jpeg_max_kb: Option<u64>,
+    #[serde(default)]
+    art_fit: ArtFit,
+    #[serde(default, deserialize_with = "deserialize_pad_color")]
+    art_pad_color: Option<[u8; 3]>,
+    #[serde(default)]
+    record_history: bool,
+    webhook_url: Option<String>,
+    mqtt_host: Option<String>,
+    #[serde(default = "default_mqtt_port")]
+    mqtt_port: u16,
+    #[serde(default = "default_mqtt_topic")]
+    mqtt_topic: String,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    overlay_title_file: Option<PathBuf>,
+    overlay_artist_file: Option<PathBuf>,
+    overlay_album_file: Option<PathBuf>,
+    overlay_cover_file: Option<PathBuf>,
+    now_playing_port: Option<u16>,
+    #[serde(default)]
+    now_playing_widget: bool,
+    mastodon_instance: Option<String>,
+    mastodon_token: Option<String>,
+    #[serde(default = "default_mastodon_template")]
+    mastodon_template: String,
+    #[serde(default)]
+    export_mpris: bool,
+    #[serde(default)]
+    synced_lyrics: bool,
+    #[serde(default = "default_lrclib_lookup")]
+    lrclib_lookup: bool,
+    discord_webhook_url: Option<String>,
+    #[serde(default)]
+    announce_albums: bool,
+    #[serde(default)]
+    odesli_lookup: bool,
+    #[serde(default = "default_track_debounce_ms")]
+    track_debounce_ms: u64,
+    #[serde(default = "default_position_resync_seconds")]
+    position_resync_seconds: u64,
+    #[cfg_attr(windows, allow(dead_code))]
+    cmus_socket_path: Option<PathBuf>,
+    #[serde(default)]
+    #[cfg_attr(windows, allow(dead_code))]
+    cmus_status_display: bool,
+    #[serde(default = "default_mpd_address")]
+    mpd_address: String,
+    mpd_music_dir: Option<PathBuf>,
+    mpd_password: Option<String>,
+    mpris_bus_name: Option<String>,
+    mpv_socket_path: Option<PathBuf>,
+    #[serde(default = "default_musikcube_address")]
+    musikcube_address: String,
+    musikcube_password: Option<String>,
+    #[serde(default = "default_foobar2000_address")]
+    foobar2000_address: String,
+    #[serde(default = "default_musicbee_address")]
+    musicbee_address: String,
+    status_command: Option<String>,
+    command_process_name: Option<String>,
+    now_playing_file: Option<PathBuf>,
+    #[serde(default)]
+    plugins: HashMap<String, String>,
+    #[serde(default)]
+    players: HashMap<String, PlayerSettings>,
+    #[serde(default)]
+    rewrites: Rewrites,
 }
 
-fn main() {
-    // Load configuration values from config file.
-    let config_values: Config = match load_config() {
-        Ok(config_values) => config_values,
+// Options of a single player, from its [players.<player_name>] table. Each replaces the top-level key for that player
+// (e.g. address replaces mpd_address), so players listed together, such as MPD and Mopidy, can be set up separately.
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+struct PlayerSettings {
+    socket_path: Option<PathBuf>,
+    address: Option<String>,
+    password: Option<String>,
+    music_dir: Option<PathBuf>,
+    bus_name: Option<String>,
+    #[cfg_attr(windows, allow(dead_code))]
+    status_display: Option<bool>,
+    status_command: Option<String>,
+    process_name: Option<String>,
+    now_playing_file: Option<PathBuf>,
+}
+
+// player_name may name a single player, or list several players in order of priority.
+fn deserialize_player_names<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PlayerNames {
+        One(String),
+        Several(Vec<String>),
+    }
+    match PlayerNames::deserialize(deserializer)? {
+        PlayerNames::One(player_name) => Ok(vec![player_name]),
+        PlayerNames::Several(player_names) if !player_names.is_empty() => Ok(player_names),
+        PlayerNames::Several(_) => Err(serde::de::Error::custom("player_name must list at least one player")),
+    }
+}
+
+// art_pad_color is written as a hex color, such as '#1e1e1e'.
+fn deserialize_pad_color<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 3]>, D::Error> {
+    let pad_color = String::deserialize(deserializer)?;
+    let hex_digits = pad_color.strip_prefix('#').unwrap_or(&pad_color);
+    let channel = |index: usize| hex_digits.get(index..index + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok());
+    match (hex_digits.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(red), Some(green), Some(blue)) => Ok(Some([red, green, blue])),
+        _ => Err(serde::de::Error::custom(format!("art_pad_color must be a hex color such as '#1e1e1e', not '{}'", pad_color))),
+    }
+}
+
+// Default values for optional configuration keys.
+fn default_error_log_max_kb() -> u64 { 1024 }
+fn default_error_log_rotations() -> usize { 3 }
+fn default_hash_flush_minutes() -> u64 { 5 }
+fn default_art_link_verify_hours() -> u64 { 24 }
+fn default_art_link_limit() -> usize { 10000 }
+fn default_max_art_kb() -> u64 { 8192 }
+fn default_va_names() -> Vec<String> { vec![String::from("Various Artists")] }
+fn default_cover_art_archive_lookup() -> bool { true }
+fn default_folder_art_names() -> Vec<String> { ["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg", "front.png"].map(String::from).to_vec() }
+fn default_mqtt_port() -> u16 { 1883 }
+fn default_mqtt_topic() -> String { String::from("lamp-drpc/now-playing") }
+fn default_mastodon_template() -> String { String::from("#nowplaying {artist} - {title}") }
+fn default_lrclib_lookup() -> bool { true }
+fn default_track_debounce_ms() -> u64 { 500 }
+fn default_position_resync_seconds() -> u64 { 3 }
+fn default_mpd_address() -> String { String::from("localhost:6600") }
+fn default_musikcube_address() -> String { String::from("localhost:7905") }
+fn default_foobar2000_address() -> String { String::from("localhost:8880") }
+fn default_musicbee_address() -> String { String::from("localhost:3000") }
+
+// The sources album art is taken from, in order. Without art_sources, every source is used in the default order, except
+// the online sources that are disabled.
+fn art_sources(config_values: &Config) -> Vec<ArtSource> {
+    match &config_values.art_sources {
+        Some(art_sources) => art_sources.clone(),
+        None => ArtSource::ALL.into_iter().filter(|art_source| match art_source {
+            ArtSource::Caa => config_values.cover_art_archive_lookup,
+            ArtSource::Itunes => config_values.itunes_lookup,
+            ArtSource::Deezer => config_values.deezer_lookup,
+            _ => true,
+        }).collect(),
+    }
+}
+
+// How album art is encoded for upload. JPEG qualities are limited to the range the encoder accepts.
+fn art_encoding(config_values: &Config) -> ArtEncoding {
+    ArtEncoding {
+        jpeg_quality: config_values.art_jpeg_quality.map(|jpeg_quality| jpeg_quality.clamp(1, 100)),
+        jpeg_max_kb: config_values.art_jpeg_max_kb,
+        fit: config_values.art_fit,
+        pad_color: config_values.art_pad_color,
+    }
+}
+
+// Minimum time between uploads made by the scan command, so large libraries don't flood the image host.
+const SCAN_UPLOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+// Time to wait between searches for a running player when several players are configured.
+const PLAYER_SEARCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Maximum time to wait for the player process to appear after launching it with player_command.
+const PLAYER_LAUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// State of the event loop, updated as each event is handled.
+struct EventLoop {
+    config_values: Config,
+    presence: PresenceService,
+    art_sender: mpsc::Sender<ArtRequest>,
+    dbus_service: Option<DbusService>,
+    presence_enabled: bool, // Whether rich presence is shown, toggled through the control socket.
+    paused: bool, // Whether the player has paused the active track, which is then shown without timestamps.
+    active_track: Option<Track>, // The track currently reported by the player.
+    active_track_id: u64, // Incremented every time the active track is processed, used to discard album art for previous tracks.
+    now_playing: Option<NowPlaying>, // Information about the active track, if its metadata could be read.
+    awaiting_art: bool, // Whether now_playing is waiting on album art. The track is shown on Discord meanwhile, but not yet reported elsewhere.
+    history: Option<History>, // The play history, if record_history is enabled.
+    webhook: WebhookService,
+    mqtt: Option<MqttService>, // Publishes the playback state if mqtt_host is set. Changes to MQTT settings require a restart.
+    overlay: OverlayService,
+    now_playing_sender: watch::Sender<Option<NowPlaying>>, // The active track, as reported by the now-playing endpoint.
+    mpris: Option<MprisService>, // Mirrors the active track as an MPRIS player if export_mpris is enabled. Changes require a restart.
+    cover_path: Option<PathBuf>, // Local copy of the active track's album art, provided to MPRIS clients.
+    lyrics: LyricsService,
+    lyric_line: Option<String>, // The current line of the active track's lyrics, shown in place of the artist if synced_lyrics is enabled.
+    announced_album: Option<String>, // The album last announced to discord_webhook_url, so each album is only announced once.
+    links: LinkService,
+    radio: RadioService, // Reads the titles of internet radio streams reported in place of files.
+    event_sender: mpsc::UnboundedSender<Event>, // Used to report when a changed track has settled.
+    pending_track: Option<Track>, // A track reported by the player that has not yet been playing for track_debounce_ms.
+    track_change_id: u64, // Incremented on every track change, used to discard settle events for tracks that were skipped.
+}
+
+#[tokio::main]
+async fn main() {
+    // Path options are read first. Any remaining arguments are sent as a command to the running instance.
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    match take_path_options(&mut args) {
+        Ok((config_file, state_dir)) => paths::init(config_file, state_dir),
         Err(e) => {
-            error_log::log_error("main:load_config Error", e.to_string().as_str());
-            process::exit(1);
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+    if !args.is_empty() {
+        match args[0].as_str() {
+            "history" | "stats" => run_history_command(&args),
+            "scan" => run_scan_command(&args).await,
+            "--status-line" => run_status_line(&args),
+            #[cfg(unix)]
+            "--cmus-status" => run_cmus_status(),
+            _ => run_client_command(&args),
         }
+    }
+
+    // Load configuration values from config file.
+    let mut config_values: Config = match load_config() {
+        Ok(config_values) => config_values,
+        Err(e) => exit_with_error("main:load_config Error", e.to_string().as_str()),
     };
+    if let Err(e) = fs::create_dir_all(paths::state_dir()) {
+        exit_with_error("main:create_dir_all Error", format!("Could not create state directory {}: {}", paths::state_dir().display(), e).as_str());
+    }
+    error_log::configure(config_values.error_log_max_kb, config_values.error_log_rotations);
 
     // Load HashMap from list stored in hash file.
-    let mut filename_hash = match load_hash_file() {
+    // Album art links can always be uploaded again, so lamp-drpc starts with an empty map if they can't be loaded.
+    let filename_hash = match art::load_hash_file() {
         Ok(filename_hash) => filename_hash,
         Err(e) => {
-            error_log::log_error("main:load_hash_file Error", e.to_string().as_str());
-            process::exit(1);
+            error_log::log_error("main:load_hash_file Warning", format!("Starting with an empty album art cache: {}", e).as_str());
+            HashMap::new()
         }
     };
 
+    // Sockets for the control socket and players are created in the runtime directory.
+    let runtime_dir = match runtime_dir::resolve() {
+        Ok(runtime_dir) => runtime_dir,
+        Err(e) => exit_with_error("main:runtime_dir::resolve Error", e.to_string().as_str()),
+    };
+
     let sleep_time: Duration = Duration::from_secs(config_values.player_check_delay);
 
-    // Assign MusicPlayer type based on provided player_name
-    /*
-     *  [PLAYER IMPLEMENTATION HERE]
-     *  This is where the player is selected and the matching MusicPlayer implementation is assigned. 
-     *  The value of player_name read from the config file should match the player's process name, 
-     *  as it will be used to find the PID and keep tabs on its status.
-     */
-    let mut active_music_player: MusicPlayer;
-    match config_values.player_name.as_str() {
-        "cmus" => active_music_player = MusicPlayer::Cmus(Cmus::default()),
-//      "player_process_name" => active_music_player = MusicPlayer::NewPlayer(NewPlayer::default()),
-        _ => {
-            error_log::log_error("main: active_music_player match Error", format!("The player_name \"{}\" provided in the lamp.toml configuration file is unsupported.", config_values.player_name).as_str());
-            process::exit(1); 
+    // Each player is created up front, so configuration errors are reported at startup.
+    let mut players = config_values.player_names.iter()
+                                                .map(|player_name| (player_name.clone(), create_player(player_name, &config_values, &runtime_dir), configured_process_name(&config_values, player_name)))
+                                                .collect::<Vec<(String, MusicPlayer, Option<String>)>>();
+
+    // Wait player_check_delay number of seconds before checking that player is running
+    tokio::time::sleep(sleep_time).await;
+
+    // Instantiate system instance to track player status
+    // It starts empty, and only the player's processes are ever loaded into it.
+    let mut sys = System::new();
+
+    // A single player must be running (or be launched) at startup, and lamp-drpc exits once it does.
+    // With several players, the first one running is followed, and once it exits, the next one to start is followed.
+    let single_player = match players.len() {
+        1 => Some(players.remove(0).1),
+        _ => None,
+    };
+    let followed_player = match single_player {
+        Some(active_music_player) => {
+            // Get PID of player process for checking process status
+            // If the player is not running and spawn_player is enabled, launch it and wait for it to start.
+            // Players found through D-Bus report the name of their process, which is used in place of player_name.
+            // A configured process_name takes the place of both.
+            let mut player_process_name = configured_process_name(&config_values, &config_values.player_name).or_else(|| active_music_player.get_process_name())
+                                                                                                          .unwrap_or(config_values.player_name.clone());
+            let player_pid = match player::get_pid_by_proc_name(&mut sys, &player_process_name) {
+                Ok(player_pid) => player_pid,
+                Err(_) if config_values.spawn_player => match launch_player(&config_values, &active_music_player, &mut sys, sleep_time).await {
+                    Ok((player_pid, launched_process_name)) => {
+                        player_process_name = launched_process_name;
+                        player_pid
+                    }
+                    Err(e) => exit_with_error("main:launch_player Error", e.to_string().as_str()),
+                },
+                Err(e) => exit_with_error("main:get_pid_by_proc_name Error", e.to_string().as_str()),
+            };
+
+            if config_values.run_secondary_checks {
+                match active_music_player.verify_running() {
+                    Ok(true) => (),
+                    Ok(false) => exit_with_error("Error", format!("Secondary check(s) failed for player {}.", config_values.player_name).as_str()),
+                    Err(e) => exit_with_error("main:verify_running Error", e.to_string().as_str()),
+                }
+            }
+            Some((active_music_player, player_pid, player_process_name))
+        }
+        None => {
+            // Services started below are given the name of the player running at startup, if any.
+            if let Some((player_index, _, _)) = find_running_player(&players, &mut sys) {
+                config_values.player_name = players[player_index].0.clone();
+            }
+            None
+        }
+    };
+
+    // All services report to the event loop through a single channel.
+    let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+
+    // Start listening for commands on the control socket and D-Bus.
+    // lamp-drpc can still run without either, so failures are only logged.
+    let socket_path = ipc::socket_path(&runtime_dir);
+    let socket_listening = match ipc::start_listener(&socket_path, event_sender.clone()) {
+        Ok(_) => true,
+        Err(e) => {
+            error_log::log_error("main:ipc::start_listener Error", e.to_string().as_str());
+            false
+        }
+    };
+    let dbus_service = match DbusService::start(event_sender.clone()).await {
+        Ok(dbus_service) => Some(dbus_service),
+        Err(e) => {
+            error_log::log_error("main:DbusService::start Error", e.to_string().as_str());
+            None
+        }
+    };
+
+    // Export the active track as an MPRIS player if enabled.
+    let mpris = match config_values.export_mpris {
+        true => match MprisService::start(&config_values.player_name).await {
+            Ok(mpris) => Some(mpris),
+            Err(e) => {
+                error_log::log_error("main:MprisService::start Error", e.to_string().as_str());
+                None
+            }
+        },
+        false => None,
+    };
+
+    // Serve metrics and health checks if enabled.
+    if let Some(metrics_port) = config_values.metrics_port {
+        if let Err(e) = metrics::start_server(metrics_port).await {
+            error_log::log_error("main:metrics::start_server Error", e.to_string().as_str());
         }
     }
 
-    // Wait player_check_delay number of seconds before checking that player is running
-    thread::sleep(sleep_time);
+    // Serve the active track on localhost if enabled.
+    let (now_playing_sender, now_playing_receiver) = watch::channel::<Option<NowPlaying>>(None);
+    if let Some(now_playing_port) = config_values.now_playing_port {
+        if let Err(e) = now_playing_server::start_server(now_playing_port, config_values.player_name.clone(), config_values.now_playing_widget, now_playing_receiver).await {
+            error_log::log_error("main:now_playing_server::start_server Error", e.to_string().as_str());
+        }
+    }
 
-    // Instantiate system instance with variable to track player status
-    let mut sys = System::new_with_specifics(RefreshKind::nothing().with_processes(ProcessRefreshKind::nothing()));
-    let mut player_status = ProcessStatus::Stop;
+    tokio::time::sleep(sleep_time).await;
+
+    // Start services for presence updates, webhook posts, lyrics, song links, radio titles, album art processing, and player polling.
+    let (presence, presence_task) = PresenceService::start(1353193853393571910);
+    let (lyrics, lyrics_task) = LyricsService::start(event_sender.clone());
+    let (links, link_task) = LinkService::start(event_sender.clone());
+    let (radio, radio_task) = RadioService::start(event_sender.clone());
+    let (webhook, webhook_task) = WebhookService::start();
+    let (overlay, overlay_task) = OverlayService::start();
+    let (mqtt, mqtt_task) = match &config_values.mqtt_host {
+        Some(mqtt_host) => {
+            let mqtt_settings = MqttSettings {
+                host: mqtt_host.clone(),
+                port: config_values.mqtt_port,
+                topic: config_values.mqtt_topic.clone(),
+                credentials: config_values.mqtt_username.clone().zip(config_values.mqtt_password.clone()),
+            };
+            let (mqtt, mqtt_task) = MqttService::start(mqtt_settings, &config_values.player_name);
+            (Some(mqtt), Some(mqtt_task))
+        }
+        None => (None, None),
+    };
+    let hash_flush_period = Duration::from_secs(config_values.hash_flush_minutes.max(1) * 60);
+    let art_verify_period = Duration::from_secs(config_values.art_link_verify_hours * 60 * 60);
+    let (art_sender, art_task) = art::start_art_service(Catbox::new(), filename_hash, hash_flush_period, art_verify_period, config_values.art_link_limit, art_encoding(&config_values), event_sender.clone());
+    let player_event_sender = event_sender.clone();
+    let resync_seconds = config_values.position_resync_seconds;
+    let player_task = tokio::task::spawn_blocking(move || match followed_player {
+        Some((mut active_music_player, player_pid, player_process_name)) => {
+            player::poll_player(&mut active_music_player, sys, player_pid, &player_process_name, resync_seconds, player_event_sender)
+        }
+        None => follow_players(players, resync_seconds, player_event_sender),
+    });
+
+    let history = open_history(&config_values);
+    let mut event_loop = EventLoop {
+        config_values,
+        presence,
+        art_sender,
+        dbus_service,
+        presence_enabled: true,
+        paused: false,
+        active_track: None,
+        active_track_id: 0,
+        now_playing: None,
+        awaiting_art: false,
+        history,
+        webhook,
+        mqtt,
+        overlay,
+        now_playing_sender,
+        mpris,
+        cover_path: None,
+        lyrics,
+        lyric_line: None,
+        announced_album: None,
+        links,
+        radio,
+        event_sender,
+        pending_track: None,
+        track_change_id: 0,
+    };
 
-    // Get PID of player process for checking process status
-    let player_pid = get_pid_by_proc_name(&sys, &config_values.player_name);
+    // Begin event loop
+    while let Some(event) = event_receiver.recv().await {
+        let event_start = Instant::now();
+        let keep_running = event_loop.handle_event(event).await;
+        metrics::record_event_latency(event_start.elapsed());
 
-    // Get status of player process by PID
-    player_status = get_status_by_pid(&sys, &player_pid);
+        if !keep_running {
+            break;
+        }
+    }
 
-    if config_values.run_secondary_checks {
-        if !&active_music_player.verify_running() {
-            error_log::log_error("Error", format!("Secondary check(s) failed for player {}.", config_values.player_name).as_str());
-            process::exit(1);
+    // Stop remaining services. The album art service updates the hash file with all changes on exit.
+    drop(event_receiver);
+    drop(event_loop);
+    let _ = art_task.await;
+    let _ = presence_task.await;
+    let _ = webhook_task.await;
+    let _ = overlay_task.await;
+    let _ = lyrics_task.await;
+    let _ = link_task.await;
+    let _ = radio_task.await;
+    if let Some(mqtt_task) = mqtt_task {
+        let _ = mqtt_task.await;
+    }
+    let _ = player_task.await;
+
+    if socket_listening {
+        let _ = remove_file(&socket_path);
+    }
+    error_log::flush();
+}
+
+impl EventLoop {
+    // Returns false once lamp-drpc should exit.
+    async fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::TrackChanged(track) => {
+                self.record_play();
+                self.track_change_id += 1;
+                self.paused = false;
+
+                // The track is only processed once it has been playing for track_debounce_ms, so skipping through tracks
+                // doesn't read, upload, and show every one of them. The previous track stays visible in the meantime.
+                if self.config_values.track_debounce_ms == 0 {
+                    self.active_track = Some(track);
+                    self.process_active_track().await;
+                } else {
+                    self.active_track = None;
+                    self.pending_track = Some(track);
+                    let (event_sender, change_id) = (self.event_sender.clone(), self.track_change_id);
+                    let debounce_period = Duration::from_millis(self.config_values.track_debounce_ms);
+                    tokio::spawn(async move {
+                        tokio::time::sleep(debounce_period).await;
+                        let _ = event_sender.send(Event::TrackSettled { change_id });
+                    });
+                }
+            }
+            Event::TrackSettled { change_id } => {
+                // Tracks that were changed again before settling are discarded.
+                if change_id == self.track_change_id {
+                    if let Some(track) = self.pending_track.take() {
+                        self.active_track = Some(track);
+                        self.process_active_track().await;
+                    }
+                }
+            }
+            Event::PositionChanged { start_time, end_time } => {
+                self.paused = false;
+                self.resync_position(start_time, end_time);
+            }
+            Event::Paused => {
+                self.paused = true;
+                if self.presence_enabled {
+                    self.show_presence();
+                }
+            }
+            Event::Stopped => self.stop_active_track().await,
+            Event::PlayerStarted(player_name) => self.config_values.player_name = player_name,
+            // With several players, lamp-drpc keeps running until the next one starts.
+            Event::PlayerExited if self.config_values.player_names.len() > 1 => self.stop_active_track().await,
+            Event::PlayerExited => {
+                self.record_play();
+                self.now_playing = None;
+                self.report_playback();
+                return false;
+            }
+            Event::ArtReady { track_id, image_link } => {
+                // Album art for tracks that are no longer active is discarded.
+                if track_id == self.active_track_id && self.awaiting_art {
+                    self.awaiting_art = false;
+                    if let Some(now_playing) = &mut self.now_playing {
+                        now_playing.image_link = image_link;
+                    }
+                    self.show_now_playing();
+                }
+            }
+            Event::LyricLine { track_id, line } => {
+                // Lines for tracks that are no longer active are discarded.
+                if track_id == self.active_track_id {
+                    self.lyric_line = line;
+                    if self.presence_enabled {
+                        self.show_presence();
+                    }
+                }
+            }
+            Event::LinkReady { track_id, link } => {
+                // Links for tracks that are no longer active are discarded. The track is shown again with its link, and
+                // reported again if it has already been reported.
+                if track_id == self.active_track_id {
+                    if let Some(now_playing) = &mut self.now_playing {
+                        now_playing.link = link;
+                    }
+                    if self.presence_enabled {
+                        self.show_presence();
+                    }
+                    if !self.awaiting_art {
+                        self.report_playback();
+                    }
+                }
+            }
+            Event::StreamMetadata { track_id, metadata } => {
+                // Titles of streams that are no longer active are discarded.
+                if let (true, Some(track)) = (track_id == self.active_track_id, self.active_track.clone()) {
+                    self.show_player_metadata(track, metadata);
+                }
+            }
+            Event::Command(request) => {
+                let quit_requested = matches!(request.command, IpcCommand::Quit);
+                self.handle_command(request.command, request.reply).await;
+                if quit_requested {
+                    self.record_play();
+                    self.now_playing = None;
+                    self.report_playback();
+                }
+                return !quit_requested;
+            }
         }
+
+        true
     }
 
-    // Declare variables for use in main loop
-    let mut active_file_path = String::new();   // The path of the currently playing track.
-    let mut previous_file_path = String::new(); // The path of the previous track, used to determine when the active track has changed.
-    let mut active_file_image_link = Some(String::new()); // Link to the album art of the currently playing track, hosted on Imgur.
-    let mut active_duration: Option<u64> = None; // The duration of audio file.
-    let mut previous_update_time = Instant::now(); // The time of the previous file update.
-    let mut previous_duration: Option<u64> = None; // The duration of the previous track.
-    let mut new_metadata_package = Some(MetadataPackage::default());
-    let http_client = reqwest::Client::new();
-    let mut discord_client = discord_presence::Client::new(1353193853393571910);
-
-    thread::sleep(sleep_time);
-
-    discord_client.start();
-
-    // Begin main loop
-    while player_status != ProcessStatus::Stop {
-        match active_music_player.get_active_file_path() {
-            // Active filename is defined
-            Ok(Some(file_path)) => {
-                // Update active file path, position, and duration.
-                active_file_path = file_path;
-                active_duration = active_music_player.get_duration();
-
-                // Only update metadata if file has changed or playback has completed.
-                let playback_complete = match previous_duration {
-                    Some(seconds) => {
-                        if Instant::now().duration_since(previous_update_time) >= Duration::from_secs(seconds) { true }
-                        else { false }
-                    },
-                    None => false,
-                };
-                
-                if active_file_path != previous_file_path || playback_complete {
-                    // Record time of file change.
-                    let (start_time, end_time): (Option<u64>, Option<u64>);
-                    previous_update_time = Instant::now();
-                    match SystemTime::now().duration_since(UNIX_EPOCH) {
-                        Ok(time) => {
-                            start_time = Some(time.as_secs());
-
-                            if let Some(duration) = active_duration {
-                                end_time = Some(time.as_secs() + duration);
-                                previous_duration = Some(duration);
-                            } else {
-                                end_time = None;
-                            }
-                        }
-                        Err(e) => {
-                            error_log::log_error("main:SystemTime::now():duration_since() Error", e.to_string().as_str());
-                            (start_time, end_time) = (None, None);
+    fn show_player_metadata(&mut self, track: Track, player_metadata: PlayerMetadata) {
+        // Album art linked by the player is shown directly if the link is HTTPS, unless rehost_remote_art is enabled.
+        // Otherwise, it is uploaded the same as album art looked up online, if there is a catbox user hash.
+        let art_request = match (player_metadata.art_url.clone(), &self.config_values.catbox_user_hash) {
+            (Some(image_link), Some(_)) if self.config_values.rehost_remote_art || !image_link.starts_with("https://") => Some(ArtRequest::Rehost {
+                track_id: self.active_track_id,
+                file_path: track.file_path.clone(),
+                image_link,
+                query: ArtQuery {
+                    release_id: None,
+                    artist: player_metadata.artist.clone(),
+                    album: player_metadata.album.clone(),
+                    lastfm_api_key: None,
+                    sources: Vec::new(),
+                },
+                catbox_user_hash: self.config_values.catbox_user_hash.clone(),
+            }),
+            _ => None,
+        };
+
+        self.now_playing = Some(NowPlaying {
+            artist: player_metadata.artist,
+            title: player_metadata.title,
+            album: player_metadata.album,
+            genre: None,
+            year: None,
+            label: None,
+            rating: track.tags.rating,
+            original_year: track.tags.original_year.clone(),
+            image_link: player_metadata.art_url.filter(|_| art_request.is_none()),
+            link: None,
+            start_time: track.start_time,
+            end_time: track.end_time,
+        });
+        if let Some(now_playing) = &mut self.now_playing {
+            self.config_values.rewrites.apply(now_playing);
+        }
+        if let (true, Some(now_playing)) = (self.config_values.synced_lyrics, &self.now_playing) {
+            self.lyrics.follow(self.active_track_id, &track.file_path, now_playing, self.config_values.lrclib_lookup);
+        }
+        if let (true, Some(now_playing)) = (self.config_values.odesli_lookup, &self.now_playing) {
+            self.links.resolve(self.active_track_id, now_playing);
+        }
+        self.update_overlay(None);
+        self.request_album_art(art_request);
+    }
+
+    // Clears the active track once the player has stopped.
+    async fn stop_active_track(&mut self) {
+        self.record_play();
+        self.track_change_id += 1;
+        self.paused = false;
+        self.pending_track = None;
+        self.active_track = None;
+        self.process_active_track().await;
+        self.presence.clear();
+        self.report_playback();
+    }
+
+    // Clears the track shown over D-Bus, MPRIS, and the overlay files, for when there is none to show.
+    fn clear_track_outputs(&mut self) {
+        if let Some(dbus_service) = &self.dbus_service {
+            dbus_service.set_now_playing(None);
+        }
+        if let Some(mpris) = &self.mpris {
+            mpris.set_track(None);
+        }
+        self.update_overlay(None);
+    }
+
+    async fn process_active_track(&mut self) {
+        self.active_track_id += 1;
+        self.now_playing = None;
+        self.awaiting_art = false;
+        self.cover_path = None;
+        self.lyric_line = None;
+        self.lyrics.stop();
+        self.radio.stop();
+
+        let Some(track) = self.active_track.clone() else {
+            self.clear_track_outputs();
+            return;
+        };
+
+        // Tracks that aren't local files are shown with the metadata reported by the player, including its album art link.
+        if let Some(player_metadata) = track.metadata.clone() {
+            self.show_player_metadata(track, player_metadata);
+            return;
+        }
+
+        // Streams are shown once the radio service has read their title.
+        if radio::is_stream_url(&track.file_path) {
+            self.radio.follow(self.active_track_id, &track.file_path);
+            self.clear_track_outputs();
+            return;
+        }
+
+        // Read metadata from active file.
+        let metadata_file_path = track.file_path.clone();
+        let va_album_individual = self.config_values.va_album_individual;
+        let va_names = self.config_values.va_names.clone();
+        let artist_delimiters = self.config_values.artist_delimiters.clone();
+        let folder_art_names = self.config_values.folder_art_names.clone();
+        let art_sources = art_sources(&self.config_values);
+        let local_art_sources = art_sources.clone();
+        let max_art_size = self.config_values.max_art_kb * 1024;
+        let new_metadata_package = match tokio::task::spawn_blocking(move || read_metadata(&metadata_file_path, &va_album_individual, &va_names, &artist_delimiters, &folder_art_names, &local_art_sources, max_art_size)).await {
+            Ok(Ok(new_metadata_package)) => Some(new_metadata_package),
+            Ok(Err(e)) => {
+                error_log::log_lamp_error("main:read_metadata", &LampError::from(e));
+                None
+            }
+            Err(e) => {
+                error_log::log_error("main:read_metadata Error", e.to_string().as_str());
+                None
+            }
+        };
+
+        // If metadata_pack is None, there is no need to check album art or send to Discord.
+        let Some(metadata_pack) = new_metadata_package else {
+            self.clear_track_outputs();
+            return;
+        };
+
+        // Players that don't report the duration are shown with the duration read from the file, if there is one.
+        let end_time = track.end_time.or_else(|| track.start_time.zip(metadata_pack.duration).map(|(start_time, duration)| start_time + duration));
+        if let Some(active_track) = &mut self.active_track {
+            active_track.end_time = end_time;
+        }
+
+        // Tracks are looked up by their release ID, or their album artist and album, on the online sources that come before
+        // the album art read from the file or its directory, or on all of them if there is none.
+        let art_query = ArtQuery {
+            release_id: metadata_pack.release_id.clone(),
+            artist: metadata_pack.album_artist.clone().unwrap_or_else(|| metadata_pack.artist.clone()),
+            album: metadata_pack.album.clone(),
+            lastfm_api_key: self.config_values.lastfm_api_key.clone(),
+            sources: art_sources.into_iter().take_while(|art_source| Some(*art_source) != metadata_pack.album_art_source)
+                                            .filter(|art_source| !art_source.is_local())
+                                            .collect(),
+        };
+
+        self.now_playing = Some(NowPlaying {
+            artist: metadata_pack.artist,
+            title: metadata_pack.title,
+            album: metadata_pack.album,
+            genre: metadata_pack.genre,
+            year: metadata_pack.year,
+            label: metadata_pack.label,
+            rating: track.tags.rating,
+            original_year: track.tags.original_year.clone(),
+            image_link: None,
+            link: None,
+            start_time: track.start_time,
+            end_time,
+        });
+        if let Some(now_playing) = &mut self.now_playing {
+            self.config_values.rewrites.apply(now_playing);
+        }
+        if let (true, Some(now_playing)) = (self.config_values.synced_lyrics, &self.now_playing) {
+            self.lyrics.follow(self.active_track_id, &track.file_path, now_playing, self.config_values.lrclib_lookup);
+        }
+        if let (true, Some(now_playing)) = (self.config_values.odesli_lookup, &self.now_playing) {
+            self.links.resolve(self.active_track_id, now_playing);
+        }
+
+        // The overlay cover is resized from the embedded album art, so it is written whether or not album art is uploaded.
+        self.update_overlay(self.config_values.overlay_cover_file.as_ref().and_then(|_| metadata_pack.album_art.clone()));
+
+        // MPRIS clients read album art from a local file, which is kept in the state directory.
+        if let (Some(_), Some(album_art)) = (&self.mpris, &metadata_pack.album_art) {
+            let album_art = album_art.clone();
+            self.cover_path = match tokio::task::spawn_blocking(move || art::cache_cover(album_art)).await {
+                Ok(Ok(cover_path)) => Some(cover_path),
+                Ok(Err(e)) => {
+                    error_log::log_lamp_error("main:art::cache_cover", &LampError::from(e));
+                    None
+                }
+                Err(e) => {
+                    error_log::log_error("main:art::cache_cover Error", e.to_string().as_str());
+                    None
+                }
+            };
+        }
+
+        // The track is shown on Discord with the default image right away, and shown again with its album art once the
+        // album art service has found or processed the image. Tracks are looked up online first if they can be, falling
+        // back to their own album art. Public HTTPS links found online are shown without being uploaded, so tracks are
+        // looked up even without a catbox user hash, but album art that has to be uploaded is only used with one.
+        let catbox_user_hash = self.config_values.catbox_user_hash.as_ref();
+        let art_request = match (metadata_pack.album_art, catbox_user_hash) {
+            (fallback_art, _) if art_query.is_searchable() && (catbox_user_hash.is_some() || !self.config_values.rehost_remote_art) => Some(ArtRequest::Lookup {
+                track_id: self.active_track_id,
+                file_path: track.file_path,
+                query: art_query,
+                fallback_art: fallback_art.filter(|_| catbox_user_hash.is_some()),
+                rehost_remote_art: self.config_values.rehost_remote_art,
+                catbox_user_hash: self.config_values.catbox_user_hash.clone(),
+            }),
+            (Some(album_art), Some(_)) => Some(ArtRequest::Process {
+                track_id: self.active_track_id,
+                file_path: track.file_path,
+                album_art,
+                catbox_user_hash: self.config_values.catbox_user_hash.clone(),
+            }),
+            _ => None,
+        };
+        self.request_album_art(art_request);
+    }
+
+    // Queues the track's album art with the album art service, or shows the track right away if there is none to process.
+    fn request_album_art(&mut self, art_request: Option<ArtRequest>) {
+        match art_request {
+            Some(art_request) => {
+                // If the album art service is backed up, show the track without waiting for its album art.
+                match self.art_sender.try_send(art_request) {
+                    Ok(_) => {
+                        self.awaiting_art = true;
+                        if self.presence_enabled {
+                            self.show_presence();
                         }
                     }
+                    Err(e) => {
+                        error_log::log_error("main:art_sender.try_send Error", format!("Album art could not be queued: {}", e).as_str());
+                        self.show_now_playing();
+                    }
+                }
+            }
+            None => self.show_now_playing(),
+        }
+    }
 
-                    // Read metadata from active file. Set active file image link to default None.
-                    new_metadata_package = read_metadata(&active_file_path, &config_values.va_album_individual);
-                    active_file_image_link = None;
-
-                    // If metadata_pack is None, there is no need to check album art or send to Discord.
-                    if let Some(metadata_pack) = new_metadata_package {
-                        // Check if catbox user hash is defined in config file.
-                        // If the user hash is not defined, album art won't be provided to Discord.
-                        if config_values.catbox_user_hash.is_some() {
-                            // If album art is defined in the metadata pack, check for upload status.
-                            // If album art is not defined, set the active image link to None.
-                            if let Some(album_art) = metadata_pack.album_art {
-                                match filename_hash.get(&album_art.filename) {
-                                    // Filename is already in hash map.
-                                    Some(image_link) => {
-                                        // Verify link status.
-                                        let link_status_good = match trpl::run(get_link_status(&http_client, image_link)) {
-                                            Ok(link_status) => link_status,
-                                            Err(e) => {
-                                                error_log::log_error("main:link_status_good Error", e.to_string().as_str());
-                                                false
-                                            }
-                                        };
-
-                                        // If link is good, set active file image link.
-                                        if link_status_good {
-                                            active_file_image_link = Some(image_link.clone());
-                                        } else { // Link is bad, reupload and update link in hash map.
-                                            // Clear current rich presence information so not visible while uploading.
-                                            match discord_client.clear_activity() {
-                                                Ok(_) => (),
-                                                Err(e) => {
-                                                    error_log::log_error("main: Discord Error on album art update", e.to_string().as_str());
-                                                }
-                                            }
-
-                                            // Reupload album art and update link in hash map.
-                                            match trpl::run(write_album_art(album_art, &config_values.catbox_user_hash)) {
-                                                Ok(filename_link_pair) => {
-                                                    active_file_image_link = Some(filename_link_pair.1.clone());
-                                                    filename_hash.insert(filename_link_pair.0, filename_link_pair.1);  
-                                                },
-                                                Err(image_error) => {
-                                                    error_log::log_error("main:write_album_art Error", format!("Error while processing album art image on file {}: {}", &active_file_path, image_error.to_string()).as_str());
-                                                }
-                                            }
-                                        }
-                                    }
-                                    // Filename is not already in hash map.
-                                    None => {
-                                        // Clear current rich presence information so not visible while uploading.
-                                        match discord_client.clear_activity() {
-                                            Ok(_) => (),
-                                            Err(e) => {
-                                                error_log::log_error("main: Discord Error on album art update", e.to_string().as_str());
-                                            }
-                                        }
-
-                                        match trpl::run(write_album_art(album_art, &config_values.catbox_user_hash)) {
-                                            Ok(filename_link_pair) => {
-                                                active_file_image_link = Some(filename_link_pair.1.clone());
-                                                filename_hash.insert(filename_link_pair.0, filename_link_pair.1);
-                                            },
-                                            Err(image_error) => {
-                                                error_log::log_error("main: write_album_art Error", format!("Error while processing album art image on file {}: {}", &active_file_path, image_error.to_string()).as_str());
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+    // Moves the timestamps of the active track after the player has been paused or seeked, and shows it again with them.
+    fn resync_position(&mut self, start_time: u64, mut end_time: Option<u64>) {
+        if let Some(track) = self.pending_track.as_mut().or(self.active_track.as_mut()) {
+            // A duration read from the file, for players that don't report one, is kept.
+            end_time = end_time.or_else(|| track.start_time.zip(track.end_time).map(|(previous_start, previous_end)| start_time + previous_end.saturating_sub(previous_start)));
+            (track.start_time, track.end_time) = (Some(start_time), end_time);
+        }
+        // The shown track is left alone while a changed track is settling.
+        let (Some(now_playing), None) = (&mut self.now_playing, &self.pending_track) else {
+            return;
+        };
+        (now_playing.start_time, now_playing.end_time) = (Some(start_time), end_time);
+
+        if self.presence_enabled {
+            self.show_presence();
+        }
+        if let (Some(mpris), Some(track), Some(now_playing), false) = (&self.mpris, &self.active_track, &self.now_playing, self.awaiting_art) {
+            mpris.set_track(Some(MprisTrack { now_playing: now_playing.clone(), file_path: track.file_path.clone(), cover_path: self.cover_path.clone() }));
+        }
+        if self.config_values.synced_lyrics {
+            self.lyrics.resync(self.active_track_id, start_time);
+        }
+        if !self.awaiting_art {
+            self.report_playback();
+        }
+    }
+
+    // Records the active track in the play history, once it is no longer active.
+    fn record_play(&self) {
+        let (Some(history), Some(track), Some(now_playing)) = (&self.history, &self.active_track, &self.now_playing) else {
+            return;
+        };
+        let Some(started_at) = track.start_time else {
+            return;
+        };
+
+        let ended_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(started_at);
+        let completion = match track.end_time {
+            Some(end_time) if end_time > started_at => Some(((ended_at.saturating_sub(started_at)) as f64 / (end_time - started_at) as f64).min(1.0)),
+            _ => None,
+        };
+
+        let play = Play {
+            file_path: track.file_path.clone(),
+            artist: now_playing.artist.clone(),
+            title: now_playing.title.clone(),
+            album: now_playing.album.clone(),
+            started_at,
+            ended_at,
+            completion,
+        };
+        if let Err(e) = history.record(&play) {
+            error_log::log_error("main:history.record Error", e.to_string().as_str());
+        }
+    }
+
+    fn show_now_playing(&mut self) {
+        let Some(now_playing) = &self.now_playing else {
+            return;
+        };
+
+        // Presence is only sent to Discord if it has not been disabled through the control socket.
+        if self.presence_enabled {
+            self.show_presence();
+            metrics::increment(&metrics::TRACKS_SHOWN);
+        }
+        if let Some(dbus_service) = &self.dbus_service {
+            dbus_service.set_now_playing(Some(now_playing));
+        }
+        if let (Some(mpris), Some(track)) = (&self.mpris, &self.active_track) {
+            mpris.set_track(Some(MprisTrack { now_playing: now_playing.clone(), file_path: track.file_path.clone(), cover_path: self.cover_path.clone() }));
+        }
+        self.report_playback();
+        self.announce_new_album();
+    }
+
+    // Announces the active track to discord_webhook_url when it starts a new album, if announce_albums is enabled.
+    // Tracks without an album are never announced automatically.
+    fn announce_new_album(&mut self) {
+        let (Some(webhook_url), true, Some(now_playing)) = (&self.config_values.discord_webhook_url, self.config_values.announce_albums, &self.now_playing) else {
+            return;
+        };
+        let Some(album) = &now_playing.album else {
+            return;
+        };
+        if self.announced_album.as_ref() == Some(album) {
+            return;
+        }
+        self.announced_album = Some(album.clone());
+
+        let (webhook_url, player_name, now_playing) = (webhook_url.clone(), self.config_values.player_name.clone(), now_playing.clone());
+        tokio::spawn(async move {
+            if let Err(e) = announce::announce(&webhook_url, &player_name, &now_playing).await {
+                error_log::log_error("main:announce::announce Error", e.to_string().as_str());
+            }
+        });
+    }
+
+    // Announces the active track to discord_webhook_url on request. Like posts to Mastodon, the announcement is made
+    // from its own task, which replies to the command once it is done.
+    fn announce_now_playing(&self, reply: oneshot::Sender<String>) {
+        let Some(webhook_url) = self.config_values.discord_webhook_url.clone() else {
+            let _ = reply.send(String::from("Error: discord_webhook_url must be set to announce tracks."));
+            return;
+        };
+        let Some(now_playing) = self.now_playing.clone() else {
+            let _ = reply.send(String::from("Error: Nothing is playing."));
+            return;
+        };
+
+        let player_name = self.config_values.player_name.clone();
+        tokio::spawn(async move {
+            let reply_text = match announce::announce(&webhook_url, &player_name, &now_playing).await {
+                Ok(_) => format!("Announced {}", now_playing.description()),
+                Err(e) => {
+                    error_log::log_error("main:announce::announce Error", e.to_string().as_str());
+                    format!("Error: {}", e)
+                }
+            };
+            let _ = reply.send(reply_text);
+        });
+    }
+
+    // Shows the active track on Discord, with the current lyric line on the state line if there is one.
+    fn show_presence(&self) {
+        let Some(now_playing) = &self.now_playing else {
+            return;
+        };
+        // A paused track is shown without its timestamps, so it doesn't keep counting down.
+        let paused_track;
+        let now_playing = match self.paused {
+            true => {
+                paused_track = NowPlaying { start_time: None, end_time: None, ..now_playing.clone() };
+                &paused_track
+            }
+            false => now_playing,
+        };
+        match &self.lyric_line {
+            Some(lyric_line) => self.presence.show_lyric(now_playing, lyric_line),
+            None => self.presence.show(now_playing),
+        }
+    }
+
+    // Posts the active track to Mastodon. The post waits on the instance, so it is made from its own task, which replies
+    // to the command once it is done.
+    fn post_now_playing(&self, reply: oneshot::Sender<String>) {
+        let (Some(instance_url), Some(access_token)) = (self.config_values.mastodon_instance.clone(), self.config_values.mastodon_token.clone()) else {
+            let _ = reply.send(String::from("Error: mastodon_instance and mastodon_token must be set to post to Mastodon."));
+            return;
+        };
+        let Some(now_playing) = &self.now_playing else {
+            let _ = reply.send(String::from("Error: Nothing is playing."));
+            return;
+        };
+
+        let status = now_playing.format(&self.config_values.mastodon_template);
+        tokio::spawn(async move {
+            let reply_text = match mastodon::post_status(&instance_url, &access_token, &status).await {
+                Ok(status_url) => format!("Posted {}", status_url),
+                Err(e) => {
+                    error_log::log_error("main:mastodon::post_status Error", e.to_string().as_str());
+                    format!("Error: {}", e)
+                }
+            };
+            let _ = reply.send(reply_text);
+        });
+    }
+
+    // Writes the active track to the overlay files, if any are configured.
+    fn update_overlay(&self, album_art: Option<AlbumArt>) {
+        let overlay_files = OverlayFiles {
+            title_file: self.config_values.overlay_title_file.clone(),
+            artist_file: self.config_values.overlay_artist_file.clone(),
+            album_file: self.config_values.overlay_album_file.clone(),
+            cover_file: self.config_values.overlay_cover_file.clone(),
+        };
+        if !overlay_files.is_empty() {
+            self.overlay.update(&overlay_files, self.now_playing.as_ref(), album_art);
+        }
+    }
+
+    // Reports the active track, or that playback has stopped, to the now-playing endpoint, and to the webhook and MQTT
+    // broker if they are configured.
+    fn report_playback(&self) {
+        self.now_playing_sender.send_replace(self.now_playing.clone());
+        if let Some(webhook_url) = &self.config_values.webhook_url {
+            self.webhook.notify(webhook_url, &self.config_values.player_name, self.now_playing.as_ref());
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(self.now_playing.as_ref());
+        }
+    }
 
-                        // Determine if album name and image link are defined.
-                        let album_name_defined = metadata_pack.album.is_some();
-                        let image_link_defined = active_file_image_link.is_some();
-
-                        if album_name_defined && image_link_defined {
-                            // Both the album and image link are defined. Apply both to Activity.
-                            match discord_client.set_activity(|a| {a.activity_type(ActivityType::Listening)
-                                                                            .status_display(DisplayType::State)
-                                                                            .state(&metadata_pack.artist)
-                                                                            .details(&metadata_pack.title)
-                                                                            .timestamps(|_t| ActivityTimestamps { start: start_time, end: end_time })
-                                                                            .assets(|a| {a.large_image(&active_file_image_link.clone().unwrap())
-                                                                            .large_text(metadata_pack.album.unwrap())}) }) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    error_log::log_error("main: Discord Error on set_activity", e.to_string().as_str());
-                                }
-                            }
-                        } else if album_name_defined && !image_link_defined {
-                            // Album is defined, but image link is None. Use default album image, but still apply album name.
-                            match discord_client.set_activity(|a| a.activity_type(ActivityType::Listening)
-                                                                            .status_display(DisplayType::State)
-                                                                            .state(&metadata_pack.artist)
-                                                                            .details(&metadata_pack.title)
-                                                                            .timestamps(|_t| ActivityTimestamps { start: start_time, end: end_time })
-                                                                            .assets(|a| {a.large_image("no_album_art")
-                                                                            .large_text(metadata_pack.album.unwrap())}) ) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    error_log::log_error("main: Discord Error on set_activity", e.to_string().as_str());
-                                }
-                            }
-                        } else if !album_name_defined && image_link_defined {
-                            // Image link is defined, but album name is None. Apply provided image link, but no album name.
-                            match discord_client.set_activity(|a| a.activity_type(ActivityType::Listening)
-                                                                            .status_display(DisplayType::State)
-                                                                            .state(&metadata_pack.artist)
-                                                                            .details(&metadata_pack.title)
-                                                                            .timestamps(|_t| ActivityTimestamps { start: start_time, end: end_time })
-                                                                            .assets(|a| a.large_image(&active_file_image_link.clone().unwrap()))) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    error_log::log_error("main: Discord Error on set_activity", e.to_string().as_str());
-                                }
-                            }
+    // Carries out a command and replies to it. Commands that wait on other services reply from their own task once done.
+    async fn handle_command(&mut self, command: IpcCommand, reply: oneshot::Sender<String>) {
+        let reply_text = match command {
+            IpcCommand::Status(StatusFormat::Waybar) => status_line::waybar_status(self.now_playing.as_ref()),
+            IpcCommand::Status(StatusFormat::Text) => status_line::text_status(self.now_playing.as_ref()),
+            IpcCommand::Status(StatusFormat::Full) => {
+                format!("player: {}\npresence: {}\ntrack: {}\nart: {}",
+                        self.config_values.player_name,
+                        if self.presence_enabled { "on" } else { "off" },
+                        self.now_playing.as_ref().map(|now_playing| now_playing.description()).unwrap_or(String::from("none")),
+                        self.now_playing.as_ref().and_then(|now_playing| now_playing.image_link.clone()).unwrap_or(String::from("none")))
+            }
+            IpcCommand::Reload => {
+                match load_config() {
+                    Ok(mut new_config_values) => {
+                        let reply = if new_config_values.player_names != self.config_values.player_names {
+                            String::from("Configuration reloaded. Changes to player_name take effect after a restart.")
                         } else {
-                            // Both album and image link are None. Use defauly album image, do not provide album name.
-                            match discord_client.set_activity(|a| a.activity_type(ActivityType::Listening)
-                                                                            .status_display(DisplayType::State)
-                                                                            .state(&metadata_pack.artist)
-                                                                            .details(&metadata_pack.title)
-                                                                            .timestamps(|_t| ActivityTimestamps { start: start_time, end: end_time })
-                                                                            .assets(|a| a.large_image("no_album_art"))) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    error_log::log_error("main: Discord Error on set_activity", e.to_string().as_str());
-                                }
-                            }
+                            String::from("Configuration reloaded.")
+                        };
+                        // Only reopen the play history if record_history was changed.
+                        if new_config_values.record_history != self.history.is_some() {
+                            self.history = open_history(&new_config_values);
                         }
+                        new_config_values.player_name = self.config_values.player_name.clone();
+                        self.config_values = new_config_values;
+                        error_log::configure(self.config_values.error_log_max_kb, self.config_values.error_log_rotations);
+
+                        // Reprocess the active track so changes are applied immediately.
+                        self.process_active_track().await;
+                        reply
                     }
+                    Err(e) => {
+                        error_log::log_error("main:ipc reload Error", e.to_string().as_str());
+                        format!("Error: Configuration could not be reloaded: {}", e)
+                    }
+                }
+            }
+            IpcCommand::Presence(true) => {
+                self.presence_enabled = true;
+                if let Some(dbus_service) = &self.dbus_service {
+                    dbus_service.set_presence_enabled(true);
+                }
+                if self.awaiting_art {
+                    self.show_presence();
+                } else {
+                    self.show_now_playing();
                 }
+                String::from("Presence enabled.")
+            }
+            IpcCommand::Presence(false) => {
+                self.presence_enabled = false;
+                if let Some(dbus_service) = &self.dbus_service {
+                    dbus_service.set_presence_enabled(false);
+                }
+                self.presence.clear();
+                String::from("Presence disabled.")
+            }
+            IpcCommand::Refresh => {
+                self.process_active_track().await;
+                String::from("Presence refreshed.")
+            }
+            IpcCommand::ClearCache => match self.art_sender.try_send(ArtRequest::ClearCache) {
+                Ok(_) => {
+                    self.process_active_track().await;
+                    String::from("Album art cache cleared.")
+                }
+                Err(e) => format!("Album art cache could not be cleared: {}", e),
+            },
+            IpcCommand::PostNowPlaying => return self.post_now_playing(reply),
+            IpcCommand::Announce => return self.announce_now_playing(reply),
+            IpcCommand::Quit => String::from("Exiting."),
+        };
+        let _ = reply.send(reply_text);
+    }
+}
+
+fn exit_with_error(etype: &str, e: &str) -> ! {
+    // Single exit path for errors lamp-drpc cannot recover from.
+    error_log::log_error(etype, e);
+    error_log::flush();
+    process::exit(1);
+}
+
+fn open_history(config_values: &Config) -> Option<History> {
+    // lamp-drpc runs without a play history if it can't be opened.
+    if !config_values.record_history {
+        return None;
+    }
+    match History::open(&history::history_path()) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            error_log::log_error("main:History::open Error", e.to_string().as_str());
+            None
+        }
+    }
+}
 
-                previous_file_path = active_file_path;
+fn run_history_command(args: &[String]) -> ! {
+    // The play history is read directly, so these commands work whether or not lamp-drpc is running.
+    let report = match History::open(&history::history_path()) {
+        Ok(history) => match args {
+            [command] if command == "history" => history::history_report(&history, history::DEFAULT_HISTORY_LENGTH),
+            [command, count] if command == "history" => match count.parse::<usize>() {
+                Ok(count) => history::history_report(&history, count),
+                Err(_) => {
+                    eprintln!("Invalid number of plays \"{}\".", count);
+                    process::exit(2);
+                }
+            },
+            [command] if command == "stats" => history::stats_report(&history),
+            _ => {
+                eprintln!("Usage: lamp-drpc history [count] | lamp-drpc stats");
+                process::exit(2);
             }
-            Ok(None) => (),
-            Err(_) => break,
-        }
-        
-        // Refresh system to get updates to player process
-        sys.refresh_processes_specifics(
-            ProcessesToUpdate::Some(&[player_pid]),
-            true,
-            ProcessRefreshKind::nothing(),
-        );
-
-        // Check player status, exit if None
-        let Some(player_process) = sys.process(Pid::from(player_pid)) else {
+        },
+        Err(e) => Err(e),
+    };
+
+    match report {
+        Ok(report) => {
+            println!("{}", report);
             process::exit(0);
-        };
-        player_status = player_process.status();
+        }
+        Err(e) => {
+            eprintln!("The play history could not be read: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+async fn run_scan_command(args: &[String]) -> ! {
+    // Upload album art for a whole music directory ahead of time, storing the links as lamp-drpc would while playing.
+    let [_, music_dir] = args else {
+        eprintln!("Usage: lamp-drpc scan <directory>");
+        process::exit(2);
+    };
+    let config_values = match load_config() {
+        Ok(config_values) => config_values,
+        Err(e) => {
+            eprintln!("The configuration could not be loaded: {}", e);
+            process::exit(1);
+        }
+    };
+    if config_values.catbox_user_hash.is_none() {
+        eprintln!("catbox_user_hash must be set to upload album art.");
+        process::exit(1);
+    }
+
+    // A running instance keeps its own copy of the stored links, and would overwrite the links added by the scan.
+    if let Ok(runtime_dir) = runtime_dir::resolve() {
+        if ipc::send_command(&ipc::socket_path(&runtime_dir), "status").is_ok() {
+            eprintln!("lamp-drpc is running. Quit it before scanning, so the stored album art links aren't overwritten.");
+            process::exit(1);
+        }
+    }
+
+    if let Err(e) = fs::create_dir_all(paths::state_dir()) {
+        eprintln!("Could not create state directory {}: {}", paths::state_dir().display(), e);
+        process::exit(1);
     }
+    let mut filename_hash = match art::load_hash_file() {
+        Ok(filename_hash) => filename_hash,
+        Err(e) => {
+            eprintln!("The stored album art links could not be loaded: {}", e);
+            process::exit(1);
+        }
+    };
 
-    // Update hash file with all changes on exit.
-    if let Err(e) = write_to_hash_file(&filename_hash) {
-        error_log::log_error("main:write_to_hash_file Error", e.to_string().as_str());
+    let scan_settings = ScanSettings {
+        va_album_individual: config_values.va_album_individual,
+        va_names: config_values.va_names.clone(),
+        artist_delimiters: config_values.artist_delimiters.clone(),
+        folder_art_names: config_values.folder_art_names.clone(),
+        art_sources: art_sources(&config_values),
+        max_art_size: config_values.max_art_kb * 1024,
+        art_encoding: art_encoding(&config_values),
+        catbox_user_hash: config_values.catbox_user_hash.clone(),
+        upload_interval: SCAN_UPLOAD_INTERVAL,
+    };
+    match scan::scan_library(Path::new(music_dir), &Catbox::new(), &mut filename_hash, &scan_settings).await {
+        Ok(scan_summary) => {
+            println!("Scanned {} files: {} albums uploaded, {} already stored, {} failed.", scan_summary.files, scan_summary.uploaded, scan_summary.stored, scan_summary.failed);
+            error_log::flush();
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{} could not be scanned: {}", music_dir, e);
+            error_log::flush();
+            process::exit(1);
+        }
     }
-    let _ = discord_client.shutdown();
 }
 
-fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    // Attempt to locate home directory and specify config directory.
-    let config_dir_path: String = match env::home_dir() {
-        Some(path) => path.to_str().unwrap().to_owned() + "/.config/lamp-drpc",
-        None => {
-            eprintln!("main:load_config:home_dir Error: Could not find home directory.");
+fn run_status_line(args: &[String]) -> ! {
+    // Print the active track for status bars until the bar exits, whether or not lamp-drpc is running.
+    let status_format = match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        [_] | [_, "waybar"] => StatusFormat::Waybar,
+        [_, "text"] => StatusFormat::Text,
+        _ => {
+            eprintln!("Usage: lamp-drpc --status-line [waybar|text]");
+            process::exit(2);
+        }
+    };
+
+    match runtime_dir::resolve() {
+        Ok(runtime_dir) => status_line::run(ipc::socket_path(&runtime_dir), status_format),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn run_cmus_status() -> ! {
+    // Run by cmus as its status_display_program. cmus shows the output of the program, so nothing is printed, even when
+    // lamp-drpc isn't running.
+    if let Ok(runtime_dir) = runtime_dir::resolve() {
+        let _ = player::notify_cmus_status(&runtime_dir);
+    }
+    process::exit(0);
+}
+
+fn run_client_command(args: &[String]) -> ! {
+    // Send the provided command to the running instance over the control socket.
+    let command_line = args.join(" ");
+    if IpcCommand::parse(&command_line).is_none() {
+        eprintln!("Unknown command \"{}\". {}", command_line, ipc::COMMAND_USAGE);
+        process::exit(2);
+    }
+
+    let runtime_dir = match runtime_dir::resolve() {
+        Ok(runtime_dir) => runtime_dir,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    match ipc::send_command(&ipc::socket_path(&runtime_dir), &command_line) {
+        Ok(reply) => {
+            println!("{}", reply);
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
             process::exit(1);
         }
+    }
+}
+
+fn take_path_options(args: &mut Vec<String>) -> Result<(Option<PathBuf>, Option<PathBuf>), String> {
+    // Removes --config <file> and --state-dir <directory> from the arguments, returning their values.
+    let (mut config_file, mut state_dir) = (None, None);
+    let mut index = 0;
+    while index < args.len() {
+        let option = match args[index].as_str() {
+            "--config" => &mut config_file,
+            "--state-dir" => &mut state_dir,
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+        if index + 1 >= args.len() {
+            return Err(format!("Missing value for option {}.", args[index]));
+        }
+        *option = Some(PathBuf::from(args.remove(index + 1)));
+        args.remove(index);
+    }
+    Ok((config_file, state_dir))
+}
+
+fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
+    // Locate the config file and the directory containing it.
+    let config_file_path = paths::config_file();
+    let config_dir_path = match config_file_path.parent() {
+        Some(config_dir_path) if !config_dir_path.as_os_str().is_empty() => config_dir_path,
+        _ => Path::new("."),
     };
 
     // Determine if config directory exists and is a directory.
-    match fs::exists(&config_dir_path) {
+    match fs::exists(config_dir_path) {
         // Config directory exists and is a directory, do nothing.
-        Ok(true) if Path::new(&config_dir_path.as_str()).is_dir() => (),
+        Ok(true) if config_dir_path.is_dir() => (),
         Ok(true) => { 
             // File exists at config directory path, but is not a directory.
-            eprintln!("main:load_config:exists(&config_dir_path) => Ok(true) Error: File at config directory path \"{}\" is not a directory.", config_dir_path);
-            process::exit(1);
+            return Err(Box::from(format!("File at config directory path \"{}\" is not a directory.", config_dir_path.display())));
         },
         Ok(false) => {
             // Config directory does not exist, create it now.
-            match fs::create_dir_all(&config_dir_path) {
-                Ok(_) => {},
-                Err(e) =>  {
-                    eprintln!("main:load_config:exists(&config_dir_path):create_dir_all(&config_dir_path) Error: {}", e);
-                    process::exit(1);
-                },
-            }
+            fs::create_dir_all(config_dir_path)?;
         },
-        Err(e) => { 
-            eprintln!("main:load_config:exists(&config_dir_path) Error: {}", e); 
-            process::exit(1); 
-        }
+        Err(e) => return Err(Box::from(e)),
     }
     
     // Check for configuration file. If it exists, read it. Otherwise, create with default values.
-    let config_file_path = config_dir_path + "/lamp.toml";
-    match fs::exists(&config_file_path) {
+    match fs::exists(config_file_path) {
         Ok(true) => {
             // Config file exists, read in values.
             let toml_string = fs::read_to_string(config_file_path)?;
-            match toml::from_str(toml_string.as_str()) {
-                Ok(config_values) => return Ok(config_values),
+            match parse_config(toml_string.as_str()) {
+                Ok(config_values) => Ok(config_values),
                 Err(e) => {
-                    return Err(Box::from(e));
+                    Err(Box::from(e))
                 }
             }
         },
@@ -406,264 +1553,887 @@ fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
                                 .read(false)
                                 .write(true)
                                 .create(true)
+                                .truncate(true)
                                 .open(config_file_path)?;
             
             /* 
                 Set default configuration values.
-                - player_name is the name of the process to be tracked while running. Default is 'cmus'.
+                - player_name is the name of the process to be tracked while running, or a list of players in order of priority,
+                  in which case whichever is running is followed. 'auto' follows any supported player. Default is 'auto'.
+                - process_name is the name of the player's process, when it differs from player_name (e.g. 'strawberry-qt6').
+                  It only applies to a single player; several players are given one in their [players.<player_name>] tables.
+                  It is optional.
                 - player_check_delay becomes the amount of time in seconds to sleep before checking for 
                   the player running when the program starts to allow music player to initialize.
                   Default is 5.
                 - run_secondary_checks determines whether or not player-specific secondary verification of status
                   should be performed. Default is true.
                 - va_album_individual indidcates whether or not tracks with "Various Artists" as the album artist and album name
-                  should have their album fields blank and album art processed individually. Default is false.
+                  should have their album fields blank and album art processed individually. Tracks with the compilation flag
+                  set are treated as having "Various Artists" as the album artist. Default is false.
+                - va_names is the list of names, such as 'VA' or 'Varios Artistas', that va_album_individual treats as
+                  "Various Artists" in album artist and album fields. Names are compared case-insensitively.
+                  Default is ['Various Artists'].
+                - artist_delimiters is the list of delimiters, such as '; ' or ' feat. ', that artist tags holding several artists
+                  are split on. Default is no delimiters.
+                - folder_art_names is the list of images, in order of preference, looked for in a track's directory when it has
+                  no embedded album art. Default is ['cover.jpg', 'cover.png', 'folder.jpg', 'folder.png', 'front.jpg', 'front.png'].
+                - cover_art_archive_lookup determines whether the front cover of tracks with no album art of their own is fetched
+                  from the Cover Art Archive, for tracks tagged with a MusicBrainz release ID. Default is true.
+                - lastfm_api_key is used to look up the album art of tracks with no album art of their own on Last.fm, by their
+                  album artist and album. It is optional, and nothing is looked up on Last.fm without it.
+                - itunes_lookup determines whether tracks with no album art of their own are searched for on the iTunes Store,
+                  by their album artist and album. Default is false.
+                - deezer_lookup determines whether tracks with no album art of their own are searched for on Deezer, by their
+                  album artist and album. Default is false.
+                - rehost_remote_art determines whether album art found online or linked by the player is uploaded to catbox.moe,
+                  rather than shown on Discord from its own HTTPS link. Default is false.
+                - art_sources is the ordered list of sources album art is taken from, out of 'embedded', 'folder', 'caa', 'lastfm',
+                  'itunes', and 'deezer'. Default is every source in that order, with the online sources enabled by their own keys.
                 - catbox_user_hash is used to upload images to the image host, catbox.moe. It is optional for minimum functionality.
+                - error_log_max_kb is the size in kilobytes at which lamp-error.log is rotated. Default is 1024.
+                - error_log_rotations is the number of rotated error logs to keep. Default is 3.
+                - hash_flush_minutes is how often, in minutes, unsaved album art links are written to the hash file. Default is 5.
+                - art_link_verify_hours is how long, in hours, a stored album art link is used without checking that it is still
+                  available. 0 checks the link on every track change. Default is 24.
+                - art_link_limit is the number of album art links kept in the hash file, beyond which the least recently used are removed.
+                  0 keeps every link. Default is 10000.
+                - metrics_port enables a localhost HTTP endpoint serving /metrics and /healthz on the given port. It is optional.
+                - spawn_player determines whether the player should be launched if it is not running at startup. Default is false.
+                - player_command is the command run through sh to launch the player when spawn_player is enabled. Defaults to player_name.
+                - max_art_kb is the size in kilobytes above which embedded album art is skipped. Default is 8192.
+                - art_jpeg_quality transcodes all album art to JPEG at the given quality, from 1 to 100, before it is uploaded.
+                  It is optional, and album art keeps its own format without it.
+                - art_jpeg_max_kb is the size in kilobytes that transcoded album art is kept under by lowering its JPEG quality.
+                  It is optional, and only used with art_jpeg_quality.
+                - art_fit determines how non-square album art is made square, either 'crop' to keep its center or 'pad' to keep
+                  the whole image on a background. Default is 'crop'.
+                - art_pad_color is the hex color, such as '#1e1e1e', that padded album art is placed on. It is optional, and
+                  padded album art is placed on a blurred copy of itself without it.
+                - record_history determines whether played tracks are recorded in the local play history. Default is false.
+                - webhook_url is the HTTP endpoint that now-playing updates are posted to as JSON. It is optional.
+                - mqtt_host is the MQTT broker that the playback state is published to. It is optional.
+                - mqtt_port is the port of the MQTT broker. Default is 1883.
+                - mqtt_topic is the topic the playback state is published to as a retained message. Default is 'lamp-drpc/now-playing'.
+                - mqtt_username and mqtt_password are used to log in to the MQTT broker. Both are optional.
+                - overlay_title_file, overlay_artist_file, and overlay_album_file are text files the active track's tags are written to,
+                  and overlay_cover_file is the file its resized album art is written to. All are optional.
+                - now_playing_port enables a localhost HTTP endpoint serving the active track as JSON at /now-playing. It is optional.
+                - now_playing_widget determines whether an HTML widget showing the track is also served at /. Default is false.
+                - mastodon_instance and mastodon_token are the Mastodon instance URL and access token used by the post-now-playing command.
+                  Both are optional.
+                - mastodon_template is the text of statuses posted to Mastodon, with {artist}, {title}, {album}, {genre}, {year}, {label},
+                  {rating}, and {original_year} placeholders. Default is '#nowplaying {artist} - {title}'.
+                - export_mpris determines whether the active track is exported on D-Bus as an MPRIS player. Default is false.
+                - synced_lyrics determines whether the current lyric line is shown in place of the artist, using the track's .lrc file
+                  or LRCLIB. Default is false.
+                - lrclib_lookup determines whether lyrics are fetched from LRCLIB for tracks without a .lrc file. Default is true.
+                - discord_webhook_url is the Discord channel webhook that tracks are announced to by the announce command. It is optional.
+                - announce_albums determines whether a track is also announced whenever a new album starts. Default is false.
+                - odesli_lookup determines whether each track's song.link page is looked up and added to the presence as a button
+                  and to webhook payloads. Default is false.
+                - track_debounce_ms is how long, in milliseconds, a new track must play before it is processed and shown. Default is 500.
+                - position_resync_seconds is how far, in seconds, the player's position may drift from the shown timestamps before
+                  they are corrected. 0 disables corrections. Changes require a restart. Default is 3.
+                - cmus_socket_path is the socket cmus is checked for and queried on when player_name is 'cmus'. Defaults to
+                  $CMUS_SOCKET, or cmus-socket in the runtime directory.
+                - cmus_status_display sets cmus's status_display_program to one that tells lamp-drpc about status changes, so
+                  track changes are shown right away rather than on the next poll. It replaces any other status_display_program.
+                  Default is false.
+                - mpd_address is the host and port MPD is queried on when player_name is 'mpd' or 'mopidy'. Default is 'localhost:6600'.
+                - mpd_music_dir is MPD's music directory (or Mopidy's media directory), which the paths it reports are relative to.
+                  Defaults to ~/Music.
+                - mpd_password is the password sent to MPD, if it requires one. It is optional.
+                - mpris_bus_name limits the players followed when player_name is 'mpris' to those whose bus name starts with
+                  org.mpris.MediaPlayer2.<mpris_bus_name>. When player_name is a player read through MPRIS, such as 'spotifyd'
+                  or 'firefox', it replaces the player's default bus name. It is optional.
+                - mpv_socket_path is the IPC socket mpv is queried on when player_name is 'mpv'. Defaults to mpv-socket in the
+                  runtime directory.
+                - musikcube_address is the host and port of musikcube's WebSocket server when player_name is 'musikcube'.
+                  Default is 'localhost:7905'.
+                - musikcube_password is the password of musikcube's server. Defaults to no password.
+                - foobar2000_address is the host and port of the beefweb plugin's HTTP server when player_name is 'foobar2000'.
+                  Default is 'localhost:8880'.
+                - musicbee_address is the host and port of the MusicBee Remote plugin's server when player_name is 'musicbee'.
+                  Default is 'localhost:3000'.
+                - status_command is the command run through sh (cmd on Windows) on every poll when player_name is 'command', which prints the
+                  player's state as JSON. It is required for 'command'.
+                - command_process_name is the process name of the player followed when player_name is 'command' or 'file'. It
+                  is required for both.
+                - now_playing_file is the file read when player_name is 'file', holding the active file's path on its first
+                  line and, optionally, its duration in seconds on its second. It is required for 'file'.
+                - players holds a table of options for each player, e.g. [players.mopidy] with address = 'localhost:6601'.
+                  socket_path, address, password, music_dir, bus_name, status_display, status_command, process_name, and
+                  now_playing_file replace the top-level keys of the same purpose for that player only. It is optional, and
+                  must come after the other settings, since its entries are tables.
+                - plugins is a table of the commands that start plugins, by player name, e.g. foobar = 'lamp-foobar'. A plugin
+                  follows a player without built-in support, and is used when player_name (or one of several) is its name.
+                  It is optional, and must come after the other settings, since it is a table.
+                - rewrites is a table of regular expressions and their replacements, applied in order to the artist, title,
+                  and album of every track, e.g. '\s*\(\d{4} Remaster\)' = ''. It is optional, and must come after the other
+                  settings, since it is a table.
             */ 
-            write!(config_file,"player_name = \'cmus\'\n\
-                                player_check_delay = 5\n\
-                                run_secondary_checks = true\n\
-                                va_album_individual = false\n")?;
-
-            let config_values = Config {
-                player_name: String::from("cmus"),
-                player_check_delay: 5,
-                run_secondary_checks: true,
-                va_album_individual: true,
-                catbox_user_hash: None,
-            };
+            let default_config = "player_name = \'auto\'\n\
+                                  player_check_delay = 5\n\
+                                  run_secondary_checks = true\n\
+                                  va_album_individual = false\n";
+            write!(config_file, "{}", default_config)?;
+
+            // Optional keys not included in the default file are filled in with their default values.
+            let config_values: Config = parse_config(default_config)?;
 
-            return Ok(config_values);
+            Ok(config_values)
         },
         Err(e) => { 
-            return Err(Box::from(e));
+            Err(Box::from(e))
         }
     }
 }
 
-async fn get_link_status(http_client: &reqwest::Client, image_link: &String) -> Result<bool, Box<dyn std::error::Error>> {
-    let response = http_client
-        .head(image_link)
-        .header(USER_AGENT, env!("CARGO_PKG_VERSION"))
-        .send()
-        .await?;
-    if response.status() == reqwest::StatusCode::OK { Ok(true) } else { Ok(false) }
-}
+/*
+ *  [PLAYER IMPLEMENTATION HERE]
+ *  Players followed when player_name is 'auto', in order of priority. Players that need to be configured first, such as
+ *  'command', and browsers, which usually play things other than music, are left out. The generic MPRIS player comes
+ *  last, so it only follows players that aren't supported directly.
+ */
+#[cfg(unix)]
+const AUTO_PLAYERS: [&str; 15] = ["cmus", "mpd", "mopidy", "mpv", "rhythmbox", "strawberry", "clementine", "spotifyd", "ncspot",
+                                  "musikcube", "musikcubed", "xmms2", "foobar2000", "musicbee", "mpris"];
+// Windows has no MPRIS, and cmus only runs on UNIX-based systems.
+#[cfg(windows)]
+const AUTO_PLAYERS: [&str; 6] = ["foobar2000", "musicbee", "mpd", "mpv", "musikcube", "musikcubed"];
 
-fn load_hash_file() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    // Check for hashed link file. If it exists, read it, otherwise create blank one.
-    let config_dir_path: String; 
-    match env::home_dir() {
-        Some(path) => {
-            config_dir_path = path.to_str().unwrap().to_owned() + "/.config/lamp-drpc";
+/*
+ *  [PLAYER IMPLEMENTATION HERE]
+ *  This is where the player is selected and the matching MusicPlayer implementation is assigned. 
+ *  The value of player_name read from the config file should match the player's process name, 
+ *  as it will be used to find the PID and keep tabs on its status.
+ */
+fn create_player(player_name: &str, config_values: &Config, runtime_dir: &Path) -> MusicPlayer {
+    // Plugins are checked first, so a plugin may replace a supported player.
+    if let Some(plugin_command) = config_values.plugins.get(player_name) {
+        return MusicPlayer::Plugin(Plugin::new(player_name, plugin_command.clone()));
+    }
+    // Options from the player's own table take the place of the top-level keys.
+    let player_settings = config_values.players.get(player_name).cloned().unwrap_or_default();
+    let bus_name = player_settings.bus_name.or(config_values.mpris_bus_name.clone());
+    let process_name = configured_process_name(config_values, player_name).or(config_values.command_process_name.clone());
+    match player_name {
+        #[cfg(unix)]
+        "cmus" => {
+            // cmus creates its socket at $CMUS_SOCKET, or in the user's runtime directory.
+            let socket_path = player_settings.socket_path.or(config_values.cmus_socket_path.clone())
+                                                         .or_else(|| env::var_os("CMUS_SOCKET").filter(|socket_path| !socket_path.is_empty()).map(PathBuf::from))
+                                                         .unwrap_or(runtime_dir.join("cmus-socket"));
+            MusicPlayer::Cmus(Cmus::new(runtime_dir, socket_path, player_settings.status_display.unwrap_or(config_values.cmus_status_display)))
+        }
+        "mpd" | "mopidy" => {
+            // MPD's music directory defaults to the user's Music directory.
+            let music_dir = player_settings.music_dir.or(config_values.mpd_music_dir.clone()).unwrap_or_else(|| env::home_dir().unwrap_or_default().join("Music"));
+            let address = player_settings.address.unwrap_or(config_values.mpd_address.clone());
+            MusicPlayer::Mpd(Mpd::new(address, music_dir, player_settings.password.or(config_values.mpd_password.clone()), player_name == "mopidy"))
+        }
+        "mpris" => MusicPlayer::Mpris(Mpris::new(bus_name)),
+        "rhythmbox" => MusicPlayer::Rhythmbox(Rhythmbox::new()),
+        "strawberry" | "clementine" => MusicPlayer::Strawberry(Strawberry::new(player_name)),
+        "spotifyd" | "ncspot" => MusicPlayer::Spotifyd(Spotifyd::new(player_name, bus_name)),
+        "musikcube" | "musikcubed" => {
+            let address = player_settings.address.unwrap_or(config_values.musikcube_address.clone());
+            MusicPlayer::Musikcube(Musikcube::new(address, player_settings.password.or(config_values.musikcube_password.clone())))
+        }
+        "youtube-music" | "ytmdesktop" | "firefox" | "chromium" | "chrome" => MusicPlayer::Browser(Browser::new(player_name, bus_name)),
+        "xmms2" | "xmms2d" => MusicPlayer::Xmms2(Xmms2::new()),
+        "foobar2000" => MusicPlayer::Foobar2000(Foobar2000::new(player_settings.address.unwrap_or(config_values.foobar2000_address.clone()))),
+        "musicbee" => MusicPlayer::MusicBee(MusicBee::new(player_settings.address.unwrap_or(config_values.musicbee_address.clone()))),
+        // Listed with other players, the sound server is only followed while something is playing, so the others are still found.
+        "pipewire" | "pulseaudio" => MusicPlayer::AudioStreams(AudioStreams::new(player_name, config_values.player_names.len() > 1)),
+        "command" => match (player_settings.status_command.or(config_values.status_command.clone()), process_name) {
+            (Some(status_command), Some(process_name)) => MusicPlayer::ExternalCommand(ExternalCommand::new(status_command, process_name)),
+            _ => exit_with_error("main: active_music_player match Error", "The player_name \"command\" requires status_command and command_process_name to be set in the lamp.toml configuration file, or in its [players.command] table."),
         },
-        None => {
-            eprintln!("Error: Could not find home directory.");
-            process::exit(1);
+        "file" => match (player_settings.now_playing_file.or(config_values.now_playing_file.clone()), process_name) {
+            (Some(now_playing_file), Some(process_name)) => MusicPlayer::NowPlayingFile(NowPlayingFile::new(now_playing_file, process_name)),
+            _ => exit_with_error("main: active_music_player match Error", "The player_name \"file\" requires now_playing_file and command_process_name to be set in the lamp.toml configuration file, or in its [players.file] table."),
         },
+        "mpv" => MusicPlayer::Mpv(Mpv::new(player_settings.socket_path.or(config_values.mpv_socket_path.clone()).unwrap_or(runtime_dir.join("mpv-socket")))),
+//      "player_process_name" => MusicPlayer::NewPlayer(NewPlayer::default()),
+        _ => exit_with_error("main: active_music_player match Error", format!("The player_name \"{}\" provided in the lamp.toml configuration file is unsupported.", player_name).as_str()),
     }
+}
 
-    let hash_file_path = config_dir_path + "/albumart_hash.json";
-    let mut filename_hash = HashMap::<String, String>::new();
+// The process name configured for a player, from its [players.<player_name>] table, or from process_name when it is
+// the only player.
+fn configured_process_name(config_values: &Config, player_name: &str) -> Option<String> {
+    config_values.players.get(player_name)
+                         .and_then(|player_settings| player_settings.process_name.clone())
+                         .or_else(|| config_values.process_name.clone().filter(|_| config_values.player_names.len() == 1))
+}
 
-    match fs::exists(&hash_file_path) {
-        Ok(true) => {
-            // Read existing hash file.
-            let hash_file = File::open(hash_file_path)?;
+// Finds the first of the players that is running, in order of priority, returning its index along with its PID and the
+// name of its process. Players whose secondary checks fail, such as mpv without an IPC socket, are skipped, since they
+// can't be followed.
+fn find_running_player(players: &[(String, MusicPlayer, Option<String>)], sys: &mut System) -> Option<(usize, sysinfo::Pid, String)> {
+    players.iter().enumerate().find_map(|(player_index, (player_name, player, process_name))| {
+        let player_process_name = process_name.clone().or_else(|| player.get_process_name()).unwrap_or(player_name.clone());
+        let player_pid = player::get_pid_by_proc_name(sys, &player_process_name).ok()?;
+        matches!(player.verify_running(), Ok(true)).then_some((player_index, player_pid, player_process_name))
+    })
+}
 
-            let hash_reader = BufReader::new(hash_file);
-            filename_hash = serde_json::from_reader(hash_reader)?;
+// Follows whichever of several players is running, until the event loop exits. Players earlier in player_names are
+// preferred: while a player is followed, the players before it are searched every PLAYER_SEARCH_INTERVAL, and the
+// first of them that has started is followed instead. While no player is running, every player is searched as often.
+fn follow_players(mut players: Vec<(String, MusicPlayer, Option<String>)>, resync_seconds: u64, event_sender: mpsc::UnboundedSender<Event>) {
+    let mut sys = System::new();
+    while !event_sender.is_closed() {
+        let Some((player_index, player_pid, player_process_name)) = find_running_player(&players, &mut sys) else {
+            wait_unless_closed(&event_sender, PLAYER_SEARCH_INTERVAL);
+            continue;
+        };
+        let (preferred_players, other_players) = players.split_at_mut(player_index);
+        let (player_name, player, _) = &mut other_players[0];
+        let _ = event_sender.send(Event::PlayerStarted(player_name.clone()));
+        let mut search_time = Instant::now();
+        player::poll_player_until(player, System::new(), player_pid, &player_process_name, resync_seconds, event_sender.clone(), || {
+            if search_time.elapsed() < PLAYER_SEARCH_INTERVAL {
+                return false;
+            }
+            search_time = Instant::now();
+            find_running_player(preferred_players, &mut sys).is_some()
+        });
+    }
+}
 
-        },
-        Ok(false) => {
-            // Create new hash file.
-            let mut hash_file = fs::OpenOptions::new()
-                                        .read(false)
-                                        .write(true)
-                                        .create(true)
-                                        .open(&hash_file_path)?;
+// Blocks the player thread for the timeout, returning as soon as the event loop exits so shutdown isn't held up.
+fn wait_unless_closed(event_sender: &mpsc::UnboundedSender<Event>, timeout: Duration) {
+    let _ = tokio::runtime::Handle::current().block_on(tokio::time::timeout(timeout, event_sender.closed()));
+}
 
-            write!(hash_file, "{{\n}}")?;
+// Until a player is followed, the first of player_names is used as the player's name.
+// player_name = 'auto' follows whichever of AUTO_PLAYERS is running.
+fn parse_config(toml_string: &str) -> Result<Config, toml::de::Error> {
+    let mut config_values: Config = toml::from_str(toml_string)?;
+    if config_values.player_names == ["auto"] {
+        config_values.player_names = AUTO_PLAYERS.map(String::from).to_vec();
+    }
+    config_values.player_name = config_values.player_names[0].clone();
+    Ok(config_values)
+}
+
+// Returns the PID of the launched player along with the name of its process.
+async fn launch_player(config_values: &Config, active_music_player: &MusicPlayer, sys: &mut System, startup_delay: Duration) -> Result<(sysinfo::Pid, String), PlayerError> {
+    // The command is run through sh (cmd on Windows), so it can start the player in a terminal or a detached session.
+    // Without player_command, the player is started by its process name.
+    let player_command = config_values.player_command.as_ref().unwrap_or(&config_values.player_name);
+    let mut player_child = player::shell_command(player_command)
+                                        .stdin(process::Stdio::null())
+                                        .stdout(process::Stdio::null())
+                                        .stderr(process::Stdio::null())
+                                        .spawn()?;
+
+    // Wait on the command in the background, so it isn't left as a zombie process once it exits.
+    std::thread::spawn(move || player_child.wait());
+
+    // Check for the player process once per second until it appears.
+    let launch_time = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let player_process_name = configured_process_name(config_values, &config_values.player_name).or_else(|| active_music_player.get_process_name())
+                                                                                                   .unwrap_or(config_values.player_name.clone());
+        if let Ok(player_pid) = player::get_pid_by_proc_name(sys, &player_process_name) {
+            // Give the newly launched player player_check_delay seconds to initialize, as at a normal startup.
+            tokio::time::sleep(startup_delay).await;
+            return Ok((player_pid, player_process_name));
         }
-        Err(e) => {
-            return Err(Box::from(e));
+        if launch_time.elapsed() >= PLAYER_LAUNCH_TIMEOUT {
+            return Err(PlayerError::LaunchTimedOut { player_name: config_values.player_name.clone(), seconds: PLAYER_LAUNCH_TIMEOUT.as_secs(), command: player_command.clone() });
         }
     }
-
-    Ok(filename_hash)
 }
 
-fn write_to_hash_file(filename_hash: &HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
-    // Check for hashed link file. If it exists, read it, otherwise create blank one.
-    let config_dir_path: String = match env::home_dir() {
-        Some(path) => path.to_str().unwrap().to_owned() + "/.config/lamp-drpc",
-        None => {
-            eprintln!("main:load_config:home_dir Error: Could not find home directory.");
-            process::exit(1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{fixture_path, use_test_state_dir};
+    #[cfg(unix)]
+    use crate::mock::FakeDiscord;
+    use crate::presence::PresenceUpdate;
+    use crate::lyrics::LyricsRequest;
+    use crate::radio::RadioRequest;
+
+    const TEST_CONFIG: &str = "player_name = 'cmus'\n\
+                               player_check_delay = 0\n\
+                               run_secondary_checks = false\n\
+                               va_album_individual = false\n\
+                               track_debounce_ms = 0\n";
+
+    // Creates an event loop without a player or Discord client, returning the receivers for its presence updates and art requests.
+    fn test_event_loop(catbox_user_hash: Option<&str>) -> (EventLoop, mpsc::UnboundedReceiver<PresenceUpdate>, mpsc::Receiver<ArtRequest>) {
+        let (presence, presence_receiver) = PresenceService::mock();
+        let (event_loop, art_receiver) = event_loop_with_presence(presence, catbox_user_hash);
+        (event_loop, presence_receiver, art_receiver)
+    }
+
+    fn event_loop_with_presence(presence: PresenceService, catbox_user_hash: Option<&str>) -> (EventLoop, mpsc::Receiver<ArtRequest>) {
+        use_test_state_dir();
+        let mut config_values: Config = parse_config(TEST_CONFIG).unwrap();
+        config_values.catbox_user_hash = catbox_user_hash.map(String::from);
+
+        let (art_sender, art_receiver) = mpsc::channel::<ArtRequest>(4);
+        let event_loop = EventLoop {
+            config_values,
+            presence,
+            art_sender,
+            dbus_service: None,
+            presence_enabled: true,
+            paused: false,
+            active_track: None,
+            active_track_id: 0,
+            now_playing: None,
+            awaiting_art: false,
+            history: None,
+            webhook: WebhookService::mock().0,
+            mqtt: None,
+            overlay: OverlayService::start().0,
+            now_playing_sender: watch::channel::<Option<NowPlaying>>(None).0,
+            mpris: None,
+            cover_path: None,
+            lyrics: LyricsService::mock().0,
+            lyric_line: None,
+            announced_album: None,
+            links: LinkService::mock().0,
+            radio: RadioService::mock().0,
+            event_sender: mpsc::unbounded_channel::<Event>().0,
+            pending_track: None,
+            track_change_id: 0,
+        };
+        (event_loop, art_receiver)
+    }
+
+    fn track_changed(fixture_name: &str) -> Event {
+        Event::TrackChanged(Track { file_path: fixture_path(fixture_name), start_time: Some(1000), end_time: Some(1180), metadata: None, tags: PlayerTags::default() })
+    }
+
+    fn shown_track(presence_receiver: &mut mpsc::UnboundedReceiver<PresenceUpdate>) -> NowPlaying {
+        match presence_receiver.try_recv() {
+            Ok(PresenceUpdate::Show(now_playing)) => now_playing,
+            _ => panic!("Expected the track to be shown."),
         }
-    };
+    }
 
-    let hash_file_path = config_dir_path + "/albumart_hash.json";
-    match fs::exists(&hash_file_path) {
-        Ok(_) => {
-            // If hash file exists, overwrite contents with current hash map.
-            // If it does not exist, create it again and write to it.
-            let mut hash_file = fs::OpenOptions::new()
-                                        .read(false)
-                                        .write(true)
-                                        .truncate(true)
-                                        .create(true)
-                                        .open(&hash_file_path)?;
-            
-            let hash_string = serde_json::to_string_pretty(&filename_hash)?;
-            write!(hash_file, "{}", hash_string)?;
-        },
-        Err(e) => {
-            return Err(Box::from(e));
+    async fn send_command(event_loop: &mut EventLoop, command: IpcCommand) -> bool {
+        let (reply, _reply_receiver) = oneshot::channel::<String>();
+        event_loop.handle_event(Event::Command(ipc::IpcRequest { command, reply })).await
+    }
+
+    #[tokio::test]
+    async fn shows_track_immediately_without_catbox_user_hash() {
+        let (mut event_loop, mut presence_receiver, mut art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!(now_playing.artist, "Test Artist");
+        assert_eq!(now_playing.title, "Test Title");
+        assert_eq!(now_playing.album.as_deref(), Some("Test Album"));
+        assert_eq!(now_playing.image_link, None);
+        assert_eq!((now_playing.start_time, now_playing.end_time), (Some(1000), Some(1180)));
+        assert!(art_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn shows_the_duration_read_from_the_file() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.opus"), start_time: Some(1000), end_time: None, metadata: None, tags: PlayerTags::default() })).await;
+        assert_eq!(shown_track(&mut presence_receiver).end_time, Some(1215));
+
+        event_loop.handle_event(Event::PositionChanged { start_time: 1100, end_time: None }).await;
+        assert_eq!(shown_track(&mut presence_receiver).end_time, Some(1315));
+    }
+
+    #[tokio::test]
+    async fn shows_titles_of_radio_streams() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        let (radio, mut radio_receiver) = RadioService::mock();
+        event_loop.radio = radio;
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: String::from("http://radio.example/stream"), start_time: Some(1000), end_time: None, metadata: None, tags: PlayerTags::default() })).await;
+        assert!(matches!(radio_receiver.try_recv(), Ok(RadioRequest::Stop)));
+        assert!(matches!(radio_receiver.try_recv(), Ok(RadioRequest::Follow { track_id: 1, url }) if url == "http://radio.example/stream"));
+        assert!(presence_receiver.try_recv().is_err());
+
+        let metadata = PlayerMetadata { artist: String::from("Test Artist"), title: String::from("Test Title"), album: Some(String::from("Test Radio")), art_url: None };
+        event_loop.handle_event(Event::StreamMetadata { track_id: 0, metadata: metadata.clone() }).await;
+        assert!(presence_receiver.try_recv().is_err());
+        event_loop.handle_event(Event::StreamMetadata { track_id: 1, metadata }).await;
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!((now_playing.artist.as_str(), now_playing.title.as_str(), now_playing.album.as_deref()), ("Test Artist", "Test Title", Some("Test Radio")));
+        assert_eq!((now_playing.start_time, now_playing.end_time), (Some(1000), None));
+    }
+
+    #[tokio::test]
+    async fn shows_metadata_reported_by_the_player() {
+        let (mut event_loop, mut presence_receiver, mut art_receiver) = test_event_loop(Some("user-hash"));
+        let player_metadata = PlayerMetadata {
+            artist: String::from("Stream Artist"),
+            title: String::from("Stream Title"),
+            album: None,
+            art_url: Some(String::from("https://images.example/stream.jpg")),
+        };
+        let track = Track { file_path: String::from("https://open.example/track/1"), start_time: Some(1000), end_time: Some(1200), metadata: Some(player_metadata), tags: PlayerTags::default() };
+        event_loop.handle_event(Event::TrackChanged(track.clone())).await;
+
+        // The album art link is shown directly, without uploading anything.
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!((now_playing.artist.as_str(), now_playing.title.as_str()), ("Stream Artist", "Stream Title"));
+        assert_eq!(now_playing.image_link.as_deref(), Some("https://images.example/stream.jpg"));
+        assert!(art_receiver.try_recv().is_err());
+
+        // Once rehosting is forced, the album art is uploaded instead.
+        event_loop.config_values.rehost_remote_art = true;
+        event_loop.handle_event(Event::TrackChanged(track)).await;
+        let Ok(ArtRequest::Rehost { image_link, query, .. }) = art_receiver.try_recv() else {
+            panic!("The player's album art was not rehosted.");
+        };
+        assert_eq!((image_link.as_str(), query.artist.as_str()), ("https://images.example/stream.jpg", "Stream Artist"));
+        assert_eq!(shown_track(&mut presence_receiver).image_link, None);
+    }
+
+    #[tokio::test]
+    async fn shows_track_while_album_art_is_processed() {
+        let (mut event_loop, mut presence_receiver, mut art_receiver) = test_event_loop(Some("user-hash"));
+        let (webhook, mut webhook_receiver) = WebhookService::mock();
+        event_loop.webhook = webhook;
+        event_loop.config_values.webhook_url = Some(String::from("http://127.0.0.1/now-playing"));
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+
+        assert!(matches!(art_receiver.try_recv(), Ok(ArtRequest::Process { track_id: 1, .. })));
+        assert_eq!(shown_track(&mut presence_receiver).image_link, None);
+        assert!(webhook_receiver.try_recv().is_err());
+
+        // The presence is never cleared, only shown again with the album art once it is ready.
+        event_loop.handle_event(Event::ArtReady { track_id: 1, image_link: Some(String::from("https://images.example/art.jpg")) }).await;
+        assert_eq!(shown_track(&mut presence_receiver).image_link.as_deref(), Some("https://images.example/art.jpg"));
+        assert_eq!(webhook_receiver.try_recv().unwrap().payload["art_url"], "https://images.example/art.jpg");
+    }
+
+    #[tokio::test]
+    async fn looks_up_album_art_of_tracks_without_any() {
+        use lofty::tag::{ItemKey, Tag, TagExt, TagType};
+        let (mut event_loop, _presence_receiver, mut art_receiver) = test_event_loop(Some("user-hash"));
+        let track_path = std::env::temp_dir().join(format!("lamp-drpc-release-{}.mp3", std::process::id())).to_string_lossy().into_owned();
+        let mut id3_tag = Tag::new(TagType::Id3v2);
+        id3_tag.insert_text(ItemKey::TrackArtist, String::from("Test Artist"));
+        id3_tag.insert_text(ItemKey::TrackTitle, String::from("Test Title"));
+        id3_tag.insert_text(ItemKey::MusicBrainzReleaseId, String::from("0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a"));
+        let mut id3_data = Vec::new();
+        id3_tag.dump_to(&mut id3_data, lofty::config::WriteOptions::default()).unwrap();
+        fs::write(&track_path, id3_data).unwrap();
+
+        let track = Track { file_path: track_path.clone(), start_time: Some(1000), end_time: Some(1180), metadata: None, tags: PlayerTags::default() };
+        event_loop.handle_event(Event::TrackChanged(track.clone())).await;
+        let Ok(ArtRequest::Lookup { track_id: 1, query, .. }) = art_receiver.try_recv() else {
+            panic!("The track's album art was not looked up.");
+        };
+        assert_eq!(query.release_id.as_deref(), Some("0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a"));
+        assert_eq!(query.artist, "Test Artist");
+
+        // Tracks without a release ID are looked up on Last.fm by their album, once there is an API key.
+        event_loop.config_values.lastfm_api_key = Some(String::from("test-key"));
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.wav"), ..track.clone() })).await;
+        let Ok(ArtRequest::Lookup { track_id: 2, query, .. }) = art_receiver.try_recv() else {
+            panic!("The track's album art was not looked up on Last.fm.");
+        };
+        assert_eq!((query.release_id, query.lastfm_api_key.as_deref()), (None, Some("test-key")));
+        event_loop.config_values.lastfm_api_key = None;
+        event_loop.config_values.itunes_lookup = true;
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.wav"), ..track.clone() })).await;
+        let Ok(ArtRequest::Lookup { track_id: 3, query, .. }) = art_receiver.try_recv() else {
+            panic!("The track's album art was not looked up on the iTunes Store.");
+        };
+        assert_eq!(query.sources, vec![ArtSource::Caa, ArtSource::Lastfm, ArtSource::Itunes]);
+        event_loop.config_values.itunes_lookup = false;
+        event_loop.config_values.deezer_lookup = true;
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.wav"), ..track.clone() })).await;
+        let Ok(ArtRequest::Lookup { track_id: 4, query, .. }) = art_receiver.try_recv() else {
+            panic!("The track's album art was not looked up on Deezer.");
+        };
+        assert_eq!(query.sources, vec![ArtSource::Caa, ArtSource::Lastfm, ArtSource::Deezer]);
+        event_loop.config_values.deezer_lookup = false;
+
+        // Nothing is looked up once lookups are disabled, or for tracks without a release ID.
+        event_loop.config_values.cover_art_archive_lookup = false;
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.wav"), ..track.clone() })).await;
+        event_loop.handle_event(Event::TrackChanged(track)).await;
+        assert!(art_receiver.try_recv().is_err());
+        fs::remove_file(&track_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn takes_album_art_from_sources_in_order() {
+        let (mut event_loop, _presence_receiver, mut art_receiver) = test_event_loop(Some("user-hash"));
+        let track = Track { file_path: fixture_path("tagged.mp3"), start_time: Some(1000), end_time: Some(1180), metadata: None, tags: PlayerTags::default() };
+
+        // Sources listed before the embedded album art are looked up first, falling back to the embedded album art.
+        event_loop.config_values.art_sources = Some(vec![ArtSource::Itunes, ArtSource::Embedded, ArtSource::Deezer]);
+        event_loop.handle_event(Event::TrackChanged(track.clone())).await;
+        let Ok(ArtRequest::Lookup { track_id: 1, query, fallback_art: Some(_), .. }) = art_receiver.try_recv() else {
+            panic!("The track's album art was not looked up before its embedded album art.");
+        };
+        assert_eq!(query.sources, vec![ArtSource::Itunes]);
+
+        // Sources listed after it, or not at all, are never used.
+        event_loop.config_values.art_sources = Some(vec![ArtSource::Embedded, ArtSource::Deezer]);
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.flac"), ..track.clone() })).await;
+        assert!(matches!(art_receiver.try_recv(), Ok(ArtRequest::Process { track_id: 2, .. })));
+        event_loop.config_values.art_sources = Some(vec![ArtSource::Caa]);
+        event_loop.handle_event(Event::TrackChanged(track.clone())).await;
+        assert!(art_receiver.try_recv().is_err());
+
+        // Without a catbox user hash, tracks are still looked up for links shown directly, but the embedded album art
+        // can't be uploaded as a fallback. Nothing is looked up if every link would have to be rehosted.
+        event_loop.config_values.catbox_user_hash = None;
+        event_loop.config_values.art_sources = Some(vec![ArtSource::Itunes, ArtSource::Embedded]);
+        event_loop.handle_event(Event::TrackChanged(track.clone())).await;
+        assert!(matches!(art_receiver.try_recv(), Ok(ArtRequest::Lookup { track_id: 4, fallback_art: None, catbox_user_hash: None, .. })));
+        event_loop.config_values.rehost_remote_art = true;
+        event_loop.handle_event(Event::TrackChanged(track)).await;
+        assert!(art_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn only_shows_tracks_once_they_settle() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        event_loop.event_sender = event_sender;
+        event_loop.config_values.track_debounce_ms = 50;
+
+        // The first track is skipped before it settles, so only the second is shown.
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        event_loop.handle_event(track_changed("tagged.flac")).await;
+        assert!(presence_receiver.try_recv().is_err());
+
+        for _ in 0..2 {
+            let settled_event = event_receiver.recv().await.unwrap();
+            event_loop.handle_event(settled_event).await;
         }
+        assert_eq!(shown_track(&mut presence_receiver).artist, "First Artist, Second Artist");
+        assert!(presence_receiver.try_recv().is_err());
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn shows_paused_tracks_without_timestamps() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        shown_track(&mut presence_receiver);
+
+        event_loop.handle_event(Event::Paused).await;
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!((now_playing.title.as_str(), now_playing.start_time, now_playing.end_time), ("Test Title", None, None));
+        // The track keeps its timestamps for the play history.
+        assert_eq!(event_loop.active_track.as_ref().unwrap().start_time, Some(1000));
+
+        event_loop.handle_event(Event::PositionChanged { start_time: 1100, end_time: Some(1280) }).await;
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!((now_playing.start_time, now_playing.end_time), (Some(1100), Some(1280)));
+    }
 
-fn get_pid_by_proc_name(sys: &System, proc_name: &String) -> sysinfo::Pid {
-    if let Some(possible_process) = sys.processes_by_exact_name(proc_name.as_ref()).next() {
-        return possible_process.pid();
-    } else {
-        error_log::log_error("main:get_pid_by_proc_name Error", format!("The PID of target player {} could not be determined. The player may not be running or may have a different process name than provided in the configuration file.", proc_name).as_str());
-        process::exit(1);
+    #[tokio::test]
+    async fn resyncs_timestamps_of_shown_track() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        shown_track(&mut presence_receiver);
+
+        event_loop.handle_event(Event::PositionChanged { start_time: 900, end_time: Some(1080) }).await;
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!((now_playing.title.as_str(), now_playing.start_time, now_playing.end_time), ("Test Title", Some(900), Some(1080)));
+        assert_eq!(event_loop.active_track.unwrap().start_time, Some(900));
     }
-}
 
-fn get_status_by_pid(sys: &System, player_pid: &sysinfo::Pid) -> ProcessStatus {
-    if let Some(player_process) = sys.process(*player_pid) {
-        return player_process.status();
-    } else {
-        error_log::log_error("main:get_status_by_pid Error", "The target PID could not be found. The player may no longer be running.");
-        process::exit(1);
+    #[tokio::test]
+    async fn discards_album_art_for_previous_tracks() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(Some("user-hash"));
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        event_loop.handle_event(track_changed("tagged.flac")).await;
+        shown_track(&mut presence_receiver);
+        shown_track(&mut presence_receiver);
+
+        event_loop.handle_event(Event::ArtReady { track_id: 1, image_link: Some(String::from("https://images.example/old.jpg")) }).await;
+        assert!(presence_receiver.try_recv().is_err());
+
+        event_loop.handle_event(Event::ArtReady { track_id: 2, image_link: Some(String::from("https://images.example/new.png")) }).await;
+        let now_playing = shown_track(&mut presence_receiver);
+        assert_eq!(now_playing.artist, "First Artist, Second Artist");
+        assert_eq!(now_playing.image_link.as_deref(), Some("https://images.example/new.png"));
     }
-}
 
-async fn write_album_art(album_art: AlbumArt, catbox_user_hash: &Option<String>) -> Result<(String, String), Box<dyn std::error::Error>> {
-    // Determine format of image to write.
-    let mut reader: ImageReader<Cursor<Vec<u8>>>;
-    let (hash_filename, mime_type): (&str, &str);
-
-    if let Some(split_filename) = album_art.filename.rsplit_once('.') {
-        hash_filename = split_filename.0;
-        mime_type = split_filename.1;
-    } else {
-        return Err(Box::from("Splitting filename failed, mime type of embedded image could not be determined."));
-    }
-
-    match mime_type {
-        "jpg" | "jpeg" => {
-            reader = ImageReader::new(Cursor::new(album_art.data));
-            reader.set_format(ImageFormat::Jpeg);
-        }
-        "png" => {
-            reader = ImageReader::new(Cursor::new(album_art.data));
-            reader.set_format(ImageFormat::Png);
-        }
-        &_ => return Err(Box::from(format!("Mime type {} is not supported.", mime_type).as_str())),
-    } 
-
-    // Decode image and get dimensions.
-    let img = reader.decode()?;
-    let dimensions = (img.width(), img.height());
-
-    // Determine new image dimensions based on current dimensions. 
-    // If the image is already a square between 512x512 and 1024x1024, no cropping is necessary.
-    let (dst_width, dst_height): (u32, u32);
-    let mut dst_image: Image<'_>;
-
-    // Image is already square (equal dimensions).
-    if dimensions.0 == dimensions.1 {
-        if dimensions.0 < 512 {
-            (dst_width, dst_height) = (512, 512);
-        } else if dimensions.0 > 1024 {
-            (dst_width, dst_height) = (1024, 1024);
-        } else {
-            (dst_width, dst_height) = (dimensions.0, dimensions.1);
-        }
-
-        match img.pixel_type() {
-            Some(pt) => dst_image = Image::new(dst_width, dst_height, pt),
-            None => return Err(Box::from("Pixel type of image could not be determined.")),
-        }
-
-        // Resize image with no cropping.
-        Resizer::new().resize(&img, &mut dst_image, None)?;
-    } else {
-        // Image is not already square.
-        // Determine which dimension is smaller.
-        let smaller_dimension: u32;
-        if dimensions.0 > dimensions.1 {
-            smaller_dimension = dimensions.1;
-        } else {
-            smaller_dimension = dimensions.0;
-        }
-
-        // Smaller dimension is between 512 and 1024.
-        // Set both dimensions to the smaller value.
-        if 512 < smaller_dimension && smaller_dimension < 1024 {
-            (dst_width, dst_height) = (smaller_dimension, smaller_dimension);
-        }
-        // Smaller dimension is greater than 1024.
-        // Set both dimensions to 1024.
-        else if 1024 < smaller_dimension {
-            (dst_width, dst_height) = (1024, 1024);
-        }
-        // Smaller dimension is less than 512.
-        // Set both dimensions to 512.
-        else {
-            (dst_width, dst_height) = (512, 512);
-        }
-
-        match img.pixel_type() {
-            Some(pt) => dst_image = Image::new(dst_width, dst_height, pt),
-            None => return Err(Box::from("Pixel type of image could not be determined.")),
-        }
-
-        // Resize image with cropping.
-        Resizer::new().resize(&img, &mut dst_image, &ResizeOptions::new().fit_into_destination(Some((0.5,0.5))),)?;
-    }
-
-    // Create file at temporary directory.
-    let tempfile_path = format!("{}/{}.{}", env::temp_dir().to_string_lossy(), hash_filename, mime_type);
-    let tempfile = File::create(&tempfile_path)?;
-    let mut result_buf = BufWriter::new(tempfile);
-
-    // Decide on image encoder to use based on mime type and write image to temp file.
-    match mime_type {
-        "jpg" | "jpeg" => JpegEncoder::new(&mut result_buf)
-            .write_image(
-            dst_image.buffer(),
-                dst_width,
-                dst_height,
-    img.color().into(),)?,
-        "png" => PngEncoder::new(&mut result_buf)
-            .write_image(
-            dst_image.buffer(),
-                dst_width,
-                dst_height,
-    img.color().into(),)?,
-        _ => return Err(Box::from(format!("Mime type {} is not supported.", mime_type).as_str())),
+    #[tokio::test]
+    async fn clears_presence_when_player_stops() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        shown_track(&mut presence_receiver);
+
+        event_loop.handle_event(Event::Stopped).await;
+        assert!(matches!(presence_receiver.try_recv(), Ok(PresenceUpdate::Clear)));
+        assert!(event_loop.now_playing.is_none());
+    }
+
+    #[tokio::test]
+    async fn shows_lyric_lines_for_active_track() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        let (lyrics, mut lyrics_receiver) = LyricsService::mock();
+        event_loop.lyrics = lyrics;
+        event_loop.config_values.synced_lyrics = true;
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        shown_track(&mut presence_receiver);
+        assert!(matches!(lyrics_receiver.try_recv(), Ok(LyricsRequest::Stop)));
+        assert!(matches!(lyrics_receiver.try_recv(), Ok(LyricsRequest::Follow { track_id: 1, .. })));
+
+        event_loop.handle_event(Event::LyricLine { track_id: 1, line: Some(String::from("First line")) }).await;
+        match presence_receiver.try_recv() {
+            Ok(PresenceUpdate::ShowLyric(now_playing, lyric_line)) => assert_eq!((now_playing.title.as_str(), lyric_line.as_str()), ("Test Title", "First line")),
+            _ => panic!("Expected the lyric line to be shown."),
+        }
+
+        // Lines for previous tracks are discarded, and the artist is shown again during instrumental breaks.
+        event_loop.handle_event(Event::LyricLine { track_id: 0, line: Some(String::from("Old line")) }).await;
+        assert!(presence_receiver.try_recv().is_err());
+        event_loop.handle_event(Event::LyricLine { track_id: 1, line: None }).await;
+        shown_track(&mut presence_receiver);
     }
-    
-    // Ensure all image data is written to temp file before proceeding.
-    result_buf.flush()?;
 
-    // Upload file to image host.
-    let uploaded_link = upload_image(&tempfile_path, catbox_user_hash.clone()).await?;
+    #[tokio::test]
+    async fn announces_each_new_album_once() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        event_loop.config_values.discord_webhook_url = Some(format!("http://{}/api/webhooks/1/token", listener.local_addr().unwrap()));
+        event_loop.config_values.announce_albums = true;
 
-    // Delete file from temp directory.
-    remove_file(tempfile_path)?;
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        let request = crate::mock::receive_http_request(&listener, "204 No Content", "").await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&request.body).unwrap()["embeds"][0]["title"], "Test Title");
 
-    Ok((album_art.filename, uploaded_link))
-}
+        // Further tracks from the same album are not announced.
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert!(tokio::time::timeout(Duration::from_millis(200), listener.accept()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn adds_song_link_once_resolved() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        let (links, mut link_receiver) = LinkService::mock();
+        event_loop.links = links;
+        event_loop.config_values.odesli_lookup = true;
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert_eq!(shown_track(&mut presence_receiver).link, None);
+        let link_request = link_receiver.try_recv().unwrap();
+        assert_eq!((link_request.track_id, link_request.artist.as_str(), link_request.title.as_str()), (1, "Test Artist", "Test Title"));
+
+        event_loop.handle_event(Event::LinkReady { track_id: 0, link: Some(String::from("https://song.link/i/1")) }).await;
+        assert!(presence_receiver.try_recv().is_err());
+        event_loop.handle_event(Event::LinkReady { track_id: 1, link: Some(String::from("https://song.link/i/1234")) }).await;
+        assert_eq!(shown_track(&mut presence_receiver).link.as_deref(), Some("https://song.link/i/1234"));
+    }
+
+    #[tokio::test]
+    async fn does_not_show_tracks_without_required_tags() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("no_artist.mp3")).await;
+
+        assert!(presence_receiver.try_recv().is_err());
+        assert!(event_loop.now_playing.is_none());
+    }
+
+    #[tokio::test]
+    async fn hides_tracks_while_presence_is_disabled() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        send_command(&mut event_loop, IpcCommand::Presence(false)).await;
+        assert!(matches!(presence_receiver.try_recv(), Ok(PresenceUpdate::Clear)));
+
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert!(presence_receiver.try_recv().is_err());
+
+        send_command(&mut event_loop, IpcCommand::Presence(true)).await;
+        assert_eq!(shown_track(&mut presence_receiver).title, "Test Title");
+    }
+
+    #[tokio::test]
+    async fn stops_when_player_exits_or_quit_is_requested() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        assert!(!event_loop.handle_event(Event::PlayerExited).await);
+        assert!(!send_command(&mut event_loop, IpcCommand::Quit).await);
+    }
+
+    #[tokio::test]
+    async fn keeps_running_when_one_of_several_players_exits() {
+        let (mut event_loop, mut presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.config_values = parse_config(&TEST_CONFIG.replace("player_name = 'cmus'", "player_name = ['cmus', 'mpd']")).unwrap();
+        assert_eq!((event_loop.config_values.player_names.len(), event_loop.config_values.player_name.as_str()), (2, "cmus"));
+
+        event_loop.handle_event(Event::PlayerStarted(String::from("mpd"))).await;
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert_eq!(shown_track(&mut presence_receiver).title, "Test Title");
+        assert!(event_loop.handle_event(Event::PlayerExited).await);
+        assert!(event_loop.now_playing.is_none());
+        assert_eq!(event_loop.config_values.player_name, "mpd");
+
+        assert!(parse_config(&TEST_CONFIG.replace("player_name = 'cmus'", "player_name = []")).is_err());
+    }
+
+    #[tokio::test]
+    async fn stops_searching_for_players_once_the_event_loop_exits() {
+        let (event_sender, event_receiver) = mpsc::unbounded_channel::<Event>();
+        let search_start = Instant::now();
+        let search_task = tokio::task::spawn_blocking(move || wait_unless_closed(&event_sender, Duration::from_secs(60)));
+        drop(event_receiver);
+        search_task.await.unwrap();
+        assert!(search_start.elapsed() < PLAYER_SEARCH_INTERVAL);
+    }
+
+    #[test]
+    fn reads_options_of_each_player() {
+        let config_values = parse_config(&format!("{}mpd_address = 'localhost:6600'\n[players.mopidy]\naddress = 'localhost:6601'\nbus_name = 'mopidy'\n", TEST_CONFIG)).unwrap();
+        assert_eq!(config_values.mpd_address, "localhost:6600");
+        assert_eq!(config_values.players["mopidy"].address.as_deref(), Some("localhost:6601"));
+        assert!(!config_values.players.contains_key("mpd"));
 
-async fn upload_image(image_path: &String, catbox_user_hash: Option<String>) -> Result<String, Box<dyn std::error::Error>> {
-    let uploaded = from_file(image_path, catbox_user_hash.as_ref()).await?;
-    Ok(uploaded)
-}
\ No newline at end of file
+        // Misspelled options are reported rather than ignored.
+        assert!(parse_config(&format!("{}[players.mpd]\nadress = 'localhost:6601'\n", TEST_CONFIG)).is_err());
+    }
+
+    #[test]
+    fn reads_album_art_encoding() {
+        assert_eq!(art_encoding(&parse_config(TEST_CONFIG).unwrap()), ArtEncoding::default());
+
+        let config_values = parse_config(&format!("{}art_jpeg_quality = 0\nart_jpeg_max_kb = 200\nart_fit = 'pad'\nart_pad_color = '#1E1e0a'\n", TEST_CONFIG)).unwrap();
+        assert_eq!(art_encoding(&config_values), ArtEncoding { jpeg_quality: Some(1), jpeg_max_kb: Some(200), fit: ArtFit::Pad, pad_color: Some([0x1e, 0x1e, 0x0a]) });
+
+        // The leading '#' may be left out, but colors must have exactly six hex digits.
+        assert_eq!(parse_config(&format!("{}art_pad_color = '1e1e1e'\n", TEST_CONFIG)).unwrap().art_pad_color, Some([0x1e; 3]));
+        for pad_color in ["#1e1e", "#1e1e1e1e", "#1e1e1g", "#1é1e1"] {
+            assert!(parse_config(&format!("{}art_pad_color = '{}'\n", TEST_CONFIG, pad_color)).is_err());
+        }
+    }
+
+    #[test]
+    fn finds_players_by_their_configured_process_name() {
+        let config_values = parse_config(&TEST_CONFIG.replace("player_name = 'cmus'", "player_name = 'strawberry'\nprocess_name = 'strawberry-qt6'")).unwrap();
+        assert_eq!(configured_process_name(&config_values, "strawberry").as_deref(), Some("strawberry-qt6"));
+
+        // With several players, only their own tables are used.
+        let config_values = parse_config(&format!("{}[players.mpd]\nprocess_name = 'mpd-wrapper'\n", TEST_CONFIG.replace("player_name = 'cmus'", "player_name = ['cmus', 'mpd']\nprocess_name = 'ignored'"))).unwrap();
+        assert_eq!(configured_process_name(&config_values, "mpd").as_deref(), Some("mpd-wrapper"));
+        assert_eq!(configured_process_name(&config_values, "cmus"), None);
+    }
+
+    #[test]
+    fn auto_follows_every_supported_player() {
+        let config_values = parse_config(&TEST_CONFIG.replace("player_name = 'cmus'", "player_name = 'auto'")).unwrap();
+        assert_eq!(config_values.player_names, AUTO_PLAYERS.map(String::from).to_vec());
+        assert_eq!(config_values.player_name, "cmus");
+    }
+
+    #[tokio::test]
+    async fn posts_tracks_to_webhook_when_configured() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        let (webhook, mut webhook_receiver) = WebhookService::mock();
+        event_loop.webhook = webhook;
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert!(webhook_receiver.try_recv().is_err());
+
+        event_loop.config_values.webhook_url = Some(String::from("http://127.0.0.1/now-playing"));
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        let webhook_post = webhook_receiver.try_recv().unwrap();
+        assert_eq!(webhook_post.webhook_url, "http://127.0.0.1/now-playing");
+        assert_eq!(webhook_post.payload["event"], "playing");
+        assert_eq!(webhook_post.payload["title"], "Test Title");
+
+        event_loop.handle_event(Event::Stopped).await;
+        assert_eq!(webhook_receiver.try_recv().unwrap().payload, serde_json::json!({ "event": "stopped", "player": "cmus" }));
+    }
+
+    #[tokio::test]
+    async fn publishes_playback_state_to_mqtt() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        let (mqtt, mut mqtt_receiver) = MqttService::mock("cmus");
+        event_loop.mqtt = Some(mqtt);
+
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+        assert_eq!(mqtt_receiver.try_recv().unwrap()["artist"], "Test Artist");
+
+        assert!(!event_loop.handle_event(Event::PlayerExited).await);
+        assert_eq!(mqtt_receiver.try_recv().unwrap()["event"], "stopped");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_announce_without_discord_webhook_url() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+
+        let (reply, mut reply_receiver) = oneshot::channel::<String>();
+        event_loop.handle_command(IpcCommand::Announce, reply).await;
+        assert_eq!(reply_receiver.try_recv().unwrap(), "Error: discord_webhook_url must be set to announce tracks.");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_post_to_mastodon_without_configuration() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        event_loop.handle_event(track_changed("tagged.mp3")).await;
+
+        let (reply, mut reply_receiver) = oneshot::channel::<String>();
+        assert!(event_loop.handle_event(Event::Command(ipc::IpcRequest { command: IpcCommand::PostNowPlaying, reply })).await);
+        assert_eq!(reply_receiver.try_recv().unwrap(), "Error: mastodon_instance and mastodon_token must be set to post to Mastodon.");
+
+        // The command is answered by handle_command itself, whichever way it reaches the event loop.
+        let (reply, mut reply_receiver) = oneshot::channel::<String>();
+        event_loop.handle_command(IpcCommand::PostNowPlaying, reply).await;
+        assert_eq!(reply_receiver.try_recv().unwrap(), "Error: mastodon_instance and mastodon_token must be set to post to Mastodon.");
+        assert_eq!(event_loop.now_playing.unwrap().format("{artist} - {title} ({album})"), "Test Artist - Test Title (Test Album)");
+    }
+
+    #[tokio::test]
+    async fn shows_tags_reported_by_the_player() {
+        let (mut event_loop, _presence_receiver, _art_receiver) = test_event_loop(None);
+        let tags = PlayerTags { rating: Some(4), original_year: Some(String::from("1994")) };
+        event_loop.handle_event(Event::TrackChanged(Track { file_path: fixture_path("tagged.mp3"), start_time: Some(1000), end_time: Some(1180), metadata: None, tags })).await;
+
+        let now_playing = event_loop.now_playing.unwrap();
+        assert_eq!(now_playing.format("{title} ({original_year}) {rating}/5"), "Test Title (1994) 4/5");
+        assert_eq!(events::playback_payload("strawberry", Some(&now_playing))["rating"], 4);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shows_tracks_on_discord() {
+        let fake_discord = FakeDiscord::start();
+        let (presence, presence_task) = PresenceService::start(1);
+        let (mut event_loop, _art_receiver) = event_loop_with_presence(presence, Some("user-hash"));
+        fake_discord.wait_until_ready();
+
+        event_loop.handle_event(track_changed("tagged.flac")).await;
+        event_loop.handle_event(Event::ArtReady { track_id: 1, image_link: Some(String::from("https://images.example/art.png")) }).await;
+
+        let activities = fake_discord.wait_for_activities(2);
+        drop(event_loop);
+        presence_task.await.unwrap();
+
+        // The activity is shown with the default image while album art is uploaded, then with the album art once it is ready.
+        assert_eq!(activities, vec![
+            Some(serde_json::json!({
+                "type": 2,
+                "status_display_type": 1,
+                "state": "First Artist, Second Artist",
+                "details": "Test Title",
+                "timestamps": { "start": 1000, "end": 1180 },
+                "assets": { "large_image": "no_album_art", "large_text": "Test Album" },
+            })),
+            Some(serde_json::json!({
+                "type": 2,
+                "status_display_type": 1,
+                "state": "First Artist, Second Artist",
+                "details": "Test Title",
+                "timestamps": { "start": 1000, "end": 1180 },
+                "assets": { "large_image": "https://images.example/art.png", "large_text": "Test Album" },
+            })),
+        ]);
+    }
+}
@@ -0,0 +1,59 @@
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde_json::{json, Value};
+
+use crate::http;
+use crate::ipc::COMMAND_REQUEST_TIMEOUT;
+
+/*
+ *  Optional Mastodon integration, enabled by setting mastodon_instance and mastodon_token in lamp.toml.
+ *
+ *  Statuses are only posted on request, through the post-now-playing command, never automatically for each track.
+ *  The access token needs the write:statuses scope.
+ */
+pub async fn post_status(instance_url: &str, access_token: &str, status: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let response = http::send(|client| client.post(format!("{}/api/v1/statuses", instance_url.trim_end_matches('/')))
+                                             .header(AUTHORIZATION, format!("Bearer {}", access_token))
+                                             .header(CONTENT_TYPE, "application/json")
+                                             .body(json!({ "status": status }).to_string())
+                                             .timeout(COMMAND_REQUEST_TIMEOUT)).await?;
+
+    // Mastodon describes failures in the error field of the response.
+    let status_code = response.status();
+    let response_json = serde_json::from_str::<Value>(&response.text().await?).unwrap_or(Value::Null);
+    if !status_code.is_success() {
+        let error = response_json["error"].as_str().map(String::from).unwrap_or(status_code.to_string());
+        return Err(Box::from(format!("The status could not be posted: {}", error)));
+    }
+
+    // Returns the link to the posted status.
+    match response_json["url"].as_str() {
+        Some(status_url) => Ok(String::from(status_url)),
+        None => Err(Box::from("The status was posted, but its link was missing from the response.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    #[tokio::test]
+    async fn posts_status_with_access_token() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let instance_url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let post_task = tokio::spawn(async move { post_status(&instance_url, "test-token", "#nowplaying Test Artist - Test Title").await.map_err(|e| e.to_string()) });
+        let request = receive_http_request(&listener, "200 OK", r#"{"url": "https://mastodon.example/@lamp/1"}"#).await;
+        assert_eq!(post_task.await.unwrap(), Ok(String::from("https://mastodon.example/@lamp/1")));
+
+        assert_eq!(request.request_line, "POST /api/v1/statuses HTTP/1.1");
+        assert_eq!(request.header("authorization"), Some("Bearer test-token"));
+        assert_eq!(serde_json::from_str::<Value>(&request.body).unwrap(), json!({ "status": "#nowplaying Test Artist - Test Title" }));
+
+        let instance_url = format!("http://{}", listener.local_addr().unwrap());
+        let post_task = tokio::spawn(async move { post_status(&instance_url, "bad-token", "Status").await.map_err(|e| e.to_string()) });
+        receive_http_request(&listener, "401 Unauthorized", r#"{"error": "The access token is invalid"}"#).await;
+        assert_eq!(post_task.await.unwrap(), Err(String::from("The status could not be posted: The access token is invalid")));
+    }
+}
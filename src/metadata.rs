@@ -1,232 +1,803 @@
-use audiotags::components::FlacTag;
-use audiotags::{AudioTagEdit, MimeType};
-use claxon::{FlacReader, FlacReaderOptions};
-use id3::{Content, Tag, TagLike};
+use image::ImageReader;
+use lofty::ape::{ApeFile, APE_PICTURE_TYPES};
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+use lofty::picture::Picture;
+use lofty::probe::Probe;
+use lofty::tag::{ItemKey, ItemValue, Tag, TagExt, TagType};
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
+use crate::art_lookup::ArtSource;
+use crate::error::MetadataError;
 use crate::error_log;
 
+#[derive(Clone)]
 pub struct AlbumArt {
     pub filename: String,
     pub data: Vec<u8>,
 }
 
+#[derive(Default)]
 pub struct MetadataPackage {
     pub album_artist: Option<String>,
     pub album: Option<String>,
     pub artist: String,
     pub title: String,
     pub album_art: Option<AlbumArt>,
+    pub album_art_source: Option<ArtSource>, // Where album_art was taken from, either the file or its directory.
+    pub duration: Option<u64>, // In seconds, for players that don't report the duration. Read from the audio properties.
+    pub release_id: Option<String>, // The MusicBrainz release ID, which album art is keyed by when present.
+    pub genre: Option<String>,
+    pub year: Option<String>, // The release year, e.g. "1997", taken from the start of full release dates.
+    pub label: Option<String>,
 }
 
-impl Default for MetadataPackage {
-    fn default() -> Self {
-        MetadataPackage {
-            album_artist: None,
-            album: None,
-            artist: String::new(),
-            title: String::new(),
-            album_art: None,
+// Global CRC32 hasher for album art filename hashing
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+
+// Files that can't be shown are returned as errors for the caller to log. Problems with album art alone only drop the
+// album art, and are logged here.
+pub fn read_metadata(active_file_path: &String, va_album_individual: &bool, va_names: &[String], artist_delimiters: &[String], folder_art_names: &[String], art_sources: &[ArtSource], max_art_size: u64) -> Result<MetadataPackage, MetadataError> {
+    // Determine which tag reader to use based on file extension. Files without an extension are unsupported.
+    let mut metadata_pack = match active_file_path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("flac" | "ogg" | "oga" | "opus" | "ape" | "mp3" | "wav") => read_tagged_file(active_file_path, va_album_individual, va_names, artist_delimiters, max_art_size),
+        Some("dsf" | "dff") => read_dsd(active_file_path, va_album_individual, va_names, artist_delimiters, max_art_size),
+        Some("mod" | "xm" | "it" | "s3m") => read_module(active_file_path),
+        _ => return Err(MetadataError::UnsupportedFormat(active_file_path.clone())),
+    }?;
+
+    // Album art is taken from the first of the local sources in art_sources that has it. The track's directory is only
+    // searched if it comes before the embedded album art, or the file has none.
+    let mut embedded_art = metadata_pack.album_art.take();
+    for art_source in art_sources {
+        let album_art = match art_source {
+            ArtSource::Embedded => embedded_art.take(),
+            ArtSource::Folder => read_folder_art(&metadata_pack, folder_art_names, max_art_size, active_file_path),
+            _ => None,
+        };
+        if album_art.is_some() {
+            (metadata_pack.album_art, metadata_pack.album_art_source) = (album_art, Some(*art_source));
+            break;
         }
     }
+    Ok(metadata_pack)
 }
 
-// Global CRC32 hasher for album art filename hashing
-const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+// Whether the file has an extension read_metadata can read tags from.
+pub fn is_supported(file_path: &str) -> bool {
+    matches!(file_path.rsplit_once('.').map(|(_, extension)| extension), Some("flac" | "ogg" | "oga" | "opus" | "ape" | "dsf" | "dff" | "mod" | "xm" | "it" | "s3m" | "mp3" | "wav"))
+}
 
-pub fn read_metadata(active_file_path: &String, va_album_individual: &bool) -> Option<MetadataPackage> {
-    // Determine which tag reader to used based on file extension.
-    match active_file_path.rsplit_once('.').unwrap().1 {
-        "flac" => return read_vorbis(&active_file_path, &va_album_individual),
-        "mp3" | "wav" => return read_id3(&active_file_path, &va_album_individual),
+// Album art larger than max_art_size bytes is skipped before it is copied out of the tag or decoded. The tag itself
+// still holds it while the file is read.
+fn art_within_limit(image_data: &[u8], max_art_size: u64, active_file_path: &String) -> bool {
+    if image_data.len() as u64 > max_art_size {
+        error_log::log_error("metadata:art_within_limit Warning", format!("Album art in file {} is {} KB, larger than the max_art_kb limit of {} KB, and was skipped.", active_file_path, image_data.len() / 1024, max_art_size / 1024).as_str());
+        return false;
+    }
+    true
+}
+
+// Finds the first of folder_art_names in the track's directory, matching file names case-insensitively, so cover.jpg also
+// finds Cover.JPG. The year shown is used for hashing its filename, since the year tags of each format aren't kept.
+fn read_folder_art(metadata_pack: &MetadataPackage, folder_art_names: &[String], max_art_size: u64, active_file_path: &str) -> Option<AlbumArt> {
+    if folder_art_names.is_empty() {
+        return None;
+    }
+    let track_dir = Path::new(active_file_path).parent()?;
+    let dir_paths: Vec<PathBuf> = std::fs::read_dir(track_dir).ok()?.filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path())).collect();
+    let art_path = folder_art_names.iter().find_map(|art_name| {
+        dir_paths.iter().find(|dir_path| dir_path.file_name().is_some_and(|file_name| file_name.to_string_lossy().eq_ignore_ascii_case(art_name)))
+    })?;
+
+    // Images over the limit are read only far enough to be found too large.
+    let art_path = art_path.to_string_lossy().into_owned();
+    let mut image_data = Vec::new();
+    if let Err(e) = File::open(&art_path).and_then(|file| file.take(max_art_size.saturating_add(1)).read_to_end(&mut image_data)) {
+        error_log::log_error("metadata:read_folder_art Error", format!("Album art could not be read from {}: {}", art_path, e).as_str());
+        return None;
+    }
+    package_album_art(metadata_pack, metadata_pack.year.clone(), &image_data, max_art_size, &art_path)
+}
+
+// Chooses the album art from a file's embedded pictures, given as picture type and image data pairs. Pictures are chosen
+// by their type first, and among pictures of the same type, the one with the most pixels is used, so files carrying a
+// thumbnail along with the full cover are shown with the full cover.
+fn choose_picture<'a>(pictures: impl Iterator<Item = (u8, &'a [u8])>) -> Option<&'a [u8]> {
+    pictures.min_by_key(|&(picture_type, image_data)| (picture_type_rank(picture_type), Reverse(image_pixels(image_data))))
+            .map(|(_, image_data)| image_data)
+}
+
+// The number of pixels in the image, read from its header without decoding it. Unreadable images count as empty.
+fn image_pixels(image_data: &[u8]) -> u64 {
+    ImageReader::new(Cursor::new(image_data)).with_guessed_format().ok()
+                                              .and_then(|image_reader| image_reader.into_dimensions().ok())
+                                              .map_or(0, |(width, height)| width as u64 * height as u64)
+}
+
+// Embedded pictures are ranked by their picture type, as numbered by ID3 and FLAC: the front cover first, then pictures
+// of no particular type, the media, the leaflet, and the back cover, then any other picture, such as a band photo. File
+// icons are only used if there is nothing else.
+fn picture_type_rank(picture_type: u8) -> u8 {
+    match picture_type {
+        3 => 0,     // Cover (front)
+        0 => 1,     // Other
+        6 => 2,     // Media
+        5 => 3,     // Leaflet page
+        4 => 4,     // Cover (back)
+        1 | 2 => 6, // File icons
+        _ => 5,
+    }
+}
+
+// Album art from every format is packaged the same way. Its type is found from the image data rather than the mime type
+// declared by the tag, which is sometimes missing or wrong. The image data is only copied once it is known to be used.
+pub fn package_album_art(metadata_pack: &MetadataPackage, album_year: Option<String>, image_data: &[u8], max_art_size: u64, active_file_path: &String) -> Option<AlbumArt> {
+    if !art_within_limit(image_data, max_art_size, active_file_path) {
+        return None;
+    }
+    let extension = match image_data {
+        [0xFF, 0xD8, ..] => ".jpg",
+        [0x89, b'P', b'N', b'G', ..] => ".png",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => ".webp",
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => ".gif",
+        [b'B', b'M', ..] => ".bmp",
         _ => {
-            error_log::log_error("metadata:read_metadata Error", format!("The file at {} is not in a supported format.", active_file_path).as_str());
+            error_log::log_error("metadata:package_album_art Error", format!("Album cover in file {} is not a JPEG, PNG, WebP, GIF, or BMP image.", active_file_path).as_str());
             return None;
         }
-    }
+    };
+    Some(AlbumArt { filename: hash_filename(&metadata_pack.release_id, &metadata_pack.album_artist, &metadata_pack.album, album_year, extension, image_data), data: image_data.to_vec() })
 }
 
-fn hash_filename(album_artist: &Option<String>, album: &Option<String>, year: Option<String>, mime_type: &str, image_data: &Vec<u8>) -> String {
-    // Construct (probably) album-unique string to be hashed as first half of filename.
-    let metadata_string = album_artist.clone().unwrap_or(String::from("0")) 
-                                + album.clone().unwrap_or(String::from("0")).as_str()
-                                + year.clone().unwrap_or(String::from("0")).as_str();
+fn hash_filename(release_id: &Option<String>, album_artist: &Option<String>, album: &Option<String>, year: Option<String>, mime_type: &str, image_data: &[u8]) -> String {
+    // Construct (probably) album-unique string to be hashed as first half of filename. The MusicBrainz release ID is
+    // used when tagged, so rips of the same release share a filename however their other tags are written.
+    let metadata_string = match release_id {
+        Some(release_id) => release_id.to_lowercase(),
+        None => album_artist.clone().unwrap_or(String::from("0")) 
+                        + album.clone().unwrap_or(String::from("0")).as_str()
+                        + year.clone().unwrap_or(String::from("0")).as_str(),
+    };
 
     // Hash metadata string and image bytes for first and second halves of filename, then concat image extension.
-    let hashed_filename = format!("{}-{}{}", CRC32.checksum(metadata_string.as_bytes()).to_string(), CRC32.checksum(image_data).to_string(), mime_type);
-    return hashed_filename;
-}
-
-fn read_vorbis(active_file_path: &String, va_album_individual: &bool) -> Option<MetadataPackage> {
-    match FlacReader::open_ext(&active_file_path, FlacReaderOptions { metadata_only: true, read_vorbis_comment: true }) {
-        Ok(vorbis_tag) => {
-            let mut metadata_pack = MetadataPackage::default();
-
-            // Declare variables for relevant tags.
-            let mut album_tag: Option<String> = None;
-            let mut album_artist_vec = Vec::<String>::new();
-            let mut artist_vec = Vec::<String>::new();
-            let mut title_tag: Option<String> = None;
-            let mut year_tag: Option<String> = None;
-
-            // Get all tags and iterate through them.
-            for tag in vorbis_tag.tags() {
-                match tag.0 {
-                    "album" => album_tag = Some(tag.1.to_owned()),
-                    "albumartist" => album_artist_vec.push(tag.1.to_owned()),
-                    "artist" => artist_vec.push(tag.1.to_owned()),
-                    "title" => title_tag = Some(tag.1.to_owned()),
-                    "year" => year_tag = Some(tag.1.to_owned()),
-                    &_ => (),
-                }
-            }
+    format!("{}-{}{}", CRC32.checksum(metadata_string.as_bytes()), CRC32.checksum(image_data), mime_type)
+}
 
-            // Assign metadata values based on retrieved tags.
-            // album
-            if album_tag.is_some() {
-                metadata_pack.album = album_tag;
-            } else {
-                metadata_pack.album = None;
-            }
+// Reads a file through lofty, which finds its format from its contents. Files whose audio can't be read, such as an MP3
+// file holding only its tag, are read again for their tags alone.
+fn read_tagged_file(active_file_path: &String, va_album_individual: &bool, va_names: &[String], artist_delimiters: &[String], max_art_size: u64) -> Result<MetadataPackage, MetadataError> {
+    let read = |parse_options: ParseOptions| Probe::open(active_file_path).map(|probe| probe.options(parse_options))
+                                                                          .and_then(|probe| Ok(probe.guess_file_type()?))
+                                                                          .and_then(Probe::read);
+    let tagged_file = match read(ParseOptions::new()) {
+        Ok(tagged_file) => tagged_file,
+        Err(e) => read(ParseOptions::new().read_properties(false)).map_err(|_| MetadataError::Unreadable { file_path: active_file_path.clone(), reason: e.to_string() })?,
+    };
 
-            // albumartist
-            if album_artist_vec.len() > 0 {
-                metadata_pack.album_artist = Some(album_artist_vec.join(", "));
+    let mut tag = match primary_tag(&tagged_file) {
+        Some(tag) => tag.clone(),
+        // Files without a tag have no artist to show.
+        None => return Err(MetadataError::MissingTag { file_path: active_file_path.clone(), tag: "artist" }),
+    };
+    if tag.tag_type() == TagType::Ape {
+        ape_pictures(active_file_path).into_iter().for_each(|picture| tag.push_picture(picture));
+    }
+    let mut metadata_pack = package_tag(&tag, active_file_path, va_album_individual, va_names, artist_delimiters, max_art_size)?;
 
-                // If va_album_individual is enabled, album_artist is "Various Artists", and the album is "Various Artists", album tag is not kept.
-                if *va_album_individual && metadata_pack.album_artist == Some(String::from("Various Artists")) 
-                                        && metadata_pack.album == Some(String::from("Various Artists")) {
-                    metadata_pack.album = None;
-                }
-            } else {
-                metadata_pack.album_artist = None; 
-            } 
-
-            // artist (Tag is required for basic functionality, so return None if not present)
-            if artist_vec.len() > 0 {
-                metadata_pack.artist = artist_vec.join(", ");
-            } else {
-                error_log::log_error("metadata:read_vorbis Warning", format!("No artist tag(s) were found in file {}.", active_file_path).as_str());
-                return None;
-            }
+    // duration (Left unknown when only the tag could be read)
+    metadata_pack.duration = Some(tagged_file.properties().duration().as_secs()).filter(|&duration| duration > 0);
+    Ok(metadata_pack)
+}
 
-            // title (Tag is required for basic functionality, so return None if not present)
-            if let Some(title) = title_tag {
-                metadata_pack.title = title;
-            } else {
-                error_log::log_error("metadata:read_vorbis Warning", format!("No title tag(s) were found in file {}.", active_file_path).as_str());
-                return None;
-            }
+// The tag read from each format, where files may hold several. MP3 files are read from their ID3v2 tag over an APEv2 or
+// ID3v1 tag, and WAV files from their ID3 chunk over their RIFF INFO chunk, which most other software writes. Empty tags,
+// which some taggers leave behind, are passed over.
+fn primary_tag(tagged_file: &TaggedFile) -> Option<&Tag> {
+    let tag_types: &[TagType] = match tagged_file.file_type() {
+        FileType::Flac | FileType::Vorbis | FileType::Opus => &[TagType::VorbisComments],
+        FileType::Ape => &[TagType::Ape],
+        FileType::Mpeg => &[TagType::Id3v2, TagType::Ape, TagType::Id3v1],
+        FileType::Wav => &[TagType::Id3v2, TagType::RiffInfo],
+        _ => &[],
+    };
+    tag_types.iter().filter_map(|&tag_type| tagged_file.tag(tag_type)).find(|tag| !tag.is_empty())
+}
 
-            // year (Used only for constructing filename hash, not included in metadata package.)
-            let album_year: Option<String> = year_tag;
-
-            // album_art
-            match FlacTag::read_from_path(&active_file_path) {
-                Ok(flac_tag) => {
-                    match flac_tag.album_cover() {
-                        Some(album_art) => {
-                            let new_image: AlbumArt;
-                            match album_art.mime_type {
-                                MimeType::Jpeg => {
-                                    // Hash album art filename
-                                    new_image = AlbumArt { filename: hash_filename(&metadata_pack.album_artist, &metadata_pack.album, album_year, ".jpg", &album_art.data.to_vec()), data: album_art.data.to_vec() };
-                                    metadata_pack.album_art = Some(new_image);
-                                },
-                                MimeType::Png =>  {
-                                    // Hash album art filename
-                                    new_image = AlbumArt { filename: hash_filename(&metadata_pack.album_artist, &metadata_pack.album, album_year, ".png", &album_art.data.to_vec()), data: album_art.data.to_vec() };
-                                    metadata_pack.album_art = Some(new_image);
-                                },
-                                _a => { // For any other types
-                                    error_log::log_error("metadata:read_vorbis:album_art.mime_type match Error", format!("Album cover in file {} is of unsupported mime type {:?}.", &active_file_path, _a).as_str());
-                                    metadata_pack.album_art = None;
-                                }
-                            }
-                        }
-                        // File does not have album art tagged.
-                        None => metadata_pack.album_art = None,
-                    }
-                }
-                Err(e) => {
-                    error_log::log_error("metadata:read_vorbis:FlacTag::read_from_path() match Error", format!("Album art could not be extracted from the file at {}:\n{:?}", active_file_path, e).as_str());
-                    metadata_pack.album_art = None;
-                }
-            }
+// lofty leaves the cover art items of APEv2 tags out of the tags it reads, so they are read from the file's APE tag.
+fn ape_pictures(active_file_path: &String) -> Vec<Picture> {
+    let ape_tag = File::open(active_file_path).ok()
+                                              .and_then(|mut file| ApeFile::read_from(&mut file, ParseOptions::new().read_properties(false)).ok())
+                                              .and_then(|ape_file| ape_file.ape().cloned());
+    let Some(ape_tag) = ape_tag else { return Vec::new() };
+    APE_PICTURE_TYPES.iter().filter_map(|&key| match ape_tag.get(key)?.value() {
+        ItemValue::Binary(picture_data) => Picture::from_ape_bytes(key, &picture_data[..]).ok(),
+        _ => None,
+    }).collect()
+}
 
-            Some(metadata_pack)
-        }
-        Err(e) => {
-            error_log::log_error("metadata:read_vorbis:open_ext() match Error", format!("Vorbis comments could not be read from the file at {}:\n{:?}", active_file_path, e).as_str());
-            return None;
-        }
+// Albums are treated as compilations when their album artist is one of va_names (e.g. "Various Artists", "VA") or when
+// they carry the compilation flag (COMPILATION=1 in vorbis comments and APEv2 items, TCMP in ID3 tags), which some
+// taggers set instead.
+fn is_compilation(album_artist: &Option<String>, compilation_flag: bool, va_names: &[String]) -> bool {
+    compilation_flag || album_artist.as_deref().is_some_and(|album_artist| is_va_name(album_artist, va_names))
+}
+
+// Names are compared case-insensitively, so "Various artists" matches "Various Artists".
+fn is_va_name(name: &str, va_names: &[String]) -> bool {
+    va_names.iter().any(|va_name| va_name.trim().to_lowercase() == name.trim().to_lowercase())
+}
+
+// Splits artist values holding several artists, e.g. "Artist A; Artist B" or "Artist A feat. Artist B", on each of the
+// artist_delimiters, so they are listed the same way as artists tagged separately. Delimiters are matched exactly.
+fn split_artists<'a>(artists: impl Iterator<Item = &'a str>, artist_delimiters: &[String]) -> Vec<String> {
+    let mut artist_vec: Vec<String> = artists.map(String::from).collect();
+    for delimiter in artist_delimiters.iter().filter(|delimiter| !delimiter.is_empty()) {
+        artist_vec = artist_vec.iter().flat_map(|artist| artist.split(delimiter.as_str())).map(String::from).collect();
     }
+    artist_vec.into_iter().map(|artist| artist.trim().to_owned()).filter(|artist| !artist.is_empty()).collect()
+}
+
+// The year at the start of a release date, e.g. "2003" from "2003-05-12". Dates that don't start with a year are ignored.
+fn release_year(date: &str) -> Option<String> {
+    let year = date.trim().get(..4)?;
+    year.bytes().all(|byte| byte.is_ascii_digit()).then(|| year.to_owned())
 }
 
-fn read_id3(active_file_path: &String, va_album_individual: &bool) -> Option<MetadataPackage> {
-    match Tag::read_from_path(&active_file_path) {
-        Ok(id3_tag) => {
-            let mut metadata_pack = MetadataPackage::default();
+// Builds the metadata package from a tag of any format, whose items lofty names the same way whatever the format.
+fn package_tag(tag: &Tag, active_file_path: &String, va_album_individual: &bool, va_names: &[String], artist_delimiters: &[String], max_art_size: u64) -> Result<MetadataPackage, MetadataError> {
+    let text = |item_key: ItemKey| tag.get_string(item_key).map(str::trim).filter(|text| !text.is_empty()).map(String::from);
+    let mut metadata_pack = MetadataPackage {
+        album_artist: Some(tag.get_strings(ItemKey::AlbumArtist).collect::<Vec<&str>>().join(", ")).filter(|album_artist| !album_artist.is_empty()),
+        album: tag.get_string(ItemKey::AlbumTitle).filter(|album| !album.is_empty()).map(String::from),
+        genre: Some(tag.get_strings(ItemKey::Genre).map(str::trim).filter(|genre| !genre.is_empty()).collect::<Vec<&str>>().join(", ")).filter(|genre| !genre.is_empty()),
+        label: text(ItemKey::Label).or_else(|| text(ItemKey::Publisher)),
+        release_id: text(ItemKey::MusicBrainzReleaseId),
+        ..MetadataPackage::default()
+    };
 
-            // Retrieve metadata from from specified file.
-            // album_artist
-            metadata_pack.album_artist = id3_tag.album_artist().map(|album_artist| album_artist.to_string());
-            
-            // album
-            let album_tag = id3_tag.album().map(|album| album.to_string()).unwrap_or_default();
+    // If va_album_individual is enabled, the album is a compilation, and the album is one of va_names, album tag is not kept.
+    let compilation = tag.get_string(ItemKey::FlagCompilation).map(str::trim) == Some("1");
+    if *va_album_individual && is_compilation(&metadata_pack.album_artist, compilation, va_names)
+                            && metadata_pack.album.as_deref().is_some_and(|album| is_va_name(album, va_names)) {
+        metadata_pack.album = None;
+    }
 
-            // If va_album_individual is enabled, album_artist is "Various Artists", and album is "Various Artists", album tag is not kept.
-            if (*va_album_individual && metadata_pack.album_artist == Some(String::from("Various Artists")) 
-                                     && album_tag == String::from("Various Artists")) 
-                                     || album_tag == String::default() {
-                metadata_pack.album = None;
-            } else {
-                metadata_pack.album = Some(album_tag);
-            }
-            
-            // artist (Tag is required for basic functionality, so return None if not present)
-            match id3_tag.artists() {
-                Some(artists) => metadata_pack.artist = artists.join(", "),
-                None => {
-                    error_log::log_error("metadata:read_id3:id3_tag.artists() match Warning", format!("No artist tag(s) were found in file {}.", active_file_path).as_str());
-                    return None;
-                }
-            }
+    // artist (Tag is required for basic functionality, so return an error if not present)
+    let artist_vec = split_artists(tag.get_strings(ItemKey::TrackArtist), artist_delimiters);
+    if artist_vec.is_empty() {
+        return Err(MetadataError::MissingTag { file_path: active_file_path.to_owned(), tag: "artist" });
+    }
+    metadata_pack.artist = artist_vec.join(", ");
+
+    // title (Tag is required for basic functionality, so return an error if not present)
+    match tag.get_string(ItemKey::TrackTitle) {
+        Some(title) => metadata_pack.title = title.to_owned(),
+        None => return Err(MetadataError::MissingTag { file_path: active_file_path.to_owned(), tag: "title" }),
+    }
+
+    // year
+    let album_year = album_year(tag);
+    metadata_pack.year = text(ItemKey::Year).or_else(|| text(ItemKey::RecordingDate)).as_deref().and_then(release_year);
+
+    // album_art
+    let pictures = tag.pictures().iter().map(|picture| (picture.pic_type().as_u8(), picture.data()));
+    metadata_pack.album_art = choose_picture(pictures).and_then(|image_data| package_album_art(&metadata_pack, album_year, image_data, max_art_size, active_file_path));
+
+    Ok(metadata_pack)
+}
+
+// The year album art filenames are hashed with: the YEAR comment of vorbis comments, and dates that are only a year in
+// other tags, as ID3v2.3 TYER frames and APEv2 Year items are, so the filenames of stored album art don't change.
+fn album_year(tag: &Tag) -> Option<String> {
+    let text = |item_key: ItemKey| tag.get_string(item_key).map(str::trim).filter(|text| !text.is_empty()).map(String::from);
+    match tag.tag_type() {
+        TagType::VorbisComments => text(ItemKey::Year),
+        _ => text(ItemKey::Year).or_else(|| text(ItemKey::RecordingDate).filter(|date| date.len() == 4)),
+    }
+}
 
-            // title (Tag is required for basic functionality, so return None if not present)
-            match id3_tag.title() {
-                Some(title) => metadata_pack.title = title.to_owned(),
-                None => {
-                    error_log::log_error("metadata:read_id3:id3_tag.title() match Warning", format!("No artist tag(s) were found in file {}.", active_file_path).as_str());
-                    return None;
+// Reads the ID3 tag of a DSD file. DSF files point to the tag from their header, while DSDIFF (.dff) files hold it in an
+// "ID3 " chunk, which isn't part of the format's specification but is written by most taggers. lofty doesn't read DSD
+// files, so it is given the tag alone, which it reads as an MP3 file without audio.
+fn read_dsd(active_file_path: &String, va_album_individual: &bool, va_names: &[String], artist_delimiters: &[String], max_art_size: u64) -> Result<MetadataPackage, MetadataError> {
+    let unreadable = |reason: String| MetadataError::Unreadable { file_path: active_file_path.clone(), reason };
+    let mut file = File::open(active_file_path).map_err(|e| unreadable(e.to_string()))?;
+    let tag_offset = match find_dsd_id3_offset(&mut file).map_err(unreadable)? {
+        Some(tag_offset) => tag_offset,
+        // Files without a tag have no artist to show.
+        None => return Err(MetadataError::MissingTag { file_path: active_file_path.clone(), tag: "artist" }),
+    };
+
+    // The tag's size, after its 10 byte header, is a 28 bit synchsafe integer.
+    let mut id3_header = [0u8; 10];
+    file.seek(SeekFrom::Start(tag_offset)).and_then(|_| file.read_exact(&mut id3_header)).map_err(|e| unreadable(e.to_string()))?;
+    let tag_size = id3_header[6..10].iter().fold(0u64, |tag_size, &byte| (tag_size << 7) | (byte & 0x7F) as u64);
+    let mut id3_tag = id3_header.to_vec();
+    (&mut file).take(tag_size).read_to_end(&mut id3_tag).map_err(|e| unreadable(e.to_string()))?;
+
+    let tagged_file = Probe::with_file_type(Cursor::new(id3_tag), FileType::Mpeg).options(ParseOptions::new().read_properties(false))
+                                                                                .read()
+                                                                                .map_err(|e| unreadable(e.to_string()))?;
+    match tagged_file.tag(TagType::Id3v2) {
+        Some(tag) => package_tag(tag, active_file_path, va_album_individual, va_names, artist_delimiters, max_art_size),
+        None => Err(MetadataError::MissingTag { file_path: active_file_path.clone(), tag: "artist" }),
+    }
+}
+
+// Finds where the ID3 tag of a DSF or DSDIFF file starts, if it has one.
+fn find_dsd_id3_offset(file: &mut File) -> Result<Option<u64>, String> {
+    let mut header = [0u8; 28];
+    file.read_exact(&mut header).map_err(|_| String::from("The file ended before its DSD header."))?;
+    match &header[0..4] {
+        // The DSF header's last field is the offset of the metadata chunk, which is 0 if there is none.
+        b"DSD " => Ok(Some(u64::from_le_bytes(header[20..28].try_into().unwrap_or_default())).filter(|&tag_offset| tag_offset > 0)),
+        // DSDIFF chunks, each with an ID and a big-endian length, follow the FRM8 header, and are padded to an even length.
+        b"FRM8" => {
+            let form_end = 12 + u64::from_be_bytes(header[4..12].try_into().unwrap_or_default());
+            let mut chunk_offset = 16;
+            let mut chunk_header = [0u8; 12];
+            while chunk_offset + 12 <= form_end {
+                file.seek(SeekFrom::Start(chunk_offset)).and_then(|_| file.read_exact(&mut chunk_header)).map_err(|e| e.to_string())?;
+                if &chunk_header[0..4] == b"ID3 " {
+                    return Ok(Some(chunk_offset + 12));
                 }
+                let chunk_length = u64::from_be_bytes(chunk_header[4..12].try_into().unwrap_or_default());
+                chunk_offset = chunk_offset.saturating_add(12).saturating_add(chunk_length).saturating_add(chunk_length % 2);
             }
+            Ok(None)
+        }
+        _ => Err(String::from("The file is neither a DSF nor a DSDIFF file.")),
+    }
+}
 
-            // year
-            // Used only for constructing filename hash, not included in metadata package.
-            let album_year: Option<String> = id3_tag.year().map(|year| year.to_string());
-            
-            // album_art
-            let extracted_images = id3_tag.pictures().collect::<Vec<_>>();
-            if extracted_images.len() > 0 {
-                match Content::Picture(extracted_images[0].clone()).picture() {
-                    Some(album_art) => {
-                        match album_art.mime_type.as_str() {
-                            "image/jpeg" => metadata_pack.album_art = Some(AlbumArt { filename: hash_filename(&metadata_pack.album_artist, &metadata_pack.album, album_year, ".jpg", &album_art.data), data: album_art.data.clone() }),
-                            "image/png"  => metadata_pack.album_art = Some(AlbumArt { filename: hash_filename(&metadata_pack.album_artist, &metadata_pack.album, album_year, ".png", &album_art.data), data: album_art.data.clone() }),
-                            _ => metadata_pack.album_art = None,
-                        }
-                    }
-                    None => metadata_pack.album_art = None,
-                }
-            } else {
-                metadata_pack.album_art = None;
+// Tracker modules have no tags, so their title is read from the module's header, and the artist is guessed from the
+// file name, when it is of the form "Artist - Title", or otherwise from the name of the directory holding the file.
+fn read_module(active_file_path: &String) -> Result<MetadataPackage, MetadataError> {
+    let unreadable = |reason: String| MetadataError::Unreadable { file_path: active_file_path.clone(), reason };
+    let mut header = Vec::new();
+    File::open(active_file_path).and_then(|file| file.take(64).read_to_end(&mut header)).map_err(|e| unreadable(e.to_string()))?;
+
+    let file_path = Path::new(active_file_path);
+    let file_stem = file_path.file_stem().map(|file_stem| file_stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let (artist_guess, title_guess) = match file_stem.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim().to_owned()), title.trim().to_owned()),
+        None => (None, file_stem.clone()),
+    };
+    let artist = artist_guess.or_else(|| file_path.parent().and_then(Path::file_name).map(|dir_name| dir_name.to_string_lossy().into_owned()))
+                             .filter(|artist| !artist.is_empty())
+                             .unwrap_or(String::from("Unknown Artist"));
+
+    Ok(MetadataPackage {
+        artist,
+        title: parse_module_title(&header).unwrap_or(title_guess),
+        ..MetadataPackage::default()
+    })
+}
+
+// Reads the title from the header of a MOD, XM, IT, or S3M module, which is padded with spaces or null characters.
+fn parse_module_title(header: &[u8]) -> Option<String> {
+    let title_bytes = if let Some(xm_header) = header.strip_prefix(b"Extended Module: ") {
+        xm_header.get(..20)?
+    } else if let Some(it_header) = header.strip_prefix(b"IMPM") {
+        it_header.get(..26)?
+    } else if header.get(44..48) == Some(b"SCRM") {
+        &header[..28]
+    } else {
+        // MOD files have no magic number at the start, and begin with their title.
+        header.get(..20)?
+    };
+    let title_end = title_bytes.iter().position(|&byte| byte == 0).unwrap_or(title_bytes.len());
+    // Titles are in the tracker's code page rather than UTF-8, so only printable ASCII is kept.
+    let title = String::from_utf8_lossy(&title_bytes[..title_end]).chars().filter(|character| character.is_ascii_graphic() || *character == ' ').collect::<String>();
+    Some(title.trim().to_owned()).filter(|title| !title.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{fixture_path, use_test_state_dir};
+    use lofty::id3::v2::Id3v2Tag;
+    use lofty::ogg::tag::VorbisComments;
+    use lofty::picture::{MimeType, PictureType};
+    use lofty::tag::Accessor;
+
+    const NO_LIMIT: u64 = u64::MAX;
+
+    fn vorbis_comments<'a>(comments: impl IntoIterator<Item = (&'a str, &'a str)>) -> Tag {
+        let mut vorbis_comments = VorbisComments::new();
+        for (name, value) in comments {
+            vorbis_comments.push(name.to_owned(), value.to_owned());
+        }
+        vorbis_comments.into()
+    }
+
+    fn id3_tag(artist: &str, title: &str) -> Id3v2Tag {
+        let mut id3_tag = Id3v2Tag::new();
+        id3_tag.set_artist(artist.to_owned());
+        id3_tag.set_title(title.to_owned());
+        id3_tag
+    }
+
+    #[test]
+    fn reads_id3_tags_and_album_art() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.mp3"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "Test Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+        assert_eq!(metadata_pack.album_artist.as_deref(), Some("Test Album Artist"));
+
+        let album_art = metadata_pack.album_art.unwrap();
+        assert!(album_art.filename.ends_with(".jpg"));
+        assert!(!album_art.data.is_empty());
+    }
+
+    #[test]
+    fn reads_vorbis_comments_and_album_art() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.flac"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "First Artist, Second Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+        assert!(metadata_pack.album_art.unwrap().filename.ends_with(".png"));
+    }
+
+    #[test]
+    fn reads_ogg_vorbis_comments_and_album_art() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.ogg"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "First Artist, Second Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+        assert_eq!(metadata_pack.album_artist.as_deref(), Some("Test Album Artist"));
+
+        let album_art = metadata_pack.album_art.unwrap();
+        assert!(album_art.filename.ends_with(".png"));
+        assert!(album_art.data.starts_with(b"\x89PNG"));
+        assert!(matches!(read_metadata(&String::from("/music/missing.ogg"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::Unreadable { .. })));
+        assert_eq!(metadata_pack.duration, Some(180));
+    }
+
+    #[test]
+    fn reads_opus_comments_album_art_and_duration() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.opus"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "Test Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+        assert!(metadata_pack.album_art.unwrap().filename.ends_with(".png"));
+        // The last page's granule position, less the pre-skip, is 215.5 seconds of samples at 48 kHz.
+        assert_eq!(metadata_pack.duration, Some(215));
+        assert_eq!(read_metadata(&fixture_path("tagged.mp3"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap().duration, None);
+    }
+
+    #[test]
+    fn reads_the_duration_of_flac_and_mp3_files() {
+        use_test_state_dir();
+        assert_eq!(read_metadata(&fixture_path("tagged.flac"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap().duration, Some(200));
+
+        // An ID3v1 tag, which holds the title and then the artist in 30 byte fields.
+        let id3v1_tag = [b"TAG".as_slice(), b"Test Title", &[0; 20], b"Test Artist", &[0; 84]].concat();
+        let mp3_duration = |mp3_stream: &[u8]| {
+            let track_path = std::env::temp_dir().join(format!("lamp-drpc-duration-{}.mp3", std::process::id())).to_string_lossy().into_owned();
+            std::fs::write(&track_path, mp3_stream).unwrap();
+            let duration = read_metadata(&track_path, &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap().duration;
+            std::fs::remove_file(&track_path).unwrap();
+            duration
+        };
+
+        // Frames of MPEG-1 layer III at 128 kbps and 44.1 kHz are 417 bytes long.
+        let frames = |stream_length: usize| {
+            let mut frames = Vec::with_capacity(stream_length);
+            while frames.len() < stream_length {
+                frames.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+                frames.resize((frames.len() + 413).min(stream_length), 0);
             }
+            frames
+        };
+
+        // A CBR stream at 128 kbps, after an empty ID3 tag, and followed by an ID3v1 tag.
+        let cbr_stream = [b"ID3\x04\0\0\0\0\0\0".as_slice(), &frames(160_000), &id3v1_tag].concat();
+        assert_eq!(mp3_duration(&cbr_stream), Some(10));
+
+        // A VBR stream whose Xing header counts 3829 frames of 1152 samples at 44.1 kHz, and the stream's length.
+        let mut vbr_stream = frames(4170);
+        vbr_stream[36..52].copy_from_slice(&[b"Xing\0\0\0\x03".as_slice(), &3829u32.to_be_bytes(), &4170u32.to_be_bytes()].concat());
+        vbr_stream.extend_from_slice(&id3v1_tag);
+        assert_eq!(mp3_duration(&vbr_stream), Some(100));
 
-            return Some(metadata_pack);
+        assert_eq!(mp3_duration(&[[0u8; 1024].as_slice(), &id3v1_tag].concat()), None);
+    }
+
+    #[test]
+    fn reads_ape_tags_and_album_art() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.ape"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "First Artist, Second Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album_artist.as_deref(), Some("Test Album Artist"));
+        let album_art = metadata_pack.album_art.unwrap();
+        assert!(album_art.filename.ends_with(".png"));
+        assert!(album_art.data.starts_with(b"\x89PNG"));
+        assert!(matches!(read_metadata(&String::from("/music/missing.ape"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::Unreadable { .. })));
+    }
+
+    #[test]
+    fn compilation_flag_marks_various_artists_albums() {
+        let va_names = [String::from("Various Artists")];
+        let va_comments = |compilation: &'static str| [("ARTIST", "Test Artist"), ("TITLE", "Test Title"), ("ALBUM", "Various Artists"), ("COMPILATION", compilation)];
+        let album = |comments: [(&'static str, &'static str); 4], va_album_individual: bool| {
+            package_tag(&vorbis_comments(comments), &String::from("/music/track.flac"), &va_album_individual, &va_names, &[], NO_LIMIT).unwrap().album
+        };
+        assert_eq!(album(va_comments("1"), true), None);
+        assert_eq!(album(va_comments("0"), true).as_deref(), Some("Various Artists"));
+        assert_eq!(album(va_comments("1"), false).as_deref(), Some("Various Artists"));
+
+        let mut id3_tag = id3_tag("Test Artist", "Test Title");
+        id3_tag.set_album(String::from("Various Artists"));
+        let mut id3_tag = Tag::from(id3_tag);
+        id3_tag.insert_text(ItemKey::FlagCompilation, String::from("1"));
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &true, &va_names, &[], NO_LIMIT).unwrap().album, None);
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &false, &va_names, &[], NO_LIMIT).unwrap().album.as_deref(), Some("Various Artists"));
+    }
+
+    #[test]
+    fn various_artists_names_are_configurable() {
+        let va_names = [String::from("Various Artists"), String::from("VA"), String::from("Varios Artistas")];
+        let album = |album_artist: &'static str, album: &'static str| {
+            let comments = [("ARTIST", "Test Artist"), ("TITLE", "Test Title"), ("ALBUMARTIST", album_artist), ("ALBUM", album)];
+            package_tag(&vorbis_comments(comments), &String::from("/music/track.flac"), &true, &va_names, &[], NO_LIMIT).unwrap().album
+        };
+        assert_eq!(album("VA", "VA"), None);
+        assert_eq!(album("Varios Artistas", "varios artistas"), None);
+        assert_eq!(album("VA", "Test Album").as_deref(), Some("Test Album"));
+        assert_eq!(album("Test Album Artist", "VA").as_deref(), Some("VA"));
+
+        let mut id3_tag = id3_tag("Test Artist", "Test Title");
+        id3_tag.set_album(String::from("Varios Artistas"));
+        let mut id3_tag = Tag::from(id3_tag);
+        id3_tag.insert_text(ItemKey::AlbumArtist, String::from("Varios Artistas"));
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &true, &va_names, &[], NO_LIMIT).unwrap().album, None);
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &true, &[], &[], NO_LIMIT).unwrap().album.as_deref(), Some("Varios Artistas"));
+    }
+
+    #[test]
+    fn reads_id3_tags_of_dsd_files() {
+        use_test_state_dir();
+        for fixture_name in ["tagged.dsf", "tagged.dff"] {
+            let metadata_pack = read_metadata(&fixture_path(fixture_name), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+            assert_eq!(metadata_pack.artist, "Test Artist");
+            assert_eq!(metadata_pack.title, "Test Title");
+            assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+            assert!(metadata_pack.album_art.unwrap().filename.ends_with(".jpg"));
         }
-        Err(e) => {
-            error_log::log_error("metadata:read_id3:Tag::read_from_path() match Error", format!("ID3 tags could not be read from the file at {}:\n{}", &active_file_path, e).as_str());
-            return None;
-        }    
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn modules_are_shown_by_their_title_and_file_name() {
+        let xm_header = [b"Extended Module: ".as_slice(), b"Space Debris\0\0\0\0\0\0\0\0\x1aFastTracker v2.00   "].concat();
+        assert_eq!(parse_module_title(&xm_header).as_deref(), Some("Space Debris"));
+        assert_eq!(parse_module_title(b"IMPMTitle of an IT module\0\0\0\0\0\0\0").as_deref(), Some("Title of an IT module"));
+        let s3m_header = [b"S3M Title".as_slice(), &[0; 35], b"SCRM"].concat();
+        assert_eq!(parse_module_title(&s3m_header).as_deref(), Some("S3M Title"));
+        assert_eq!(parse_module_title(b"  mod title         M.K.").as_deref(), Some("mod title"));
+        assert_eq!(parse_module_title(&[0; 64]), None);
+
+        let module_dir = std::env::temp_dir().join(format!("lamp-drpc-modules-{}", std::process::id())).join("Purple Motion");
+        std::fs::create_dir_all(&module_dir).unwrap();
+        let untitled_path = module_dir.join("Some Artist - Untitled Song.mod");
+        std::fs::write(&untitled_path, [0; 1084]).unwrap();
+        let metadata_pack = read_metadata(&untitled_path.to_string_lossy().into_owned(), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+        assert_eq!((metadata_pack.artist.as_str(), metadata_pack.title.as_str()), ("Some Artist", "Untitled Song"));
+
+        let titled_path = module_dir.join("satell.s3m");
+        std::fs::write(&titled_path, &s3m_header).unwrap();
+        let metadata_pack = read_metadata(&titled_path.to_string_lossy().into_owned(), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+        assert_eq!((metadata_pack.artist.as_str(), metadata_pack.title.as_str(), metadata_pack.album_art.is_none()), ("Purple Motion", "S3M Title", true));
+        let _ = std::fs::remove_dir_all(module_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn artists_are_split_on_delimiters() {
+        let artist_delimiters = [String::from("; "), String::from(" feat. ")];
+        let tags = [("ARTIST", "Artist A; Artist B feat. Artist C"), ("ARTIST", "Artist D"), ("TITLE", "Title")];
+        let metadata_pack = package_tag(&vorbis_comments(tags), &String::from("/music/track.flac"), &false, &[], &artist_delimiters, NO_LIMIT).unwrap();
+        assert_eq!(metadata_pack.artist, "Artist A, Artist B, Artist C, Artist D");
+
+        let id3_tag = Tag::from(id3_tag("Artist A; Artist B", "Title"));
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &false, &[], &artist_delimiters, NO_LIMIT).unwrap().artist, "Artist A, Artist B");
+        assert_eq!(package_tag(&id3_tag, &String::from("/music/track.mp3"), &false, &[], &[], NO_LIMIT).unwrap().artist, "Artist A; Artist B");
+        assert_eq!(split_artists(["; "].into_iter(), &artist_delimiters), Vec::<String>::new());
+    }
+
+    #[test]
+    fn prefers_the_front_cover_of_id3_tags() {
+        let picture = |pic_type: PictureType, data: &[u8]| Picture::unchecked(data.to_vec()).pic_type(pic_type).mime_type(MimeType::Jpeg).build();
+        let mut id3_tag = Tag::from(id3_tag("Test Artist", "Test Title"));
+        id3_tag.push_picture(picture(PictureType::Band, &[0xFF, 0xD8, 0xFF, 0x01]));
+        id3_tag.push_picture(picture(PictureType::CoverBack, &[0xFF, 0xD8, 0xFF, 0x02]));
+        id3_tag.push_picture(picture(PictureType::CoverFront, &[0xFF, 0xD8, 0xFF, 0x03]));
+        let album_art = |id3_tag: &Tag| package_tag(id3_tag, &String::from("/music/track.mp3"), &false, &[], &[], NO_LIMIT).unwrap().album_art.unwrap().data;
+        assert_eq!(album_art(&id3_tag), [0xFF, 0xD8, 0xFF, 0x03]);
+
+        // Without a front cover, the back cover is used over the band photo.
+        id3_tag.remove_picture_type(PictureType::CoverFront);
+        assert_eq!(album_art(&id3_tag), [0xFF, 0xD8, 0xFF, 0x02]);
+    }
+
+    #[test]
+    fn chooses_the_largest_front_cover() {
+        use lofty::config::WriteOptions;
+        use_test_state_dir();
+        let png = |width: u32, height: u32| {
+            let mut image_data = Vec::new();
+            image::RgbImage::new(width, height).write_to(&mut Cursor::new(&mut image_data), image::ImageFormat::Png).unwrap();
+            image_data
+        };
+        let (thumbnail, front_cover, band_photo) = (png(50, 50), png(600, 600), png(1000, 1000));
+
+        // FLAC files often carry a thumbnail along with the full front cover.
+        let track_path = std::env::temp_dir().join(format!("lamp-drpc-pictures-{}.flac", std::process::id())).to_string_lossy().into_owned();
+        std::fs::copy(fixture_path("tagged.flac"), &track_path).unwrap();
+        let mut flac_tag = Probe::open(&track_path).unwrap().read().unwrap().tag(TagType::VorbisComments).unwrap().clone();
+        while !flac_tag.pictures().is_empty() {
+            flac_tag.remove_picture(0);
+        }
+        for (pic_type, image_data) in [(PictureType::CoverFront, &thumbnail), (PictureType::Band, &band_photo), (PictureType::CoverFront, &front_cover)] {
+            flac_tag.push_picture(Picture::unchecked(image_data.clone()).pic_type(pic_type).mime_type(MimeType::Png).build());
+        }
+        flac_tag.save_to_path(&track_path, WriteOptions::default()).unwrap();
+        let album_art = read_metadata(&track_path, &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap().album_art.unwrap();
+        assert_eq!(album_art.data, front_cover);
+        std::fs::remove_file(&track_path).unwrap();
+
+        // Unreadable images count as empty, and pictures of the same size are taken in the order they are stored.
+        assert_eq!(choose_picture([(3, &[0xFF, 0xD8, 0x01][..]), (3, thumbnail.as_slice())].into_iter()), Some(thumbnail.as_slice()));
+        assert_eq!(choose_picture([(3, &[0xFF, 0xD8, 0x01][..]), (3, &[0xFF, 0xD8, 0x02][..])].into_iter()), Some(&[0xFF, 0xD8, 0x01][..]));
+        assert_eq!(choose_picture(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn reads_genre_year_and_label() {
+        let tags = [("ARTIST", "Artist"), ("TITLE", "Title"), ("GENRE", "Jazz"), ("GENRE", "Fusion"), ("DATE", "1997-03-12"), ("ORGANIZATION", "Test Label")];
+        let vorbis_tag = vorbis_comments(tags);
+        let metadata_pack = package_tag(&vorbis_tag, &String::from("/music/track.flac"), &false, &[], &[], NO_LIMIT).unwrap();
+        assert_eq!((metadata_pack.genre.as_deref(), metadata_pack.year.as_deref(), metadata_pack.label.as_deref()), (Some("Jazz, Fusion"), Some("1997"), Some("Test Label")));
+        // Album art filenames are still hashed with the YEAR comment alone, so they don't change.
+        assert_eq!(album_year(&vorbis_tag), None);
+
+        let mut id3_tag = id3_tag("Artist", "Title");
+        id3_tag.set_genre(String::from("8"));
+        let mut id3_tag = Tag::from(id3_tag);
+        id3_tag.insert_text(ItemKey::RecordingDate, String::from("2001-06-01"));
+        id3_tag.insert_text(ItemKey::Publisher, String::from("Test Label"));
+        let metadata_pack = package_tag(&id3_tag, &String::from("/music/track.mp3"), &false, &[], &[], NO_LIMIT).unwrap();
+        assert_eq!((metadata_pack.genre.as_deref(), metadata_pack.year.as_deref(), metadata_pack.label.as_deref()), (Some("Jazz"), Some("2001"), Some("Test Label")));
+
+        assert_eq!(release_year("2003-05-12").as_deref(), Some("2003"));
+        assert_eq!(release_year("unknown"), None);
+    }
+
+    #[test]
+    fn files_without_embedded_art_use_a_folder_image() {
+        use_test_state_dir();
+        let album_dir = std::env::temp_dir().join(format!("lamp-drpc-folder-art-{}", std::process::id()));
+        std::fs::create_dir_all(&album_dir).unwrap();
+        let track_path = album_dir.join("track.wav").to_string_lossy().into_owned();
+        std::fs::copy(fixture_path("tagged.wav"), &track_path).unwrap();
+        let folder_art_names = [String::from("cover.jpg"), String::from("folder.png")];
+        assert!(read_metadata(&track_path, &false, &[], &[], &folder_art_names, &ArtSource::ALL, NO_LIMIT).unwrap().album_art.is_none());
+
+        // Names are matched case-insensitively, in order of preference.
+        let png_data = b"\x89PNG\r\n\x1a\nfolder image".to_vec();
+        std::fs::write(album_dir.join("Folder.PNG"), &png_data).unwrap();
+        std::fs::write(album_dir.join("front.jpg"), [0xFF, 0xD8, 0xFF]).unwrap();
+        let album_art = read_metadata(&track_path, &false, &[], &[], &folder_art_names, &ArtSource::ALL, NO_LIMIT).unwrap().album_art.unwrap();
+        assert!(album_art.filename.ends_with(".png"));
+        assert_eq!(album_art.data, png_data);
+
+        assert!(read_metadata(&track_path, &false, &[], &[], &folder_art_names, &ArtSource::ALL, 4).unwrap().album_art.is_none());
+        assert!(read_metadata(&track_path, &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap().album_art.is_none());
+
+        // Embedded album art is used over any folder image.
+        let embedded_path = album_dir.join("track.mp3").to_string_lossy().into_owned();
+        std::fs::copy(fixture_path("tagged.mp3"), &embedded_path).unwrap();
+        let metadata_pack = read_metadata(&embedded_path, &false, &[], &[], &folder_art_names, &ArtSource::ALL, NO_LIMIT).unwrap();
+        assert!(metadata_pack.album_art.unwrap().filename.ends_with(".jpg"));
+        assert_eq!(metadata_pack.album_art_source, Some(ArtSource::Embedded));
+
+        // Unless the folder image comes first in art_sources, and sources left out are never used.
+        let metadata_pack = read_metadata(&embedded_path, &false, &[], &[], &folder_art_names, &[ArtSource::Folder, ArtSource::Embedded], NO_LIMIT).unwrap();
+        assert_eq!((metadata_pack.album_art.unwrap().data, metadata_pack.album_art_source), (png_data, Some(ArtSource::Folder)));
+        assert!(read_metadata(&embedded_path, &false, &[], &[], &folder_art_names, &[ArtSource::Caa], NO_LIMIT).unwrap().album_art.is_none());
+        std::fs::remove_dir_all(&album_dir).unwrap();
+    }
+
+    #[test]
+    fn reads_riff_info_of_wav_files() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.wav"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT).unwrap();
+
+        assert_eq!(metadata_pack.artist, "Test Artist");
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert_eq!(metadata_pack.album.as_deref(), Some("Test Album"));
+        assert!(metadata_pack.album_art.is_none());
+        assert!(matches!(read_metadata(&String::from("/music/missing.wav"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::Unreadable { .. })));
+    }
+
+    #[test]
+    fn missing_artist_is_not_shown() {
+        use_test_state_dir();
+        assert!(matches!(read_metadata(&fixture_path("no_artist.mp3"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::MissingTag { tag: "artist", .. })));
+    }
+
+    #[test]
+    fn unsupported_format_is_not_shown() {
+        use_test_state_dir();
+        assert!(matches!(read_metadata(&fixture_path("cover.wma"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::UnsupportedFormat(_))));
+        assert!(matches!(read_metadata(&String::from("/music/no-extension"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn unreadable_tags_are_not_shown() {
+        use_test_state_dir();
+        assert!(matches!(read_metadata(&String::from("/music/missing.mp3"), &false, &[], &[], &[], &ArtSource::ALL, NO_LIMIT), Err(MetadataError::Unreadable { .. })));
+    }
+
+    #[test]
+    fn oversized_album_art_is_skipped() {
+        use_test_state_dir();
+        let metadata_pack = read_metadata(&fixture_path("tagged.mp3"), &false, &[], &[], &[], &ArtSource::ALL, 1024).unwrap();
+
+        assert_eq!(metadata_pack.title, "Test Title");
+        assert!(metadata_pack.album_art.is_none());
+    }
+
+    #[test]
+    fn album_art_type_is_read_from_the_image_data() {
+        use_test_state_dir();
+        let metadata_pack = MetadataPackage::default();
+        let jpeg = package_album_art(&metadata_pack, None, &[0xFF, 0xD8, 0xFF, 0xE0], NO_LIMIT, &String::from("/music/track.mp3")).unwrap();
+        assert!(jpeg.filename.ends_with(".jpg"));
+        let webp = package_album_art(&metadata_pack, None, b"RIFF\x24\x00\x00\x00WEBPVP8L", NO_LIMIT, &String::from("/music/track.mp3")).unwrap();
+        assert!(webp.filename.ends_with(".webp"));
+        assert!(package_album_art(&metadata_pack, None, b"GIF89a", NO_LIMIT, &String::from("/music/track.mp3")).unwrap().filename.ends_with(".gif"));
+        assert!(package_album_art(&metadata_pack, None, b"BM\x36\x00", NO_LIMIT, &String::from("/music/track.mp3")).unwrap().filename.ends_with(".bmp"));
+        assert!(package_album_art(&metadata_pack, None, b"II*\x00", NO_LIMIT, &String::from("/music/track.mp3")).is_none());
+    }
+
+    #[test]
+    fn album_art_filename_is_stable() {
+        let image_data = [1, 2, 3, 4];
+        let album_artist = Some(String::from("Album Artist"));
+        let album = Some(String::from("Album"));
+
+        let first_filename = hash_filename(&None, &album_artist, &album, Some(String::from("2020")), ".jpg", &image_data);
+        let second_filename = hash_filename(&None, &album_artist, &album, Some(String::from("2020")), ".jpg", &image_data);
+        let other_album_filename = hash_filename(&None, &album_artist, &None, Some(String::from("2020")), ".jpg", &image_data);
+
+        assert_eq!(first_filename, second_filename);
+        assert_ne!(first_filename, other_album_filename);
+
+        // Differently tagged rips of the same release share a filename.
+        let release_id = Some(String::from("0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a"));
+        let release_filename = hash_filename(&release_id, &album_artist, &album, Some(String::from("2020")), ".jpg", &image_data);
+        let retagged_filename = hash_filename(&release_id.as_ref().map(|release_id| release_id.to_uppercase()), &Some(String::from("album artist")), &None, None, ".jpg", &image_data);
+        assert_eq!(release_filename, retagged_filename);
+        assert_ne!(release_filename, first_filename);
+
+        let tags = [("ARTIST", "Artist"), ("TITLE", "Title"), ("MUSICBRAINZ_ALBUMID", "0b7d0ba2-7c95-4ef5-9bd6-4a6d1f5e3c2a")];
+        let metadata_pack = package_tag(&vorbis_comments(tags), &String::from("/music/track.flac"), &false, &[], &[], NO_LIMIT).unwrap();
+        assert_eq!(metadata_pack.release_id, release_id);
+    }
+}
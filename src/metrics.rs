@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error_log;
+
+/*
+ *  Optional metrics endpoint, enabled by setting metrics_port in lamp.toml.
+ *
+ *  - /metrics reports the counters below in the Prometheus text format.
+ *  - /healthz reports whether lamp-drpc is running, for use in health checks.
+ *
+ *  The endpoint only listens on localhost. Counters are always updated, whether or not the endpoint is enabled.
+ */
+pub static TRACKS_SHOWN: AtomicU64 = AtomicU64::new(0);
+pub static UPLOADS: AtomicU64 = AtomicU64::new(0);
+pub static UPLOAD_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub static DISCORD_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+static EVENT_LOOP_LATENCY_MICROS: AtomicU64 = AtomicU64::new(0);
+static EVENTS_HANDLED: AtomicU64 = AtomicU64::new(0);
+
+pub fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_event_latency(latency: Duration) {
+    EVENT_LOOP_LATENCY_MICROS.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    EVENTS_HANDLED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub async fn start_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream).await {
+                            error_log::log_error("metrics:handle_connection Error", e.to_string().as_str());
+                        }
+                    });
+                }
+                Err(e) => error_log::log_error("metrics:listener.accept() Error", e.to_string().as_str()),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    // Only the request line is needed to route the request, e.g. "GET /metrics HTTP/1.1".
+    let mut request_line = String::new();
+    BufReader::new(&mut stream).read_line(&mut request_line).await?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", render_metrics()),
+        "/healthz" => ("200 OK", String::from("ok\n")),
+        _ => ("404 Not Found", String::from("not found\n")),
+    };
+
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn render_metrics() -> String {
+    let counters = [
+        ("lamp_tracks_shown_total", "Number of times a track was shown on Discord.", &TRACKS_SHOWN),
+        ("lamp_uploads_total", "Number of album art images uploaded.", &UPLOADS),
+        ("lamp_upload_failures_total", "Number of album art images that failed to process or upload.", &UPLOAD_FAILURES),
+        ("lamp_discord_reconnects_total", "Number of times the Discord client reconnected.", &DISCORD_RECONNECTS),
+    ];
+
+    let mut metrics_text = String::new();
+    for (name, help, counter) in counters {
+        metrics_text.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, counter.load(Ordering::Relaxed)));
+    }
+
+    // Time spent handling each event in the event loop, as a Prometheus summary without quantiles.
+    let latency_seconds = EVENT_LOOP_LATENCY_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+    metrics_text.push_str(&format!("# HELP lamp_event_loop_latency_seconds Time spent handling events in the event loop.\n\
+                                    # TYPE lamp_event_loop_latency_seconds summary\n\
+                                    lamp_event_loop_latency_seconds_sum {}\n\
+                                    lamp_event_loop_latency_seconds_count {}\n",
+                                    latency_seconds, EVENTS_HANDLED.load(Ordering::Relaxed)));
+    metrics_text
+}
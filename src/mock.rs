@@ -0,0 +1,355 @@
+use std::collections::VecDeque;
+use std::io::Cursor;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::Instant;
+use bytes::Bytes;
+#[cfg(unix)]
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::art::ImageHost;
+use crate::error::{ArtError, PlayerError};
+use crate::events::{PlayerMetadata, PlayerTags};
+use crate::paths;
+use crate::player::{PlaybackState, StandardPlayer};
+
+/*
+ *  Test doubles for the player and image host, along with helpers for locating fixtures.
+ *
+ *  - MockPlayer reports a scripted sequence of active files (and optionally their positions and playback states), one
+ *    per poll, and exits once the script runs out. Its secondary check passes unless it is made to fail, and its
+ *    first polls can be made to fail, as a player's do when its connection drops for a moment, or every poll can be
+ *    made to panic.
+ *  - MockImageHost records each upload instead of sending it anywhere, and reports every link as available
+ *    unless configured otherwise.
+ *  - FakeDiscord is a minimal Discord IPC server, which completes the handshake and records the activity of every
+ *    SET_ACTIVITY command it receives.
+ *  - receive_http_request stands in for an HTTP server, such as a webhook endpoint, for a single request.
+ */
+pub struct MockPlayer {
+    active_files: VecDeque<(Option<String>, Option<u64>, PlaybackState)>,
+    duration: Option<u64>,
+    position: Option<u64>,
+    playback_state: PlaybackState,
+    secondary_check: bool,
+    failing_polls: usize,
+    panicking: bool,
+}
+
+impl MockPlayer {
+    pub fn new(active_files: &[Option<&str>], duration: Option<u64>) -> Self {
+        let active_files = active_files.iter().map(|file_path| (*file_path, None)).collect::<Vec<(Option<&str>, Option<u64>)>>();
+        MockPlayer::with_positions(&active_files, duration)
+    }
+
+    pub fn with_positions(active_files: &[(Option<&str>, Option<u64>)], duration: Option<u64>) -> Self {
+        let active_files = active_files.iter().map(|(file_path, position)| (*file_path, *position, PlaybackState::Playing)).collect::<Vec<(Option<&str>, Option<u64>, PlaybackState)>>();
+        MockPlayer::with_states(&active_files, duration)
+    }
+
+    pub fn with_states(active_files: &[(Option<&str>, Option<u64>, PlaybackState)], duration: Option<u64>) -> Self {
+        MockPlayer {
+            active_files: active_files.iter().map(|(file_path, position, playback_state)| (file_path.map(String::from), *position, *playback_state)).collect(),
+            duration,
+            position: None,
+            playback_state: PlaybackState::Stopped,
+            secondary_check: true,
+            failing_polls: 0,
+            panicking: false,
+        }
+    }
+
+    pub fn failing_polls(mut self, failing_polls: usize) -> Self {
+        self.failing_polls = failing_polls;
+        self
+    }
+
+    pub fn panicking(mut self) -> Self {
+        self.panicking = true;
+        self
+    }
+
+    pub fn failing_secondary_check(mut self) -> Self {
+        self.secondary_check = false;
+        self
+    }
+}
+
+impl StandardPlayer for MockPlayer {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        Ok(self.secondary_check)
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        if self.panicking {
+            panic!("Mock player output could not be parsed.");
+        }
+        if self.failing_polls > 0 {
+            self.failing_polls -= 1;
+            return Err(PlayerError::Protocol(String::from("Mock player connection dropped.")));
+        }
+        match self.active_files.pop_front() {
+            Some((active_file, position, playback_state)) => {
+                self.position = position;
+                self.playback_state = playback_state;
+                Ok(active_file)
+            }
+            None => Err(PlayerError::Exited(String::from("Mock player"))),
+        }
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.playback_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// An upload received by MockImageHost, along with the dimensions of the uploaded image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockUpload {
+    pub file_name: String,
+    pub dimensions: (u32, u32),
+}
+
+#[derive(Clone, Default)]
+pub struct MockImageHost {
+    pub uploads: Arc<Mutex<Vec<MockUpload>>>,
+    pub link_checks: Arc<Mutex<usize>>,
+    pub links_expired: bool,
+    pub fail_uploads: bool,
+}
+
+impl ImageHost for MockImageHost {
+    const MAX_UPLOAD_SIZE: u64 = 64 * 1024;
+
+    async fn upload(&self, image_data: Bytes, file_name: &str, _user_hash: Option<String>) -> Result<String, ArtError> {
+        if self.fail_uploads {
+            return Err(ArtError::UploadRefused { host: "Mock image host", status: reqwest::StatusCode::INTERNAL_SERVER_ERROR, message: String::from("Mock upload failed.") });
+        }
+
+        let dimensions = image::ImageReader::new(Cursor::new(image_data)).with_guessed_format()?.into_dimensions()?;
+        self.uploads.lock().unwrap().push(MockUpload { file_name: String::from(file_name), dimensions });
+        Ok(format!("https://images.example/{}", file_name))
+    }
+
+    async fn link_status(&self, _image_link: &str) -> Result<bool, ArtError> {
+        *self.link_checks.lock().unwrap() += 1;
+        Ok(!self.links_expired)
+    }
+}
+
+pub fn fixture_path(file_name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file_name)
+}
+
+// Keeps the hash file and error log written during tests out of the user's config directory.
+pub fn use_test_state_dir() {
+    let state_dir = std::env::temp_dir().join(format!("lamp-drpc-test-{}", std::process::id()));
+    std::fs::create_dir_all(&state_dir).unwrap();
+    paths::init(Some(state_dir.join("lamp.toml")), Some(state_dir));
+}
+
+/*
+ *  Discord IPC frames consist of an opcode and payload length (both little-endian u32) followed by a JSON payload.
+ *
+ *  The Discord client library keeps its ready state in a global and finds the server through $XDG_RUNTIME_DIR,
+ *  so only one FakeDiscord can run at a time. Starting a server waits for any other server to be dropped.
+ */
+#[cfg(unix)]
+const OPCODE_HANDSHAKE: u32 = 0;
+#[cfg(unix)]
+const OPCODE_FRAME: u32 = 1;
+#[cfg(unix)]
+const OPCODE_CLOSE: u32 = 2;
+
+#[cfg(unix)]
+static FAKE_DISCORD_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(unix)]
+pub struct FakeDiscord {
+    socket_path: PathBuf,
+    activities: Arc<Mutex<Vec<Option<Value>>>>,
+    stopped: Arc<AtomicBool>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+#[cfg(unix)]
+impl FakeDiscord {
+    pub fn start() -> FakeDiscord {
+        let lock = FAKE_DISCORD_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let runtime_dir = std::env::temp_dir().join(format!("lamp-drpc-discord-{}", std::process::id()));
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        let socket_path = runtime_dir.join("discord-ipc-0");
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        std::env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+
+        let activities = Arc::new(Mutex::new(Vec::<Option<Value>>::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (thread_activities, thread_stopped) = (activities.clone(), stopped.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let connection_activities = thread_activities.clone();
+                    thread::spawn(move || handle_discord_connection(stream, connection_activities));
+                }
+            }
+        });
+
+        FakeDiscord { socket_path, activities, stopped, _lock: lock }
+    }
+
+    // Waits until the client has connected and is ready to send commands.
+    pub fn wait_until_ready(&self) {
+        wait_until(discord_presence::Client::is_ready);
+    }
+
+    // Waits for the given number of SET_ACTIVITY commands, returning all activities received so far.
+    pub fn wait_for_activities(&self, count: usize) -> Vec<Option<Value>> {
+        wait_until(|| self.activities.lock().unwrap().len() >= count);
+        self.activities.lock().unwrap().clone()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FakeDiscord {
+    fn drop(&mut self) {
+        // Wake the listener so its thread can stop.
+        self.stopped.store(true, Ordering::Relaxed);
+        let _ = UnixStream::connect(&self.socket_path);
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+#[cfg(unix)]
+fn wait_until(condition: impl Fn() -> bool) {
+    let start_time = Instant::now();
+    while !condition() {
+        assert!(start_time.elapsed() < Duration::from_secs(10), "Timed out waiting for the fake Discord server.");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(unix)]
+fn handle_discord_connection(mut stream: UnixStream, activities: Arc<Mutex<Vec<Option<Value>>>>) {
+    loop {
+        let mut header = [0u8; 8];
+        if stream.read_exact(&mut header).is_err() {
+            return;
+        }
+        let opcode = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let mut payload = vec![0u8; u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize];
+        if stream.read_exact(&mut payload).is_err() {
+            return;
+        }
+        let payload: Value = serde_json::from_slice(&payload).unwrap_or(Value::Null);
+
+        let reply = match opcode {
+            OPCODE_HANDSHAKE => json!({
+                "cmd": "DISPATCH",
+                "evt": "READY",
+                "data": {
+                    "v": 1,
+                    "config": { "cdn_host": "cdn.discordapp.com", "api_endpoint": "//discord.com/api", "environment": "production" },
+                    "user": { "id": "1", "username": "lamp", "discriminator": "0", "avatar": null },
+                },
+            }),
+            OPCODE_FRAME if payload["cmd"] == "SET_ACTIVITY" => {
+                let activity = payload["args"].get("activity").cloned().filter(|activity| !activity.is_null());
+                activities.lock().unwrap().push(activity.clone());
+                json!({ "cmd": "SET_ACTIVITY", "data": activity, "evt": null, "nonce": payload["nonce"] })
+            }
+            OPCODE_CLOSE => return,
+            _ => json!({ "cmd": payload["cmd"], "data": null, "evt": null, "nonce": payload["nonce"] }),
+        };
+
+        let reply = reply.to_string();
+        let mut frame = Vec::<u8>::new();
+        frame.extend_from_slice(&OPCODE_FRAME.to_le_bytes());
+        frame.extend_from_slice(&(reply.len() as u32).to_le_bytes());
+        frame.extend_from_slice(reply.as_bytes());
+        if stream.write_all(&frame).is_err() {
+            return;
+        }
+    }
+}
+
+// An HTTP request received by receive_http_request. Header names are lowercase.
+pub struct ReceivedRequest {
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header_name, _)| header_name == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// Accepts a single request on the listener and answers it with the given status and body.
+pub async fn receive_http_request(listener: &TcpListener, status: &str, response_body: &str) -> ReceivedRequest {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut reader = BufReader::new(&mut stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.unwrap();
+    let mut headers = Vec::<(String, String)>::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.unwrap();
+        match header.trim().split_once(':') {
+            Some((name, value)) => headers.push((name.trim().to_lowercase(), value.trim().to_string())),
+            None => break,
+        }
+    }
+
+    let content_length = headers.iter().find(|(name, _)| name == "content-length").map(|(_, value)| value.parse::<usize>().unwrap()).unwrap_or(0);
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await.unwrap();
+
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response_body.len(), response_body);
+    stream.write_all(response.as_bytes()).await.unwrap();
+    ReceivedRequest { request_line: request_line.trim().to_string(), headers, body: String::from_utf8(body).unwrap() }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zbus::connection;
+use zbus::interface;
+use zbus::zvariant::{ObjectPath, OwnedValue, Str, Value};
+
+use crate::events::NowPlaying;
+
+/*
+ *  MPRIS player registered on the session bus as org.mpris.MediaPlayer2.lamp_drpc, when export_mpris is enabled.
+ *
+ *  - The player mirrors the active track of the tracked player, so MPRIS clients (e.g. playerctl, waybar, and desktop
+ *    widgets) can show it even if the player has no MPRIS support of its own. The album art is provided as a local file.
+ *
+ *  - lamp-drpc can't control the player, so CanControl is false and the playback methods return NotSupported.
+ */
+const SERVICE_NAME: &str = "org.mpris.MediaPlayer2.lamp_drpc";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+// The active track, along with the file it was read from and the local copy of its album art.
+#[derive(Clone)]
+pub struct MprisTrack {
+    pub now_playing: NowPlaying,
+    pub file_path: String,
+    pub cover_path: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct PlayerState {
+    track: Option<MprisTrack>,
+    track_number: u64, // Incremented for each track, giving each its own mpris:trackid.
+}
+
+struct RootInterface {
+    player_name: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl RootInterface {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.player_name.clone()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct PlayerInterface {
+    state: Arc<Mutex<PlayerState>>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl PlayerInterface {
+    fn next(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn previous(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn pause(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn play_pause(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn stop(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn play(&self) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn seek(&self, _offset: i64) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn set_position(&self, _track_id: ObjectPath<'_>, _position: i64) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    fn open_uri(&self, _uri: &str) -> zbus::fdo::Result<()> {
+        Err(not_supported())
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        String::from(if self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).track.is_some() { "Playing" } else { "Stopped" })
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(track) = &state.track else {
+            return HashMap::new();
+        };
+        let now_playing = &track.now_playing;
+
+        let mut metadata = HashMap::<String, OwnedValue>::new();
+        if let Ok(track_id) = ObjectPath::try_from(format!("/org/lamp_drpc/track/{}", state.track_number)) {
+            metadata.insert(String::from("mpris:trackid"), OwnedValue::from(track_id));
+        }
+        metadata.insert(String::from("xesam:title"), owned_string(&now_playing.title));
+        if let Some(track_url) = track_url(&track.file_path) {
+            metadata.insert(String::from("xesam:url"), owned_string(&track_url));
+        }
+        if let Ok(artist) = OwnedValue::try_from(Value::from(vec![now_playing.artist.clone()])) {
+            metadata.insert(String::from("xesam:artist"), artist);
+        }
+        if let Some(album) = &now_playing.album {
+            metadata.insert(String::from("xesam:album"), owned_string(album));
+        }
+        if let Some(cover_path) = &track.cover_path {
+            metadata.insert(String::from("mpris:artUrl"), owned_string(&file_url(&cover_path.to_string_lossy())));
+        }
+        if let (Some(start_time), Some(end_time)) = (now_playing.start_time, now_playing.end_time) {
+            metadata.insert(String::from("mpris:length"), OwnedValue::from(end_time.saturating_sub(start_time) as i64 * 1_000_000));
+        }
+        metadata
+    }
+
+    // Position in microseconds, estimated from the track's start time.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(start_time) = state.track.as_ref().and_then(|track| track.now_playing.start_time) else {
+            return 0;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(start_time);
+        now.saturating_sub(start_time) as i64 * 1_000_000
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        false
+    }
+}
+
+fn not_supported() -> zbus::fdo::Error {
+    zbus::fdo::Error::NotSupported(String::from("lamp-drpc can't control the player."))
+}
+
+fn owned_string(value: &str) -> OwnedValue {
+    OwnedValue::from(Str::from(value.to_string()))
+}
+
+// The URL of the track, for local files and streams. Other tracks, such as Mopidy URIs or tracks identified by their
+// artist and title, have none.
+fn track_url(file_path: &str) -> Option<String> {
+    if file_path.contains("://") {
+        Some(String::from(file_path))
+    } else {
+        file_path.starts_with('/').then(|| file_url(file_path))
+    }
+}
+
+// Builds a file:// URL, percent-encoding characters that aren't allowed in a URL path.
+fn file_url(file_path: &str) -> String {
+    let mut url = String::from("file://");
+    for byte in file_path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+pub struct MprisService {
+    connection: zbus::Connection,
+    state: Arc<Mutex<PlayerState>>,
+}
+
+impl MprisService {
+    pub async fn start(player_name: &str) -> Result<MprisService, Box<dyn std::error::Error>> {
+        let state = Arc::new(Mutex::new(PlayerState::default()));
+        let root_interface = RootInterface { player_name: String::from(player_name) };
+        let player_interface = PlayerInterface { state: Arc::clone(&state) };
+
+        let connection = connection::Builder::session()?
+                                            .name(SERVICE_NAME)?
+                                            .serve_at(OBJECT_PATH, root_interface)?
+                                            .serve_at(OBJECT_PATH, player_interface)?
+                                            .build()
+                                            .await?;
+
+        Ok(MprisService { connection, state })
+    }
+
+    pub fn set_track(&self, track: Option<MprisTrack>) {
+        {
+            let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if track.is_some() {
+                state.track_number += 1;
+            }
+            state.track = track;
+        }
+
+        // Notify listening clients that the track has changed. Errors only affect clients relying on signals.
+        // Signals are emitted from their own task, so the event loop never waits on the bus.
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            let Ok(interface_ref) = connection.object_server().interface::<_, PlayerInterface>(OBJECT_PATH).await else {
+                return;
+            };
+            let player_interface = interface_ref.get().await;
+            let emitter = interface_ref.signal_emitter();
+            let _ = player_interface.metadata_changed(emitter).await;
+            let _ = player_interface.playback_status_changed(emitter).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_metadata_for_active_track() {
+        let state = Arc::new(Mutex::new(PlayerState::default()));
+        let player_interface = PlayerInterface { state: Arc::clone(&state) };
+        assert_eq!(player_interface.playback_status(), "Stopped");
+        assert!(player_interface.metadata().is_empty());
+
+        *state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = PlayerState {
+            track: Some(MprisTrack {
+                now_playing: NowPlaying {
+                    artist: String::from("Test Artist"),
+                    title: String::from("Test Title"),
+                    album: Some(String::from("Test Album")),
+                    start_time: Some(1000),
+                    end_time: Some(1180),
+                    ..NowPlaying::default()
+                },
+                file_path: String::from("/music/Test Artist/01 Test Title.flac"),
+                cover_path: Some(PathBuf::from("/state/covers/cover.jpg")),
+            }),
+            track_number: 3,
+        };
+
+        let metadata = player_interface.metadata();
+        assert_eq!(player_interface.playback_status(), "Playing");
+        assert_eq!(metadata["mpris:trackid"], OwnedValue::from(ObjectPath::try_from("/org/lamp_drpc/track/3").unwrap()));
+        assert_eq!(metadata["xesam:title"], owned_string("Test Title"));
+        assert_eq!(metadata["xesam:album"], owned_string("Test Album"));
+        assert_eq!(metadata["xesam:url"], owned_string("file:///music/Test%20Artist/01%20Test%20Title.flac"));
+        assert_eq!(metadata["mpris:artUrl"], owned_string("file:///state/covers/cover.jpg"));
+        assert_eq!(metadata["mpris:length"], OwnedValue::from(180_000_000i64));
+    }
+
+    #[test]
+    fn only_reports_urls_of_local_files_and_streams() {
+        assert_eq!(track_url("/music/track.flac").as_deref(), Some("file:///music/track.flac"));
+        assert_eq!(track_url("https://radio.example/stream").as_deref(), Some("https://radio.example/stream"));
+        assert_eq!(track_url("spotify:track:4uLU6hMCjMI75M1A2tKUQC"), None);
+        assert_eq!(track_url("Artist - Title"), None);
+    }
+}
@@ -0,0 +1,191 @@
+use rumqttc::{AsyncClient, ConnectionError, LastWill, MqttOptions, Outgoing, QoS};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error_log;
+use crate::events::{self, NowPlaying};
+use crate::supervisor;
+
+// Time to wait before reconnecting after the connection to the broker fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Maximum time to wait for pending messages to be sent when lamp-drpc exits.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+ *  Optional MQTT publishing, enabled by setting mqtt_host in lamp.toml.
+ *
+ *  - The playback state is published to mqtt_topic as a retained message, using the same JSON payload as the webhook,
+ *    so new subscribers such as Home Assistant immediately receive what is playing.
+ *  - A "stopped" payload is registered as the last will, so the topic is also updated if lamp-drpc stops unexpectedly.
+ *
+ *  The connection is kept in the MQTT service's own task, and is retried every few seconds while the broker is unavailable.
+ */
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub topic: String,
+    pub credentials: Option<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct MqttService {
+    player_name: String,
+    mqtt_sender: mpsc::UnboundedSender<Value>,
+}
+
+impl MqttService {
+    pub fn start(mqtt_settings: MqttSettings, player_name: &str) -> (MqttService, JoinHandle<()>) {
+        let (mqtt_sender, mqtt_receiver) = mpsc::unbounded_channel::<Value>();
+
+        let mut mqtt_options = MqttOptions::new(format!("lamp-drpc-{}", std::process::id()), mqtt_settings.host, mqtt_settings.port);
+        mqtt_options.set_keep_alive(Duration::from_secs(30));
+        mqtt_options.set_last_will(LastWill::new(&mqtt_settings.topic, events::playback_payload(player_name, None).to_string(), QoS::AtLeastOnce, true));
+        if let Some((username, password)) = mqtt_settings.credentials {
+            mqtt_options.set_credentials(username, password);
+        }
+
+        // The receiver is shared so pending messages are kept if the worker panics and is restarted.
+        let mqtt_receiver = Arc::new(Mutex::new(mqtt_receiver));
+        let mqtt_topic = mqtt_settings.topic;
+        let mqtt_task = supervisor::supervise("mqtt", move || {
+            run_mqtt_task(mqtt_options.clone(), mqtt_topic.clone(), mqtt_receiver.clone())
+        });
+
+        (MqttService { player_name: String::from(player_name), mqtt_sender }, mqtt_task)
+    }
+
+    // Creates a service without a broker connection, returning the receiver for the payloads it is sent.
+    #[cfg(test)]
+    pub fn mock(player_name: &str) -> (MqttService, mpsc::UnboundedReceiver<Value>) {
+        let (mqtt_sender, mqtt_receiver) = mpsc::unbounded_channel::<Value>();
+        (MqttService { player_name: String::from(player_name), mqtt_sender }, mqtt_receiver)
+    }
+
+    // Publishes the given track as playing, or that playback has stopped if there is none.
+    pub fn publish(&self, now_playing: Option<&NowPlaying>) {
+        let _ = self.mqtt_sender.send(events::playback_payload(&self.player_name, now_playing));
+    }
+}
+
+async fn run_mqtt_task(mqtt_options: MqttOptions, mqtt_topic: String, mqtt_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Value>>>) {
+    let mut mqtt_receiver = mqtt_receiver.lock_owned().await;
+    let (mqtt_client, mut mqtt_event_loop) = AsyncClient::new(mqtt_options, 10);
+
+    // The event loop must be polled continuously to keep the connection alive, reconnecting after any error.
+    // It is polled in a separate task, as interrupting a poll can drop the connection while it is being made.
+    let connection_task = tokio::spawn(async move {
+        loop {
+            match mqtt_event_loop.poll().await {
+                Ok(rumqttc::Event::Outgoing(Outgoing::Disconnect)) | Err(ConnectionError::RequestsDone) => break,
+                Ok(_) => (),
+                Err(e) => {
+                    error_log::log_error("mqtt: MQTT Error", e.to_string().as_str());
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    // Publish until every MqttService handle has been dropped.
+    while let Some(payload) = mqtt_receiver.recv().await {
+        if let Err(e) = mqtt_client.try_publish(&mqtt_topic, QoS::AtLeastOnce, true, payload.to_string()) {
+            error_log::log_error("mqtt:try_publish Error", e.to_string().as_str());
+        }
+    }
+
+    // Send any pending messages before disconnecting, giving up if the broker can't be reached in time.
+    let _ = mqtt_client.try_disconnect();
+    let connection_abort = connection_task.abort_handle();
+    if tokio::time::timeout(DISCONNECT_TIMEOUT, connection_task).await.is_err() {
+        connection_abort.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::mock::use_test_state_dir;
+
+    // Reads an MQTT packet, returning its fixed header byte and body.
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let packet_type = stream.read_u8().await.unwrap();
+
+        // The remaining length is encoded in 7-bit groups, least significant first.
+        let (mut remaining_length, mut shift) = (0usize, 0);
+        loop {
+            let length_byte = stream.read_u8().await.unwrap();
+            remaining_length |= ((length_byte & 0x7f) as usize) << shift;
+            if length_byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+
+        let mut body = vec![0u8; remaining_length];
+        stream.read_exact(&mut body).await.unwrap();
+        (packet_type, body)
+    }
+
+    #[tokio::test]
+    async fn publishes_retained_playback_state() {
+        use_test_state_dir();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let mqtt_settings = MqttSettings {
+            host: String::from("127.0.0.1"),
+            port: listener.local_addr().unwrap().port(),
+            topic: String::from("lamp-drpc/now-playing"),
+            credentials: None,
+        };
+        let (mqtt, mqtt_task) = MqttService::start(mqtt_settings, "cmus");
+        mqtt.publish(Some(&NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            start_time: Some(1000),
+            ..NowPlaying::default()
+        }));
+
+        // Accept the connection, then acknowledge the published message.
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (connect_type, _) = read_packet(&mut stream).await;
+        assert_eq!(connect_type, 0x10);
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+        let (publish_type, publish_body) = read_packet(&mut stream).await;
+        let topic_length = u16::from_be_bytes([publish_body[0], publish_body[1]]) as usize;
+        let topic = String::from_utf8(publish_body[2..2 + topic_length].to_vec()).unwrap();
+        let packet_id = &publish_body[2 + topic_length..4 + topic_length];
+        let payload: Value = serde_json::from_slice(&publish_body[4 + topic_length..]).unwrap();
+        stream.write_all(&[0x40, 0x02, packet_id[0], packet_id[1]]).await.unwrap();
+
+        // PUBLISH with QoS 1 and the retain flag set.
+        assert_eq!(publish_type, 0x33);
+        assert_eq!(topic, "lamp-drpc/now-playing");
+        assert_eq!(payload, json!({
+            "event": "playing",
+            "player": "cmus",
+            "artist": "Test Artist",
+            "title": "Test Title",
+            "album": null,
+            "genre": null,
+            "year": null,
+            "label": null,
+            "rating": null,
+            "original_year": null,
+            "art_url": null,
+            "link": null,
+            "start_time": 1000,
+            "end_time": null,
+        }));
+
+        drop(mqtt);
+        assert_eq!(read_packet(&mut stream).await.0, 0xe0);
+        mqtt_task.await.unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use crate::error_log;
+use crate::events::{self, NowPlaying};
+
+/*
+ *  Optional now-playing endpoint, enabled by setting now_playing_port in lamp.toml.
+ *
+ *  - /now-playing reports the active track as JSON, using the same payload as the webhook along with the
+ *    track's progress. Any origin may read it, so it can be fetched from personal websites.
+ *  - / serves a small HTML widget showing the track, for use as a browser source, if now_playing_widget is enabled.
+ *
+ *  The endpoint only listens on localhost.
+ */
+const WIDGET_HTML: &str = include_str!("now_playing_widget.html");
+
+pub async fn start_server(port: u16, player_name: String, widget_enabled: bool, now_playing_receiver: watch::Receiver<Option<NowPlaying>>) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    let local_addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (player_name, now_playing_receiver) = (player_name.clone(), now_playing_receiver.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, &player_name, widget_enabled, &now_playing_receiver).await {
+                            error_log::log_error("now_playing_server:handle_connection Error", e.to_string().as_str());
+                        }
+                    });
+                }
+                Err(e) => error_log::log_error("now_playing_server:listener.accept() Error", e.to_string().as_str()),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle_connection(mut stream: TcpStream, player_name: &str, widget_enabled: bool, now_playing_receiver: &watch::Receiver<Option<NowPlaying>>) -> Result<(), Box<dyn std::error::Error>> {
+    // Only the request line is needed to route the request, e.g. "GET /now-playing HTTP/1.1".
+    let mut request_line = String::new();
+    BufReader::new(&mut stream).read_line(&mut request_line).await?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, content_type, body) = match path {
+        "/now-playing" => ("200 OK", "application/json", now_playing_json(player_name, now_playing_receiver.borrow().as_ref()).to_string()),
+        "/" if widget_enabled => ("200 OK", "text/html; charset=utf-8", String::from(WIDGET_HTML)),
+        _ => ("404 Not Found", "text/plain", String::from("not found\n")),
+    };
+
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+                           status, content_type, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// The playback payload, with the elapsed time and duration of the track in seconds if the player reported them.
+fn now_playing_json(player_name: &str, now_playing: Option<&NowPlaying>) -> Value {
+    let mut payload = events::playback_payload(player_name, now_playing);
+    if let Some(NowPlaying { start_time: Some(start_time), end_time: Some(end_time), .. }) = now_playing {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(*start_time);
+        let duration = end_time.saturating_sub(*start_time);
+        payload["elapsed"] = Value::from(now.saturating_sub(*start_time).min(duration));
+        payload["duration"] = Value::from(duration);
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+    use crate::mock::use_test_state_dir;
+
+    async fn get(local_addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(local_addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn response_body(response: &str) -> &str {
+        response.split_once("\r\n\r\n").unwrap().1
+    }
+
+    #[tokio::test]
+    async fn serves_now_playing_and_widget() {
+        use_test_state_dir();
+        let (now_playing_sender, now_playing_receiver) = watch::channel::<Option<NowPlaying>>(None);
+        let local_addr = start_server(0, String::from("cmus"), true, now_playing_receiver).await.unwrap();
+
+        assert_eq!(serde_json::from_str::<Value>(response_body(&get(local_addr, "/now-playing").await)).unwrap(), json!({ "event": "stopped", "player": "cmus" }));
+
+        // The track started long enough ago to have finished, so its elapsed time is its full duration.
+        now_playing_sender.send_replace(Some(NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            image_link: Some(String::from("https://images.example/art.jpg")),
+            start_time: Some(1000),
+            end_time: Some(1180),
+            ..NowPlaying::default()
+        }));
+        let response = get(local_addr, "/now-playing").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+        let now_playing: Value = serde_json::from_str(response_body(&response)).unwrap();
+        assert_eq!(now_playing["title"], "Test Title");
+        assert_eq!(now_playing["art_url"], "https://images.example/art.jpg");
+        assert_eq!((&now_playing["elapsed"], &now_playing["duration"]), (&json!(180), &json!(180)));
+
+        assert!(response_body(&get(local_addr, "/").await).contains("/now-playing"));
+        assert!(get(local_addr, "/missing").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::art;
+use crate::error::ArtError;
+use crate::error_log;
+use crate::error_log::fs;
+use crate::events::NowPlaying;
+use crate::metadata::AlbumArt;
+use crate::state_file;
+use crate::supervisor;
+
+/*
+ *  Optional overlay files, for use as text and image sources in OBS or other streaming software.
+ *
+ *  - The title, artist, and album of the active track are each written to their own file, if configured. The files are
+ *    emptied when nothing is playing.
+ *  - The album art is resized as it would be for upload and written to the cover file, which is removed for tracks without album art.
+ *
+ *  Files are replaced atomically, so they are never read partially written.
+ */
+#[derive(Clone, Default)]
+pub struct OverlayFiles {
+    pub title_file: Option<PathBuf>,
+    pub artist_file: Option<PathBuf>,
+    pub album_file: Option<PathBuf>,
+    pub cover_file: Option<PathBuf>,
+}
+
+pub struct OverlayUpdate {
+    overlay_files: OverlayFiles,
+    now_playing: Option<NowPlaying>,
+    album_art: Option<AlbumArt>,
+}
+
+#[derive(Clone)]
+pub struct OverlayService {
+    overlay_sender: mpsc::UnboundedSender<OverlayUpdate>,
+}
+
+impl OverlayFiles {
+    pub fn is_empty(&self) -> bool {
+        self.title_file.is_none() && self.artist_file.is_none() && self.album_file.is_none() && self.cover_file.is_none()
+    }
+}
+
+impl OverlayService {
+    pub fn start() -> (OverlayService, JoinHandle<()>) {
+        let (overlay_sender, overlay_receiver) = mpsc::unbounded_channel::<OverlayUpdate>();
+
+        // The receiver is shared so pending updates are kept if the worker panics and is restarted.
+        let overlay_receiver = Arc::new(Mutex::new(overlay_receiver));
+        let overlay_task = supervisor::supervise("overlay", move || run_overlay_task(overlay_receiver.clone()));
+
+        (OverlayService { overlay_sender }, overlay_task)
+    }
+
+    pub fn update(&self, overlay_files: &OverlayFiles, now_playing: Option<&NowPlaying>, album_art: Option<AlbumArt>) {
+        let _ = self.overlay_sender.send(OverlayUpdate { overlay_files: overlay_files.clone(), now_playing: now_playing.cloned(), album_art });
+    }
+}
+
+async fn run_overlay_task(overlay_receiver: Arc<Mutex<mpsc::UnboundedReceiver<OverlayUpdate>>>) {
+    let mut overlay_receiver = overlay_receiver.lock_owned().await;
+
+    // Write updates until every OverlayService handle has been dropped.
+    while let Some(mut overlay_update) = overlay_receiver.recv().await {
+        // Only the latest track needs to be written, so updates that were replaced while waiting are skipped.
+        while let Ok(next_update) = overlay_receiver.try_recv() {
+            overlay_update = next_update;
+        }
+
+        // Resizing album art is CPU-bound, so the files are written on a blocking thread.
+        if let Err(e) = tokio::task::spawn_blocking(move || write_overlay_files(overlay_update)).await {
+            error_log::log_error("overlay:write_overlay_files Error", e.to_string().as_str());
+        }
+    }
+}
+
+fn write_overlay_files(overlay_update: OverlayUpdate) {
+    let OverlayUpdate { overlay_files, now_playing, album_art } = overlay_update;
+    let text_files = [
+        (&overlay_files.title_file, now_playing.as_ref().map(|now_playing| now_playing.title.clone())),
+        (&overlay_files.artist_file, now_playing.as_ref().map(|now_playing| now_playing.artist.clone())),
+        (&overlay_files.album_file, now_playing.as_ref().and_then(|now_playing| now_playing.album.clone())),
+    ];
+    for (text_file, text) in text_files {
+        if let Some(text_file) = text_file {
+            if let Err(e) = state_file::write_atomic(text_file, text.unwrap_or_default().as_bytes()) {
+                error_log::log_error("overlay:write_atomic Error", format!("Could not write overlay file {}: {}", text_file.display(), e).as_str());
+            }
+        }
+    }
+
+    let Some(cover_file) = &overlay_files.cover_file else {
+        return;
+    };
+    let cover_result = match album_art.filter(|_| now_playing.is_some()) {
+        Some(album_art) => art::resize_album_art(album_art, art::ArtEncoding::default()).and_then(|image_data| Ok(state_file::write_atomic(cover_file, &image_data)?)),
+        None => match fs::remove_file(cover_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ArtError::from(e)),
+            _ => Ok(()),
+        },
+    };
+    if let Err(e) = cover_result {
+        error_log::log_error("overlay:write_cover Error", format!("Could not update overlay cover {}: {}", cover_file.display(), e).as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::art_lookup::ArtSource;
+    use crate::metadata::read_metadata;
+    use crate::mock::{fixture_path, use_test_state_dir};
+    use crate::paths;
+
+    #[tokio::test]
+    async fn writes_and_clears_overlay_files() {
+        use_test_state_dir();
+        let overlay_dir = paths::state_dir().join("overlay");
+        std::fs::create_dir_all(&overlay_dir).unwrap();
+        let overlay_files = OverlayFiles {
+            title_file: Some(overlay_dir.join("title.txt")),
+            artist_file: Some(overlay_dir.join("artist.txt")),
+            album_file: Some(overlay_dir.join("album.txt")),
+            cover_file: Some(overlay_dir.join("cover.jpg")),
+        };
+        let now_playing = NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            ..NowPlaying::default()
+        };
+        let album_art = read_metadata(&fixture_path("tagged.mp3"), &false, &[], &[], &[], &ArtSource::ALL, u64::MAX).unwrap().album_art;
+
+        let (overlay, overlay_task) = OverlayService::start();
+        overlay.update(&overlay_files, Some(&now_playing), album_art);
+        drop(overlay);
+        overlay_task.await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(overlay_dir.join("title.txt")).unwrap(), "Test Title");
+        assert_eq!(std::fs::read_to_string(overlay_dir.join("artist.txt")).unwrap(), "Test Artist");
+        assert_eq!(std::fs::read_to_string(overlay_dir.join("album.txt")).unwrap(), "Test Album");
+        assert_eq!(image::image_dimensions(overlay_dir.join("cover.jpg")).unwrap(), (512, 512));
+
+        let (overlay, overlay_task) = OverlayService::start();
+        overlay.update(&overlay_files, None, None);
+        drop(overlay);
+        overlay_task.await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(overlay_dir.join("title.txt")).unwrap(), "");
+        assert!(!overlay_dir.join("cover.jpg").exists());
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/*
+ *  Locations of the configuration file and the state directory, which holds the album art hash file and error log.
+ *
+ *  - Paths passed with --config and --state-dir take priority.
+ *  - Otherwise, both are kept in ~/.config/lamp-drpc, or %APPDATA%\lamp-drpc on Windows.
+ *  - Without a home directory (e.g. in containers or system services), SYSTEM_DIR is used instead.
+ */
+struct Paths {
+    config_file: PathBuf,
+    state_dir: PathBuf,
+}
+
+#[cfg(unix)]
+const SYSTEM_DIR: &str = "/etc/lamp-drpc";
+#[cfg(windows)]
+const SYSTEM_DIR: &str = "C:\\ProgramData\\lamp-drpc";
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+// Must be called before the paths are first used, otherwise the defaults are kept.
+pub fn init(config_file: Option<PathBuf>, state_dir: Option<PathBuf>) {
+    let _ = PATHS.set(Paths {
+        config_file: config_file.unwrap_or_else(|| default_dir().join("lamp.toml")),
+        state_dir: state_dir.unwrap_or_else(default_dir),
+    });
+}
+
+pub fn config_file() -> &'static Path {
+    &paths().config_file
+}
+
+pub fn state_dir() -> &'static Path {
+    &paths().state_dir
+}
+
+fn paths() -> &'static Paths {
+    PATHS.get_or_init(|| Paths {
+        config_file: default_dir().join("lamp.toml"),
+        state_dir: default_dir(),
+    })
+}
+
+#[cfg(unix)]
+fn default_dir() -> PathBuf {
+    match std::env::home_dir() {
+        Some(home_path) => home_path.join(".config/lamp-drpc"),
+        None => PathBuf::from(SYSTEM_DIR),
+    }
+}
+
+#[cfg(windows)]
+fn default_dir() -> PathBuf {
+    match std::env::var_os("APPDATA").filter(|app_data| !app_data.is_empty()) {
+        Some(app_data) => PathBuf::from(app_data).join("lamp-drpc"),
+        None => PathBuf::from(SYSTEM_DIR),
+    }
+}
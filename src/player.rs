@@ -1,8 +1,44 @@
-pub use std::path::Path;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::fd::AsFd;
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::{self, AssertUnwindSafe};
+pub use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(unix)]
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+#[cfg(unix)]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde_json::{json, Value};
+use sysinfo::{Pid, Process, ProcessStatus, ProcessesToUpdate, ProcessRefreshKind, System};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+#[cfg(windows)]
+use sysinfo::UpdateKind;
+use tokio::sync::mpsc::UnboundedSender;
+use zbus::zvariant::OwnedValue;
 
+use crate::error::PlayerError;
 use crate::error_log;
 use crate::error_log::fs;
 use crate::error_log::process;
+use crate::events::{Event, PlayerMetadata, PlayerTags, Track};
+use crate::http;
+use crate::supervisor;
+
+// Time to wait between each poll of the active player.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// A track whose position jumps back to within this many seconds of its start is treated as restarted.
+const RESTART_POSITION: u64 = 2;
 
 /* 
  *  StandardPlayer includes definitions for standard functionality, including filename retrieval and secondary status verification.
@@ -10,123 +46,473 @@ use crate::error_log::process;
  *  - Only get_active_file_path is required to have an actual implementation for minimum functionality (Showing metadata for a current track on Discord).
  * 
  *  - verify_running is only used as a secondary check in addition to referencing a player's PID, to be really sure that the player is running properly.
- *    If no such secondary check is desired, this function should simply return true.
+ *    If no such secondary check is desired, this function should simply return Ok(true).
+ *
+ *  - Errors should be returned rather than exiting, so main.rs can decide how lamp-drpc shuts down.
  *  
  *  - Implementing get_duration will enable the display of a progress bar on Discord's rich presence in addition to the metadata.
+ *
+ *  - Implementing get_position allows restarts of the active track (including repeats of a single track) to be detected
+ *    as soon as they happen, rather than only once its duration has elapsed.
+ *
+ *  - Players that are found through another interface rather than by process name (e.g. MPRIS players) implement
+ *    get_process_name to report the name of the player's process, which is then used in place of player_name.
+ *    Other players should return None.
+ *
+ *  - Players that don't play local files (e.g. streaming players) implement get_metadata to report the active track's
+ *    artist, title, album, and album art link, which are shown in place of tags read from a file. get_active_file_path
+ *    should then return something that identifies the track, such as its URL. Players of local files should return None.
+ *
+ *  - Players that keep tags in their library beyond those in the file (e.g. Strawberry's ratings) implement
+ *    get_player_tags to report them for the active track. Other players should return PlayerTags::default().
+ *
+ *  - wait_for_change is called between polls. Players that announce changes (e.g. musikcube) implement it to return as
+ *    soon as the player reports one, so track changes are shown without waiting for the next poll. Other players should
+ *    simply sleep for the timeout.
+ *
+ *  - get_playback_state reports whether the active track is playing or paused, so a paused track isn't shown counting
+ *    down. A track reported as Stopped is treated as if no file were active. Players that can't tell should report Playing.
  */
 pub trait StandardPlayer {
-    fn verify_running(&self) -> bool;
-    fn get_active_file_path(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>>;
+    fn verify_running(&self) -> Result<bool, PlayerError>;
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError>;
     fn get_duration(&self) -> Option<u64>;
+    fn get_position(&self) -> Option<u64>;
+    fn get_process_name(&self) -> Option<String>;
+    fn get_metadata(&self) -> Option<PlayerMetadata>;
+    fn get_player_tags(&self) -> PlayerTags;
+    fn get_playback_state(&self) -> PlaybackState;
+    fn wait_for_change(&mut self, timeout: Duration);
+}
+
+// The playback state of the active track, as read by get_active_file_path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/*
+ *  Polls the active player and emits events to the event loop whenever its state changes.
+ *
+ *  - TrackChanged is emitted when the active file changes, or when the same file is played again. A file is played again
+ *    when its position jumps back to its start, or, for players that don't report a position, once its duration has elapsed.
+ *  - PositionChanged is emitted when the position reported by the player drifts from the position expected from the
+ *    track's start time by at least resync_seconds, such as after a seek or pause. 0 disables resyncing.
+ *  - Stopped is emitted when the player no longer reports an active file, or reports it as stopped.
+ *  - Paused is emitted when the active track is paused. Once it resumes, PositionChanged is emitted with its start time
+ *    moved to its position, or, for players that don't report a position, forward by the time it was paused.
+ *  - File paths are canonicalized before they are compared or reported, so a file reached through different symlinks is
+ *    only ever read, uploaded, and recorded under one path.
+ *  - PlayerExited is emitted once the player process has exited, or the player reports that it has, after which polling
+ *    ends. Other errors reading the player are logged and polling continues while its process is running. If the player
+ *    has exited but another process with its name is running (e.g. after it re-executed itself), polling continues with that process,
+ *    as long as the player's secondary check still passes. A process that only shares the player's name, such as an
+ *    instance without the player's socket, isn't followed.
+ *    Stopped processes (e.g. suspended with Ctrl-Z) are still running, while zombie and dead processes have exited.
+ *  - poll_player_until also ends polling, emitting PlayerExited, once switch_player returns true, such as when a player
+ *    that is preferred over this one has started. It is called once per poll.
+ */
+pub fn poll_player<P: StandardPlayer>(player: &mut P, sys: System, player_pid: Pid, player_name: &str, resync_seconds: u64, event_sender: UnboundedSender<Event>) {
+    poll_player_until(player, sys, player_pid, player_name, resync_seconds, event_sender, || false);
+}
+
+pub fn poll_player_until<P: StandardPlayer, F: FnMut() -> bool>(player: &mut P, mut sys: System, mut player_pid: Pid, player_name: &str, resync_seconds: u64, event_sender: UnboundedSender<Event>, mut switch_player: F) {
+    let mut previous_file_path = String::new(); // The path of the previous track, used to determine when the active track has changed.
+    let mut previous_update_time = Instant::now(); // The time of the previous file update.
+    let mut previous_duration: Option<u64> = None; // The duration of the previous track.
+    let mut previous_position: Option<u64> = None; // The position of the active track at the previous poll.
+    let mut track_start_time: Option<u64> = None; // The Unix timestamp at which the active track was last reported to have started.
+    let mut pause_time: Option<Instant> = None; // The time at which the active track was paused, while it is paused.
+
+    loop {
+        // A panic while reading the player's output is handled like any other error reading the player, so one
+        // malformed response can't stop polling.
+        let active_file_path = panic::catch_unwind(AssertUnwindSafe(|| player.get_active_file_path()))
+                                     .unwrap_or_else(|panic_payload| Err(PlayerError::Panicked(supervisor::panic_message(panic_payload))));
+
+        // A track the player reports as stopped is treated as if no file were active.
+        let playback_state = player.get_playback_state();
+        let active_file_path = active_file_path.map(|file_path| file_path.filter(|_| playback_state != PlaybackState::Stopped));
+
+        match active_file_path {
+            Ok(Some(file_path)) => {
+                let file_path = canonical_path(file_path);
+                let paused = playback_state == PlaybackState::Paused;
+
+                // Only emit an event if file has changed or has been restarted.
+                // The position is used when the player reports it, so restarts are noticed even before the track finishes.
+                let active_position = player.get_position();
+                let track_restarted = match (previous_position, active_position, previous_duration) {
+                    (Some(previous_position), Some(position), _) => position < previous_position && position <= RESTART_POSITION,
+                    (_, None, Some(seconds)) => !paused && Instant::now().duration_since(previous_update_time) >= Duration::from_secs(seconds),
+                    _ => false,
+                };
+                previous_position = active_position;
+
+                if file_path != previous_file_path || track_restarted {
+                    // Record time of file change.
+                    let active_duration = player.get_duration();
+                    previous_update_time = Instant::now();
+                    previous_duration = active_duration;
+
+                    // Tracks that are already partway through when they are first seen start as far back as their position.
+                    let (start_time, end_time) = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                        Ok(time) => {
+                            let start_time = time.as_secs().saturating_sub(active_position.unwrap_or_default());
+                            (Some(start_time), active_duration.map(|duration| start_time + duration))
+                        }
+                        Err(e) => {
+                            error_log::log_error("player:poll_player:duration_since() Error", e.to_string().as_str());
+                            (None, None)
+                        }
+                    };
+                    track_start_time = start_time;
+
+                    let _ = event_sender.send(Event::TrackChanged(Track { file_path: file_path.clone(), start_time, end_time, metadata: player.get_metadata(), tags: player.get_player_tags() }));
+                    pause_time = None;
+                    if paused {
+                        pause_time = Some(Instant::now());
+                        let _ = event_sender.send(Event::Paused);
+                    }
+                } else if paused {
+                    if pause_time.is_none() {
+                        pause_time = Some(Instant::now());
+                        let _ = event_sender.send(Event::Paused);
+                    }
+                } else if let Some(paused_at) = pause_time.take() {
+                    // The time spent paused doesn't count towards the track's duration.
+                    let paused_for = paused_at.elapsed();
+                    previous_update_time += paused_for;
+                    if let Some(start_time) = track_start_time {
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(start_time);
+                        let start_time = active_position.map(|position| now.saturating_sub(position)).unwrap_or(start_time + paused_for.as_secs());
+                        track_start_time = Some(start_time);
+                        let _ = event_sender.send(Event::PositionChanged { start_time, end_time: previous_duration.map(|duration| start_time + duration) });
+                    }
+                } else if let (true, Some(position), Some(start_time)) = (resync_seconds > 0, active_position, track_start_time) {
+                    // Resync the start time from the position if the track has been paused or seeked.
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(start_time);
+                    if now.saturating_sub(start_time).abs_diff(position) >= resync_seconds {
+                        let start_time = now.saturating_sub(position);
+                        track_start_time = Some(start_time);
+                        let _ = event_sender.send(Event::PositionChanged { start_time, end_time: previous_duration.map(|duration| start_time + duration) });
+                    }
+                }
+
+                previous_file_path = file_path;
+            }
+            Ok(None) => {
+                if !previous_file_path.is_empty() {
+                    previous_file_path.clear();
+                    previous_duration = None;
+                    previous_position = None;
+                    track_start_time = None;
+                    pause_time = None;
+                    let _ = event_sender.send(Event::Stopped);
+                }
+            }
+            // A player that reports it has exited stops polling right away. Other errors, such as a dropped connection,
+            // are logged and the previous state is kept, so polling only ends below once the process is gone.
+            Err(PlayerError::Exited(_)) => break,
+            Err(e) => error_log::log_error("player:poll_player Error", format!("The active track of {} could not be read: {}", player_name, e).as_str()),
+        }
+
+        // Stop polling if the event loop has exited.
+        if event_sender.is_closed() {
+            return;
+        }
+
+        // Check that the player is still running, following it to a new process if its PID has changed.
+        // Stop polling once no process with its name is left.
+        if !is_running(&mut sys, player_pid, player_name) {
+            match get_pid_by_proc_name(&mut sys, player_name) {
+                Ok(new_player_pid) if matches!(player.verify_running(), Ok(true)) => player_pid = new_player_pid,
+                _ => break,
+            }
+        }
+
+        // Stop polling if another player should be followed instead.
+        if switch_player() {
+            break;
+        }
+
+        player.wait_for_change(POLL_INTERVAL);
+    }
+
+    let _ = event_sender.send(Event::PlayerExited);
+}
+
+/*
+ *  Finds the PID of the player's process by its exact name.
+ *
+ *  - Processes are found through /proc, so only matching processes are refreshed rather than every process on the system.
+ *    Windows has no /proc, so every process is refreshed along with its owner.
+ *  - Only processes owned by the same user as lamp-drpc are considered, since another user's player can't be
+ *    controlled or queried.
+ *  - Zombie and dead processes are skipped. If several processes remain (e.g. the player has forked), the one that
+ *    started first is used.
+ */
+#[cfg(unix)]
+pub fn get_pid_by_proc_name(sys: &mut System, proc_name: &str) -> Result<Pid, PlayerError> {
+    let comm_name = comm_name(proc_name.as_bytes());
+    let user_id = fs::metadata("/proc/self")?.uid();
+    let mut matching_pids = Vec::<Pid>::new();
+    for proc_entry in fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = proc_entry.file_name().to_str().and_then(|pid| pid.parse::<usize>().ok()) else {
+            continue;
+        };
+
+        // Processes may exit while /proc is being read, so unreadable entries are skipped.
+        let owned_by_user = fs::metadata(proc_entry.path()).map(|proc_metadata| proc_metadata.uid() == user_id).unwrap_or(false);
+        if let (true, Ok(comm)) = (owned_by_user, fs::read(proc_entry.path().join("comm"))) {
+            if comm.strip_suffix(b"\n").unwrap_or(&comm) == comm_name {
+                matching_pids.push(Pid::from(pid));
+            }
+        }
+    }
+
+    sys.refresh_processes_specifics(ProcessesToUpdate::Some(&matching_pids), true, ProcessRefreshKind::nothing());
+    first_live_player(matching_pids.iter().filter_map(|pid| sys.process(*pid)), proc_name)
+}
+
+#[cfg(windows)]
+pub fn get_pid_by_proc_name(sys: &mut System, proc_name: &str) -> Result<Pid, PlayerError> {
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing().with_user(UpdateKind::OnlyIfNotSet));
+    let user_id = sysinfo::get_current_pid().ok().and_then(|lamp_pid| sys.process(lamp_pid)).and_then(Process::user_id).cloned();
+    first_live_player(sys.processes().values().filter(|player_process| player_process.user_id() == user_id.as_ref()), proc_name)
+}
+
+fn first_live_player<'a>(processes: impl Iterator<Item = &'a Process>, proc_name: &str) -> Result<Pid, PlayerError> {
+    let player_process = processes.filter(|player_process| is_live_player(player_process, proc_name))
+                                  .min_by_key(|player_process| (player_process.start_time(), player_process.pid()));
+    match player_process {
+        Some(player_process) => Ok(player_process.pid()),
+        None => Err(PlayerError::NotFound(String::from(proc_name))),
+    }
+}
+
+// Refreshes the process and checks that it is still the player. A PID that has been reused by another process after
+// the player exited no longer matches the player's name.
+fn is_running(sys: &mut System, player_pid: Pid, proc_name: &str) -> bool {
+    sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[player_pid]), true, ProcessRefreshKind::nothing());
+    sys.process(player_pid).is_some_and(|player_process| is_live_player(player_process, proc_name))
+}
+
+fn is_live_player(player_process: &Process, proc_name: &str) -> bool {
+    is_process_name(player_process.name().as_encoded_bytes(), proc_name) && !matches!(player_process.status(), ProcessStatus::Zombie | ProcessStatus::Dead)
+}
+
+// Process names are compared as the kernel keeps them, so players named with more than 15 bytes are still matched.
+#[cfg(unix)]
+fn is_process_name(process_name: &[u8], proc_name: &str) -> bool {
+    comm_name(process_name) == comm_name(proc_name.as_bytes())
+}
+
+// Windows process names are case-insensitive and end in .exe, which may be left out of proc_name, e.g. MusicBee for
+// MusicBee.exe.
+#[cfg(windows)]
+fn is_process_name(process_name: &[u8], proc_name: &str) -> bool {
+    let process_name = String::from_utf8_lossy(process_name);
+    process_name.eq_ignore_ascii_case(proc_name) || process_name.eq_ignore_ascii_case(&format!("{}.exe", proc_name))
+}
+
+// The kernel truncates process names in /proc/<pid>/comm, and the process status read by sysinfo, to 15 bytes.
+#[cfg(unix)]
+fn comm_name(proc_name: &[u8]) -> &[u8] {
+    &proc_name[..proc_name.len().min(15)]
+}
+
+// Commands from lamp.toml are run through sh, or through cmd on Windows, so they may use pipes and quoting.
+pub fn shell_command(command: &str) -> process::Command {
+    #[cfg(unix)]
+    let mut shell_command = process::Command::new("sh");
+    #[cfg(unix)]
+    shell_command.arg("-c");
+    #[cfg(windows)]
+    let mut shell_command = process::Command::new("cmd");
+    #[cfg(windows)]
+    shell_command.arg("/C");
+    shell_command.arg(command);
+    shell_command
+}
+
+// Resolves symlinks and relative components of the file path. Paths that can't be resolved, such as stream URLs or files
+// that have since been removed, are returned as given.
+fn canonical_path(file_path: String) -> String {
+    match fs::canonicalize(&file_path) {
+        Ok(canonical_path) => canonical_path.to_str().map(String::from).unwrap_or(file_path),
+        Err(_) => file_path,
+    }
+}
+
+// Converts a file:// URI to a local path, decoding percent-encoded characters. Returns None for other URIs, such as
+// streams, and for URIs that don't decode to UTF-8.
+// Some players (e.g. Clementine) leave characters such as spaces and percent signs unencoded, so a percent sign that
+// isn't followed by two hex digits is kept as it is.
+fn file_uri_to_path(uri: &str) -> Option<String> {
+    let encoded_path = uri.strip_prefix("file://")?;
+    let encoded_path = encoded_path.strip_prefix("localhost").unwrap_or(encoded_path).as_bytes();
+    let mut path_bytes = Vec::<u8>::with_capacity(encoded_path.len());
+    let mut index = 0;
+    while index < encoded_path.len() {
+        let decoded_byte = match encoded_path[index] {
+            b'%' => encoded_path.get(index + 1..index + 3).and_then(|hex_digits| std::str::from_utf8(hex_digits).ok())
+                                                            .and_then(|hex_digits| u8::from_str_radix(hex_digits, 16).ok()),
+            _ => None,
+        };
+        match decoded_byte {
+            Some(decoded_byte) => {
+                path_bytes.push(decoded_byte);
+                index += 3;
+            }
+            None => {
+                path_bytes.push(encoded_path[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(path_bytes).ok()
+}
+
+// Reads an integer D-Bus value, which players send as any of the signed or unsigned integer types.
+fn integer_value(value: &OwnedValue) -> Option<i64> {
+    i64::try_from(&**value).ok()
+        .or(i32::try_from(&**value).ok().map(i64::from))
+        .or(u32::try_from(&**value).ok().map(i64::from))
+        .or(u64::try_from(&**value).ok().and_then(|value| i64::try_from(value).ok()))
 }
 
 /************************** Function Implementations for cmus **************************/
+/*
+ *  cmus is polled through cmus-remote -Q.
+ *
+ *  - cmus runs its status_display_program whenever its status changes. lamp-drpc listens on CMUS_STATUS_SOCKET in the
+ *    runtime directory, and a program that runs lamp-drpc --cmus-status connects to it, which ends the wait between polls
+ *    so track changes are shown right away. Without it, cmus is still polled every POLL_INTERVAL.
+ *  - With cmus_status_display, lamp-drpc writes such a program to the runtime directory and sets it as cmus's
+ *    status_display_program once cmus is running, replacing any other.
+ */
+#[cfg(unix)]
 pub struct Cmus {
     pub cmus_remote_output: Option<String>,
     active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+    socket_path: PathBuf,
+    status_listener: Option<UnixListener>,
+    status_display_program: Option<PathBuf>,
+    status_display_installed: bool,
 }
 
-impl Default for Cmus {
-    fn default() -> Self {
+#[cfg(unix)]
+pub const CMUS_STATUS_SOCKET: &str = "lamp-drpc-cmus.sock";
+#[cfg(unix)]
+const CMUS_STATUS_PROGRAM: &str = "lamp-drpc-cmus-status";
+
+#[cfg(unix)]
+impl Cmus {
+    // cmus-remote is pointed at socket_path, so the cmus that is checked for is the one that is queried.
+    pub fn new(runtime_dir: &Path, socket_path: PathBuf, cmus_status_display: bool) -> Self {
+        let status_listener = listen_for_cmus_status(runtime_dir);
+        if let Err(e) = &status_listener {
+            error_log::log_error("player:Cmus:listen_for_cmus_status Error", format!("Status changes can't be received from cmus, so it is only polled: {}", e).as_str());
+        }
+        let status_display_program = cmus_status_display.then(|| write_cmus_status_program(runtime_dir)).and_then(|status_display_program| {
+            status_display_program.map_err(|e| error_log::log_error("player:Cmus:write_cmus_status_program Error", e.to_string().as_str())).ok()
+        });
         Cmus {
             cmus_remote_output: Some(String::new()),
             active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+            socket_path,
+            status_listener: status_listener.ok(),
+            status_display_program,
+            status_display_installed: false,
         }
     }
-}
 
-impl Cmus {
-    fn update_cmus_remote_output() -> Option<String> {
+    // Sets cmus's status_display_program the first time cmus answers, and again once cmus has exited and answers again.
+    fn install_status_display_program(&mut self) {
+        let Some(status_display_program) = &self.status_display_program else {
+            return;
+        };
+        if self.status_display_installed {
+            return;
+        }
+        self.status_display_installed = true;
+        let set_command = format!("set status_display_program={}", status_display_program.display());
+        match process::Command::new("cmus-remote").arg("--server").arg(&self.socket_path).arg("-C").arg(set_command).output() {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => error_log::log_error("player:Cmus:install_status_display_program Error", format!("cmus-remote exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).as_str()),
+            Err(e) => error_log::log_error("player:Cmus:install_status_display_program Error", e.to_string().as_str()),
+        }
+    }
+
+    fn update_cmus_remote_output(&self) -> Result<Option<String>, PlayerError> {
         // Get info about current track from cmus-remote.
         let cmus_remote_output = process::Command::new("cmus-remote")
+                                                                .arg("--server")
+                                                                .arg(&self.socket_path)
                                                                 .arg("-Q")
                                                                 .output();
         
-        // If output returns an error, log and exit. Otherwise, attempt to process string.
+        // If output returns an error, log and return it. Otherwise, attempt to process string.
         match cmus_remote_output {
             Ok(output) => {
                 match String::from_utf8(output.stdout) {
-                    Ok(ok_string) => Some(ok_string),
+                    Ok(ok_string) => Ok(Some(ok_string)),
                     Err(e) => {
                         error_log::log_error("UTF-8 Error", e.to_string().as_str());
-                        return None;
+                        Ok(None)
                     }
                 }
             }
             Err(e) => {
                 error_log::log_error("player:Cmus:update_cmus_remote_output Error", e.to_string().as_str());
-                process::exit(1);
+                Err(PlayerError::Io(e))
             }
         }
     }
 }
 
+#[cfg(unix)]
 impl StandardPlayer for Cmus {
-    fn verify_running(&self) -> bool {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
         // If cmus-socket exists and is not a directory/symlink, secondary check is passed.
-        match fs::exists("/run/user/1000/cmus-socket") {
-            Ok(true) if !Path::new("/run/user/1000/cmus-socket").is_dir() => true,
+        match fs::exists(&self.socket_path) {
+            Ok(true) if !self.socket_path.is_dir() => Ok(true),
             Ok(true) => { 
                 // File exists, but is a directory.
-                error_log::log_error("player:Cmus:verify_running Error", "File at /run/user/1000/cmus-socket is not a normal file. It may be a directory or was unaccessible.");
-                false
+                error_log::log_error("player:Cmus:verify_running Error", format!("File at {} is not a normal file. It may be a directory or was unaccessible.", self.socket_path.display()).as_str());
+                Ok(false)
             },
-            Ok(false) => false,
-            Err(io_error) => {
-                error_log::log_error("player:Cmus:verify_running Error", io_error.to_string().as_str());
-                process::exit(1);
-            }
+            Ok(false) => Ok(false),
+            Err(io_error) => Err(PlayerError::Io(io_error)),
         }
     }
 
-    fn get_active_file_path(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
         // Update output from cmus-remote, along with position and duration.
         // This update will always occur before position and duration is requested in the main loop, so those values will always be up to date.
-        self.cmus_remote_output = Cmus::update_cmus_remote_output();
+        self.cmus_remote_output = self.update_cmus_remote_output()?;
         match &self.cmus_remote_output  {
             Some(cmus_remote_output) => {
-                let output_string_lines = cmus_remote_output.split('\n').collect::<Vec<&str>>();
-
-                let active_file_path: Option<String>;
-                let active_file_duration: Option<&str>;
-                //let active_file_position: Option<&str>;
-
-                // Check the status reported by cmus-remote.
-                match output_string_lines[0] {
-                    // If playing, paused, or stopped, read file path, duration, and position as normal from output.
-                    // If file path cannot be parsed, log error and exit.
-                    "status playing" | "status paused" | "status stopped" => {
-                        match output_string_lines[1].strip_prefix("file ") {
-                            Some(file_path) => {
-                                active_file_path = Some(file_path.to_string());
-                                active_file_duration = output_string_lines[2].strip_prefix("duration ");
-                            },
-                            None => {
-                                active_file_path = None;
-                                active_file_duration = None;
-                            }
-                        };
-                        
-                        //active_file_position = output_string_lines[3].strip_prefix("position ");
-                    },
-                    &_ => return Err(Box::from("cmus has exited.")),
-                }
-
-                // Check str options. If duration and position could not be parsed, set to None. 
-                self.active_duration = match active_file_duration.unwrap_or_default().parse::<u64>() {
-                    Ok(duration) => Some(duration),
-                    Err(_) => None,
+                // cmus-remote only omits the status line once cmus has exited.
+                let Some(cmus_status) = parse_cmus_remote_output(cmus_remote_output) else {
+                    self.status_display_installed = false;
+                    return Err(PlayerError::Exited(String::from("cmus")));
                 };
 
-                /* match active_file_position.unwrap_or_default().parse::<u64>() {
-                    Ok(position) => self.active_position_duration.0 = Some(position),
-                    Err(e) => {
-                        error_log::log_error("player:Cmus:get_active_file_path Error", e.to_string().as_str());
-                        self.active_position_duration.0 = None;
-                    }
-                } */
-
-                Ok(active_file_path)
+                // If duration and position could not be parsed, set to None.
+                self.active_state = cmus_status.state;
+                self.active_duration = cmus_status.duration;
+                self.active_position = cmus_status.position;
+                let file_path = cmus_status.file_path.map(String::from);
+                self.install_status_display_program();
+                Ok(file_path)
             }
             None => Ok(None)
         }
@@ -135,45 +521,3192 @@ impl StandardPlayer for Cmus {
     fn get_duration(&self) -> Option<u64> {
         self.active_duration
     }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        let Some(status_listener) = &self.status_listener else {
+            thread::sleep(timeout);
+            return;
+        };
+        let mut poll_fds = [PollFd::new(status_listener.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut poll_fds, PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX)) {
+            Ok(0) => {}
+            // cmus runs its status_display_program several times for a single change, so all pending connections are
+            // accepted at once.
+            Ok(_) => while status_listener.accept().is_ok() {},
+            Err(_) => thread::sleep(timeout),
+        }
+    }
+}
+
+// The values reported by cmus-remote -Q that are used to follow the active track.
+#[cfg(unix)]
+struct CmusStatus<'a> {
+    state: PlaybackState,
+    file_path: Option<&'a str>,
+    duration: Option<u64>,
+    position: Option<u64>,
+}
+
+// Reads the status, file, duration, and position lines from cmus-remote -Q output by their prefixes, wherever they appear.
+// cmus leaves out the file and duration lines when nothing is loaded, and adds stream and tag lines for some entries,
+// so lines are never read by position. Returns None if there is no status line.
+#[cfg(unix)]
+fn parse_cmus_remote_output(cmus_remote_output: &str) -> Option<CmusStatus<'_>> {
+    let mut status_found = false;
+    let mut cmus_status = CmusStatus { state: PlaybackState::Stopped, file_path: None, duration: None, position: None };
+
+    for output_line in cmus_remote_output.lines() {
+        if let Some(state) = output_line.strip_prefix("status ") {
+            status_found = true;
+            cmus_status.state = match state.trim() {
+                "paused" => PlaybackState::Paused,
+                "stopped" => PlaybackState::Stopped,
+                _ => PlaybackState::Playing,
+            };
+        } else if let Some(file_path) = output_line.strip_prefix("file ") {
+            cmus_status.file_path = Some(file_path).filter(|file_path| !file_path.is_empty());
+        } else if let Some(duration) = output_line.strip_prefix("duration ") {
+            cmus_status.duration = duration.trim().parse::<u64>().ok();
+        } else if let Some(position) = output_line.strip_prefix("position ") {
+            cmus_status.position = position.trim().parse::<u64>().ok();
+        }
+    }
+
+    status_found.then_some(cmus_status)
+}
+
+// Listens for status changes on CMUS_STATUS_SOCKET. A socket left behind by an earlier run is replaced, but one that
+// another instance is still listening on is kept.
+#[cfg(unix)]
+fn listen_for_cmus_status(runtime_dir: &Path) -> io::Result<UnixListener> {
+    let status_socket_path = runtime_dir.join(CMUS_STATUS_SOCKET);
+    if UnixStream::connect(&status_socket_path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another instance.", status_socket_path.display())));
+    }
+    let _ = fs::remove_file(&status_socket_path);
+    let status_listener = UnixListener::bind(&status_socket_path)?;
+    status_listener.set_nonblocking(true)?;
+    Ok(status_listener)
+}
+
+// Tells a running lamp-drpc that cmus's status has changed. Used by lamp-drpc --cmus-status.
+#[cfg(unix)]
+pub fn notify_cmus_status(runtime_dir: &Path) -> io::Result<()> {
+    UnixStream::connect(runtime_dir.join(CMUS_STATUS_SOCKET))?;
+    Ok(())
+}
+
+// cmus runs its status_display_program without arguments of its own, so a script passes --cmus-status to lamp-drpc.
+#[cfg(unix)]
+fn write_cmus_status_program(runtime_dir: &Path) -> io::Result<PathBuf> {
+    let lamp_path = std::env::current_exe()?;
+    let program_path = runtime_dir.join(CMUS_STATUS_PROGRAM);
+    fs::write(&program_path, format!("#!/bin/sh\nexec '{}' --cmus-status \"$@\"\n", lamp_path.display().to_string().replace('\'', "'\\''")))?;
+    fs::set_permissions(&program_path, fs::Permissions::from_mode(0o755))?;
+    Ok(program_path)
 }
 /************************** END Function Implementations for cmus **************************/
 
-/************************** Function Implementations Template **************************/
+/************************** Function Implementations for MPD **************************/
+// Time to wait for MPD to accept a connection or answer a command.
+const MPD_TIMEOUT: Duration = Duration::from_secs(2);
+
 /*
+ *  MPD is queried over its protocol, keeping one connection open between polls.
+ *
+ *  - Each poll sends status and currentsong. A connection that MPD has closed is reopened once, and the poll fails
+ *    only if that also fails.
+ *  - MPD reports files relative to its music directory, which music_dir must match. Absolute paths and stream URLs
+ *    are used as they are.
+ *  - Mopidy, which also speaks MPD's protocol, reports tracks by URI. Local files (local:track:<path>) are found in
+ *    music_dir, which must then match Mopidy's media directory. Tracks from streaming services (e.g. spotify: or
+ *    tidal:) are identified by their URI and shown with the artist, title, and album from Mopidy's library. URIs are
+ *    only looked for when the player is mopidy, since MPD's relative paths may also start with e.g. live:.
+ */
+pub struct Mpd {
+    address: String,
+    music_dir: PathBuf,
+    password: Option<String>,
+    mopidy: bool,
+    connection: Option<BufReader<TcpStream>>,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_metadata: Option<PlayerMetadata>,
+    active_state: PlaybackState,
+}
 
-[PLAYER IMPLEMENTATION HERE]
+impl Mpd {
+    pub fn new(address: String, music_dir: PathBuf, password: Option<String>, mopidy: bool) -> Self {
+        Mpd {
+            address,
+            music_dir,
+            password,
+            mopidy,
+            connection: None,
+            active_duration: None,
+            active_position: None,
+            active_metadata: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
 
-// If a player struct will require data values for use in its functions, they should be included here
-// and in the Default trait implementation.
+    // Opens a connection, reading MPD's greeting and logging in if a password is set.
+    fn connect(&self) -> Result<BufReader<TcpStream>, PlayerError> {
+        let mut connection_error = None;
+        for socket_address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_address, MPD_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(MPD_TIMEOUT))?;
+                    stream.set_write_timeout(Some(MPD_TIMEOUT))?;
+                    let mut connection = BufReader::new(stream);
+                    let mut greeting = String::new();
+                    connection.read_line(&mut greeting)?;
+                    if !greeting.starts_with("OK MPD ") {
+                        return Err(PlayerError::Protocol(format!("{} is not an MPD server.", self.address)));
+                    }
+                    if let Some(password) = &self.password {
+                        mpd_command(&mut connection, format!("password \"{}\"", mpd_escape(password)).as_str())?;
+                    }
+                    return Ok(connection);
+                }
+                Err(e) => connection_error = Some(e),
+            }
+        }
+        Err(PlayerError::Io(connection_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} could not be resolved.", self.address)))))
+    }
 
-pub struct NewPlayer {
+    // Sends status and currentsong on the open connection, or on a new one if MPD has closed it.
+    fn query_status(&mut self) -> Result<Vec<String>, PlayerError> {
+        if let Some(connection) = &mut self.connection {
+            match query_mpd_status(connection) {
+                Err(PlayerError::Io(_)) => self.connection = None,
+                query_result => return query_result,
+            }
+        }
+
+        let mut connection = self.connect()?;
+        let response = query_mpd_status(&mut connection)?;
+        self.connection = Some(connection);
+        Ok(response)
+    }
+}
+
+impl StandardPlayer for Mpd {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // MPD is running properly if it accepts connections.
+        match self.connect() {
+            Ok(_) => Ok(true),
+            Err(PlayerError::Io(e)) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let response = self.query_status()?;
+        let mpd_status = parse_mpd_response(&response);
+        self.active_metadata = None;
+
+        // Nothing is active while MPD is stopped, even if a song is queued.
+        if mpd_status.state != Some("play") && mpd_status.state != Some("pause") {
+            self.active_duration = None;
+            self.active_position = None;
+            return Ok(None);
+        }
+        self.active_state = if mpd_status.state == Some("pause") { PlaybackState::Paused } else { PlaybackState::Playing };
+        self.active_duration = mpd_status.duration;
+        self.active_position = mpd_status.position;
+        let Some(file_path) = mpd_status.file_path else {
+            return Ok(None);
+        };
+
+        // Mopidy's local files are percent-encoded paths in its media directory.
+        if let Some(local_path) = file_path.strip_prefix("local:track:").filter(|_| self.mopidy) {
+            let local_path = file_uri_to_path(format!("file://{}", local_path).as_str()).unwrap_or(String::from(local_path));
+            return Ok(Some(self.music_dir.join(local_path).to_string_lossy().into_owned()));
+        }
+        if file_path.starts_with('/') || file_path.contains("://") {
+            return Ok(Some(String::from(file_path)));
+        }
+        if self.mopidy && is_mopidy_uri(file_path) {
+            self.active_metadata = mpd_status.title.filter(|_| !mpd_status.artists.is_empty()).map(|title| PlayerMetadata {
+                artist: mpd_status.artists.join(", "),
+                title: String::from(title),
+                album: mpd_status.album.map(String::from),
+                art_url: None,
+            });
+            return Ok(Some(String::from(file_path)).filter(|_| self.active_metadata.is_some()));
+        }
+        Ok(Some(self.music_dir.join(file_path).to_string_lossy().into_owned()))
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.active_metadata.clone()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Checks whether a file reported by Mopidy is a URI of one of its backends (e.g. spotify:track:<id>), rather than a
+// path. URI schemes only contain lowercase letters, digits, and + - . characters.
+fn is_mopidy_uri(file_path: &str) -> bool {
+    file_path.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty() && scheme.bytes().all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"+-.".contains(&byte))
+    })
+}
+
+// Sends a command and reads its response lines up to the final OK. An ACK response is returned as an error.
+fn mpd_command(connection: &mut BufReader<TcpStream>, command: &str) -> Result<Vec<String>, PlayerError> {
+    writeln!(connection.get_mut(), "{}", command)?;
+    let mut response = Vec::<String>::new();
+    loop {
+        let mut response_line = String::new();
+        if connection.read_line(&mut response_line)? == 0 {
+            return Err(PlayerError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "MPD closed the connection.")));
+        }
+        let response_line = response_line.trim_end_matches('\n');
+        if response_line == "OK" {
+            return Ok(response);
+        }
+        if let Some(ack) = response_line.strip_prefix("ACK ") {
+            return Err(PlayerError::Protocol(String::from(ack)));
+        }
+        response.push(String::from(response_line));
+    }
+}
+
+fn query_mpd_status(connection: &mut BufReader<TcpStream>) -> Result<Vec<String>, PlayerError> {
+    let mut response = mpd_command(connection, "status")?;
+    response.extend(mpd_command(connection, "currentsong")?);
+    Ok(response)
+}
+
+fn mpd_escape(argument: &str) -> String {
+    argument.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// The values reported by MPD's status and currentsong commands that are used to follow the active track. Tags are only
+// used for tracks that aren't local files.
+struct MpdStatus<'a> {
+    state: Option<&'a str>,
+    file_path: Option<&'a str>,
+    duration: Option<u64>,
+    position: Option<u64>,
+    artists: Vec<&'a str>,
+    title: Option<&'a str>,
+    album: Option<&'a str>,
+}
+
+// Reads the state, file, and timing keys from MPD's responses. Older versions of MPD only report the elapsed time and
+// duration in whole seconds, as time: <elapsed>:<duration>.
+fn parse_mpd_response(response: &[String]) -> MpdStatus<'_> {
+    let mut mpd_status = MpdStatus { state: None, file_path: None, duration: None, position: None, artists: Vec::new(), title: None, album: None };
+    let seconds = |value: &str| value.trim().parse::<f64>().ok().filter(|value| *value >= 0.0).map(|value| value as u64);
+
+    for response_line in response {
+        let Some((key, value)) = response_line.split_once(": ") else {
+            continue;
+        };
+        match key {
+            "state" => mpd_status.state = Some(value),
+            "file" => mpd_status.file_path = Some(value).filter(|file_path| !file_path.is_empty()),
+            "elapsed" => mpd_status.position = seconds(value),
+            "duration" => mpd_status.duration = seconds(value),
+            "Artist" if !value.is_empty() => mpd_status.artists.push(value),
+            "Title" => mpd_status.title = Some(value).filter(|title| !title.is_empty()),
+            "Album" => mpd_status.album = Some(value).filter(|album| !album.is_empty()),
+            "time" => {
+                if let Some((elapsed, duration)) = value.split_once(':') {
+                    mpd_status.position = mpd_status.position.or(seconds(elapsed));
+                    mpd_status.duration = mpd_status.duration.or(seconds(duration).filter(|duration| *duration > 0));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    mpd_status
+}
+/************************** END Function Implementations for MPD **************************/
+
+/************************** Function Implementations for MPRIS **************************/
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/*
+ *  Any player exposing MPRIS2 on the session bus is read through its org.mpris.MediaPlayer2.Player interface.
+ *
+ *  - bus_filter limits the players followed to those whose bus name starts with org.mpris.MediaPlayer2.<bus_filter>,
+ *    e.g. "strawberry". Without it, any player is followed other than lamp-drpc's own MPRIS player. Once a player is
+ *    found, it is followed for as long as it stays on the bus.
+ *  - Properties are read on every poll rather than cached, since players don't announce changes to their position.
+ *  - The player's process is found from the owner of its bus name, so player_name doesn't need to match it.
+ *  - Tracks that aren't local files (e.g. from Spotify) are identified by their URL, or by their track ID if they have
+ *    none, and shown with the artist, title, album, and album art link the player reports.
+ */
+pub struct Mpris {
+    bus_filter: Option<String>,
+    connection: Option<zbus::blocking::Connection>,
+    bus_name: Option<String>,
     active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_metadata: Option<PlayerMetadata>,
+    active_tags: PlayerTags,
+    active_state: PlaybackState,
 }
 
-impl Default for NewPlayer {
-    fn default() -> Self {
-        NewPlayer {
+impl Mpris {
+    pub fn new(bus_filter: Option<String>) -> Self {
+        Mpris {
+            bus_filter,
+            connection: None,
+            bus_name: None,
             active_duration: None,
+            active_position: None,
+            active_metadata: None,
+            active_tags: PlayerTags::default(),
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    fn connection(&mut self) -> Result<zbus::blocking::Connection, PlayerError> {
+        match &self.connection {
+            Some(connection) => Ok(connection.clone()),
+            None => {
+                let connection = zbus::blocking::Connection::session()?;
+                self.connection = Some(connection.clone());
+                Ok(connection)
+            }
+        }
+    }
+
+    // Finds the bus name of the player to follow, keeping the current one while it is still on the bus.
+    fn find_bus_name(&self, connection: &zbus::blocking::Connection) -> Result<Option<String>, PlayerError> {
+        let bus_names = zbus::blocking::fdo::DBusProxy::new(connection)?.list_names().map_err(zbus::Error::from)?;
+        let bus_names = bus_names.iter().map(|bus_name| bus_name.to_string()).collect::<Vec<String>>();
+        if let Some(bus_name) = self.bus_name.as_ref().filter(|bus_name| bus_names.contains(bus_name)) {
+            return Ok(Some(bus_name.clone()));
         }
+        Ok(select_mpris_bus_name(bus_names, self.bus_filter.as_deref()))
     }
 }
 
-impl StandardPlayer for NewPlayer {
-    fn verify_running(&self) -> bool {
-        return true;
+impl StandardPlayer for Mpris {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        let connection = zbus::blocking::Connection::session()?;
+        Ok(self.find_bus_name(&connection)?.is_some())
     }
 
-    // If None is returned, nothing will be shown on Discord, but the program will continue running.
-    // Error logging is handled in main.rs when this function is called, so an error returned here will be logged.
-    fn get_active_file_path(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        Ok(None)
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let connection = self.connection()?;
+        self.bus_name = self.find_bus_name(&connection)?;
+        self.active_duration = None;
+        self.active_position = None;
+        self.active_metadata = None;
+        self.active_tags = PlayerTags::default();
+        self.active_state = PlaybackState::Stopped;
+        let Some(bus_name) = self.bus_name.clone() else {
+            return Ok(None);
+        };
+
+        let player_proxy: zbus::blocking::Proxy = zbus::blocking::proxy::Builder::new(&connection).destination(bus_name)?
+                                                                                                  .path(MPRIS_PATH)?
+                                                                                                  .interface(MPRIS_PLAYER_INTERFACE)?
+                                                                                                  .cache_properties(zbus::proxy::CacheProperties::No)
+                                                                                                  .build()?;
+        self.active_state = match player_proxy.get_property::<String>("PlaybackStatus")?.as_str() {
+            "Stopped" => return Ok(None),
+            "Paused" => PlaybackState::Paused,
+            _ => PlaybackState::Playing,
+        };
+        let metadata = player_proxy.get_property::<HashMap<String, OwnedValue>>("Metadata")?;
+        let (file_path, duration) = parse_mpris_metadata(&metadata);
+        self.active_tags = parse_player_tags(&metadata);
+
+        // Tracks that aren't local files are shown with the metadata reported by the player.
+        let file_path = match file_path {
+            Some(file_path) if file_path.starts_with('/') => Some(file_path),
+            file_path => {
+                self.active_metadata = parse_mpris_player_metadata(&metadata);
+                file_path.or(metadata.get("mpris:trackid").and_then(|track_id| match &**track_id {
+                             zbus::zvariant::Value::Str(track_id) => Some(String::from(track_id.as_str())),
+                             zbus::zvariant::Value::ObjectPath(track_id) => Some(String::from(track_id.as_str())),
+                             _ => None,
+                         }))
+                         .filter(|_| self.active_metadata.is_some())
+            }
+        };
+
+        // Position and length are reported in microseconds. Players that don't support Position return an error for it.
+        self.active_duration = duration;
+        self.active_position = player_proxy.get_property::<i64>("Position").ok().and_then(|position| u64::try_from(position / 1_000_000).ok());
+        Ok(file_path)
     }
 
-    // active_duration does not need to be stored in the struct.
     fn get_duration(&self) -> Option<u64> {
         self.active_duration
     }
-} 
 
-*/
-/************************** END Function Implementations Template **************************/
\ No newline at end of file
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        let connection = zbus::blocking::Connection::session().ok()?;
+        let bus_name = self.find_bus_name(&connection).ok()??;
+        let bus_name = zbus::names::BusName::try_from(bus_name.as_str()).ok()?;
+        let player_pid = zbus::blocking::fdo::DBusProxy::new(&connection).ok()?.get_connection_unix_process_id(bus_name).ok()?;
+        let comm = fs::read_to_string(format!("/proc/{}/comm", player_pid)).ok()?;
+        Some(String::from(comm.trim_end_matches('\n')))
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.active_metadata.clone()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        self.active_tags.clone()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Picks the first MPRIS player on the bus, in name order so the choice is stable, that matches the filter.
+fn select_mpris_bus_name(bus_names: Vec<String>, bus_filter: Option<&str>) -> Option<String> {
+    let mut player_names = bus_names.into_iter()
+                                    .filter(|bus_name| bus_name.strip_prefix(MPRIS_PREFIX).is_some_and(|player| match bus_filter {
+                                        Some(bus_filter) => player.starts_with(bus_filter),
+                                        None => player != "lamp_drpc",
+                                    }))
+                                    .collect::<Vec<String>>();
+    player_names.sort();
+    player_names.into_iter().next()
+}
+
+// Reads the file path and duration of the active track from MPRIS metadata. The length may be signed or unsigned.
+fn parse_mpris_metadata(metadata: &HashMap<String, OwnedValue>) -> (Option<String>, Option<u64>) {
+    let file_path = metadata.get("xesam:url").and_then(|url| <&str>::try_from(&**url).ok()).map(|url| file_uri_to_path(url).unwrap_or(String::from(url)));
+    let duration = metadata.get("mpris:length").and_then(integer_value).and_then(|length| u64::try_from(length / 1_000_000).ok());
+    (file_path, duration.filter(|duration| *duration > 0))
+}
+
+// Reads the artist, title, album, and album art link of a track from MPRIS metadata, for tracks that aren't local files.
+// Returns None without an artist and title. Album art is only linked if Discord can load it, i.e. over HTTP(S).
+fn parse_mpris_player_metadata(metadata: &HashMap<String, OwnedValue>) -> Option<PlayerMetadata> {
+    let text = |key: &str| metadata.get(key).and_then(|value| <&str>::try_from(&**value).ok()).filter(|text| !text.is_empty()).map(String::from);
+    let artists = match metadata.get("xesam:artist").map(|artists| &**artists) {
+        Some(zbus::zvariant::Value::Array(artists)) => artists.iter().filter_map(|artist| <&str>::try_from(artist).ok()).collect::<Vec<&str>>().join(", "),
+        _ => String::new(),
+    };
+
+    Some(PlayerMetadata {
+        artist: Some(artists).filter(|artists| !artists.is_empty())?,
+        title: text("xesam:title")?,
+        album: text("xesam:album"),
+        art_url: text("mpris:artUrl").filter(|art_url| art_url.starts_with("https://") || art_url.starts_with("http://")),
+    })
+}
+
+// Reads the rating and original release year of a track from MPRIS metadata or that of Clementine's own interface.
+// MPRIS reports the rating from 0 to 1 as xesam:userRating, and Clementine's interface reports it in stars as rating,
+// where 0 (or -1 in Strawberry) means the track is unrated. Strawberry and Clementine report originalyear and year, and
+// other players only report the release date as xesam:contentCreated.
+fn parse_player_tags(metadata: &HashMap<String, OwnedValue>) -> PlayerTags {
+    let rating = match metadata.get("xesam:userRating").and_then(|rating| f64::try_from(&**rating).ok()) {
+        Some(rating) => Some((rating.clamp(0.0, 1.0) * 5.0).round() as u8),
+        None => metadata.get("rating").and_then(integer_value).map(|rating| rating.clamp(0, 5) as u8),
+    };
+    let year = |key: &str| metadata.get(key).and_then(integer_value).filter(|year| *year > 0).map(|year| year.to_string());
+    let content_year = metadata.get("xesam:contentCreated").and_then(|date| <&str>::try_from(&**date).ok())
+                                                           .and_then(|date| date.get(..4))
+                                                           .filter(|year| year.bytes().all(|byte| byte.is_ascii_digit()))
+                                                           .map(String::from);
+
+    PlayerTags {
+        rating: rating.filter(|rating| *rating > 0),
+        original_year: year("originalyear").or_else(|| year("year")).or(content_year),
+    }
+}
+/************************** END Function Implementations for MPRIS **************************/
+
+/************************** Function Implementations for mpv **************************/
+// Time to wait for mpv to answer a command.
+#[cfg(unix)]
+const MPV_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[cfg(unix)]
+type MpvStream = UnixStream;
+#[cfg(windows)]
+type MpvStream = fs::File;
+
+/*
+ *  mpv is queried through the JSON IPC socket it opens when started with --input-ipc-server=<socket_path>, keeping one
+ *  connection open between polls.
+ *
+ *  - Each poll reads the path, duration, and time-pos properties. A connection that mpv has closed is reopened once,
+ *    and the poll fails only if that also fails.
+ *  - Paths mpv reports relative to its working directory are resolved against it. Stream URLs are used as they are.
+ *  - mpv sends events on the same connection, which are skipped while waiting for a reply.
+ *  - On Windows, the IPC server is a named pipe (e.g. --input-ipc-server=mpv-socket opens \\.\pipe\mpv-socket), which
+ *    is opened as a file. Named pipes opened this way have no timeouts, so mpv is trusted to answer.
+ */
+pub struct Mpv {
+    socket_path: PathBuf,
+    connection: Option<BufReader<MpvStream>>,
+    request_id: u64,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+}
+
+impl Mpv {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Mpv {
+            socket_path,
+            connection: None,
+            request_id: 0,
+            active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    #[cfg(unix)]
+    fn connect(&self) -> Result<BufReader<MpvStream>, PlayerError> {
+        let stream = UnixStream::connect(&self.socket_path)?;
+        stream.set_read_timeout(Some(MPV_TIMEOUT))?;
+        stream.set_write_timeout(Some(MPV_TIMEOUT))?;
+        Ok(BufReader::new(stream))
+    }
+
+    #[cfg(windows)]
+    fn connect(&self) -> Result<BufReader<MpvStream>, PlayerError> {
+        Ok(BufReader::new(fs::OpenOptions::new().read(true).write(true).open(&self.socket_path)?))
+    }
+
+    // Reads a property, returning None if it is unavailable, such as the path while no file is loaded.
+    fn get_property(&mut self, property_name: &str) -> Result<Option<Value>, PlayerError> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        self.request_id += 1;
+        let request_id = self.request_id;
+        let Some(connection) = &mut self.connection else {
+            return Ok(None);
+        };
+        match mpv_command(connection, request_id, property_name) {
+            Err(PlayerError::Io(_)) => {
+                let mut connection = self.connect()?;
+                let property_value = mpv_command(&mut connection, request_id, property_name)?;
+                self.connection = Some(connection);
+                Ok(property_value)
+            }
+            command_result => command_result,
+        }
+    }
+}
+
+impl StandardPlayer for Mpv {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // mpv is running properly if its IPC socket accepts connections.
+        match self.connect() {
+            Ok(_) => Ok(true),
+            Err(PlayerError::Io(e)) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let seconds = |property_value: Option<Value>| property_value.and_then(|value| value.as_f64()).filter(|value| *value >= 0.0).map(|value| value as u64);
+        let Some(file_path) = self.get_property("path")?.and_then(|path| path.as_str().map(String::from)) else {
+            self.active_duration = None;
+            self.active_position = None;
+            return Ok(None);
+        };
+        self.active_duration = seconds(self.get_property("duration")?).filter(|duration| *duration > 0);
+        self.active_position = seconds(self.get_property("time-pos")?);
+        self.active_state = match self.get_property("pause")?.and_then(|pause| pause.as_bool()) {
+            Some(true) => PlaybackState::Paused,
+            _ => PlaybackState::Playing,
+        };
+
+        if Path::new(&file_path).is_absolute() || file_path.contains("://") {
+            return Ok(Some(file_path));
+        }
+        let working_dir = self.get_property("working-directory")?.and_then(|working_dir| working_dir.as_str().map(PathBuf::from)).unwrap_or_default();
+        Ok(Some(working_dir.join(file_path).to_string_lossy().into_owned()))
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Sends a get_property command and waits for the reply with its request ID. Replies with an error other than an
+// unavailable property are returned as errors.
+fn mpv_command(connection: &mut BufReader<MpvStream>, request_id: u64, property_name: &str) -> Result<Option<Value>, PlayerError> {
+    writeln!(connection.get_mut(), "{}", json!({ "command": ["get_property", property_name], "request_id": request_id }))?;
+    loop {
+        let mut reply_line = String::new();
+        if connection.read_line(&mut reply_line)? == 0 {
+            return Err(PlayerError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "mpv closed the connection.")));
+        }
+        let Ok(reply) = serde_json::from_str::<Value>(&reply_line) else {
+            continue;
+        };
+        if reply["request_id"].as_u64() != Some(request_id) {
+            continue;
+        }
+        return match reply["error"].as_str() {
+            Some("success") => Ok(Some(reply["data"].clone()).filter(|data| !data.is_null())),
+            Some("property unavailable") => Ok(None),
+            error => Err(PlayerError::Protocol(format!("mpv could not read {}: {}", property_name, error.unwrap_or("no reply")))),
+        };
+    }
+}
+/************************** END Function Implementations for mpv **************************/
+
+/************************** Function Implementations for Rhythmbox **************************/
+/*
+ *  Rhythmbox is read through the MPRIS plugin it enables by default, on the bus name org.mpris.MediaPlayer2.rhythmbox.
+ *
+ *  - Unlike the generic MPRIS player, only Rhythmbox is ever followed, and its process is found by player_name.
+ *  - Rhythmbox reports library files as file:// URIs, which are converted to paths, and reports durations in its metadata.
+ *    Streams are reported by their URL.
+ */
+pub struct Rhythmbox {
+    mpris: Mpris,
+}
+
+impl Rhythmbox {
+    pub fn new() -> Self {
+        Rhythmbox {
+            mpris: Mpris::new(Some(String::from(RHYTHMBOX_BUS_FILTER))),
+        }
+    }
+}
+
+// Rhythmbox's bus name, after the MPRIS prefix. The filter also matches instances with a suffix, e.g. rhythmbox.instance123.
+const RHYTHMBOX_BUS_FILTER: &str = "rhythmbox";
+
+impl StandardPlayer for Rhythmbox {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        self.mpris.verify_running()
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.mpris.get_active_file_path()
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.mpris.get_duration()
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.mpris.get_position()
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.mpris.get_metadata()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.mpris.get_playback_state()
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        self.mpris.wait_for_change(timeout);
+    }
+}
+/************************** END Function Implementations for Rhythmbox **************************/
+
+/************************** Function Implementations for Strawberry and Clementine **************************/
+const LEGACY_MPRIS_PATH: &str = "/Player";
+const LEGACY_MPRIS_INTERFACE: &str = "org.freedesktop.MediaPlayer";
+
+/*
+ *  Strawberry and Clementine are read through their own D-Bus interface when they provide it, and through MPRIS2 otherwise.
+ *
+ *  - Clementine's own interface (org.mpris.clementine on /Player) reports the file's location and duration directly,
+ *    and keeps working when its MPRIS2 support is disabled. Strawberry only provides MPRIS2.
+ *  - Both players report locations as file:// URIs, where Clementine leaves some characters unencoded. Either way,
+ *    locations are converted to paths.
+ *  - The rating and original release year kept in the player's library are reported along with the track, through
+ *    either interface.
+ *  - Only the player named by player_name is followed, and its process is found by player_name.
+ */
+pub struct Strawberry {
+    legacy_bus_name: String,
+    connection: Option<zbus::blocking::Connection>,
+    mpris: Mpris,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_tags: PlayerTags,
+    active_state: PlaybackState,
+}
+
+impl Strawberry {
+    // player_name is either strawberry or clementine.
+    pub fn new(player_name: &str) -> Self {
+        Strawberry {
+            legacy_bus_name: format!("org.mpris.{}", player_name),
+            connection: None,
+            mpris: Mpris::new(Some(String::from(player_name))),
+            active_duration: None,
+            active_position: None,
+            active_tags: PlayerTags::default(),
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    // Reads the active file through the player's own interface. Returns an error if the player doesn't provide it.
+    fn read_legacy_interface(&mut self) -> Result<Option<String>, PlayerError> {
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => {
+                let connection = zbus::blocking::Connection::session()?;
+                self.connection = Some(connection.clone());
+                connection
+            }
+        };
+        let player_proxy: zbus::blocking::Proxy = zbus::blocking::proxy::Builder::new(&connection).destination(self.legacy_bus_name.as_str())?
+                                                                                                  .path(LEGACY_MPRIS_PATH)?
+                                                                                                  .interface(LEGACY_MPRIS_INTERFACE)?
+                                                                                                  .cache_properties(zbus::proxy::CacheProperties::No)
+                                                                                                  .build()?;
+
+        // The first field of the status is 0 while playing, 1 while paused, and 2 while stopped.
+        let (playback_status, _, _, _) = player_proxy.call::<_, _, (i32, i32, i32, i32)>("GetStatus", &())?;
+        self.active_state = match playback_status {
+            2 => return Ok(None),
+            1 => PlaybackState::Paused,
+            _ => PlaybackState::Playing,
+        };
+        let metadata = player_proxy.call::<_, _, HashMap<String, OwnedValue>>("GetMetadata", &())?;
+        let (file_path, duration) = parse_legacy_metadata(&metadata);
+        self.active_tags = parse_player_tags(&metadata);
+
+        // The position is reported in milliseconds.
+        self.active_duration = duration;
+        self.active_position = player_proxy.call::<_, _, i32>("PositionGet", &()).ok().and_then(|position| u64::try_from(position / 1000).ok());
+        Ok(file_path)
+    }
+}
+
+impl StandardPlayer for Strawberry {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        self.mpris.verify_running()
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.active_duration = None;
+        self.active_position = None;
+        self.active_tags = PlayerTags::default();
+        if let Ok(file_path) = self.read_legacy_interface() {
+            return Ok(file_path);
+        }
+
+        let file_path = self.mpris.get_active_file_path()?;
+        self.active_tags = self.mpris.get_player_tags();
+        self.active_state = self.mpris.get_playback_state();
+        self.active_duration = self.mpris.get_duration();
+        self.active_position = self.mpris.get_position();
+        Ok(file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        self.active_tags.clone()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Reads the file path and duration of the active track from the metadata of Clementine's own interface, where the
+// duration is reported in seconds as time.
+fn parse_legacy_metadata(metadata: &HashMap<String, OwnedValue>) -> (Option<String>, Option<u64>) {
+    let file_path = metadata.get("location").and_then(|location| <&str>::try_from(&**location).ok()).map(|location| file_uri_to_path(location).unwrap_or(String::from(location)));
+    let duration = metadata.get("time").and_then(integer_value).and_then(|duration| u64::try_from(duration).ok());
+    (file_path, duration.filter(|duration| *duration > 0))
+}
+/************************** END Function Implementations for Strawberry and Clementine **************************/
+
+/************************** Function Implementations for spotifyd and ncspot **************************/
+/*
+ *  spotifyd and ncspot are read through MPRIS2, on the bus name org.mpris.MediaPlayer2.<player_name> unless
+ *  mpris_bus_name is set, e.g. to follow one of several spotifyd instances, which add an instance suffix.
+ *
+ *  - Tracks are identified by their open.spotify.com URL, and shown with the artist, title, album, and i.scdn.co album
+ *    art link the player reports, so no album art is uploaded.
+ *  - Only the player named by player_name is followed, and its process is found by player_name.
+ */
+pub struct Spotifyd {
+    mpris: Mpris,
+}
+
+impl Spotifyd {
+    // player_name is either spotifyd or ncspot.
+    pub fn new(player_name: &str, bus_name: Option<String>) -> Self {
+        Spotifyd {
+            mpris: Mpris::new(Some(bus_name.unwrap_or(String::from(player_name)))),
+        }
+    }
+}
+
+impl StandardPlayer for Spotifyd {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        self.mpris.verify_running()
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.mpris.get_active_file_path()
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.mpris.get_duration()
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.mpris.get_position()
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.mpris.get_metadata()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.mpris.get_playback_state()
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        self.mpris.wait_for_change(timeout);
+    }
+}
+/************************** END Function Implementations for spotifyd and ncspot **************************/
+
+/************************** Function Implementations for musikcube **************************/
+// Time to wait for musikcube to accept a connection or answer a request.
+const MUSIKCUBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Largest WebSocket message accepted from musikcube, so a corrupt length can't exhaust memory.
+const MAX_WEBSOCKET_MESSAGE: usize = 16 * 1024 * 1024;
+
+/*
+ *  musikcube is queried through the WebSocket server of its server plugin, keeping one connection open between polls.
+ *
+ *  - Each poll requests the playback overview. A connection that musikcube has closed is reopened once, and the poll
+ *    fails only if that also fails.
+ *  - musikcube broadcasts playback_overview_changed on the same connection whenever playback changes, so
+ *    wait_for_change returns as soon as one arrives and the change is shown right away rather than at the next poll.
+ *  - Tracks are identified by their file path. Tracks musikcube reports without one (e.g. from a remote library) are
+ *    identified by their external ID, and shown with the artist, title, and album musikcube reports.
+ */
+pub struct Musikcube {
+    address: String,
+    password: String,
+    connection: Option<WebSocket<TcpStream>>,
+    request_id: u64,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_metadata: Option<PlayerMetadata>,
+    active_state: PlaybackState,
+}
+
+impl Musikcube {
+    pub fn new(address: String, password: Option<String>) -> Self {
+        Musikcube {
+            address,
+            password: password.unwrap_or_default(),
+            connection: None,
+            request_id: 0,
+            active_duration: None,
+            active_position: None,
+            active_metadata: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    // Opens a WebSocket connection and authenticates with the server's password.
+    fn connect(&mut self) -> Result<WebSocket<TcpStream>, PlayerError> {
+        let mut connection_error = None;
+        for socket_address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_address, MUSIKCUBE_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(MUSIKCUBE_TIMEOUT))?;
+                    stream.set_write_timeout(Some(MUSIKCUBE_TIMEOUT))?;
+                    let websocket_config = WebSocketConfig::default().max_message_size(Some(MAX_WEBSOCKET_MESSAGE)).max_frame_size(Some(MAX_WEBSOCKET_MESSAGE));
+                    let mut connection = match tungstenite::client::client_with_config(format!("ws://{}/", self.address), stream, Some(websocket_config)) {
+                        Ok((connection, _)) => connection,
+                        Err(HandshakeError::Failure(e)) => return Err(websocket_error(e)),
+                        Err(HandshakeError::Interrupted(_)) => return Err(PlayerError::Io(io::Error::new(io::ErrorKind::TimedOut, "musikcube did not accept a WebSocket connection in time."))),
+                    };
+
+                    self.request_id += 1;
+                    let options = musikcube_request(&mut connection, self.request_id, "authenticate", json!({ "password": self.password }))?;
+                    if options["authenticated"].as_bool() != Some(true) {
+                        return Err(PlayerError::Protocol(String::from("musikcube refused the password.")));
+                    }
+                    return Ok(connection);
+                }
+                Err(e) => connection_error = Some(e),
+            }
+        }
+        Err(PlayerError::Io(connection_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} could not be resolved.", self.address)))))
+    }
+
+    // Requests the playback overview on the open connection, or on a new one if musikcube has closed it.
+    fn query_overview(&mut self) -> Result<Value, PlayerError> {
+        self.request_id += 1;
+        let request_id = self.request_id;
+        if let Some(connection) = &mut self.connection {
+            match musikcube_request(connection, request_id, "get_playback_overview", json!({})) {
+                Err(PlayerError::Io(_)) => self.connection = None,
+                request_result => return request_result,
+            }
+        }
+
+        let mut connection = self.connect()?;
+        let overview = musikcube_request(&mut connection, request_id, "get_playback_overview", json!({}))?;
+        self.connection = Some(connection);
+        Ok(overview)
+    }
+}
+
+impl StandardPlayer for Musikcube {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // musikcube is running properly if its server accepts connections.
+        match self.address.to_socket_addrs()?.find_map(|socket_address| TcpStream::connect_timeout(&socket_address, MUSIKCUBE_TIMEOUT).ok()) {
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let overview = self.query_overview()?;
+        let musikcube_status = parse_musikcube_overview(&overview);
+        self.active_state = musikcube_status.state;
+        self.active_duration = musikcube_status.duration;
+        self.active_position = musikcube_status.position;
+        self.active_metadata = musikcube_status.metadata;
+        Ok(musikcube_status.file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.active_metadata.clone()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        let Some(connection) = &mut self.connection else {
+            thread::sleep(timeout);
+            return;
+        };
+        // The connection is reopened at the next poll.
+        if wait_for_musikcube_broadcast(connection, timeout).is_err() {
+            self.connection = None;
+        }
+    }
+}
+
+// Sends a request and waits for the response with its ID, returning the response's options. Broadcasts sent in the
+// meantime are skipped.
+fn musikcube_request(connection: &mut WebSocket<TcpStream>, request_id: u64, name: &str, options: Value) -> Result<Value, PlayerError> {
+    let request_id = request_id.to_string();
+    let request = json!({ "name": name, "type": "request", "id": request_id, "device_id": "lamp-drpc", "options": options });
+    connection.send(Message::text(request.to_string())).map_err(websocket_error)?;
+    loop {
+        let Ok(message) = serde_json::from_str::<Value>(&read_text_message(connection)?) else {
+            continue;
+        };
+        if message["type"] == "response" && message["id"] == request_id.as_str() {
+            return Ok(message["options"].clone());
+        }
+    }
+}
+
+// Waits up to timeout for musikcube to broadcast a change to playback. Returns Ok(false) if none arrived in time.
+fn wait_for_musikcube_broadcast(connection: &mut WebSocket<TcpStream>, timeout: Duration) -> Result<bool, PlayerError> {
+    let wait_start = Instant::now();
+    loop {
+        let remaining = timeout.saturating_sub(wait_start.elapsed());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        // A message read partway when the timeout runs out is kept by the WebSocket, and finished by the next read.
+        connection.get_ref().set_read_timeout(Some(remaining))?;
+        let message = read_text_message(connection);
+        connection.get_ref().set_read_timeout(Some(MUSIKCUBE_TIMEOUT))?;
+        let message = match message {
+            Ok(message) => serde_json::from_str::<Value>(&message).unwrap_or_default(),
+            Err(PlayerError::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if message["type"] == "broadcast" && message["name"] == "playback_overview_changed" {
+            return Ok(true);
+        }
+    }
+}
+
+// The values reported in musikcube's playback overview that are used to follow the active track.
+struct MusikcubeStatus {
+    state: PlaybackState,
+    file_path: Option<String>,
+    duration: Option<u64>,
+    position: Option<u64>,
+    metadata: Option<PlayerMetadata>,
+}
+
+// Reads the active track from a playback overview, where times are reported in seconds. Nothing is active while
+// musikcube is stopped.
+fn parse_musikcube_overview(overview: &Value) -> MusikcubeStatus {
+    let mut musikcube_status = MusikcubeStatus { state: PlaybackState::Stopped, file_path: None, duration: None, position: None, metadata: None };
+    musikcube_status.state = match overview["state"].as_str() {
+        Some("playing") => PlaybackState::Playing,
+        Some("paused") => PlaybackState::Paused,
+        _ => return musikcube_status,
+    };
+    let seconds = |value: &Value| value.as_f64().filter(|value| *value >= 0.0).map(|value| value as u64);
+    let text = |value: &Value| value.as_str().filter(|text| !text.is_empty()).map(String::from);
+    let track = &overview["playing_track"];
+
+    musikcube_status.duration = seconds(&overview["playing_duration"]).filter(|duration| *duration > 0);
+    musikcube_status.position = seconds(&overview["playing_current_time"]);
+    musikcube_status.file_path = text(&track["filename"]).filter(|file_path| file_path.starts_with('/'));
+    if musikcube_status.file_path.is_none() {
+        musikcube_status.metadata = text(&track["artist"]).zip(text(&track["title"])).map(|(artist, title)| PlayerMetadata {
+            artist,
+            title,
+            album: text(&track["album"]),
+            art_url: None,
+        });
+        musikcube_status.file_path = text(&track["external_id"]).filter(|_| musikcube_status.metadata.is_some());
+    }
+    musikcube_status
+}
+
+// Reads up to the next text message. Pings are answered by the WebSocket as they are read.
+fn read_text_message(connection: &mut WebSocket<TcpStream>) -> Result<String, PlayerError> {
+    loop {
+        match connection.read().map_err(websocket_error)? {
+            Message::Text(message) => return Ok(message.to_string()),
+            Message::Close(_) => return Err(PlayerError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "musikcube closed the connection."))),
+            _ => (),
+        }
+    }
+}
+
+// A closed connection is reported as an I/O error, so it is reopened at the next poll.
+fn websocket_error(websocket_error: tungstenite::Error) -> PlayerError {
+    match websocket_error {
+        tungstenite::Error::Io(e) => PlayerError::Io(e),
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => PlayerError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "musikcube closed the connection.")),
+        e => PlayerError::Protocol(format!("musikcube's WebSocket failed: {}", e)),
+    }
+}
+/************************** END Function Implementations for musikcube **************************/
+
+/************************** Function Implementations for YouTube Music and browsers **************************/
+/*
+ *  YouTube Music desktop apps and web browsers are read through MPRIS2, on a bus name that depends on the player
+ *  (see browser_bus_filter) unless mpris_bus_name is set.
+ *
+ *  - Tracks are shown with the artist, title, album, and thumbnail link the player reports, so no album art is
+ *    uploaded. Thumbnails that browsers only provide as local copies aren't shown.
+ *  - Browsers don't report a URL for tracks, and may reuse one track ID for every track, so tracks are identified by
+ *    their URL if there is one, and by their artist and title otherwise.
+ *  - The player's process is found from the owner of its bus name, since browsers' process names vary.
+ */
+pub struct Browser {
+    mpris: Mpris,
+}
+
+impl Browser {
+    pub fn new(player_name: &str, bus_name: Option<String>) -> Self {
+        Browser {
+            mpris: Mpris::new(Some(bus_name.unwrap_or(String::from(browser_bus_filter(player_name))))),
+        }
+    }
+}
+
+// The bus name each player registers, after the MPRIS prefix. Chromium-based browsers and Electron apps such as
+// ytmdesktop register as chromium, followed by an instance suffix.
+fn browser_bus_filter(player_name: &str) -> &str {
+    match player_name {
+        "chrome" | "chromium" | "ytmdesktop" => "chromium",
+        player_name => player_name,
+    }
+}
+
+impl StandardPlayer for Browser {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        self.mpris.verify_running()
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let file_path = self.mpris.get_active_file_path()?;
+        let Some(player_metadata) = self.mpris.get_metadata() else {
+            return Ok(file_path);
+        };
+        Ok(file_path.filter(|file_path| file_path.starts_with("https://") || file_path.starts_with("http://"))
+                    .or(Some(format!("{} - {}", player_metadata.artist, player_metadata.title))))
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.mpris.get_duration()
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.mpris.get_position()
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        self.mpris.get_process_name()
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.mpris.get_metadata()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.mpris.get_playback_state()
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        self.mpris.wait_for_change(timeout);
+    }
+}
+/************************** END Function Implementations for YouTube Music and browsers **************************/
+
+/************************** Function Implementations for XMMS2 **************************/
+// The fields read from xmms2 current, one per line. playtime and duration are formatted as [hours:]minutes:seconds.
+const XMMS2_FORMAT: &str = "${playback_status}\n${playtime}\n${duration}\n${url}";
+
+/*
+ *  XMMS2 is queried through its command line client, running xmms2 current on every poll.
+ *
+ *  - XMMS2 reports files as file:// URLs, where spaces are encoded as + and other characters are percent-encoded.
+ *    They are converted to paths. Streams are used as they are.
+ *  - The player's process is the xmms2d server, so player_name may be either xmms2 or xmms2d.
+ */
+pub struct Xmms2 {
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+}
+
+impl Xmms2 {
+    pub fn new() -> Self {
+        Xmms2 {
+            active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    fn query_current() -> Result<String, PlayerError> {
+        let output = process::Command::new("xmms2").arg("current").arg("-f").arg(XMMS2_FORMAT).output()?;
+        if !output.status.success() {
+            return Err(PlayerError::Protocol(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl StandardPlayer for Xmms2 {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // xmms2d is running properly if the client can connect to it.
+        match Xmms2::query_current() {
+            Ok(_) => Ok(true),
+            Err(PlayerError::Protocol(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let xmms2_status = parse_xmms2_output(&Xmms2::query_current()?);
+        self.active_state = xmms2_status.state;
+        self.active_duration = xmms2_status.duration;
+        self.active_position = xmms2_status.position;
+        Ok(xmms2_status.file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(String::from("xmms2d"))
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// The values reported by xmms2 current that are used to follow the active track.
+struct Xmms2Status {
+    state: PlaybackState,
+    file_path: Option<String>,
+    duration: Option<u64>,
+    position: Option<u64>,
+}
+
+// Reads the lines of XMMS2_FORMAT from xmms2 current. Nothing is active while XMMS2 is stopped.
+fn parse_xmms2_output(xmms2_output: &str) -> Xmms2Status {
+    let mut xmms2_status = Xmms2Status { state: PlaybackState::Stopped, file_path: None, duration: None, position: None };
+    let mut output_lines = xmms2_output.lines();
+    let (Some(playback_status), Some(playtime), Some(duration), Some(url)) = (output_lines.next(), output_lines.next(), output_lines.next(), output_lines.next()) else {
+        return xmms2_status;
+    };
+    xmms2_status.state = match playback_status.trim() {
+        "Playing" => PlaybackState::Playing,
+        "Paused" => PlaybackState::Paused,
+        _ => return xmms2_status,
+    };
+
+    // Each field of a time is added to the previous fields multiplied by 60.
+    let seconds = |time: &str| time.trim().split(':').try_fold(0, |seconds: u64, field| Some(seconds * 60 + field.parse::<u64>().ok()?));
+    xmms2_status.duration = seconds(duration).filter(|duration| *duration > 0);
+    xmms2_status.position = seconds(playtime);
+    xmms2_status.file_path = Some(url.trim()).filter(|url| !url.is_empty())
+                                             .map(|url| file_uri_to_path(url.replace('+', " ").as_str()).unwrap_or(String::from(url)));
+    xmms2_status
+}
+/************************** END Function Implementations for XMMS2 **************************/
+
+/************************** Function Implementations for foobar2000 **************************/
+// Time to wait for beefweb to accept a connection or answer a request.
+const FOOBAR2000_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The player state requested from beefweb, along with the path of the active file.
+const FOOBAR2000_REQUEST_PATH: &str = "/api/player?columns=%25path%25";
+
+/*
+ *  foobar2000, on Windows or run through Wine, is queried through the HTTP API of its beefweb plugin on every poll.
+ *
+ *  - beefweb reports the active item's position and duration in seconds, and its path as a Windows path. Under Wine,
+ *    paths are converted to paths on the host through the drives of the Wine prefix (see wine_prefix). Streams are
+ *    used as they are.
+ *  - The player's process is foobar2000.exe, so player_name is foobar2000.
+ */
+pub struct Foobar2000 {
+    address: String,
+    wine_prefix: Option<PathBuf>,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+}
+
+impl Foobar2000 {
+    pub fn new(address: String) -> Self {
+        Foobar2000 {
+            address,
+            wine_prefix: wine_prefix(),
+            active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, PlayerError> {
+        let stream = self.address.to_socket_addrs()?
+                                 .find_map(|socket_address| TcpStream::connect_timeout(&socket_address, FOOBAR2000_TIMEOUT).ok())
+                                 .ok_or(io::Error::new(io::ErrorKind::ConnectionRefused, format!("beefweb is not listening on {}.", self.address)))?;
+        Ok(stream)
+    }
+
+    // Requests the player state through the shared HTTP client, on the runtime the player thread was started from.
+    fn query_player(&self) -> Result<Value, PlayerError> {
+        let request_url = format!("http://{}{}", self.address, FOOBAR2000_REQUEST_PATH);
+        tokio::runtime::Handle::current().block_on(async {
+            let response = http::send(|client| client.get(&request_url).timeout(FOOBAR2000_TIMEOUT)).await?.error_for_status()?;
+            Ok(response.json::<Value>().await?)
+        })
+    }
+}
+
+impl StandardPlayer for Foobar2000 {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // foobar2000 is running properly if beefweb accepts connections.
+        match self.connect() {
+            Ok(_) => Ok(true),
+            Err(PlayerError::Io(e)) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let foobar2000_status = parse_beefweb_player(&self.query_player()?, self.wine_prefix.as_deref());
+        self.active_state = foobar2000_status.state;
+        self.active_duration = foobar2000_status.duration;
+        self.active_position = foobar2000_status.position;
+        Ok(foobar2000_status.file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(String::from("foobar2000.exe"))
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// The values reported by beefweb that are used to follow the active track.
+struct Foobar2000Status {
+    state: PlaybackState,
+    file_path: Option<String>,
+    duration: Option<u64>,
+    position: Option<u64>,
+}
+
+// Reads the active item from beefweb's player state. Nothing is active while foobar2000 is stopped.
+fn parse_beefweb_player(player_state: &Value, wine_prefix: Option<&Path>) -> Foobar2000Status {
+    let mut foobar2000_status = Foobar2000Status { state: PlaybackState::Stopped, file_path: None, duration: None, position: None };
+    let player = &player_state["player"];
+    foobar2000_status.state = match player["playbackState"].as_str() {
+        Some("playing") => PlaybackState::Playing,
+        Some("paused") => PlaybackState::Paused,
+        _ => return foobar2000_status,
+    };
+    let active_item = &player["activeItem"];
+    let seconds = |value: &Value| value.as_f64().filter(|value| *value >= 0.0).map(|value| value as u64);
+    foobar2000_status.duration = seconds(&active_item["duration"]).filter(|duration| *duration > 0);
+    foobar2000_status.position = seconds(&active_item["position"]);
+    foobar2000_status.file_path = active_item["columns"][0].as_str().filter(|file_path| !file_path.is_empty()).map(|file_path| host_path(file_path, wine_prefix));
+    foobar2000_status
+}
+
+// The Wine prefix Windows players run in, $WINEPREFIX or ~/.wine, where Z: is the root directory by default. Windows
+// players on Windows report paths that are used as they are.
+#[cfg(unix)]
+fn wine_prefix() -> Option<PathBuf> {
+    Some(std::env::var_os("WINEPREFIX").map(PathBuf::from).unwrap_or_else(|| std::env::home_dir().unwrap_or_default().join(".wine")))
+}
+
+#[cfg(windows)]
+fn wine_prefix() -> Option<PathBuf> {
+    None
+}
+
+// The path of a file reported by a Windows player, converted through the Wine prefix when there is one.
+fn host_path(file_path: &str, wine_prefix: Option<&Path>) -> String {
+    wine_prefix.and_then(|wine_prefix| windows_path_to_path(file_path, wine_prefix)).unwrap_or(String::from(file_path))
+}
+
+// Converts a Windows path, such as Z:\music\track.flac, to a path through the drive's link in the Wine prefix. Returns
+// None for anything else, such as stream URLs.
+fn windows_path_to_path(windows_path: &str, wine_prefix: &Path) -> Option<String> {
+    let (drive, drive_path) = windows_path.split_once(":\\")?;
+    if drive.len() != 1 || !drive.chars().all(|drive| drive.is_ascii_alphabetic()) {
+        return None;
+    }
+    let drive_dir = wine_prefix.join("dosdevices").join(format!("{}:", drive.to_ascii_lowercase()));
+    Some(drive_dir.join(drive_path.replace('\\', "/")).to_string_lossy().into_owned())
+}
+/************************** END Function Implementations for foobar2000 **************************/
+
+/************************** Function Implementations for MusicBee **************************/
+// Time to wait for the MusicBee Remote plugin to accept a connection or answer a request.
+const MUSICBEE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The version of the MusicBee Remote protocol that is requested, the first to report the active file's path.
+const MUSICBEE_PROTOCOL_VERSION: u64 = 4;
+
+/*
+ *  MusicBee, on Windows or run through Wine, is queried through the TCP server of its MusicBee Remote plugin on every
+ *  poll.
+ *
+ *  - Messages are JSON objects with a context and its data, each ending in \r\n. A connection must introduce itself as
+ *    a client and agree on the protocol version before making requests, and asks not to be sent broadcasts, so that
+ *    each request is only answered by its reply. Messages with another context are skipped anyway.
+ *  - The plugin reports the position and duration in milliseconds, and the active file's path as a Windows path,
+ *    which is converted through the Wine prefix as for foobar2000.
+ *  - The player's process is MusicBee.exe, so player_name is musicbee.
+ */
+pub struct MusicBee {
+    address: String,
+    wine_prefix: Option<PathBuf>,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+}
+
+impl MusicBee {
+    pub fn new(address: String) -> Self {
+        MusicBee {
+            address,
+            wine_prefix: wine_prefix(),
+            active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, PlayerError> {
+        let stream = self.address.to_socket_addrs()?
+                                 .find_map(|socket_address| TcpStream::connect_timeout(&socket_address, MUSICBEE_TIMEOUT).ok())
+                                 .ok_or(io::Error::new(io::ErrorKind::ConnectionRefused, format!("MusicBee Remote is not listening on {}.", self.address)))?;
+        stream.set_read_timeout(Some(MUSICBEE_TIMEOUT))?;
+        stream.set_write_timeout(Some(MUSICBEE_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    // Connects and completes the handshake, after which requests can be made on the connection.
+    fn open_session(&self) -> Result<BufReader<TcpStream>, PlayerError> {
+        let mut connection = BufReader::new(self.connect()?);
+        musicbee_request(&mut connection, "player", json!("Android"))?;
+        musicbee_request(&mut connection, "protocol", json!({ "protocol_version": MUSICBEE_PROTOCOL_VERSION, "no_broadcast": true }))?;
+        Ok(connection)
+    }
+}
+
+impl StandardPlayer for MusicBee {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // MusicBee is running properly if MusicBee Remote accepts connections.
+        match self.connect() {
+            Ok(_) => Ok(true),
+            Err(PlayerError::Io(e)) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        let mut connection = self.open_session()?;
+        let player_state = musicbee_request(&mut connection, "playerstate", json!(""))?;
+        let (track, position) = match parse_musicbee_state(&player_state) {
+            PlaybackState::Stopped => (Value::Null, Value::Null),
+            _ => (musicbee_request(&mut connection, "nowplayingtrack", json!(""))?, musicbee_request(&mut connection, "nowplayingposition", json!(""))?),
+        };
+        let musicbee_status = parse_musicbee_status(&player_state, &track, &position, self.wine_prefix.as_deref());
+        self.active_state = musicbee_status.state;
+        self.active_duration = musicbee_status.duration;
+        self.active_position = musicbee_status.position;
+        Ok(musicbee_status.file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(String::from("MusicBee.exe"))
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Sends a message and returns the data of its reply, skipping messages with another context.
+fn musicbee_request(connection: &mut BufReader<TcpStream>, context: &str, data: Value) -> Result<Value, PlayerError> {
+    write!(connection.get_mut(), "{}\r\n", json!({ "context": context, "data": data }))?;
+    loop {
+        let mut line = String::new();
+        if connection.read_line(&mut line)? == 0 {
+            return Err(PlayerError::Protocol(String::from("MusicBee Remote closed the connection.")));
+        }
+        let Ok(mut reply) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if reply["context"] == context {
+            return Ok(reply["data"].take());
+        }
+    }
+}
+
+// The values reported by MusicBee Remote that are used to follow the active track.
+struct MusicBeeStatus {
+    state: PlaybackState,
+    file_path: Option<String>,
+    duration: Option<u64>,
+    position: Option<u64>,
+}
+
+fn parse_musicbee_state(player_state: &Value) -> PlaybackState {
+    match player_state.as_str().map(str::to_ascii_lowercase).as_deref() {
+        Some("playing") => PlaybackState::Playing,
+        Some("paused") => PlaybackState::Paused,
+        _ => PlaybackState::Stopped,
+    }
+}
+
+// Reads the active track from the replies to playerstate, nowplayingtrack, and nowplayingposition. Nothing is active
+// while MusicBee is stopped.
+fn parse_musicbee_status(player_state: &Value, track: &Value, position: &Value, wine_prefix: Option<&Path>) -> MusicBeeStatus {
+    let mut musicbee_status = MusicBeeStatus { state: parse_musicbee_state(player_state), file_path: None, duration: None, position: None };
+    if musicbee_status.state == PlaybackState::Stopped {
+        return musicbee_status;
+    }
+    let seconds = |value: &Value| value.as_i64().filter(|value| *value >= 0).map(|value| value as u64 / 1000);
+    musicbee_status.duration = seconds(&position["total"]).filter(|duration| *duration > 0);
+    musicbee_status.position = seconds(&position["current"]);
+    musicbee_status.file_path = track["path"].as_str().filter(|file_path| !file_path.is_empty()).map(|file_path| host_path(file_path, wine_prefix));
+    musicbee_status
+}
+/************************** END Function Implementations for MusicBee **************************/
+
+/************************** Function Implementations for PipeWire and PulseAudio **************************/
+/*
+ *  As a last resort, players without support of their own are followed through the audio streams they play, listed
+ *  by pactl, which works with both PulseAudio and PipeWire (through pipewire-pulse).
+ *
+ *  - The first stream that is playing and has a media.title is shown, with its media.artist, or the name of the
+ *    application playing it if it has none. Streams only report a title, so there is no duration or album art.
+ *  - The player's process is the sound server, pipewire-pulse or pulseaudio, depending on player_name.
+ *  - When listed with other players, nothing active is treated as the player exiting, so that the other players,
+ *    which are listed first, are looked for again rather than the sound server being followed for as long as it runs.
+ */
+pub struct AudioStreams {
+    process_name: String,
+    yield_when_idle: bool,
+    active_metadata: Option<PlayerMetadata>,
+}
+
+impl AudioStreams {
+    // player_name is either pipewire or pulseaudio.
+    pub fn new(player_name: &str, yield_when_idle: bool) -> Self {
+        AudioStreams {
+            process_name: String::from(if player_name == "pulseaudio" { "pulseaudio" } else { "pipewire-pulse" }),
+            yield_when_idle,
+            active_metadata: None,
+        }
+    }
+
+    fn list_streams() -> Result<Value, PlayerError> {
+        let output = process::Command::new("pactl").arg("--format=json").arg("list").arg("sink-inputs").output()?;
+        if !output.status.success() {
+            return Err(PlayerError::Protocol(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| PlayerError::Protocol(e.to_string()))
+    }
+}
+
+impl StandardPlayer for AudioStreams {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        // The sound server is only worth following while one of its streams is playing something with a title.
+        Ok(parse_audio_streams(&AudioStreams::list_streams()?).is_some())
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.active_metadata = parse_audio_streams(&AudioStreams::list_streams()?);
+        if self.active_metadata.is_none() && self.yield_when_idle {
+            return Err(PlayerError::Exited(self.process_name.clone()));
+        }
+        // Streams are identified by their artist and title.
+        Ok(self.active_metadata.as_ref().map(|metadata| format!("{} - {}", metadata.artist, metadata.title)))
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        None
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        None
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(self.process_name.clone())
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.active_metadata.clone()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        PlaybackState::Playing
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// Reads the metadata of the first stream that is playing (not corked) from pactl's list of sink inputs. Streams
+// titled with the name of their application, such as "Firefox", don't describe what is playing, and are skipped.
+fn parse_audio_streams(sink_inputs: &Value) -> Option<PlayerMetadata> {
+    let text = |value: &Value| value.as_str().map(str::trim).filter(|text| !text.is_empty()).map(String::from);
+    sink_inputs.as_array()?.iter().filter(|sink_input| sink_input["corked"] != true).find_map(|sink_input| {
+        let properties = &sink_input["properties"];
+        let application_name = text(&properties["application.name"]);
+        let title = text(&properties["media.title"]).filter(|title| Some(title) != application_name.as_ref())?;
+        Some(PlayerMetadata {
+            artist: text(&properties["media.artist"]).or(application_name)?,
+            title,
+            album: None,
+            art_url: None,
+        })
+    })
+}
+/************************** END Function Implementations for PipeWire and PulseAudio **************************/
+
+/************************** Function Implementations for external commands **************************/
+/*
+ *  Any player can be followed through a command that reports its state, run through sh (cmd on Windows) on every poll.
+ *
+ *  - The command prints a JSON object such as {"path": "/music/track.flac", "duration": 215, "position": 12,
+ *    "state": "playing"}, with times in seconds. state is playing, paused, or stopped, and nothing is active while the
+ *    player is stopped or no path is printed.
+ *  - A command that fails or prints something else is logged, and the poll is treated as if nothing were playing, so a
+ *    broken script doesn't stop lamp-drpc.
+ *  - The player's process is found by process_name, since player_name is 'command'.
+ */
+pub struct ExternalCommand {
+    status_command: String,
+    process_name: String,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_state: PlaybackState,
+}
+
+impl ExternalCommand {
+    pub fn new(status_command: String, process_name: String) -> Self {
+        ExternalCommand {
+            status_command,
+            process_name,
+            active_duration: None,
+            active_position: None,
+            active_state: PlaybackState::Stopped,
+        }
+    }
+}
+
+impl StandardPlayer for ExternalCommand {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        Ok(true)
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.active_duration = None;
+        self.active_position = None;
+        let output = shell_command(&self.status_command).stdin(process::Stdio::null()).output()?;
+        if !output.status.success() {
+            error_log::log_error("player:ExternalCommand Error", format!("The status command exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).as_str());
+            return Ok(None);
+        }
+        let command_status = match parse_command_output(&output.stdout) {
+            Ok(command_status) => command_status,
+            Err(e) => {
+                error_log::log_error("player:ExternalCommand Error", format!("The status command printed invalid JSON: {}", e).as_str());
+                return Ok(None);
+            }
+        };
+
+        self.active_state = command_status.state;
+        self.active_duration = command_status.duration;
+        self.active_position = command_status.position;
+        Ok(command_status.file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(self.process_name.clone())
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// The active track read from a status command's output. Times are whole seconds.
+struct CommandStatus {
+    active: bool,
+    state: PlaybackState,
+    file_path: Option<String>,
+    duration: Option<u64>,
+    position: Option<u64>,
+}
+
+// Reads a status command's output.
+fn parse_command_output(command_output: &[u8]) -> Result<CommandStatus, serde_json::Error> {
+    Ok(read_command_status(&serde_json::from_slice::<Value>(command_output)?))
+}
+
+// Reads the state printed by a status command or a plugin. A missing state is treated as playing, so simple scripts
+// only need to print a path.
+fn read_command_status(command_output: &Value) -> CommandStatus {
+    let active = matches!(command_output["state"].as_str(), None | Some("playing") | Some("paused"));
+    let seconds = |value: &Value| value.as_f64().filter(|value| active && *value >= 0.0).map(|value| value as u64);
+    CommandStatus {
+        active,
+        state: match command_output["state"].as_str() {
+            Some("paused") => PlaybackState::Paused,
+            Some("stopped") => PlaybackState::Stopped,
+            _ => PlaybackState::Playing,
+        },
+        file_path: command_output["path"].as_str().filter(|file_path| active && !file_path.is_empty()).map(String::from),
+        duration: seconds(&command_output["duration"]).filter(|duration| *duration > 0),
+        position: seconds(&command_output["position"]),
+    }
+}
+/************************** END Function Implementations for external commands **************************/
+
+/************************** Function Implementations for now playing files **************************/
+/*
+ *  Players that can only write a "now playing" file (e.g. through a plugin) are followed by reading that file, which
+ *  holds the active file's path on its first line and, optionally, its duration in seconds on its second.
+ *
+ *  - The file's directory is watched with inotify, so a rewritten file is read as soon as it is closed or moved into
+ *    place, rather than at the next poll. Without inotify, such as on Windows, the file is still read on every poll.
+ *  - Nothing is active while the file is missing or empty.
+ *  - The player's process is found by process_name, since player_name is 'file'.
+ */
+pub struct NowPlayingFile {
+    file_path: PathBuf,
+    process_name: String,
+    #[cfg(unix)]
+    inotify: Option<Inotify>,
+    active_duration: Option<u64>,
+}
+
+impl NowPlayingFile {
+    pub fn new(file_path: PathBuf, process_name: String) -> Self {
+        #[cfg(unix)]
+        let inotify = watch_directory(&file_path);
+        #[cfg(unix)]
+        if let Err(e) = &inotify {
+            error_log::log_error("player:NowPlayingFile:watch_directory Error", format!("Changes to {} can't be watched, so it is only read on every poll: {}", file_path.display(), e).as_str());
+        }
+        NowPlayingFile {
+            file_path,
+            process_name,
+            #[cfg(unix)]
+            inotify: inotify.ok(),
+            active_duration: None,
+        }
+    }
+}
+
+impl StandardPlayer for NowPlayingFile {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        Ok(true)
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.active_duration = None;
+        let contents = match fs::read_to_string(&self.file_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(PlayerError::Io(e)),
+        };
+        let (file_path, duration) = parse_now_playing_file(&contents);
+        self.active_duration = duration;
+        Ok(file_path)
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        None
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(self.process_name.clone())
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        PlaybackState::Playing
+    }
+
+    #[cfg(windows)]
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+
+    #[cfg(unix)]
+    fn wait_for_change(&mut self, timeout: Duration) {
+        let Some(inotify) = &self.inotify else {
+            thread::sleep(timeout);
+            return;
+        };
+        let file_name = self.file_path.file_name();
+        let wait_start = Instant::now();
+        loop {
+            let remaining = timeout.saturating_sub(wait_start.elapsed());
+            let mut poll_fds = [PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut poll_fds, PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)) {
+                Ok(0) => return,
+                Ok(_) => {
+                    // Only changes to the now playing file itself end the wait.
+                    let events = inotify.read_events().unwrap_or_default();
+                    if events.iter().any(|event| event.name.as_deref() == file_name) {
+                        return;
+                    }
+                }
+                Err(_) => {
+                    thread::sleep(remaining);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Watches the directory of the now playing file, since players often replace the file rather than write to it.
+#[cfg(unix)]
+fn watch_directory(file_path: &Path) -> nix::Result<Inotify> {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    let watched_dir = file_path.parent().filter(|watched_dir| !watched_dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    inotify.add_watch(watched_dir, AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE)?;
+    Ok(inotify)
+}
+
+// Reads the path on the first line of a now playing file, and the duration in seconds on the second, if any.
+fn parse_now_playing_file(contents: &str) -> (Option<String>, Option<u64>) {
+    let mut lines = contents.lines();
+    let file_path = lines.next().map(str::trim).filter(|file_path| !file_path.is_empty()).map(String::from);
+    let duration = lines.next().and_then(|duration| duration.trim().parse::<f64>().ok()).filter(|duration| *duration > 0.0 && file_path.is_some());
+    (file_path, duration.map(|duration| duration as u64))
+}
+/************************** END Function Implementations for now playing files **************************/
+
+/************************** Function Implementations for plugins **************************/
+/*
+ *  Players without built-in support can be followed through plugins, configured in the plugins table of lamp.toml by
+ *  player name. A plugin is a long-lived process, started through sh (cmd on Windows), that answers requests written to its stdin with
+ *  replies printed to its stdout, one JSON object per line.
+ *
+ *  - Every request holds PLUGIN_PROTOCOL_VERSION and the method called, e.g. {"version": 1, "method": "poll"}.
+ *    - hello is sent once the plugin has started, and is answered with the name of the player's process, e.g.
+ *      {"process_name": "foobar"}. Without one, the plugin's name is used.
+ *    - verify_running is answered with {"running": true} once the player can be followed.
+ *    - poll is answered with the player's state, in the form status commands print (e.g. {"path": "/music/track.flac",
+ *      "duration": 215, "position": 12, "state": "playing"}), along with the artist, title, album, and art_url of
+ *      tracks that aren't local files.
+ *  - Replies must be printed within PLUGIN_TIMEOUT. A plugin that exits or stops replying is stopped and treated as an
+ *    exited player, and it is started again the next time it is needed.
+ *  - Unknown fields are ignored and missing fields are treated as unknown, so plugins keep working as methods and fields
+ *    are added. PLUGIN_PROTOCOL_VERSION only changes when existing ones change meaning.
+ */
+pub struct Plugin {
+    plugin_name: String,
+    plugin_command: String,
+    connection: RefCell<Option<PluginConnection>>,
+    process_name: RefCell<String>,
+    active_duration: Option<u64>,
+    active_position: Option<u64>,
+    active_metadata: Option<PlayerMetadata>,
+    active_state: PlaybackState,
+}
+
+const PLUGIN_PROTOCOL_VERSION: u64 = 1;
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Plugin {
+    pub fn new(plugin_name: &str, plugin_command: String) -> Self {
+        let plugin = Plugin {
+            plugin_name: String::from(plugin_name),
+            plugin_command,
+            connection: RefCell::new(None),
+            process_name: RefCell::new(String::from(plugin_name)),
+            active_duration: None,
+            active_position: None,
+            active_metadata: None,
+            active_state: PlaybackState::Stopped,
+        };
+        // The plugin is started right away, so the player's process name is known when its PID is looked up.
+        if let Err(e) = plugin.plugin_request("hello") {
+            error_log::log_error("player:Plugin:new Error", format!("The plugin {} could not be started: {}", plugin.plugin_name, e).as_str());
+        }
+        plugin
+    }
+
+    // Sends a request to the plugin, starting it first if it isn't running. A plugin that fails to reply is stopped.
+    fn plugin_request(&self, method: &str) -> Result<Value, PlayerError> {
+        let mut connection = self.connection.borrow_mut();
+        if connection.is_none() {
+            let mut started = PluginConnection::start(&self.plugin_command)?;
+            let hello = started.request("hello")?;
+            *self.process_name.borrow_mut() = hello["process_name"].as_str().filter(|process_name| !process_name.is_empty()).unwrap_or(&self.plugin_name).to_string();
+            *connection = Some(started);
+            if method == "hello" {
+                return Ok(hello);
+            }
+        }
+        let reply = connection.as_mut().map(|connection| connection.request(method)).unwrap_or_else(|| Err(PlayerError::Exited(self.plugin_name.clone())));
+        if reply.is_err() {
+            *connection = None;
+        }
+        reply
+    }
+}
+
+impl StandardPlayer for Plugin {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        Ok(self.plugin_request("verify_running")?["running"].as_bool().unwrap_or(true))
+    }
+
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        self.active_duration = None;
+        self.active_position = None;
+        self.active_metadata = None;
+        let reply = self.plugin_request("poll").map_err(|e| {
+            error_log::log_error("player:Plugin Error", format!("The plugin {} stopped replying: {}", self.plugin_name, e).as_str());
+            PlayerError::Exited(self.plugin_name.clone())
+        })?;
+        let command_status = read_command_status(&reply);
+        let text = |value: &Value| value.as_str().filter(|text| !text.is_empty()).map(String::from);
+        let metadata = text(&reply["artist"]).zip(text(&reply["title"])).filter(|_| command_status.active).map(|(artist, title)| PlayerMetadata {
+            artist,
+            title,
+            album: text(&reply["album"]),
+            art_url: text(&reply["art_url"]),
+        });
+
+        self.active_state = command_status.state;
+        self.active_duration = command_status.duration;
+        self.active_position = command_status.position;
+        // Tracks that aren't local files are identified by their artist and title when no path is given. Local files are
+        // read for their own tags, so the plugin's are only used for the others.
+        match command_status.file_path {
+            Some(file_path) => Ok(Some(file_path)),
+            None => {
+                let file_path = metadata.as_ref().map(|metadata| format!("{} - {}", metadata.artist, metadata.title));
+                self.active_metadata = metadata;
+                Ok(file_path)
+            }
+        }
+    }
+
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    fn get_position(&self) -> Option<u64> {
+        self.active_position
+    }
+
+    fn get_process_name(&self) -> Option<String> {
+        Some(self.process_name.borrow().clone())
+    }
+
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        self.active_metadata.clone()
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    fn get_playback_state(&self) -> PlaybackState {
+        self.active_state
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+}
+
+// A running plugin. Its replies are read on a separate thread, so a plugin that stops replying can be timed out.
+struct PluginConnection {
+    plugin_child: process::Child,
+    plugin_stdin: process::ChildStdin,
+    replies: std::sync::mpsc::Receiver<String>,
+}
+
+impl PluginConnection {
+    fn start(plugin_command: &str) -> Result<Self, PlayerError> {
+        let mut plugin_child = shell_command(plugin_command)
+                                            .stdin(process::Stdio::piped())
+                                            .stdout(process::Stdio::piped())
+                                            .spawn()?;
+        let (Some(plugin_stdin), Some(plugin_stdout)) = (plugin_child.stdin.take(), plugin_child.stdout.take()) else {
+            let _ = plugin_child.kill();
+            return Err(PlayerError::Protocol(String::from("The plugin's stdin and stdout could not be opened.")));
+        };
+        let (reply_sender, replies) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(plugin_stdout).lines().map_while(Result::ok) {
+                if reply_sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(PluginConnection { plugin_child, plugin_stdin, replies })
+    }
+
+    fn request(&mut self, method: &str) -> Result<Value, PlayerError> {
+        writeln!(self.plugin_stdin, "{}", json!({"version": PLUGIN_PROTOCOL_VERSION, "method": method}))?;
+        self.plugin_stdin.flush()?;
+        loop {
+            let line = match self.replies.recv_timeout(PLUGIN_TIMEOUT) {
+                Ok(line) => line,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => return Err(PlayerError::Protocol(format!("No reply to {} was received within {} seconds.", method, PLUGIN_TIMEOUT.as_secs()))),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return Err(PlayerError::Protocol(String::from("The plugin has exited."))),
+            };
+            // Blank lines are skipped, so plugins may end replies with an extra newline.
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line).map_err(|e| PlayerError::Protocol(format!("The reply to {} is invalid JSON: {}", method, e)));
+        }
+    }
+}
+
+impl Drop for PluginConnection {
+    fn drop(&mut self) {
+        let _ = self.plugin_child.kill();
+        let _ = self.plugin_child.wait();
+    }
+}
+/************************** END Function Implementations for plugins **************************/
+
+/************************** Function Implementations Template **************************/
+/*
+
+[PLAYER IMPLEMENTATION HERE]
+
+// If a player struct will require data values for use in its functions, they should be included here
+// and in the Default trait implementation.
+
+pub struct NewPlayer {
+    active_duration: Option<u64>,
+}
+
+impl Default for NewPlayer {
+    fn default() -> Self {
+        NewPlayer {
+            active_duration: None,
+        }
+    }
+}
+
+impl StandardPlayer for NewPlayer {
+    fn verify_running(&self) -> Result<bool, PlayerError> {
+        Ok(true)
+    }
+
+    // If None is returned, nothing will be shown on Discord, but the program will continue running.
+    // Error logging is handled in main.rs when this function is called, so an error returned here will be logged.
+    fn get_active_file_path(&mut self) -> Result<Option<String>, PlayerError> {
+        Ok(None)
+    }
+
+    // active_duration does not need to be stored in the struct.
+    fn get_duration(&self) -> Option<u64> {
+        self.active_duration
+    }
+
+    // Players that can't report a position should return None.
+    fn get_position(&self) -> Option<u64> {
+        None
+    }
+
+    // Players found by their process name should return None.
+    fn get_process_name(&self) -> Option<String> {
+        None
+    }
+
+    // Players of local files should return None, so tags are read from the file.
+    fn get_metadata(&self) -> Option<PlayerMetadata> {
+        None
+    }
+
+    fn get_player_tags(&self) -> PlayerTags {
+        PlayerTags::default()
+    }
+
+    // Players that can't tell whether they are paused should return Playing.
+    fn get_playback_state(&self) -> PlaybackState {
+        PlaybackState::Playing
+    }
+
+    // Players that don't announce changes should sleep for the timeout.
+    fn wait_for_change(&mut self, timeout: Duration) {
+        thread::sleep(timeout);
+    }
+} 
+
+*/
+/************************** END Function Implementations Template **************************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+    use crate::mock::{use_test_state_dir, MockPlayer};
+
+    // The test process stands in for the player process.
+    fn test_process_name() -> String {
+        let mut sys = System::new();
+        let test_pid = sysinfo::get_current_pid().unwrap();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[test_pid]), true, ProcessRefreshKind::nothing());
+        sys.process(test_pid).unwrap().name().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn finds_running_player_by_name() {
+        let mut sys = System::new();
+        let test_pid = sysinfo::get_current_pid().unwrap();
+        let player_pid = get_pid_by_proc_name(&mut sys, &test_process_name()).unwrap();
+
+        // Other processes may share the test binary's name, so the match is only checked to be a running player.
+        assert!(is_running(&mut sys, player_pid, &test_process_name()));
+        assert!(is_running(&mut sys, test_pid, &test_process_name()));
+        assert!(!is_running(&mut sys, test_pid, "not-the-player"));
+        assert!(matches!(get_pid_by_proc_name(&mut sys, "not-the-player"), Err(PlayerError::NotFound(_))));
+    }
+
+    #[test]
+    fn finds_players_with_names_longer_than_15_bytes() {
+        let mut sys = System::new();
+        let test_pid = sysinfo::get_current_pid().unwrap();
+
+        // The test binary is named with its hash, such as lamp_drpc-0123456789abcdef, which the kernel truncates.
+        let full_name = std::env::current_exe().unwrap().file_name().unwrap().to_string_lossy().into_owned();
+        assert!(full_name.len() > 15);
+        assert!(get_pid_by_proc_name(&mut sys, &full_name).is_ok());
+        assert!(is_running(&mut sys, test_pid, &full_name));
+        assert!(!is_running(&mut sys, test_pid, &format!("{}-other", &full_name[..10])));
+    }
+
+    #[test]
+    fn emits_events_as_player_state_changes() {
+        use_test_state_dir();
+        let mut player = MockPlayer::new(&[Some("first.mp3"), Some("first.mp3"), None, Some("second.mp3")], Some(180));
+
+        // The test process stands in for the player process.
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[player_pid]), true, ProcessRefreshKind::nothing());
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        poll_player(&mut player, sys, player_pid, &test_process_name(), 0, event_sender);
+
+        let mut events = Vec::<Event>::new();
+        while let Ok(event) = event_receiver.try_recv() {
+            events.push(event);
+        }
+
+        assert_eq!(events.len(), 4);
+        match &events[0] {
+            Event::TrackChanged(track) => {
+                assert_eq!(track.file_path, "first.mp3");
+                assert_eq!(track.end_time, track.start_time.map(|start_time| start_time + 180));
+            }
+            _ => panic!("Expected TrackChanged for the first track."),
+        }
+        assert!(matches!(events[1], Event::Stopped));
+        assert!(matches!(&events[2], Event::TrackChanged(track) if track.file_path == "second.mp3"));
+        assert!(matches!(events[3], Event::PlayerExited));
+    }
+
+    #[test]
+    fn keeps_polling_through_player_errors() {
+        use_test_state_dir();
+        let mut player = MockPlayer::new(&[Some("first.mp3")], Some(180)).failing_polls(2);
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        poll_player(&mut player, System::new(), player_pid, &test_process_name(), 0, event_sender);
+
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::TrackChanged(track)) if track.file_path == "first.mp3"));
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::PlayerExited)));
+    }
+
+    #[test]
+    fn stops_polling_a_panicking_player_to_switch_players() {
+        use_test_state_dir();
+        let mut player = MockPlayer::new(&[Some("first.mp3")], Some(180)).panicking();
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        let mut switch_checks = 0;
+        poll_player_until(&mut player, System::new(), player_pid, &test_process_name(), 0, event_sender, || {
+            switch_checks += 1;
+            true
+        });
+
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::PlayerExited)));
+        assert_eq!(switch_checks, 1);
+    }
+
+    #[test]
+    fn stops_polling_to_switch_players() {
+        use_test_state_dir();
+        let mut player = MockPlayer::new(&[Some("first.mp3"), Some("first.mp3"), Some("second.mp3")], Some(180));
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        let mut switch_checks = 0;
+        poll_player_until(&mut player, System::new(), player_pid, &test_process_name(), 0, event_sender, || {
+            switch_checks += 1;
+            switch_checks == 2
+        });
+
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::TrackChanged(track)) if track.file_path == "first.mp3"));
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::PlayerExited)));
+        assert_eq!(switch_checks, 2);
+    }
+
+    #[test]
+    fn follows_new_processes_only_if_the_secondary_check_passes() {
+        use_test_state_dir();
+        // The PID of an exited process stands in for a player that has exited, and the test process for another process
+        // with the player's name.
+        let mut exited_child = process::Command::new("true").spawn().unwrap();
+        let exited_pid = Pid::from_u32(exited_child.id());
+        exited_child.wait().unwrap();
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        let mut player = MockPlayer::new(&[Some("first.mp3"), Some("second.mp3")], Some(180));
+        poll_player(&mut player, System::new(), exited_pid, &test_process_name(), 0, event_sender);
+        assert!(matches!(&event_receiver.try_recv().unwrap(), Event::TrackChanged(track) if track.file_path == "first.mp3"));
+        assert!(matches!(&event_receiver.try_recv().unwrap(), Event::TrackChanged(track) if track.file_path == "second.mp3"));
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        let mut player = MockPlayer::new(&[Some("first.mp3"), Some("second.mp3")], Some(180)).failing_secondary_check();
+        poll_player(&mut player, System::new(), exited_pid, &test_process_name(), 0, event_sender);
+        assert!(matches!(&event_receiver.try_recv().unwrap(), Event::TrackChanged(track) if track.file_path == "first.mp3"));
+        assert!(matches!(event_receiver.try_recv().unwrap(), Event::PlayerExited));
+    }
+
+    #[test]
+    fn emits_paused_and_resumes_from_position() {
+        use_test_state_dir();
+        let mut player = MockPlayer::with_states(&[(Some("track.mp3"), Some(10), PlaybackState::Playing),
+                                                   (Some("track.mp3"), Some(11), PlaybackState::Paused),
+                                                   (Some("track.mp3"), Some(11), PlaybackState::Paused),
+                                                   (Some("track.mp3"), Some(12), PlaybackState::Playing),
+                                                   (Some("track.mp3"), Some(13), PlaybackState::Stopped)], Some(180));
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[player_pid]), true, ProcessRefreshKind::nothing());
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        poll_player(&mut player, sys, player_pid, &test_process_name(), 0, event_sender);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(matches!(event_receiver.try_recv().unwrap(), Event::TrackChanged(_)));
+        assert!(matches!(event_receiver.try_recv().unwrap(), Event::Paused));
+        // Resuming reports the start time from the position, even with resyncing disabled.
+        match event_receiver.try_recv().unwrap() {
+            Event::PositionChanged { start_time, end_time } => {
+                assert!(now.abs_diff(start_time + 12) <= 2);
+                assert_eq!(end_time, Some(start_time + 180));
+            }
+            _ => panic!("Expected PositionChanged once the track resumed."),
+        }
+        // A track reported as stopped is no longer active.
+        assert!(matches!(event_receiver.try_recv().unwrap(), Event::Stopped));
+        assert!(matches!(event_receiver.try_recv().unwrap(), Event::PlayerExited));
+    }
+
+    #[test]
+    fn emits_track_changed_when_track_restarts() {
+        use_test_state_dir();
+        let mut player = MockPlayer::with_positions(&[(Some("repeat.mp3"), Some(178)), (Some("repeat.mp3"), Some(0)), (Some("repeat.mp3"), Some(1))], Some(180));
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[player_pid]), true, ProcessRefreshKind::nothing());
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        poll_player(&mut player, sys, player_pid, &test_process_name(), 0, event_sender);
+
+        // The track is shown again once its position jumps back to the start, but not as it keeps playing.
+        let mut track_changes = 0;
+        while let Ok(event) = event_receiver.try_recv() {
+            if matches!(&event, Event::TrackChanged(track) if track.file_path == "repeat.mp3") {
+                track_changes += 1;
+            }
+        }
+        assert_eq!(track_changes, 2);
+    }
+
+    #[test]
+    fn emits_position_changed_when_position_drifts() {
+        use_test_state_dir();
+        let mut player = MockPlayer::with_positions(&[(Some("seek.mp3"), Some(10)), (Some("seek.mp3"), Some(11)), (Some("seek.mp3"), Some(120))], Some(180));
+        let player_pid = sysinfo::get_current_pid().unwrap();
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&[player_pid]), true, ProcessRefreshKind::nothing());
+
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel::<Event>();
+        poll_player(&mut player, sys, player_pid, &test_process_name(), 3, event_sender);
+
+        // The track starts as far back as its first position, and is resynced once after the seek.
+        let track_start_time = match event_receiver.try_recv() {
+            Ok(Event::TrackChanged(track)) => track.start_time.unwrap(),
+            _ => panic!("Expected TrackChanged for the track."),
+        };
+        match event_receiver.try_recv() {
+            Ok(Event::PositionChanged { start_time, end_time }) => {
+                assert!((track_start_time - 112..=track_start_time - 108).contains(&start_time));
+                assert_eq!(end_time, Some(start_time + 180));
+            }
+            _ => panic!("Expected PositionChanged after the seek."),
+        }
+        assert!(matches!(event_receiver.try_recv(), Ok(Event::PlayerExited)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn canonicalizes_symlinked_paths() {
+        let link_dir = std::env::temp_dir().join(format!("lamp-drpc-test-links-{}", std::process::id()));
+        fs::create_dir_all(&link_dir).unwrap();
+        let link_path = link_dir.join("linked.mp3");
+        let _ = fs::remove_file(&link_path);
+        std::os::unix::fs::symlink(crate::mock::fixture_path("tagged.mp3"), &link_path).unwrap();
+
+        let canonical_fixture = fs::canonicalize(crate::mock::fixture_path("tagged.mp3")).unwrap();
+        assert_eq!(canonical_path(link_path.to_string_lossy().into_owned()), canonical_fixture.to_string_lossy());
+        assert_eq!(canonical_path(format!("{}/./linked.mp3", link_dir.display())), canonical_fixture.to_string_lossy());
+        assert_eq!(canonical_path(String::from("http://radio.example/stream")), "http://radio.example/stream");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parses_cmus_remote_output_by_prefix() {
+        let playing = parse_cmus_remote_output("status playing\nfile /music/track.flac\nduration 215\nposition 12\ntag artist Test Artist\n").unwrap();
+        assert_eq!((playing.file_path, playing.duration, playing.position), (Some("/music/track.flac"), Some(215), Some(12)));
+
+        // Lines may be reordered or left out, and streams report no duration.
+        let stream = parse_cmus_remote_output("status playing\nstream Test Radio\nduration -1\nfile http://radio.example/stream\n").unwrap();
+        assert_eq!((stream.file_path, stream.duration), (Some("http://radio.example/stream"), None));
+        let stopped = parse_cmus_remote_output("status stopped\nset aaa_mode all\n").unwrap();
+        assert_eq!((stopped.file_path, stopped.duration, stopped.position), (None, None, None));
+        assert_eq!((playing.state, stopped.state), (PlaybackState::Playing, PlaybackState::Stopped));
+        assert_eq!(parse_cmus_remote_output("status paused\nfile /music/track.flac\n").unwrap().state, PlaybackState::Paused);
+
+        assert!(parse_cmus_remote_output("").is_none());
+        assert!(parse_cmus_remote_output("cmus-remote: cmus is not running\n").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cmus_is_checked_for_on_its_socket() {
+        use_test_state_dir();
+        let runtime_dir = std::env::temp_dir().join(format!("lamp-drpc-cmus-socket-{}", std::process::id()));
+        fs::create_dir_all(&runtime_dir).unwrap();
+        let socket_path = runtime_dir.join("custom-cmus-socket");
+        let cmus = Cmus::new(&runtime_dir, socket_path.clone(), false);
+        assert!(!cmus.verify_running().unwrap());
+        fs::write(&socket_path, "").unwrap();
+        assert!(cmus.verify_running().unwrap());
+        let _ = fs::remove_dir_all(&runtime_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cmus_status_changes_end_the_wait() {
+        use_test_state_dir();
+        let runtime_dir = std::env::temp_dir().join(format!("lamp-drpc-cmus-status-{}", std::process::id()));
+        fs::create_dir_all(&runtime_dir).unwrap();
+        let mut cmus = Cmus::new(&runtime_dir, runtime_dir.join("cmus-socket"), true);
+        let status_display_program = fs::read_to_string(runtime_dir.join(CMUS_STATUS_PROGRAM)).unwrap();
+        assert!(status_display_program.starts_with("#!/bin/sh\n") && status_display_program.contains("--cmus-status"));
+
+        // A second listener is refused while the first is listening.
+        assert!(listen_for_cmus_status(&runtime_dir).is_err());
+
+        let notified_dir = runtime_dir.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            notify_cmus_status(&notified_dir).unwrap();
+        });
+        let wait_start = Instant::now();
+        cmus.wait_for_change(Duration::from_secs(10));
+        assert!(wait_start.elapsed() < Duration::from_secs(5));
+        let _ = fs::remove_dir_all(&runtime_dir);
+    }
+
+    // Answers each connection with MPD's greeting, then replies to status and currentsong with the given responses.
+    fn start_fake_mpd(status: &'static str, current_song: &'static str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut connection = BufReader::new(stream);
+                let _ = connection.get_mut().write_all(b"OK MPD 0.23.5\n");
+                let mut command = String::new();
+                while connection.read_line(&mut command).unwrap_or(0) > 0 {
+                    let response = match command.trim_end() {
+                        "status" => status,
+                        "currentsong" => current_song,
+                        _ => "ACK [5@0] {} unknown command\n",
+                    };
+                    let _ = connection.get_mut().write_all(response.as_bytes());
+                    command.clear();
+                }
+            }
+        });
+        address
+    }
+
+    // Answers musikcube's authenticate and get_playback_overview requests over a WebSocket, accepting only the password
+    // "secret". A playback_overview_changed broadcast follows each overview shortly after.
+    fn start_fake_musikcube(overview: Value) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(mut connection) = tungstenite::accept(stream) else {
+                    continue;
+                };
+                while let Ok(Message::Text(request)) = connection.read() {
+                    let request = serde_json::from_str::<Value>(&request).unwrap();
+                    let options = match request["name"].as_str() {
+                        Some("authenticate") => json!({ "authenticated": request["options"]["password"] == "secret" }),
+                        _ => overview.clone(),
+                    };
+                    let response = json!({ "name": request["name"], "type": "response", "id": request["id"], "options": options });
+                    let _ = connection.send(Message::text(response.to_string()));
+                    if request["name"] == "get_playback_overview" {
+                        thread::sleep(Duration::from_millis(200));
+                        let broadcast = json!({ "name": "playback_overview_changed", "type": "broadcast", "id": "", "options": overview });
+                        let _ = connection.send(Message::text(broadcast.to_string()));
+                    }
+                }
+            }
+        });
+        address
+    }
+
+    // Answers get_property commands over a Unix socket with the given properties, sending an event before each reply.
+    #[cfg(unix)]
+    fn start_fake_mpv(socket_name: &str, properties: Value) -> PathBuf {
+        let socket_dir = std::env::temp_dir().join(format!("lamp-drpc-mpv-{}", std::process::id()));
+        fs::create_dir_all(&socket_dir).unwrap();
+        let socket_path = socket_dir.join(socket_name);
+        let _ = fs::remove_file(&socket_path);
+        let listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut connection = BufReader::new(stream);
+                let mut command = String::new();
+                while connection.read_line(&mut command).unwrap_or(0) > 0 {
+                    let command_json = serde_json::from_str::<Value>(&command).unwrap();
+                    let property_value = &properties[command_json["command"][1].as_str().unwrap()];
+                    let reply = match property_value.is_null() {
+                        true => json!({ "error": "property unavailable", "request_id": command_json["request_id"] }),
+                        false => json!({ "data": property_value, "error": "success", "request_id": command_json["request_id"] }),
+                    };
+                    let _ = writeln!(connection.get_mut(), "{}\n{}", json!({ "event": "playback-restart" }), reply);
+                    command.clear();
+                }
+            }
+        });
+        socket_path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_active_file_from_mpv() {
+        let socket_path = start_fake_mpv("playing", json!({ "path": "Album/track.flac", "working-directory": "/music", "duration": 215.3, "time-pos": 12.8 }));
+        let mut mpv = Mpv::new(socket_path);
+
+        assert!(mpv.verify_running().unwrap());
+        assert_eq!(mpv.get_active_file_path().unwrap().as_deref(), Some("/music/Album/track.flac"));
+        assert_eq!((mpv.get_duration(), mpv.get_position()), (Some(215), Some(12)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn idle_mpv_has_no_active_file() {
+        let socket_path = start_fake_mpv("idle", json!({ "working-directory": "/music" }));
+        let mut mpv = Mpv::new(socket_path);
+
+        assert_eq!(mpv.get_active_file_path().unwrap(), None);
+        assert_eq!(mpv.get_duration(), None);
+
+        let mut mpv = Mpv::new(std::env::temp_dir().join("lamp-drpc-no-mpv-socket"));
+        assert!(!mpv.verify_running().unwrap());
+        assert!(matches!(mpv.get_active_file_path(), Err(PlayerError::Io(_))));
+    }
+
+    #[test]
+    fn converts_file_uris_to_paths() {
+        assert_eq!(file_uri_to_path("file:///music/Test%20Artist/01%20Caf%C3%A9.flac").as_deref(), Some("/music/Test Artist/01 Café.flac"));
+        assert_eq!(file_uri_to_path("file://localhost/music/track.mp3").as_deref(), Some("/music/track.mp3"));
+        assert_eq!(file_uri_to_path("https://radio.example/stream"), None);
+        assert_eq!(file_uri_to_path("file:///music/100% Hits/track.mp3").as_deref(), Some("/music/100% Hits/track.mp3"));
+        assert_eq!(file_uri_to_path("file:///music/100%25 Hits/track%2"), Some(String::from("/music/100% Hits/track%2")));
+    }
+
+    #[test]
+    fn selects_mpris_player_by_bus_name() {
+        let bus_names = ["org.freedesktop.DBus", "org.mpris.MediaPlayer2.strawberry", "org.mpris.MediaPlayer2.lamp_drpc", "org.mpris.MediaPlayer2.elisa"].map(String::from).to_vec();
+
+        assert_eq!(select_mpris_bus_name(bus_names.clone(), None).as_deref(), Some("org.mpris.MediaPlayer2.elisa"));
+        assert_eq!(select_mpris_bus_name(bus_names.clone(), Some("strawberry")).as_deref(), Some("org.mpris.MediaPlayer2.strawberry"));
+        assert_eq!(select_mpris_bus_name(bus_names, Some("rhythmbox")), None);
+    }
+
+    #[test]
+    fn parses_metadata_of_streamed_tracks() {
+        let text = |text: &str| OwnedValue::try_from(zbus::zvariant::Value::from(text)).unwrap();
+        let metadata = HashMap::from([(String::from("xesam:artist"), OwnedValue::try_from(zbus::zvariant::Value::from(vec!["First Artist", "Second Artist"])).unwrap()),
+                                      (String::from("xesam:title"), text("Stream Title")),
+                                      (String::from("xesam:album"), text("")),
+                                      (String::from("mpris:artUrl"), text("https://images.example/cover.jpg"))]);
+        assert_eq!(parse_mpris_player_metadata(&metadata), Some(PlayerMetadata {
+            artist: String::from("First Artist, Second Artist"),
+            title: String::from("Stream Title"),
+            album: None,
+            art_url: Some(String::from("https://images.example/cover.jpg")),
+        }));
+
+        // Local album art can't be shown on Discord, and tracks without an artist aren't shown at all.
+        let metadata = HashMap::from([(String::from("xesam:artist"), OwnedValue::try_from(zbus::zvariant::Value::from(vec!["Artist"])).unwrap()),
+                                      (String::from("xesam:title"), text("Title")),
+                                      (String::from("mpris:artUrl"), text("file:///tmp/cover.jpg"))]);
+        assert_eq!(parse_mpris_player_metadata(&metadata).unwrap().art_url, None);
+        assert_eq!(parse_mpris_player_metadata(&HashMap::from([(String::from("xesam:title"), text("Title"))])), None);
+    }
+
+    #[test]
+    fn browsers_follow_their_bus_name() {
+        let bus_names = ["org.mpris.MediaPlayer2.chromium.instance4096", "org.mpris.MediaPlayer2.firefox.instance_1_42", "org.mpris.MediaPlayer2.youtube-music"].map(String::from).to_vec();
+        let followed_bus_name = |player_name: &str| select_mpris_bus_name(bus_names.clone(), Browser::new(player_name, None).mpris.bus_filter.as_deref());
+        assert_eq!(followed_bus_name("ytmdesktop").as_deref(), Some("org.mpris.MediaPlayer2.chromium.instance4096"));
+        assert_eq!(followed_bus_name("chrome").as_deref(), Some("org.mpris.MediaPlayer2.chromium.instance4096"));
+        assert_eq!(followed_bus_name("firefox").as_deref(), Some("org.mpris.MediaPlayer2.firefox.instance_1_42"));
+        assert_eq!(followed_bus_name("youtube-music").as_deref(), Some("org.mpris.MediaPlayer2.youtube-music"));
+    }
+
+    #[test]
+    fn parses_clementine_metadata() {
+        let metadata = HashMap::from([(String::from("location"), OwnedValue::try_from(zbus::zvariant::Value::from("file:///music/100% Hits/01 Track.flac")).unwrap()),
+                                      (String::from("time"), OwnedValue::from(215i32))]);
+        assert_eq!(parse_legacy_metadata(&metadata), (Some(String::from("/music/100% Hits/01 Track.flac")), Some(215)));
+        assert_eq!(parse_legacy_metadata(&HashMap::new()), (None, None));
+    }
+
+    #[test]
+    fn parses_ratings_and_original_years() {
+        // Clementine's own interface reports the rating in stars.
+        let metadata = HashMap::from([(String::from("rating"), OwnedValue::from(4i32)),
+                                      (String::from("year"), OwnedValue::from(2011i32)),
+                                      (String::from("originalyear"), OwnedValue::from(1994i32))]);
+        assert_eq!(parse_player_tags(&metadata), PlayerTags { rating: Some(4), original_year: Some(String::from("1994")) });
+
+        // Strawberry's MPRIS metadata reports the rating from 0 to 1, and tracks without an original year fall back to their year.
+        let metadata = HashMap::from([(String::from("xesam:userRating"), OwnedValue::from(0.6f64)),
+                                      (String::from("year"), OwnedValue::from(2011i32)),
+                                      (String::from("originalyear"), OwnedValue::from(0i32))]);
+        assert_eq!(parse_player_tags(&metadata), PlayerTags { rating: Some(3), original_year: Some(String::from("2011")) });
+
+        // Other MPRIS players only report the release date. Unrated tracks have no rating.
+        let metadata = HashMap::from([(String::from("xesam:userRating"), OwnedValue::from(-1f64)),
+                                      (String::from("xesam:contentCreated"), OwnedValue::try_from(zbus::zvariant::Value::from("1994-05-02T00:00:00")).unwrap())]);
+        assert_eq!(parse_player_tags(&metadata), PlayerTags { rating: None, original_year: Some(String::from("1994")) });
+        assert_eq!(parse_player_tags(&HashMap::new()), PlayerTags::default());
+    }
+
+    #[test]
+    fn rhythmbox_only_follows_rhythmbox() {
+        let bus_names = ["org.mpris.MediaPlayer2.elisa", "org.mpris.MediaPlayer2.rhythmbox", "org.gnome.Rhythmbox3"].map(String::from).to_vec();
+        assert_eq!(select_mpris_bus_name(bus_names, Some(RHYTHMBOX_BUS_FILTER)).as_deref(), Some("org.mpris.MediaPlayer2.rhythmbox"));
+        assert_eq!(select_mpris_bus_name(vec![String::from("org.mpris.MediaPlayer2.elisa")], Some(RHYTHMBOX_BUS_FILTER)), None);
+    }
+
+    #[test]
+    fn spotifyd_follows_its_bus_name() {
+        let bus_names = ["org.mpris.MediaPlayer2.spotifyd.instance2048", "org.mpris.MediaPlayer2.spotifyd.instance1024", "org.mpris.MediaPlayer2.ncspot"].map(String::from).to_vec();
+        let bus_filter = |player: Spotifyd| player.mpris.bus_filter;
+        assert_eq!(bus_filter(Spotifyd::new("spotifyd", None)).as_deref(), Some("spotifyd"));
+        assert_eq!(select_mpris_bus_name(bus_names.clone(), Some("spotifyd")).as_deref(), Some("org.mpris.MediaPlayer2.spotifyd.instance1024"));
+
+        // mpris_bus_name picks one of several instances.
+        let bus_filter = bus_filter(Spotifyd::new("spotifyd", Some(String::from("spotifyd.instance2048"))));
+        assert_eq!(select_mpris_bus_name(bus_names.clone(), bus_filter.as_deref()).as_deref(), Some("org.mpris.MediaPlayer2.spotifyd.instance2048"));
+        assert_eq!(select_mpris_bus_name(bus_names, Some("ncspot")).as_deref(), Some("org.mpris.MediaPlayer2.ncspot"));
+    }
+
+    #[test]
+    fn parses_mpris_metadata() {
+        let metadata = HashMap::from([(String::from("xesam:url"), OwnedValue::try_from(zbus::zvariant::Value::from("file:///music/Test%20Artist/track.flac")).unwrap()),
+                                      (String::from("mpris:length"), OwnedValue::from(215_300_000i64))]);
+        assert_eq!(parse_mpris_metadata(&metadata), (Some(String::from("/music/Test Artist/track.flac")), Some(215)));
+
+        // Some players report the length as unsigned, and streams have no length.
+        let metadata = HashMap::from([(String::from("xesam:url"), OwnedValue::try_from(zbus::zvariant::Value::from("https://radio.example/stream")).unwrap()),
+                                      (String::from("mpris:length"), OwnedValue::from(0u64))]);
+        assert_eq!(parse_mpris_metadata(&metadata), (Some(String::from("https://radio.example/stream")), None));
+        assert_eq!(parse_mpris_metadata(&HashMap::new()), (None, None));
+    }
+
+    #[test]
+    fn parses_mpd_responses() {
+        let response = ["volume: 50", "state: play", "elapsed: 12.831", "duration: 215.300", "file: Artist/Album/track.flac", "Title: Track"].map(String::from);
+        let playing = parse_mpd_response(&response);
+        assert_eq!((playing.state, playing.file_path, playing.duration, playing.position), (Some("play"), Some("Artist/Album/track.flac"), Some(215), Some(12)));
+
+        // Older versions of MPD only report time, and streams have no duration.
+        let response = ["state: pause", "time: 40:0", "file: http://radio.example/stream"].map(String::from);
+        let stream = parse_mpd_response(&response);
+        assert_eq!((stream.state, stream.duration, stream.position), (Some("pause"), None, Some(40)));
+    }
+
+    #[test]
+    fn parses_xmms2_output() {
+        let playing = parse_xmms2_output("Playing\n01:02\n1:03:15\nfile:///music/My+Artist/01%20Track%2B1.flac\n");
+        assert_eq!(playing.file_path.as_deref(), Some("/music/My Artist/01 Track+1.flac"));
+        assert_eq!((playing.duration, playing.position), (Some(3795), Some(62)));
+
+        // Streams have no duration, and nothing is active while XMMS2 is stopped.
+        let stream = parse_xmms2_output("Paused\n00:40\n00:00\nhttp://radio.example/stream\n");
+        assert_eq!((stream.file_path.as_deref(), stream.duration), (Some("http://radio.example/stream"), None));
+        assert_eq!(parse_xmms2_output("Stopped\n00:00\n03:00\nfile:///music/track.flac\n").file_path, None);
+        assert_eq!(parse_xmms2_output("").file_path, None);
+    }
+
+    #[test]
+    fn parses_beefweb_player() {
+        let wine_prefix = Some(Path::new("/home/user/.wine"));
+        let playing = parse_beefweb_player(&json!({
+            "player": {
+                "playbackState": "paused",
+                "activeItem": { "playlistId": "p1", "index": 3, "position": 12.6, "duration": 215.3, "columns": ["Z:\\music\\Artist\\track.flac"] },
+            },
+        }), wine_prefix);
+        assert_eq!((playing.state, playing.file_path.as_deref()), (PlaybackState::Paused, Some("/home/user/.wine/dosdevices/z:/music/Artist/track.flac")));
+        assert_eq!((playing.duration, playing.position), (Some(215), Some(12)));
+
+        let stopped = parse_beefweb_player(&json!({ "player": { "playbackState": "stopped", "activeItem": { "columns": ["C:\\track.flac"] } } }), wine_prefix);
+        assert_eq!((stopped.state, stopped.file_path), (PlaybackState::Stopped, None));
+        assert_eq!(host_path("http://radio.example/stream", wine_prefix), "http://radio.example/stream");
+        assert_eq!(host_path("C:\\Music\\track.flac", None), "C:\\Music\\track.flac");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_active_file_from_foobar2000() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                let _ = request.read_line(&mut request_line);
+                let mut header = String::new();
+                while request.read_line(&mut header).is_ok_and(|read| read > 0) && header.trim() != "" {
+                    header.clear();
+                }
+
+                // beefweb may send its response in chunks rather than with a Content-Length.
+                let (status, response) = match request_line.starts_with(&format!("GET {} ", FOOBAR2000_REQUEST_PATH)) {
+                    true => ("200 OK", json!({ "player": { "playbackState": "playing", "activeItem": { "position": 3, "duration": 180, "columns": ["Z:\\music\\track.flac"] } } }).to_string()),
+                    false => ("404 Not Found", String::new()),
+                };
+                let (first_chunk, last_chunk) = response.split_at(response.len() / 2);
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n", status);
+                for chunk in [first_chunk, last_chunk].iter().filter(|chunk| !chunk.is_empty()) {
+                    let _ = write!(stream, "{:x}\r\n{}\r\n", chunk.len(), chunk);
+                }
+                let _ = write!(stream, "0\r\n\r\n");
+            }
+        });
+
+        // The player is polled from a blocking thread, as in main.
+        tokio::task::spawn_blocking(move || {
+            let mut foobar2000 = Foobar2000::new(address);
+            foobar2000.wine_prefix = Some(PathBuf::from("/wine"));
+            assert!(foobar2000.verify_running().unwrap());
+            assert_eq!(foobar2000.get_active_file_path().unwrap().as_deref(), Some("/wine/dosdevices/z:/music/track.flac"));
+            assert_eq!((foobar2000.get_duration(), foobar2000.get_position(), foobar2000.get_playback_state()), (Some(180), Some(3), PlaybackState::Playing));
+            assert_eq!(foobar2000.get_process_name().as_deref(), Some("foobar2000.exe"));
+        }).await.unwrap();
+    }
+
+    #[test]
+    fn parses_musicbee_status() {
+        let wine_prefix = Some(Path::new("/home/user/.wine"));
+        let track = json!({ "artist": "Artist", "title": "Track", "album": "Album", "year": "2001", "path": "Z:\\music\\track.flac" });
+        let playing = parse_musicbee_status(&json!("Playing"), &track, &json!({ "current": 12600, "total": 215300 }), wine_prefix);
+        assert_eq!((playing.state, playing.file_path.as_deref()), (PlaybackState::Playing, Some("/home/user/.wine/dosdevices/z:/music/track.flac")));
+        assert_eq!((playing.duration, playing.position), (Some(215), Some(12)));
+
+        let stopped = parse_musicbee_status(&json!("Stopped"), &track, &json!({ "current": 0, "total": 215300 }), wine_prefix);
+        assert_eq!((stopped.state, stopped.file_path), (PlaybackState::Stopped, None));
+        assert_eq!(parse_musicbee_state(&json!("paused")), PlaybackState::Paused);
+    }
+
+    #[test]
+    fn reads_active_file_from_musicbee() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut connection = BufReader::new(stream);
+                let mut line = String::new();
+                while connection.read_line(&mut line).unwrap_or(0) > 0 {
+                    let request: Value = serde_json::from_str(&line).unwrap();
+                    let data = match request["context"].as_str() {
+                        Some("protocol") => json!(MUSICBEE_PROTOCOL_VERSION),
+                        Some("playerstate") => json!("Playing"),
+                        Some("nowplayingtrack") => json!({ "artist": "Artist", "title": "Track", "path": "Z:\\music\\track.flac" }),
+                        Some("nowplayingposition") => json!({ "current": 3000, "total": 180000 }),
+                        _ => Value::Null,
+                    };
+                    // A broadcast sent before the reply is skipped.
+                    let _ = write!(connection.get_mut(), "{}\r\n{}\r\n", json!({ "context": "volume", "data": 50 }), json!({ "context": request["context"], "data": data }));
+                    line.clear();
+                }
+            }
+        });
+
+        let mut musicbee = MusicBee::new(address);
+        musicbee.wine_prefix = Some(PathBuf::from("/wine"));
+        assert!(musicbee.verify_running().unwrap());
+        assert_eq!(musicbee.get_active_file_path().unwrap().as_deref(), Some("/wine/dosdevices/z:/music/track.flac"));
+        assert_eq!((musicbee.get_duration(), musicbee.get_position(), musicbee.get_playback_state()), (Some(180), Some(3), PlaybackState::Playing));
+        assert_eq!(musicbee.get_process_name().as_deref(), Some("MusicBee.exe"));
+    }
+
+    #[test]
+    fn parses_audio_streams() {
+        let metadata = parse_audio_streams(&json!([
+            { "corked": true, "properties": { "application.name": "Celluloid", "media.title": "Paused Track" } },
+            { "corked": false, "properties": { "application.name": "Firefox", "media.title": "Firefox" } },
+            { "corked": false, "properties": { "application.name": "Amberol", "media.title": "Track", "media.artist": "" } },
+        ])).unwrap();
+        assert_eq!((metadata.artist.as_str(), metadata.title.as_str(), metadata.album), ("Amberol", "Track", None));
+
+        let metadata = parse_audio_streams(&json!([{ "corked": false, "properties": { "media.title": "Track", "media.artist": "Artist" } }])).unwrap();
+        assert_eq!((metadata.artist.as_str(), metadata.title.as_str()), ("Artist", "Track"));
+        assert!(parse_audio_streams(&json!([{ "corked": false, "properties": { "media.title": "Track" } }])).is_none());
+        assert!(parse_audio_streams(&json!([])).is_none());
+    }
+
+    #[test]
+    fn parses_command_output() {
+        let playing = parse_command_output(br#"{"path": "/music/track.flac", "duration": 215.3, "position": 12, "state": "playing"}"#).unwrap();
+        assert_eq!((playing.file_path.as_deref(), playing.duration, playing.position), (Some("/music/track.flac"), Some(215), Some(12)));
+
+        // Only a path is required, and nothing is active while the player is stopped.
+        let path_only = parse_command_output(br#"{"path": "/music/track.flac"}"#).unwrap();
+        assert_eq!((path_only.file_path.as_deref(), path_only.duration), (Some("/music/track.flac"), None));
+        let stopped = parse_command_output(br#"{"path": "/music/track.flac", "duration": 215, "state": "stopped"}"#).unwrap();
+        assert_eq!((stopped.file_path, stopped.duration), (None, None));
+        assert!(parse_command_output(b"Not JSON").is_err());
+    }
+
+    #[test]
+    fn reads_active_file_from_command() {
+        let mut command_player = ExternalCommand::new(String::from(r#"echo '{"path": "/music/track.flac", "duration": 180, "position": 3}'"#), String::from("player"));
+        assert_eq!(command_player.get_active_file_path().unwrap().as_deref(), Some("/music/track.flac"));
+        assert_eq!((command_player.get_duration(), command_player.get_position()), (Some(180), Some(3)));
+        assert_eq!(command_player.get_process_name().as_deref(), Some("player"));
+
+        // A failing command is treated as nothing playing.
+        use_test_state_dir();
+        let mut command_player = ExternalCommand::new(String::from("exit 1"), String::from("player"));
+        assert_eq!(command_player.get_active_file_path().unwrap(), None);
+    }
+
+    #[test]
+    fn reads_active_file_from_plugin() {
+        let plugin_command = r#"while read -r request; do
+            case "$request" in
+                *'"hello"'*) echo '{"process_name": "foobar"}' ;;
+                *'"verify_running"'*) echo '{"running": true}' ;;
+                *'"poll"'*) echo '{"artist": "Artist", "title": "Title", "art_url": "https://example.com/art.jpg", "duration": 180, "position": 3}' ;;
+            esac
+        done"#;
+        let mut plugin = Plugin::new("foobar-plugin", String::from(plugin_command));
+        assert_eq!(plugin.get_process_name().as_deref(), Some("foobar"));
+        assert!(plugin.verify_running().unwrap());
+        assert_eq!(plugin.get_active_file_path().unwrap().as_deref(), Some("Artist - Title"));
+        assert_eq!((plugin.get_duration(), plugin.get_position()), (Some(180), Some(3)));
+        assert_eq!(plugin.get_metadata(), Some(PlayerMetadata {
+            artist: String::from("Artist"),
+            title: String::from("Title"),
+            album: None,
+            art_url: Some(String::from("https://example.com/art.jpg")),
+        }));
+    }
+
+    #[test]
+    fn reads_tags_of_local_files_reported_by_plugins() {
+        let plugin_command = r#"while read -r request; do
+            case "$request" in
+                *'"poll"'*) echo '{"path": "/music/track.flac", "artist": "Plugin Artist", "title": "Plugin Title"}' ;;
+                *) echo '{}' ;;
+            esac
+        done"#;
+        let mut plugin = Plugin::new("foobar-plugin", String::from(plugin_command));
+        assert_eq!(plugin.get_active_file_path().unwrap().as_deref(), Some("/music/track.flac"));
+        assert_eq!(plugin.get_metadata(), None);
+    }
+
+    #[test]
+    fn plugins_that_exit_are_started_again() {
+        use_test_state_dir();
+        // The plugin exits after replying to hello and a single poll, and is started again by the poll after it fails.
+        let plugin_count_path = std::env::temp_dir().join(format!("lamp-drpc-plugin-{}", std::process::id()));
+        let _ = fs::remove_file(&plugin_count_path);
+        let plugin_command = format!(r#"echo started >> '{}'; read -r hello; echo '{{}}'; read -r poll; echo '{{"path": "/music/track.flac"}}'"#, plugin_count_path.display());
+        let mut plugin = Plugin::new("foobar", plugin_command);
+        assert_eq!(plugin.get_process_name().as_deref(), Some("foobar"));
+        assert_eq!(plugin.get_active_file_path().unwrap().as_deref(), Some("/music/track.flac"));
+        assert_eq!(plugin.get_active_file_path().unwrap_err().to_string(), "foobar has exited.");
+        assert_eq!(plugin.get_active_file_path().unwrap().as_deref(), Some("/music/track.flac"));
+        assert_eq!(fs::read_to_string(&plugin_count_path).unwrap().lines().count(), 2);
+        let _ = fs::remove_file(&plugin_count_path);
+    }
+
+    #[test]
+    fn parses_now_playing_file() {
+        assert_eq!(parse_now_playing_file("/music/track.flac\n215.3\n"), (Some(String::from("/music/track.flac")), Some(215)));
+        assert_eq!(parse_now_playing_file("/music/track.flac"), (Some(String::from("/music/track.flac")), None));
+        assert_eq!(parse_now_playing_file("\n215\n"), (None, None));
+    }
+
+    #[test]
+    fn now_playing_file_changes_end_the_wait() {
+        let test_dir = std::env::temp_dir().join(format!("lamp-drpc-now-playing-{}", std::process::id()));
+        fs::create_dir_all(&test_dir).unwrap();
+        let file_path = test_dir.join("now-playing.txt");
+        let _ = fs::remove_file(&file_path);
+        let mut now_playing_file = NowPlayingFile::new(file_path.clone(), String::from("player"));
+        assert_eq!(now_playing_file.get_active_file_path().unwrap(), None);
+
+        // Other files in the directory are ignored.
+        let written_path = file_path.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            fs::write(written_path.with_file_name("other.txt"), "other").unwrap();
+            fs::write(written_path, "/music/track.flac\n180\n").unwrap();
+        });
+        let wait_start = Instant::now();
+        now_playing_file.wait_for_change(Duration::from_secs(10));
+        assert!(wait_start.elapsed() < Duration::from_secs(5));
+        assert_eq!(now_playing_file.get_active_file_path().unwrap().as_deref(), Some("/music/track.flac"));
+        assert_eq!(now_playing_file.get_duration(), Some(180));
+    }
+
+    #[test]
+    fn reads_active_file_from_mpd() {
+        let address = start_fake_mpd("state: play\nelapsed: 3.5\nduration: 180.0\nOK\n", "file: Artist/track.mp3\nOK\n");
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+
+        assert!(mpd.verify_running().unwrap());
+        assert_eq!(mpd.get_active_file_path().unwrap().as_deref(), Some("/music/Artist/track.mp3"));
+        assert_eq!((mpd.get_duration(), mpd.get_position()), (Some(180), Some(3)));
+
+        // The connection is kept between polls.
+        assert_eq!(mpd.get_active_file_path().unwrap().as_deref(), Some("/music/Artist/track.mp3"));
+    }
+
+    #[test]
+    fn reads_mopidy_tracks() {
+        let address = start_fake_mpd("state: play\nelapsed: 3.5\nOK\n", "file: spotify:track:4uLU6hMCjMI75M1A2tKUQC\nArtist: First Artist\nArtist: Second Artist\nTitle: Title\nAlbum: Album\nOK\n");
+        let mut mopidy = Mpd::new(address, PathBuf::from("/music"), None, true);
+        assert_eq!(mopidy.get_active_file_path().unwrap().as_deref(), Some("spotify:track:4uLU6hMCjMI75M1A2tKUQC"));
+        assert_eq!(mopidy.get_metadata(), Some(PlayerMetadata {
+            artist: String::from("First Artist, Second Artist"),
+            title: String::from("Title"),
+            album: Some(String::from("Album")),
+            art_url: None,
+        }));
+
+        // Local files are found in the media directory, and their tags are read from the file.
+        let address = start_fake_mpd("state: play\nOK\n", "file: local:track:Artist/Album/01%20Track.flac\nArtist: Artist\nTitle: Track\nOK\n");
+        let mut mopidy = Mpd::new(address, PathBuf::from("/music"), None, true);
+        assert_eq!(mopidy.get_active_file_path().unwrap().as_deref(), Some("/music/Artist/Album/01 Track.flac"));
+        assert_eq!(mopidy.get_metadata(), None);
+
+        assert!(is_mopidy_uri("tidal:track:1234"));
+        assert!(!is_mopidy_uri("Artist: Live/track.mp3"));
+    }
+
+    #[test]
+    fn reads_mpd_paths_that_look_like_uris_as_files() {
+        // Relative paths starting with a lowercase word and a colon are only URIs when the player is Mopidy.
+        let address = start_fake_mpd("state: play\nOK\n", "file: live:2003/track.flac\nTitle: Track\nOK\n");
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+        assert_eq!(mpd.get_active_file_path().unwrap().as_deref(), Some("/music/live:2003/track.flac"));
+        assert_eq!(mpd.get_metadata(), None);
+
+        let address = start_fake_mpd("state: play\nOK\n", "file: local:track:Artist/track.flac\nOK\n");
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+        assert_eq!(mpd.get_active_file_path().unwrap().as_deref(), Some("/music/local:track:Artist/track.flac"));
+    }
+
+    #[test]
+    fn reads_active_file_from_musikcube() {
+        let address = start_fake_musikcube(json!({
+            "state": "playing",
+            "playing_duration": 215.4,
+            "playing_current_time": 12.8,
+            "playing_track": { "filename": "/music/Artist/track.flac", "artist": "Artist", "title": "Title", "external_id": "1234" },
+        }));
+        let mut musikcube = Musikcube::new(address, Some(String::from("secret")));
+
+        assert!(musikcube.verify_running().unwrap());
+        assert_eq!(musikcube.get_active_file_path().unwrap().as_deref(), Some("/music/Artist/track.flac"));
+        assert_eq!((musikcube.get_duration(), musikcube.get_position()), (Some(215), Some(12)));
+        assert_eq!(musikcube.get_metadata(), None);
+
+        // Waiting ends as soon as musikcube broadcasts a change, and the connection is kept between polls.
+        let wait_start = Instant::now();
+        musikcube.wait_for_change(Duration::from_secs(10));
+        assert!(wait_start.elapsed() < Duration::from_secs(5));
+        assert_eq!(musikcube.get_active_file_path().unwrap().as_deref(), Some("/music/Artist/track.flac"));
+    }
+
+    #[test]
+    fn musikcube_password_is_checked() {
+        let address = start_fake_musikcube(json!({ "state": "stopped" }));
+        let mut musikcube = Musikcube::new(address.clone(), None);
+        assert!(matches!(musikcube.get_active_file_path(), Err(PlayerError::Protocol(_))));
+
+        let mut musikcube = Musikcube::new(address, Some(String::from("secret")));
+        assert_eq!(musikcube.get_active_file_path().unwrap(), None);
+    }
+
+    #[test]
+    fn parses_musikcube_overview() {
+        let paused = parse_musikcube_overview(&json!({
+            "state": "paused",
+            "playing_duration": 0.0,
+            "playing_track": { "artist": "Artist", "title": "Title", "album": "Album", "external_id": "remote-1234" },
+        }));
+        assert_eq!((paused.state, paused.file_path.as_deref(), paused.duration), (PlaybackState::Paused, Some("remote-1234"), None));
+        assert_eq!(paused.metadata, Some(PlayerMetadata {
+            artist: String::from("Artist"),
+            title: String::from("Title"),
+            album: Some(String::from("Album")),
+            art_url: None,
+        }));
+
+        let stopped = parse_musikcube_overview(&json!({ "state": "stopped", "playing_track": { "filename": "/music/track.flac" } }));
+        assert_eq!(stopped.file_path, None);
+    }
+
+    #[test]
+    fn stopped_mpd_has_no_active_file() {
+        let address = start_fake_mpd("state: stop\nOK\n", "file: Artist/track.mp3\nOK\n");
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+
+        assert_eq!(mpd.get_active_file_path().unwrap(), None);
+        assert_eq!(mpd.get_duration(), None);
+    }
+
+    #[test]
+    fn mpd_errors_are_returned() {
+        let address = start_fake_mpd("ACK [4@0] {status} you don't have permission for \"status\"\n", "OK\n");
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+        assert!(matches!(mpd.get_active_file_path(), Err(PlayerError::Protocol(_))));
+
+        // Nothing is listening on the address once the listener is dropped.
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut mpd = Mpd::new(address, PathBuf::from("/music"), None, false);
+        assert!(!mpd.verify_running().unwrap());
+        assert!(matches!(mpd.get_active_file_path(), Err(PlayerError::Io(_))));
+    }
+}
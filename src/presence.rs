@@ -0,0 +1,189 @@
+use discord_presence::models::rich_presence::{ActivityTimestamps, ActivityType, DisplayType};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error::{DiscordError, LampError};
+use crate::error_log;
+use crate::events::NowPlaying;
+use crate::metrics;
+use crate::supervisor;
+
+/*
+ *  The presence service owns the Discord client and applies presence updates in its own task,
+ *  so the event loop never waits on Discord.
+ */
+pub enum PresenceUpdate {
+    Show(NowPlaying),
+    ShowLyric(NowPlaying, String), // Shows the lyric line on the state line in place of the artist.
+    Clear,
+}
+
+#[derive(Clone)]
+pub struct PresenceService {
+    presence_sender: mpsc::UnboundedSender<PresenceUpdate>,
+}
+
+impl PresenceService {
+    pub fn start(client_id: u64) -> (PresenceService, JoinHandle<()>) {
+        let (presence_sender, presence_receiver) = mpsc::unbounded_channel::<PresenceUpdate>();
+
+        // The receiver is shared so pending updates are kept if the worker panics and is restarted.
+        let presence_receiver = Arc::new(Mutex::new(presence_receiver));
+        let connected_before = Arc::new(AtomicBool::new(false));
+        let presence_task = supervisor::supervise("presence", move || {
+            run_presence_task(start_discord_client(client_id, connected_before.clone()), presence_receiver.clone())
+        });
+
+        (PresenceService { presence_sender }, presence_task)
+    }
+
+    // Creates a service without a Discord client, returning the receiver for the updates it is sent.
+    #[cfg(test)]
+    pub fn mock() -> (PresenceService, mpsc::UnboundedReceiver<PresenceUpdate>) {
+        let (presence_sender, presence_receiver) = mpsc::unbounded_channel::<PresenceUpdate>();
+        (PresenceService { presence_sender }, presence_receiver)
+    }
+
+    pub fn show(&self, now_playing: &NowPlaying) {
+        let _ = self.presence_sender.send(PresenceUpdate::Show(now_playing.clone()));
+    }
+
+    pub fn show_lyric(&self, now_playing: &NowPlaying, lyric_line: &str) {
+        let _ = self.presence_sender.send(PresenceUpdate::ShowLyric(now_playing.clone(), String::from(lyric_line)));
+    }
+
+    pub fn clear(&self) {
+        let _ = self.presence_sender.send(PresenceUpdate::Clear);
+    }
+}
+
+fn start_discord_client(client_id: u64, connected_before: Arc<AtomicBool>) -> discord_presence::Client {
+    let mut discord_client = discord_presence::Client::new(client_id);
+
+    // Every connection after the first is a reconnect, including those made by a restarted worker.
+    discord_client.on_connected(move |_| {
+        if connected_before.swap(true, Ordering::Relaxed) {
+            metrics::increment(&metrics::DISCORD_RECONNECTS);
+        }
+    }).persist();
+
+    discord_client.start();
+    discord_client
+}
+
+async fn run_presence_task(mut discord_client: discord_presence::Client, presence_receiver: Arc<Mutex<mpsc::UnboundedReceiver<PresenceUpdate>>>) {
+    let mut presence_receiver = presence_receiver.lock_owned().await;
+
+    // Apply updates until every PresenceService handle has been dropped.
+    while let Some(presence_update) = presence_receiver.recv().await {
+        let update_result = match presence_update {
+            PresenceUpdate::Show(now_playing) => {
+                let state = now_playing.artist.clone();
+                set_activity(&mut discord_client, now_playing, &state)
+            }
+            PresenceUpdate::ShowLyric(now_playing, lyric_line) => set_activity(&mut discord_client, now_playing, &lyric_line),
+            PresenceUpdate::Clear => discord_client.clear_activity().map(|_| ()).map_err(DiscordError::ClearActivity),
+        };
+        if let Err(e) = update_result {
+            error_log::log_lamp_error("presence", &LampError::from(e));
+        }
+    }
+
+    let _ = discord_client.shutdown();
+}
+
+fn set_activity(discord_client: &mut discord_presence::Client, now_playing: NowPlaying, state: &str) -> Result<(), DiscordError> {
+    // Use the default album image if no image link is defined, and only apply the album name and link if they are defined.
+    let large_image = now_playing.image_link.unwrap_or(String::from("no_album_art"));
+    let set_result = discord_client.set_activity(|a| {
+        let activity = a.activity_type(ActivityType::Listening)
+                        .status_display(DisplayType::State)
+                        .state(state)
+                        .details(&now_playing.title)
+                        .timestamps(|_t| ActivityTimestamps { start: now_playing.start_time, end: now_playing.end_time })
+                        .assets(|a| match &now_playing.album {
+                            Some(album) => a.large_image(&large_image).large_text(album),
+                            None => a.large_image(&large_image),
+                        });
+        match &now_playing.link {
+            Some(link) => activity.append_buttons(|b| b.label("Listen").url(link)),
+            None => activity,
+        }
+    });
+    set_result.map(|_| ()).map_err(DiscordError::SetActivity)
+}
+
+// The fake Discord server listens on a Unix socket.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::mock::{use_test_state_dir, FakeDiscord};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sends_now_playing_to_discord() {
+        use_test_state_dir();
+        let fake_discord = FakeDiscord::start();
+        let (presence, presence_task) = PresenceService::start(1);
+        fake_discord.wait_until_ready();
+
+        presence.show(&NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            image_link: Some(String::from("https://images.example/art.jpg")),
+            link: Some(String::from("https://song.link/i/1234")),
+            start_time: Some(1000),
+            end_time: Some(1180),
+            ..NowPlaying::default()
+        });
+        presence.show(&NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Single"),
+            start_time: Some(2000),
+            ..NowPlaying::default()
+        });
+        presence.show_lyric(&NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Single"),
+            start_time: Some(2000),
+            ..NowPlaying::default()
+        }, "First line");
+        presence.clear();
+
+        let activities = fake_discord.wait_for_activities(4);
+        drop(presence);
+        presence_task.await.unwrap();
+
+        assert_eq!(activities, vec![
+            Some(json!({
+                "type": 2,
+                "status_display_type": 1,
+                "state": "Test Artist",
+                "details": "Test Title",
+                "timestamps": { "start": 1000, "end": 1180 },
+                "assets": { "large_image": "https://images.example/art.jpg", "large_text": "Test Album" },
+                "buttons": [{ "label": "Listen", "url": "https://song.link/i/1234" }],
+            })),
+            Some(json!({
+                "type": 2,
+                "status_display_type": 1,
+                "state": "Test Artist",
+                "details": "Single",
+                "timestamps": { "start": 2000 },
+                "assets": { "large_image": "no_album_art" },
+            })),
+            Some(json!({
+                "type": 2,
+                "status_display_type": 1,
+                "state": "First line",
+                "details": "Single",
+                "timestamps": { "start": 2000 },
+                "assets": { "large_image": "no_album_art" },
+            })),
+            None,
+        ]);
+    }
+}
@@ -0,0 +1,214 @@
+use reqwest::header::HeaderMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error_log;
+use crate::events::{Event, PlayerMetadata};
+use crate::http;
+use crate::supervisor;
+
+// How often the stream's title is checked. Each check reads the stream up to its first metadata block.
+const RADIO_UPDATE_INTERVAL: Duration = Duration::from_secs(20);
+const RADIO_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Streams whose metadata blocks are further apart than this are assumed not to send any.
+const MAX_METADATA_INTERVAL: usize = 1024 * 1024;
+
+/*
+ *  Titles of internet radio streams, for players that report a stream's URL rather than a file.
+ *
+ *  - Streams are requested with the Icy-MetaData header, which makes SHOUTcast and Icecast servers insert a metadata
+ *    block every icy-metaint bytes of audio. The block's StreamTitle is usually in the form "Artist - Title".
+ *  - The radio service checks the followed stream every RADIO_UPDATE_INTERVAL, emitting a StreamMetadata event
+ *    whenever its title changes. The station's name, from the icy-name header, is shown as the album.
+ */
+pub enum RadioRequest {
+    Follow { track_id: u64, url: String },
+    Stop,
+}
+
+// The stream being followed, and the metadata last reported for it.
+struct FollowedStream {
+    track_id: u64,
+    url: String,
+    metadata: Option<PlayerMetadata>,
+}
+
+#[derive(Clone)]
+pub struct RadioService {
+    radio_sender: mpsc::UnboundedSender<RadioRequest>,
+}
+
+impl RadioService {
+    pub fn start(event_sender: mpsc::UnboundedSender<Event>) -> (RadioService, JoinHandle<()>) {
+        let (radio_sender, radio_receiver) = mpsc::unbounded_channel::<RadioRequest>();
+
+        // The receiver is shared so pending requests are kept if the worker panics and is restarted.
+        let radio_receiver = Arc::new(Mutex::new(radio_receiver));
+        let radio_task = supervisor::supervise("radio", move || run_radio_task(radio_receiver.clone(), event_sender.clone()));
+
+        (RadioService { radio_sender }, radio_task)
+    }
+
+    // Creates a service that follows no streams, returning the receiver for the requests it is sent.
+    #[cfg(test)]
+    pub fn mock() -> (RadioService, mpsc::UnboundedReceiver<RadioRequest>) {
+        let (radio_sender, radio_receiver) = mpsc::unbounded_channel::<RadioRequest>();
+        (RadioService { radio_sender }, radio_receiver)
+    }
+
+    pub fn follow(&self, track_id: u64, url: &str) {
+        let _ = self.radio_sender.send(RadioRequest::Follow { track_id, url: String::from(url) });
+    }
+
+    pub fn stop(&self) {
+        let _ = self.radio_sender.send(RadioRequest::Stop);
+    }
+}
+
+// Whether the active track is a stream rather than a local file.
+pub fn is_stream_url(file_path: &str) -> bool {
+    file_path.starts_with("http://") || file_path.starts_with("https://")
+}
+
+async fn run_radio_task(radio_receiver: Arc<Mutex<mpsc::UnboundedReceiver<RadioRequest>>>, event_sender: mpsc::UnboundedSender<Event>) {
+    let mut radio_receiver = radio_receiver.lock_owned().await;
+    let mut followed_stream: Option<FollowedStream> = None;
+    let mut update_interval = tokio::time::interval(RADIO_UPDATE_INTERVAL);
+
+    // Follow streams until every RadioService handle has been dropped.
+    loop {
+        tokio::select! {
+            radio_request = radio_receiver.recv() => match radio_request {
+                Some(RadioRequest::Follow { track_id, url }) => {
+                    followed_stream = Some(FollowedStream { track_id, url, metadata: None });
+                    update_interval.reset_immediately();
+                }
+                Some(RadioRequest::Stop) => followed_stream = None,
+                None => break,
+            },
+            _ = update_interval.tick() => {
+                let Some(followed_stream) = &mut followed_stream else {
+                    continue;
+                };
+                let metadata = match fetch_stream_metadata(&followed_stream.url).await {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        error_log::log_error("radio:fetch_stream_metadata Error", format!("The title of the stream at {} could not be read: {}", followed_stream.url, e).as_str());
+                        continue;
+                    }
+                };
+                if let Some(metadata) = metadata.filter(|metadata| Some(metadata) != followed_stream.metadata.as_ref()) {
+                    followed_stream.metadata = Some(metadata.clone());
+                    let _ = event_sender.send(Event::StreamMetadata { track_id: followed_stream.track_id, metadata });
+                }
+            }
+        }
+    }
+}
+
+// Reads the stream up to its first metadata block, returning the stream's title along with the station's name. Returns
+// None if the stream has neither.
+pub async fn fetch_stream_metadata(url: &str) -> Result<Option<PlayerMetadata>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut response = http::send(|client| client.get(url).header("Icy-MetaData", "1").timeout(RADIO_TIMEOUT)).await?.error_for_status()?;
+    let station = header_text(response.headers(), "icy-name");
+    let metadata_interval = header_text(response.headers(), "icy-metaint").and_then(|metadata_interval| metadata_interval.parse::<usize>().ok())
+                                                                         .filter(|&metadata_interval| metadata_interval > 0 && metadata_interval <= MAX_METADATA_INTERVAL);
+    let Some(metadata_interval) = metadata_interval else {
+        return Ok(stream_metadata(station, None));
+    };
+
+    // The metadata block follows metadata_interval bytes of audio. Its first byte is its length in units of 16 bytes.
+    let mut stream_data = Vec::<u8>::new();
+    while let Some(chunk) = response.chunk().await? {
+        stream_data.extend_from_slice(&chunk);
+        let Some(&length_byte) = stream_data.get(metadata_interval) else {
+            continue;
+        };
+        let metadata_end = metadata_interval + 1 + length_byte as usize * 16;
+        if let Some(metadata_block) = stream_data.get(metadata_interval + 1..metadata_end) {
+            return Ok(stream_metadata(station, parse_stream_title(metadata_block)));
+        }
+    }
+    Err(Box::from("The stream ended before its first metadata block."))
+}
+
+fn header_text(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim).filter(|value| !value.is_empty()).map(String::from)
+}
+
+// Reads StreamTitle from a metadata block, e.g. "StreamTitle='Artist - Title';StreamUrl='';", padded with null
+// characters. Titles are UTF-8 on most stations, and are otherwise read as Latin-1.
+pub fn parse_stream_title(metadata_block: &[u8]) -> Option<String> {
+    let metadata_end = metadata_block.iter().position(|&byte| byte == 0).unwrap_or(metadata_block.len());
+    let metadata = match std::str::from_utf8(&metadata_block[..metadata_end]) {
+        Ok(metadata) => String::from(metadata),
+        Err(_) => metadata_block[..metadata_end].iter().map(|&byte| byte as char).collect(),
+    };
+    // Titles may contain quotes, so the title ends at the quote that ends the field.
+    let title_start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let title_length = metadata[title_start..].find("';").unwrap_or(metadata[title_start..].trim_end_matches('\'').len());
+    Some(metadata[title_start..title_start + title_length].trim().to_owned()).filter(|title| !title.is_empty())
+}
+
+// Shows "Artist - Title" stream titles by their artist and title, along with the station's name as the album. Titles
+// in any other form are shown by the station's name in place of the artist.
+pub fn stream_metadata(station: Option<String>, stream_title: Option<String>) -> Option<PlayerMetadata> {
+    let (artist, title) = match (stream_title, &station) {
+        (Some(stream_title), _) => match stream_title.split_once(" - ") {
+            Some((artist, title)) => (artist.trim().to_owned(), title.trim().to_owned()),
+            None => (station.clone().unwrap_or(String::from("Internet radio")), stream_title),
+        },
+        (None, Some(station)) => (String::from("Internet radio"), station.clone()),
+        (None, None) => return None,
+    };
+    Some(PlayerMetadata { artist, title, album: station, art_url: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_stream_titles() {
+        assert_eq!(parse_stream_title(b"StreamTitle='Artist - It's a Title';StreamUrl='';\0\0\0").as_deref(), Some("Artist - It's a Title"));
+        assert_eq!(parse_stream_title(b"StreamTitle='Caf\xe9 Artist - Title';").as_deref(), Some("Café Artist - Title"));
+        assert_eq!(parse_stream_title(b"StreamTitle='';"), None);
+        assert_eq!(parse_stream_title(b"StreamUrl='https://radio.example';"), None);
+
+        let station = Some(String::from("Test Radio"));
+        assert_eq!(stream_metadata(station.clone(), Some(String::from("Artist - Title"))),
+                   Some(PlayerMetadata { artist: String::from("Artist"), title: String::from("Title"), album: station.clone(), art_url: None }));
+        assert_eq!(stream_metadata(station.clone(), Some(String::from("Morning Show"))).map(|metadata| (metadata.artist, metadata.title)),
+                   Some((String::from("Test Radio"), String::from("Morning Show"))));
+        assert_eq!(stream_metadata(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn reads_the_first_metadata_block_of_a_stream() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let server_task = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 1024];
+            let request_length = stream.read(&mut request).await.unwrap();
+            let metadata = b"StreamTitle='Test Artist - Test Title';";
+            let mut metadata_block = vec![metadata.len().div_ceil(16) as u8];
+            metadata_block.extend_from_slice(metadata);
+            metadata_block.resize(1 + metadata.len().div_ceil(16) * 16, 0);
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\nicy-name: Test Radio\r\nicy-metaint: 64\r\n\r\n").await;
+            let _ = stream.write_all(&[0xFF; 64]).await;
+            let _ = stream.write_all(&metadata_block).await;
+            let _ = stream.write_all(&[0xFF; 64]).await;
+            String::from_utf8_lossy(&request[..request_length]).to_lowercase()
+        });
+
+        let metadata = fetch_stream_metadata(&url).await.unwrap().unwrap();
+        assert_eq!((metadata.artist.as_str(), metadata.title.as_str(), metadata.album.as_deref()), ("Test Artist", "Test Title", Some("Test Radio")));
+        assert!(server_task.await.unwrap().contains("icy-metadata: 1"));
+    }
+}
@@ -0,0 +1,117 @@
+use regex::Regex;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::events::NowPlaying;
+
+/*
+ *  Rewrite rules for the tags shown, from the [rewrites] table of lamp.toml.
+ *
+ *  - Each key is a regular expression, and its value is the replacement for every match, in which $1 or ${name}
+ *    refer to capture groups, e.g. '\s*\(\d{4} Remaster\)' = '' strips remaster suffixes.
+ *  - Rules are applied to the artist, title, and album of every track, in the order they are written, before the
+ *    track is shown or sent anywhere else.
+ *  - Invalid expressions are reported when the configuration is loaded.
+ */
+#[derive(Clone, Default)]
+pub struct Rewrites {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Rewrites {
+    // Rewrites the tags of the track. Artists and titles rewritten to nothing are kept as they were, since tracks are
+    // shown by them, while albums rewritten to nothing are left out.
+    pub fn apply(&self, now_playing: &mut NowPlaying) {
+        if self.rules.is_empty() {
+            return;
+        }
+        if let Some(artist) = Some(self.rewrite(&now_playing.artist)).filter(|artist| !artist.is_empty()) {
+            now_playing.artist = artist;
+        }
+        if let Some(title) = Some(self.rewrite(&now_playing.title)).filter(|title| !title.is_empty()) {
+            now_playing.title = title;
+        }
+        now_playing.album = now_playing.album.as_deref().map(|album| self.rewrite(album)).filter(|album| !album.is_empty());
+    }
+
+    fn rewrite(&self, text: &str) -> String {
+        let mut text = String::from(text);
+        for (pattern, replacement) in &self.rules {
+            text = pattern.replace_all(&text, replacement.as_str()).into_owned();
+        }
+        String::from(text.trim())
+    }
+}
+
+// The table is read entry by entry, rather than into a map, so rules keep the order they are written in.
+impl<'de> Deserialize<'de> for Rewrites {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RewritesVisitor;
+
+        impl<'de> Visitor<'de> for RewritesVisitor {
+            type Value = Rewrites;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a table of regular expressions and their replacements")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Rewrites, A::Error> {
+                let mut rules = Vec::new();
+                while let Some((pattern, replacement)) = map.next_entry::<String, String>()? {
+                    let pattern = Regex::new(&pattern).map_err(|e| serde::de::Error::custom(format!("invalid rewrite rule '{}': {}", pattern, e)))?;
+                    rules.push((pattern, replacement));
+                }
+                Ok(Rewrites { rules })
+            }
+        }
+
+        deserializer.deserialize_map(RewritesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct TestConfig {
+        rewrites: Rewrites,
+    }
+
+    fn parse_rewrites(toml_string: &str) -> Result<Rewrites, toml::de::Error> {
+        toml::from_str::<TestConfig>(toml_string).map(|test_config| test_config.rewrites)
+    }
+
+    fn now_playing(artist: &str, title: &str, album: Option<&str>) -> NowPlaying {
+        NowPlaying {
+            artist: String::from(artist),
+            title: String::from(title),
+            album: album.map(String::from),
+            ..NowPlaying::default()
+        }
+    }
+
+    #[test]
+    fn rewrites_tags_in_order() {
+        let rewrites = parse_rewrites("[rewrites]\n\
+                                      '\\s*\\(\\d{4} Remaster(ed)?\\)' = ''\n\
+                                      '^The (.+)$' = '$1, The'\n\
+                                      'Remaster' = 'Edition'\n").unwrap();
+        let mut track = now_playing("The Band", "Song (2011 Remaster)", Some("Album (2011 Remastered)"));
+        rewrites.apply(&mut track);
+        assert_eq!((track.artist.as_str(), track.title.as_str(), track.album.as_deref()), ("Band, The", "Song", Some("Album")));
+
+        // Albums rewritten to nothing are left out, while artists and titles are kept.
+        let rewrites = parse_rewrites("[rewrites]\n'.*' = ''\n").unwrap();
+        let mut track = now_playing("Artist", "Title", Some("Album"));
+        rewrites.apply(&mut track);
+        assert_eq!((track.artist.as_str(), track.title.as_str(), track.album), ("Artist", "Title", None));
+    }
+
+    #[test]
+    fn invalid_rules_are_reported() {
+        let error = parse_rewrites("[rewrites]\n'(unclosed' = ''\n").err().unwrap();
+        assert!(error.to_string().contains("invalid rewrite rule '(unclosed'"));
+    }
+}
@@ -0,0 +1,46 @@
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use crate::error_log::fs;
+
+/*
+ *  Runtime directory used for sockets (the control socket, and cmus-socket for cmus), resolved once at startup.
+ *
+ *  - $XDG_RUNTIME_DIR is used when it is set.
+ *  - Otherwise, /run/user/<uid> is used for the user running lamp-drpc, if it exists.
+ *  - If neither is available (e.g. in containers), an error is returned explaining how to provide one.
+ *  - On Windows, sockets are named pipes, so the pipe namespace (\\.\pipe\) takes the place of the runtime directory.
+ */
+#[cfg(unix)]
+pub fn resolve() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        let runtime_dir = PathBuf::from(runtime_dir);
+        return match runtime_dir.is_dir() {
+            true => Ok(runtime_dir),
+            false => Err(Box::from(format!("$XDG_RUNTIME_DIR is set to {}, which is not a directory.", runtime_dir.display()))),
+        };
+    }
+
+    let uid = current_uid()?;
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", uid));
+    match runtime_dir.is_dir() {
+        true => Ok(runtime_dir),
+        false => Err(Box::from(format!("No runtime directory found. $XDG_RUNTIME_DIR is not set and {} does not exist. Set $XDG_RUNTIME_DIR to a directory owned by UID {}.", runtime_dir.display(), uid))),
+    }
+}
+
+#[cfg(windows)]
+pub fn resolve() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(PathBuf::from(r"\\.\pipe\"))
+}
+
+#[cfg(unix)]
+fn current_uid() -> Result<u32, Box<dyn std::error::Error>> {
+    // /proc/self is owned by the user the process runs as.
+    match fs::metadata("/proc/self") {
+        Ok(metadata) => Ok(metadata.uid()),
+        Err(e) => Err(Box::from(format!("Could not determine the current UID from /proc/self: {}", e))),
+    }
+}
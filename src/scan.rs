@@ -0,0 +1,155 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::art::{self, ArtEncoding, CachedLink, ImageHost};
+use crate::art_lookup::ArtSource;
+use crate::error::LampError;
+use crate::error_log;
+use crate::error_log::fs;
+use crate::metadata::{self, AlbumArt};
+
+// Maximum number of extracted images waiting to be uploaded. Extraction pauses until the uploads catch up.
+const SCAN_QUEUE_SIZE: usize = 4;
+
+/*
+ *  Library pre-scan, run with lamp-drpc scan <directory>.
+ *
+ *  - The directory is walked for supported files, whose album art is extracted on one blocking thread per CPU.
+ *  - Each album's art is resized and uploaded once, at most one upload per upload_interval, and its link is stored
+ *    in albumart_hash.json as if it had been played. Album art that already has a stored link is skipped.
+ *  - The hash file is saved after every upload, so an interrupted scan keeps its progress.
+ */
+pub struct ScanSettings {
+    pub va_album_individual: bool,
+    pub va_names: Vec<String>,
+    pub artist_delimiters: Vec<String>,
+    pub folder_art_names: Vec<String>,
+    pub art_sources: Vec<ArtSource>, // Only the local sources are read. Album art looked up online is never scanned.
+    pub max_art_size: u64,
+    pub art_encoding: ArtEncoding,
+    pub catbox_user_hash: Option<String>,
+    pub upload_interval: Duration,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ScanSummary {
+    pub files: usize, // Supported files found in the directory.
+    pub stored: usize, // Albums whose art already had a stored link.
+    pub uploaded: usize,
+    pub failed: usize,
+}
+
+pub async fn scan_library<H: ImageHost>(music_dir: &Path,
+                                        image_host: &H,
+                                        filename_hash: &mut HashMap<String, CachedLink>,
+                                        settings: &ScanSettings) -> Result<ScanSummary, Box<dyn std::error::Error>> {
+    let mut file_paths = Vec::<PathBuf>::new();
+    find_supported_files(music_dir, &mut file_paths)?;
+    let mut scan_summary = ScanSummary { files: file_paths.len(), ..ScanSummary::default() };
+
+    // Workers take files from a shared list and send the album art they extract to the uploads below.
+    let file_paths = Arc::new(Mutex::new(file_paths.into_iter()));
+    let (art_sender, mut art_receiver) = mpsc::channel::<(String, AlbumArt)>(SCAN_QUEUE_SIZE);
+    let worker_count = std::thread::available_parallelism().map(|worker_count| worker_count.get()).unwrap_or(1);
+    for _ in 0..worker_count {
+        let (file_paths, art_sender) = (file_paths.clone(), art_sender.clone());
+        let (va_album_individual, va_names, max_art_size) = (settings.va_album_individual, settings.va_names.clone(), settings.max_art_size);
+        let (artist_delimiters, folder_art_names, art_sources) = (settings.artist_delimiters.clone(), settings.folder_art_names.clone(), settings.art_sources.clone());
+        tokio::task::spawn_blocking(move || {
+            while let Some(file_path) = file_paths.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next() {
+                let file_path = file_path.to_string_lossy().into_owned();
+                let album_art = match metadata::read_metadata(&file_path, &va_album_individual, &va_names, &artist_delimiters, &folder_art_names, &art_sources, max_art_size) {
+                    Ok(metadata_pack) => metadata_pack.album_art,
+                    Err(e) => {
+                        error_log::log_lamp_error("scan:read_metadata", &LampError::from(e));
+                        None
+                    }
+                };
+                let Some(album_art) = album_art else {
+                    continue;
+                };
+                if art_sender.blocking_send((file_path, album_art)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(art_sender);
+
+    // Tracks from the same album share their album art filename, so each album is only counted and uploaded once.
+    let mut seen_filenames = HashSet::<String>::new();
+    let mut upload_interval = tokio::time::interval(settings.upload_interval.max(Duration::from_millis(1)));
+    upload_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    while let Some((file_path, album_art)) = art_receiver.recv().await {
+        if !seen_filenames.insert(album_art.filename.clone()) {
+            continue;
+        }
+        if filename_hash.contains_key(&art::cache_key(&album_art.filename, settings.art_encoding)) {
+            scan_summary.stored += 1;
+            continue;
+        }
+
+        upload_interval.tick().await;
+        match art::write_album_art(image_host, album_art, settings.art_encoding, &settings.catbox_user_hash).await {
+            Ok((filename, link)) => {
+                println!("Uploaded album art from {}", file_path);
+                let now = art::unix_time();
+                filename_hash.insert(filename, CachedLink { link, verified_at: now, last_used: now });
+                art::save_hash_file(filename_hash);
+                scan_summary.uploaded += 1;
+            }
+            Err(e) => {
+                error_log::log_error("scan:write_album_art Error", format!("Error while processing album art image on file {}: {}", file_path, e).as_str());
+                scan_summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(scan_summary)
+}
+
+// Collects supported files in the directory and its subdirectories. Symlinked directories are not followed, so links
+// back up the tree can't cause a loop, and unreadable subdirectories are skipped.
+fn find_supported_files(dir_path: &Path, file_paths: &mut Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    for dir_entry in fs::read_dir(dir_path)?.flatten() {
+        let Ok(file_type) = dir_entry.file_type() else {
+            continue;
+        };
+        let entry_path = dir_entry.path();
+        if file_type.is_dir() {
+            let _ = find_supported_files(&entry_path, file_paths);
+        } else if metadata::is_supported(&entry_path.to_string_lossy()) {
+            file_paths.push(entry_path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{fixture_path, use_test_state_dir, MockImageHost};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn uploads_each_album_once() {
+        use_test_state_dir();
+        let image_host = MockImageHost::default();
+        let settings = ScanSettings { va_album_individual: false, va_names: Vec::new(), artist_delimiters: Vec::new(), folder_art_names: Vec::new(), art_sources: ArtSource::ALL.to_vec(), max_art_size: u64::MAX, art_encoding: ArtEncoding::default(), catbox_user_hash: None, upload_interval: Duration::from_millis(1) };
+        let fixtures_dir = PathBuf::from(fixture_path(""));
+
+        // The fixtures hold seven tracks with album art, which share three albums, one without album art, and one without an
+        // artist, which is skipped.
+        let mut filename_hash = HashMap::<String, CachedLink>::new();
+        let scan_summary = scan_library(&fixtures_dir, &image_host, &mut filename_hash, &settings).await.unwrap();
+        assert_eq!(scan_summary, ScanSummary { files: 9, stored: 0, uploaded: 3, failed: 0 });
+        assert_eq!(filename_hash.len(), 3);
+
+        let scan_summary = scan_library(&fixtures_dir, &image_host, &mut filename_hash, &settings).await.unwrap();
+        assert_eq!(scan_summary, ScanSummary { files: 9, stored: 3, uploaded: 0, failed: 0 });
+        assert_eq!(image_host.uploads.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,141 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error_log;
+use crate::events::{Event, NowPlaying};
+use crate::http;
+use crate::supervisor;
+
+// Odesli only resolves links from streaming services, so tracks are first matched through the iTunes Search API.
+const ITUNES_SEARCH_URL: &str = "https://itunes.apple.com/search";
+const ODESLI_API_URL: &str = "https://api.song.link/v1-alpha.1/links";
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Maximum number of tracks kept in the link cache. The cache is cleared once it is full.
+const LINK_CACHE_SIZE: usize = 1000;
+
+/*
+ *  Optional universal links from Odesli (song.link), enabled by setting odesli_lookup in lamp.toml.
+ *
+ *  - Each track is resolved to a song.link page listing it on every streaming service. Once a link is resolved, a
+ *    LinkReady event is emitted, and the event loop adds it to the presence as a button and to the webhook payload.
+ *  - Results are cached by artist and title for as long as lamp-drpc runs, including tracks that could not be found,
+ *    since Odesli limits how often it can be queried. Failed lookups are logged and retried the next time the track plays.
+ */
+pub struct LinkRequest {
+    pub track_id: u64,
+    pub artist: String,
+    pub title: String,
+}
+
+#[derive(Clone)]
+pub struct LinkService {
+    link_sender: mpsc::UnboundedSender<LinkRequest>,
+}
+
+impl LinkService {
+    pub fn start(event_sender: mpsc::UnboundedSender<Event>) -> (LinkService, JoinHandle<()>) {
+        let (link_sender, link_receiver) = mpsc::unbounded_channel::<LinkRequest>();
+
+        // The receiver is shared so pending requests are kept if the worker panics and is restarted.
+        let link_receiver = Arc::new(Mutex::new(link_receiver));
+        let link_task = supervisor::supervise("song link", move || run_link_task(link_receiver.clone(), event_sender.clone()));
+
+        (LinkService { link_sender }, link_task)
+    }
+
+    // Creates a service that resolves nothing, returning the receiver for the requests it is sent.
+    #[cfg(test)]
+    pub fn mock() -> (LinkService, mpsc::UnboundedReceiver<LinkRequest>) {
+        let (link_sender, link_receiver) = mpsc::unbounded_channel::<LinkRequest>();
+        (LinkService { link_sender }, link_receiver)
+    }
+
+    pub fn resolve(&self, track_id: u64, now_playing: &NowPlaying) {
+        let _ = self.link_sender.send(LinkRequest { track_id, artist: now_playing.artist.clone(), title: now_playing.title.clone() });
+    }
+}
+
+async fn run_link_task(link_receiver: Arc<Mutex<mpsc::UnboundedReceiver<LinkRequest>>>, event_sender: mpsc::UnboundedSender<Event>) {
+    let mut link_receiver = link_receiver.lock_owned().await;
+    let mut link_cache = HashMap::<(String, String), Option<String>>::new();
+
+    // Resolve links until every LinkService handle has been dropped.
+    while let Some(link_request) = link_receiver.recv().await {
+        let cache_key = (link_request.artist, link_request.title);
+        let link = match link_cache.get(&cache_key) {
+            Some(link) => link.clone(),
+            None => match resolve_link(ITUNES_SEARCH_URL, ODESLI_API_URL, &cache_key.0, &cache_key.1).await {
+                Ok(link) => {
+                    if link_cache.len() >= LINK_CACHE_SIZE {
+                        link_cache.clear();
+                    }
+                    link_cache.insert(cache_key, link.clone());
+                    link
+                }
+                Err(e) => {
+                    error_log::log_error("song_link:resolve_link Error", e.to_string().as_str());
+                    None
+                }
+            },
+        };
+
+        if link.is_some() {
+            let _ = event_sender.send(Event::LinkReady { track_id: link_request.track_id, link });
+        }
+    }
+}
+
+// Finds the track on iTunes, then returns its song.link page. Returns None if the track could not be found.
+pub async fn resolve_link(itunes_url: &str, odesli_url: &str, artist: &str, title: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let search_term = format!("{} {}", artist, title);
+    let search_response = http::send(|client| client.get(itunes_url)
+                                                    .query(&[("term", search_term.as_str()), ("entity", "song"), ("limit", "1")])
+                                                    .timeout(LOOKUP_TIMEOUT)).await?
+                                                    .error_for_status()?;
+    let search_json: Value = serde_json::from_str(&search_response.text().await?)?;
+    let Some(itunes_id) = search_json["results"][0]["trackId"].as_u64() else {
+        return Ok(None);
+    };
+
+    let itunes_id = itunes_id.to_string();
+    let links_response = http::send(|client| client.get(odesli_url)
+                                                   .query(&[("platform", "itunes"), ("type", "song"), ("id", itunes_id.as_str())])
+                                                   .timeout(LOOKUP_TIMEOUT)).await?
+                                                   .error_for_status()?;
+    let links_json: Value = serde_json::from_str(&links_response.text().await?)?;
+    Ok(links_json["pageUrl"].as_str().map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::mock::receive_http_request;
+
+    #[tokio::test]
+    async fn resolves_song_link_through_itunes() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let itunes_url = format!("http://{}/search", listener.local_addr().unwrap());
+        let odesli_url = format!("http://{}/links", listener.local_addr().unwrap());
+
+        let resolve_task = {
+            let (itunes_url, odesli_url) = (itunes_url.clone(), odesli_url.clone());
+            tokio::spawn(async move { resolve_link(&itunes_url, &odesli_url, "Test Artist", "Test Title").await.map_err(|e| e.to_string()) })
+        };
+        let search_request = receive_http_request(&listener, "200 OK", r#"{"resultCount": 1, "results": [{"trackId": 1234}]}"#).await;
+        let links_request = receive_http_request(&listener, "200 OK", r#"{"pageUrl": "https://song.link/i/1234"}"#).await;
+        assert_eq!(resolve_task.await.unwrap(), Ok(Some(String::from("https://song.link/i/1234"))));
+        assert_eq!(search_request.request_line, "GET /search?term=Test+Artist+Test+Title&entity=song&limit=1 HTTP/1.1");
+        assert_eq!(links_request.request_line, "GET /links?platform=itunes&type=song&id=1234 HTTP/1.1");
+
+        // Tracks missing from iTunes are not looked up on Odesli.
+        let resolve_task = tokio::spawn(async move { resolve_link(&itunes_url, &odesli_url, "Unknown", "Unknown").await.map_err(|e| e.to_string()) });
+        receive_http_request(&listener, "200 OK", r#"{"resultCount": 0, "results": []}"#).await;
+        assert_eq!(resolve_task.await.unwrap(), Ok(None));
+    }
+}
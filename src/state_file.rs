@@ -0,0 +1,32 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error_log::fs;
+use crate::error_log::Write;
+
+/*
+ *  State files (such as albumart_hash.json) are written to a temporary file in the same directory first, then renamed
+ *  over the original. A crash partway through writing leaves the previous contents intact instead of a truncated file.
+ */
+pub fn write_atomic(file_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_file_path = temp_path(file_path);
+
+    let write_result = (|| {
+        let mut temp_file = fs::File::create(&temp_file_path)?;
+        temp_file.write_all(contents)?;
+        temp_file.sync_all()?;
+        fs::rename(&temp_file_path, file_path)
+    })();
+
+    // Don't leave a partially written temporary file behind.
+    if write_result.is_err() {
+        let _ = fs::remove_file(&temp_file_path);
+    }
+    write_result
+}
+
+fn temp_path(file_path: &Path) -> PathBuf {
+    // e.g. albumart_hash.json -> .albumart_hash.json.tmp
+    let file_name = file_path.file_name().map(|file_name| file_name.to_string_lossy().into_owned()).unwrap_or_default();
+    file_path.with_file_name(format!(".{}.tmp", file_name))
+}
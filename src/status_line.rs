@@ -0,0 +1,82 @@
+use serde_json::json;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::events::NowPlaying;
+use crate::ipc;
+use crate::ipc::StatusFormat;
+
+// How often the running instance is asked for the active track.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/*
+ *  Status line output for status bars, started with lamp-drpc --status-line [waybar|text].
+ *
+ *  - The running instance is polled for the active track, and a new line is printed whenever it changes, as expected by
+ *    Waybar custom modules (with return-type json) and Polybar script modules (with tail = true).
+ *  - Waybar output is JSON with text, tooltip, and class fields. The class is playing, stopped, or offline if
+ *    lamp-drpc is not running.
+ *  - Text output is the track alone, or an empty line if nothing is playing.
+ */
+pub fn waybar_status(now_playing: Option<&NowPlaying>) -> String {
+    match now_playing {
+        Some(now_playing) => json!({
+            "text": format!("{} - {}", now_playing.artist, now_playing.title),
+            "tooltip": now_playing.description(),
+            "class": "playing",
+        }).to_string(),
+        None => json!({ "text": "", "tooltip": "", "class": "stopped" }).to_string(),
+    }
+}
+
+pub fn text_status(now_playing: Option<&NowPlaying>) -> String {
+    now_playing.map(|now_playing| format!("{} - {}", now_playing.artist, now_playing.title)).unwrap_or_default()
+}
+
+pub fn run(socket_path: PathBuf, status_format: StatusFormat) -> ! {
+    let (status_command, offline_status) = match status_format {
+        StatusFormat::Waybar => ("status --waybar", json!({ "text": "", "tooltip": "lamp-drpc is not running", "class": "offline" }).to_string()),
+        _ => ("status --text", String::new()),
+    };
+
+    let mut last_status: Option<String> = None;
+    loop {
+        let status = ipc::send_command(&socket_path, status_command).unwrap_or(offline_status.clone());
+        if last_status.as_ref() != Some(&status) {
+            // Stop once the bar closes its end of the pipe.
+            let mut stdout = std::io::stdout();
+            if writeln!(stdout, "{}", status).and_then(|_| stdout.flush()).is_err() {
+                std::process::exit(0);
+            }
+            last_status = Some(status);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn formats_active_track_for_status_bars() {
+        let now_playing = NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            ..NowPlaying::default()
+        };
+
+        assert_eq!(serde_json::from_str::<Value>(&waybar_status(Some(&now_playing))).unwrap(), json!({
+            "text": "Test Artist - Test Title",
+            "tooltip": "Test Artist - Test Title (Test Album)",
+            "class": "playing",
+        }));
+        assert_eq!(serde_json::from_str::<Value>(&waybar_status(None)).unwrap()["class"], "stopped");
+        assert_eq!(text_status(Some(&now_playing)), "Test Artist - Test Title");
+        assert_eq!(text_status(None), "");
+    }
+}
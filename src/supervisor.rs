@@ -0,0 +1,67 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::error_log;
+
+// Time to wait before the first restart of a worker. Doubled after each consecutive panic, up to MAX_BACKOFF.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// A worker that runs at least this long before panicking is considered to have recovered, resetting the backoff.
+const STABLE_RUNTIME: Duration = Duration::from_secs(60);
+
+/*
+ *  Runs a worker task under a supervisor, which restarts it if it panics.
+ *
+ *  - start_worker is called to create the worker each time it is (re)started. Any state that must survive a restart,
+ *    such as the receiving end of the worker's channel, should be shared with the worker rather than moved into it.
+ *
+ *  - Panics are logged, and the worker is restarted after a backoff that grows with each consecutive panic.
+ *
+ *  - Once the worker returns normally, the supervisor stops as well.
+ */
+pub fn supervise<F, Fut>(worker_name: &'static str, mut start_worker: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            let start_time = Instant::now();
+            let join_error = match tokio::spawn(start_worker()).await {
+                Ok(_) => break,
+                Err(join_error) => join_error,
+            };
+
+            // Workers are never cancelled, so any other error would mean the runtime is shutting down.
+            if !join_error.is_panic() {
+                break;
+            }
+
+            if start_time.elapsed() >= STABLE_RUNTIME {
+                backoff = INITIAL_BACKOFF;
+            }
+
+            error_log::log_error("supervisor: Worker panicked",
+                                 format!("The {} worker panicked and will be restarted in {} seconds: {}", worker_name, backoff.as_secs(), panic_message(join_error.into_panic())).as_str());
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    })
+}
+
+pub fn panic_message(panic_payload: Box<dyn std::any::Any + Send>) -> String {
+    // Panic payloads are usually a &str or String, depending on whether the panic message was formatted.
+    if let Some(message) = panic_payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic_payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Unknown panic payload.")
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error_log;
+use crate::events::{self, NowPlaying};
+use crate::http;
+use crate::supervisor;
+
+// Maximum time to wait for the webhook endpoint to respond before the post is abandoned.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+ *  Optional now-playing webhook, enabled by setting webhook_url in lamp.toml.
+ *
+ *  - A "playing" payload is posted whenever a track is shown, including its metadata, album art link, and timestamps.
+ *  - A "stopped" payload is posted when the player stops or exits, or lamp-drpc quits.
+ *
+ *  Posts are sent in order from the webhook service's own task, so the event loop never waits on the endpoint.
+ *  Failed posts are logged and not retried.
+ */
+pub struct WebhookPost {
+    pub webhook_url: String,
+    pub payload: Value,
+}
+
+#[derive(Clone)]
+pub struct WebhookService {
+    webhook_sender: mpsc::UnboundedSender<WebhookPost>,
+}
+
+impl WebhookService {
+    pub fn start() -> (WebhookService, JoinHandle<()>) {
+        let (webhook_sender, webhook_receiver) = mpsc::unbounded_channel::<WebhookPost>();
+
+        // The receiver is shared so pending posts are kept if the worker panics and is restarted.
+        let webhook_receiver = Arc::new(Mutex::new(webhook_receiver));
+        let webhook_task = supervisor::supervise("webhook", move || run_webhook_task(webhook_receiver.clone()));
+
+        (WebhookService { webhook_sender }, webhook_task)
+    }
+
+    // Creates a service that posts nothing, returning the receiver for the posts it is sent.
+    #[cfg(test)]
+    pub fn mock() -> (WebhookService, mpsc::UnboundedReceiver<WebhookPost>) {
+        let (webhook_sender, webhook_receiver) = mpsc::unbounded_channel::<WebhookPost>();
+        (WebhookService { webhook_sender }, webhook_receiver)
+    }
+
+    // Posts the given track as playing, or that playback has stopped if there is none.
+    pub fn notify(&self, webhook_url: &str, player_name: &str, now_playing: Option<&NowPlaying>) {
+        let payload = events::playback_payload(player_name, now_playing);
+        let _ = self.webhook_sender.send(WebhookPost { webhook_url: String::from(webhook_url), payload });
+    }
+}
+
+async fn run_webhook_task(webhook_receiver: Arc<Mutex<mpsc::UnboundedReceiver<WebhookPost>>>) {
+    let mut webhook_receiver = webhook_receiver.lock_owned().await;
+
+    // Send posts until every WebhookService handle has been dropped.
+    while let Some(webhook_post) = webhook_receiver.recv().await {
+        let post_result = http::send(|client| client.post(&webhook_post.webhook_url)
+                                                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                                                    .body(webhook_post.payload.to_string())
+                                                    .timeout(WEBHOOK_TIMEOUT)).await;
+        match post_result {
+            Ok(response) if response.status().is_success() => (),
+            Ok(response) => error_log::log_error("webhook: Webhook Error", format!("{} responded with {}.", webhook_post.webhook_url, response.status()).as_str()),
+            Err(e) => error_log::log_error("webhook: Webhook Error", e.to_string().as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use crate::mock::{receive_http_request, use_test_state_dir};
+
+    #[tokio::test]
+    async fn posts_now_playing_to_webhook() {
+        use_test_state_dir();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let webhook_url = format!("http://{}/now-playing", listener.local_addr().unwrap());
+        let (webhook, webhook_task) = WebhookService::start();
+
+        webhook.notify(&webhook_url, "cmus", Some(&NowPlaying {
+            artist: String::from("Test Artist"),
+            title: String::from("Test Title"),
+            album: Some(String::from("Test Album")),
+            genre: Some(String::from("Test Genre")),
+            year: Some(String::from("2020")),
+            image_link: Some(String::from("https://images.example/art.jpg")),
+            link: Some(String::from("https://song.link/i/1234")),
+            start_time: Some(1000),
+            end_time: Some(1180),
+            ..NowPlaying::default()
+        }));
+        webhook.notify(&webhook_url, "cmus", None);
+
+        let request = receive_http_request(&listener, "204 No Content", "").await;
+        assert_eq!(request.request_line, "POST /now-playing HTTP/1.1");
+        assert_eq!(serde_json::from_str::<Value>(&request.body).unwrap(), json!({
+            "event": "playing",
+            "player": "cmus",
+            "artist": "Test Artist",
+            "title": "Test Title",
+            "album": "Test Album",
+            "genre": "Test Genre",
+            "year": "2020",
+            "label": null,
+            "rating": null,
+            "original_year": null,
+            "art_url": "https://images.example/art.jpg",
+            "link": "https://song.link/i/1234",
+            "start_time": 1000,
+            "end_time": 1180,
+        }));
+        let request = receive_http_request(&listener, "204 No Content", "").await;
+        assert_eq!(serde_json::from_str::<Value>(&request.body).unwrap(), json!({ "event": "stopped", "player": "cmus" }));
+
+        drop(webhook);
+        webhook_task.await.unwrap();
+    }
+}